@@ -52,6 +52,24 @@ enum Commands {
     /// Start interactive shell
     Repl,
 
+    /// Nearest-neighbour search; prints one `id score` line per hit
+    SearchVector {
+        /// Query vector as comma-separated floats, e.g. 0.1,0.2,0.3
+        #[arg(long, value_delimiter = ',', allow_negative_numbers = true)]
+        query: Vec<f32>,
+        #[arg(long, default_value_t = 10)]
+        k: usize,
+        /// l2, cosine or dot (server default l2)
+        #[arg(long)]
+        metric: Option<String>,
+        #[arg(long)]
+        namespace: Option<String>,
+        /// Print similarities in [0, 1] (higher = closer) instead of raw scores,
+        /// which for l2 are negated distances
+        #[arg(long)]
+        normalized: bool,
+    },
+
     /// Follow WAL from a leader and apply to a follower server
     Follow {
         /// Leader base URL (e.g. http://leader:8000)
@@ -73,6 +91,21 @@ enum Commands {
         #[arg(long)]
         follower_token: Option<String>,
     },
+
+    /// Create the first admin user in the local data dir (embedded; server need not run)
+    AdminInit {
+        #[arg(long)]
+        user: String,
+        /// Password inline (discouraged); prompted for when omitted
+        #[arg(long)]
+        password: Option<String>,
+        /// Data directory (defaults env PIESKIEO_DATA, then the platform data dir)
+        #[arg(long, env = "PIESKIEO_DATA")]
+        data_dir: Option<String>,
+        /// Replace this user even if auth_users.json already has users
+        #[arg(long)]
+        force: bool,
+    },
 }
 
 fn main() -> Result<()> {
@@ -83,7 +116,41 @@ fn main() -> Result<()> {
         .or_else(|| std::env::var("PIESKIEO_URL").ok())
         .unwrap_or_else(|| "http://127.0.0.1:8000".into());
 
-    run_network_mode(cli, &base_url)
+    match cli.command {
+        Some(Commands::AdminInit {
+            user,
+            password,
+            data_dir,
+            force,
+        }) => admin_init(&user, password, data_dir, force),
+        _ => run_network_mode(cli, &base_url),
+    }
+}
+
+fn admin_init(
+    user: &str,
+    password: Option<String>,
+    data_dir: Option<String>,
+    force: bool,
+) -> Result<()> {
+    let pass = match password {
+        Some(p) => p,
+        None => {
+            let p = rpassword::prompt_password("Password: ")?;
+            if rpassword::prompt_password("Confirm password: ")? != p {
+                anyhow::bail!("passwords do not match");
+            }
+            p
+        }
+    };
+    let path = pieskieo_server::bootstrap_admin(data_dir.as_deref(), user, &pass, force)?;
+    println!("admin {user} written to {}", path.display());
+    if std::env::var("PIESKIEO_USERS").is_ok() || std::env::var("PIESKIEO_AUTH_USER").is_ok() {
+        eprintln!(
+            "note: PIESKIEO_USERS/PIESKIEO_AUTH_USER are set and take precedence over this file"
+        );
+    }
+    Ok(())
 }
 
 fn run_network_mode(cli: Cli, base_url: &str) -> Result<()> {
@@ -93,7 +160,7 @@ fn run_network_mode(cli: Cli, base_url: &str) -> Result<()> {
         .build()?;
     match cli.command {
         Some(Commands::Sql { sql }) => {
-            let data = net_query_sql(
+            let (data, _) = net_query_sql(
                 &client,
                 base_url,
                 AuthOpt {
@@ -106,6 +173,25 @@ fn run_network_mode(cli: Cli, base_url: &str) -> Result<()> {
             println!("{}", data);
             Ok(())
         }
+        Some(Commands::SearchVector {
+            query,
+            k,
+            metric,
+            namespace,
+            normalized,
+        }) => {
+            let body = serde_json::json!({
+                "query": query,
+                "k": k,
+                "metric": metric,
+                "namespace": namespace,
+                "normalized": normalized,
+            });
+            for hit in net_search_vector(&client, base_url, token, &body)? {
+                println!("{}\t{:.4}", hit.id, hit.score);
+            }
+            Ok(())
+        }
         Some(Commands::Follow {
             leader,
             follower,
@@ -164,7 +250,7 @@ fn run_network_mode(cli: Cli, base_url: &str) -> Result<()> {
             );
         }
         _ => {
-            println!("Network mode supports Repl, Connect, Sql, Follow.");
+            println!("Network mode supports Repl, Connect, Sql, SearchVector, Follow.");
             Ok(())
         }
     }
@@ -177,9 +263,15 @@ struct AuthOpt {
     basic_pass: Option<String>,
 }
 
-fn net_query_sql(client: &Client, base: &str, auth: AuthOpt, sql: &str) -> Result<String> {
-    let (out, _) = net_query_sql_with_status(client, base, auth, sql)?;
-    Ok(out)
+/// Pretty-printed result and the server's own handling time in ms.
+fn net_query_sql(
+    client: &Client,
+    base: &str,
+    auth: AuthOpt,
+    sql: &str,
+) -> Result<(String, Option<f64>)> {
+    let (out, _, took_ms) = net_query_sql_with_status(client, base, auth, sql)?;
+    Ok((out, took_ms))
 }
 
 fn net_query_sql_with_status(
@@ -187,14 +279,15 @@ fn net_query_sql_with_status(
     base: &str,
     auth: AuthOpt,
     sql: &str,
-) -> Result<(String, Option<reqwest::StatusCode>)> {
+) -> Result<(String, Option<reqwest::StatusCode>, Option<f64>)> {
     #[derive(Deserialize)]
     struct Resp {
         ok: bool,
         data: serde_json::Value,
+        took_ms: Option<f64>,
     }
     let mut req = client
-        .post(format!("{}/v1/sql", base))
+        .post(format!("{}/v1/sql?timing=true", base))
         .json(&serde_json::json!({ "sql": sql }));
     if let Some(t) = auth.bearer {
         req = req.bearer_auth(t);
@@ -213,7 +306,38 @@ fn net_query_sql_with_status(
     if !parsed.ok {
         anyhow::bail!("server returned ok=false");
     }
-    Ok((serde_json::to_string_pretty(&parsed.data)?, Some(status)))
+    Ok((
+        serde_json::to_string_pretty(&parsed.data)?,
+        Some(status),
+        parsed.took_ms,
+    ))
+}
+
+fn net_search_vector(
+    client: &Client,
+    base: &str,
+    bearer: Option<String>,
+    body: &serde_json::Value,
+) -> Result<Vec<pieskieo_core::VectorSearchResult>> {
+    #[derive(Deserialize)]
+    struct Resp {
+        ok: bool,
+        data: Vec<pieskieo_core::VectorSearchResult>,
+    }
+    let mut req = client.post(format!("{}/v1/vector/search", base)).json(body);
+    if let Some(t) = bearer {
+        req = req.bearer_auth(t);
+    }
+    let resp = req.send()?;
+    let status = resp.status();
+    if !status.is_success() {
+        anyhow::bail!("HTTP status {} for /v1/vector/search", status);
+    }
+    let parsed: Resp = resp.json()?;
+    if !parsed.ok {
+        anyhow::bail!("server returned ok=false");
+    }
+    Ok(parsed.data)
 }
 
 fn run_net_repl(client: &Client, base: &str, auth: AuthOpt) -> Result<()> {
@@ -299,9 +423,12 @@ fn run_net_repl_with_prompt(
         }
         let start = Instant::now();
         match net_query_sql(client, base, auth.clone(), stmt) {
-            Ok(out) => {
+            Ok((out, took_ms)) => {
                 let elapsed = start.elapsed();
-                println!("{out}\n({:.2?})", elapsed);
+                match took_ms {
+                    Some(ms) => println!("{out}\n({:.2?}, server {ms:.2}ms)", elapsed),
+                    None => println!("{out}\n({:.2?})", elapsed),
+                }
             }
             Err(e) => eprintln!("error: {e}"),
         }
@@ -1,18 +1,21 @@
 use crate::error::Result;
-use crate::vector::{VectorIndex, VectorMetric};
+use crate::vector::{decode_snapshot, encode_snapshot, VectorIndex, VectorMeta, VectorMetric};
 use crate::wal::{DataFamily, RecordKind, Wal};
 use crate::{error::PieskieoError, graph::GraphStore};
 use parking_lot::RwLock;
+use rayon::prelude::*;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use sqlparser::ast::{
-    BinaryOperator, Expr, Function, FunctionArg, FunctionArgExpr, JoinConstraint, JoinOperator,
-    OrderByExpr, Select, SelectItem, SetExpr, Statement, TableFactor,
+    Assignment, BinaryOperator, ConflictTarget, Expr, FromTable, Function, FunctionArg,
+    FunctionArgExpr, JoinConstraint, JoinOperator, OnConflict, OnConflictAction, OnInsert,
+    OrderByExpr, Query, Select, SelectItem, SetExpr, Statement, TableFactor,
 };
 use sqlparser::dialect::GenericDialect;
 use sqlparser::parser::Parser;
-use std::collections::{BTreeMap, HashMap};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
 use std::sync::Arc;
 use uuid::Uuid;
 
@@ -24,9 +27,103 @@ pub(crate) struct Collections {
     // simple equality secondary index: ns -> collection -> field -> value_json -> ids
     row_index: HashMap<String, HashMap<String, HashMap<String, HashMap<String, Vec<Uuid>>>>>,
     doc_index: HashMap<String, HashMap<String, HashMap<String, HashMap<String, Vec<Uuid>>>>>,
+    // numeric doc fields in sorted order: ns -> collection -> field -> value -> ids;
+    // entries may be stale after updates, so readers re-check the stored value
+    doc_range: HashMap<String, HashMap<String, HashMap<String, BTreeMap<RangeKey, Vec<Uuid>>>>>,
     // schemas
     row_schema: HashMap<String, HashMap<String, SchemaDef>>,
     doc_schema: HashMap<String, HashMap<String, SchemaDef>>,
+    // insertion order behind ORDER BY _seq: ns -> collection/table -> id -> seq,
+    // given on first put and kept across updates
+    doc_seq: HashMap<String, HashMap<String, HashMap<Uuid, u64>>>,
+    row_seq: HashMap<String, HashMap<String, HashMap<Uuid, u64>>>,
+    next_seq: u64,
+}
+
+impl Collections {
+    /// Moves in collections replayed on their own; callers keep the
+    /// `(namespace, collection)` sets disjoint, so nothing is overwritten.
+    fn absorb(&mut self, other: Collections) {
+        fn merge<T>(
+            into: &mut HashMap<String, HashMap<String, T>>,
+            from: HashMap<String, HashMap<String, T>>,
+        ) {
+            for (ns, colls) in from {
+                into.entry(ns).or_default().extend(colls);
+            }
+        }
+        let Collections {
+            rows,
+            docs,
+            row_index,
+            doc_index,
+            doc_range,
+            row_schema,
+            doc_schema,
+            doc_seq,
+            row_seq,
+            next_seq,
+        } = other;
+        merge(&mut self.rows, rows);
+        merge(&mut self.docs, docs);
+        merge(&mut self.row_index, row_index);
+        merge(&mut self.doc_index, doc_index);
+        merge(&mut self.doc_range, doc_range);
+        merge(&mut self.row_schema, row_schema);
+        merge(&mut self.doc_schema, doc_schema);
+        merge(&mut self.doc_seq, doc_seq);
+        merge(&mut self.row_seq, row_seq);
+        self.next_seq = self.next_seq.max(next_seq);
+    }
+
+    fn seqs(&mut self, rows: bool) -> &mut HashMap<String, HashMap<String, HashMap<Uuid, u64>>> {
+        if rows {
+            &mut self.row_seq
+        } else {
+            &mut self.doc_seq
+        }
+    }
+
+    /// Numbers `id` as the newest record unless it is already numbered, so
+    /// updates keep their place in insertion order.
+    fn assign_seq(&mut self, rows: bool, ns: &str, coll: &str, id: Uuid) {
+        let next = self.next_seq;
+        let seq = self
+            .seqs(rows)
+            .entry(ns.to_string())
+            .or_default()
+            .entry(coll.to_string())
+            .or_default()
+            .entry(id)
+            .or_insert(next);
+        if *seq == next {
+            self.next_seq += 1;
+        }
+    }
+
+    fn drop_seq(&mut self, rows: bool, ns: &str, coll: &str, id: &Uuid) {
+        if let Some(seqs) = self.seqs(rows).get_mut(ns).and_then(|m| m.get_mut(coll)) {
+            seqs.remove(id);
+        }
+    }
+}
+
+/// f64 with a total order so numeric field values can key a `BTreeMap`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct RangeKey(f64);
+
+impl Eq for RangeKey {}
+
+impl PartialOrd for RangeKey {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for RangeKey {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.0.total_cmp(&other.0)
+    }
 }
 
 pub struct PieskieoDb {
@@ -39,13 +136,43 @@ pub struct PieskieoDb {
     // vector id -> namespace (for auto-link + delete convenience)
     pub(crate) vector_ns: Arc<RwLock<HashMap<Uuid, String>>>,
     pub(crate) graph: GraphStore,
-    link_top_k: usize,
+    link_top_k: AtomicUsize,
+    // per-namespace overrides of link_top_k
+    link_top_k_ns: RwLock<HashMap<String, usize>>,
     shard_id: usize,
     shard_total: usize,
     default_params: VectorParams,
+    // false once a WAL flush fails; cleared by the next successful flush
+    wal_healthy: std::sync::atomic::AtomicBool,
+    writes: WriteCounters,
+    compactions: CompactionStats,
+    // unix ms of the last WAL append / start of the last vector snapshot
+    // since open; 0 until one happens
+    last_write_ms: AtomicU64,
+    snapshot_ms: AtomicU64,
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+/// Successful local writes since open; WAL replay is not counted, so these
+/// only ever grow and suit Prometheus `rate()`.
+#[derive(Default)]
+struct WriteCounters {
+    docs: AtomicU64,
+    rows: AtomicU64,
+    vectors: AtomicU64,
+    edges: AtomicU64,
+}
+
+/// WAL compactions (vacuums that rewrote the log) since open.
+#[derive(Default)]
+struct CompactionStats {
+    runs: AtomicU64,
+    records: AtomicU64,
+    // unix ms when the last one finished and how long it took
+    last_ms: AtomicU64,
+    last_us: AtomicU64,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
 pub struct SchemaField {
     #[serde(default)]
     pub required: bool,
@@ -60,6 +187,210 @@ pub struct SchemaDef {
     pub fields: HashMap<String, SchemaField>,
 }
 
+/// JSON Schema keywords that only annotate, so importing drops them.
+const JSON_SCHEMA_ANNOTATIONS: &[&str] = &[
+    "$schema",
+    "$id",
+    "$comment",
+    "title",
+    "description",
+    "default",
+    "examples",
+    "format",
+    "deprecated",
+    "readOnly",
+    "writeOnly",
+];
+
+const JSON_SCHEMA_TYPES: &[&str] = &[
+    "string", "number", "integer", "boolean", "object", "array", "null",
+];
+
+impl SchemaDef {
+    /// Translates an object JSON Schema into field constraints: `required`
+    /// names become required fields, each property's `type` its type and the
+    /// `x-unique: true` extension a unique field. Any other constraining
+    /// keyword (`enum`, `minimum`, `pattern`, ...) has no equivalent here and
+    /// is rejected instead of being silently dropped.
+    pub fn from_json_schema(schema: &Value) -> Result<Self> {
+        let unsupported = |keyword: &str, at: &str| {
+            PieskieoError::Validation(format!(
+                "unsupported JSON Schema keyword '{keyword}' at {at}: only type, properties, \
+                 required and x-unique translate to field constraints"
+            ))
+        };
+        let obj = schema
+            .as_object()
+            .ok_or_else(|| PieskieoError::Validation("JSON Schema must be an object".into()))?;
+        let mut fields: HashMap<String, SchemaField> = HashMap::new();
+        for (keyword, val) in obj {
+            match keyword.as_str() {
+                "type" if val == "object" => {}
+                "type" => {
+                    return Err(PieskieoError::Validation(format!(
+                        "top-level type must be \"object\", got {val}"
+                    )))
+                }
+                "properties" => {
+                    let props = val.as_object().ok_or_else(|| {
+                        PieskieoError::Validation("properties must be an object".into())
+                    })?;
+                    for (name, prop) in props {
+                        let field = fields.entry(name.clone()).or_default();
+                        let prop = match prop {
+                            Value::Bool(true) => continue,
+                            Value::Object(prop) => prop,
+                            _ => {
+                                return Err(PieskieoError::Validation(format!(
+                                    "properties.{name} must be a schema object"
+                                )))
+                            }
+                        };
+                        for (k, v) in prop {
+                            match k.as_str() {
+                                "type" => {
+                                    let ty = v
+                                        .as_str()
+                                        .filter(|t| JSON_SCHEMA_TYPES.contains(t))
+                                        .ok_or_else(|| {
+                                        PieskieoError::Validation(format!(
+                                            "properties.{name}.type must be one JSON type name"
+                                        ))
+                                    })?;
+                                    field.r#type = Some(ty.to_string());
+                                }
+                                "x-unique" => {
+                                    field.unique = v.as_bool().ok_or_else(|| {
+                                        PieskieoError::Validation(format!(
+                                            "properties.{name}.x-unique must be a boolean"
+                                        ))
+                                    })?;
+                                }
+                                k if JSON_SCHEMA_ANNOTATIONS.contains(&k) => {}
+                                k => return Err(unsupported(k, &format!("properties.{name}"))),
+                            }
+                        }
+                    }
+                }
+                "required" => {
+                    let names = val.as_array().ok_or_else(|| {
+                        PieskieoError::Validation("required must be an array of names".into())
+                    })?;
+                    for name in names {
+                        let name = name.as_str().ok_or_else(|| {
+                            PieskieoError::Validation("required must be an array of names".into())
+                        })?;
+                        fields.entry(name.to_string()).or_default().required = true;
+                    }
+                }
+                // extra fields are always allowed, so `true` says nothing new
+                "additionalProperties" if val == true => {}
+                k if JSON_SCHEMA_ANNOTATIONS.contains(&k) => {}
+                k => return Err(unsupported(k, "the top level")),
+            }
+        }
+        Ok(SchemaDef { fields })
+    }
+}
+
+/// A stored record that a proposed schema would reject.
+#[derive(Clone, Debug, Serialize)]
+pub struct SchemaViolation {
+    pub id: Uuid,
+    pub reason: String,
+}
+
+/// What a SQL statement would do, worked out without running it.
+#[derive(Clone, Debug, Serialize)]
+pub struct SqlAnalysis {
+    /// `select`, `insert`, `update` or `delete`
+    pub kind: &'static str,
+    /// resolved `family.namespace.collection`, family being `rows` or `docs`
+    pub target: String,
+    pub is_select: bool,
+}
+
+/// How a filter query was answered: through an equality index bucket or a
+/// full scan, how many stored records it examined and how many matched
+/// (records skipped by `offset` included).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct QueryStats {
+    pub used_index: bool,
+    pub scanned: usize,
+    pub matched: usize,
+}
+
+/// What `drop_namespace` removed from one shard.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct NamespaceDrop {
+    pub docs: usize,
+    pub rows: usize,
+    pub vectors: usize,
+    pub edges: usize,
+    /// every removed id; incoming edges live on the shards owning their
+    /// sources, so callers broadcast `remove_node` for these
+    #[serde(skip)]
+    pub ids: Vec<Uuid>,
+}
+
+/// One write inside an `apply_batch`; tagged by `op` on the wire.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum BatchOp {
+    PutDoc {
+        namespace: Option<String>,
+        collection: Option<String>,
+        id: Uuid,
+        data: Value,
+    },
+    PutRow {
+        namespace: Option<String>,
+        table: Option<String>,
+        id: Uuid,
+        data: Value,
+    },
+    /// `collection` names the table for rows and is ignored for vectors,
+    /// which are removed from whichever namespace holds them
+    Delete {
+        family: BatchFamily,
+        namespace: Option<String>,
+        collection: Option<String>,
+        id: Uuid,
+    },
+    PutVector {
+        namespace: Option<String>,
+        id: Uuid,
+        vector: Vec<f32>,
+        meta: Option<VectorMeta>,
+    },
+    AddEdge {
+        src: Uuid,
+        dst: Uuid,
+        weight: Option<f32>,
+    },
+}
+
+impl BatchOp {
+    /// The id that decides which shard owns the op (the source for edges).
+    pub fn key(&self) -> Uuid {
+        match self {
+            BatchOp::PutDoc { id, .. }
+            | BatchOp::PutRow { id, .. }
+            | BatchOp::Delete { id, .. }
+            | BatchOp::PutVector { id, .. } => *id,
+            BatchOp::AddEdge { src, .. } => *src,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BatchFamily {
+    Doc,
+    Row,
+    Vector,
+}
+
 #[derive(Default, Clone, Serialize, Deserialize, Debug)]
 pub struct Stats {
     docs: HashMap<String, HashMap<String, usize>>,
@@ -70,8 +401,8 @@ pub struct Stats {
 pub enum SqlResult {
     Select(Vec<(Uuid, Value)>),
     Insert { ids: Vec<Uuid> },
-    Update { affected: usize },
-    Delete { affected: usize },
+    Update { affected: usize, ids: Vec<Uuid> },
+    Delete { affected: usize, ids: Vec<Uuid> },
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -80,14 +411,220 @@ pub struct UpsertDoc {
     pub json: Value,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+/// Vector put as logged in the WAL; see `encode`/`decode` for the wire forms.
+#[derive(Debug, Clone)]
 struct VecWalRecord {
+    namespace: Option<String>,
+    vector: Vec<f32>,
+    meta: Option<VectorMeta>,
+}
+
+/// Leads vector payloads written since metadata became typed JSON. Older
+/// payloads start with bincode's `Option` tag (0 or 1), never these bytes.
+const VEC_WAL_V2: &[u8] = b"PVM2";
+
+/// Current wire form: metadata travels as JSON text since bincode cannot
+/// encode `serde_json::Value`.
+#[derive(Serialize, Deserialize)]
+struct VecWalWire {
+    namespace: Option<String>,
+    vector: Vec<f32>,
+    meta: Option<String>,
+}
+
+/// Wire form before typed metadata.
+#[derive(Deserialize)]
+struct VecWalWireV1 {
     #[serde(default)]
     namespace: Option<String>,
     vector: Vec<f32>,
     meta: Option<HashMap<String, String>>,
 }
 
+impl VecWalRecord {
+    fn encode(self) -> Result<Vec<u8>> {
+        let wire = VecWalWire {
+            meta: crate::vector::meta_to_json(self.meta.as_ref())?,
+            namespace: self.namespace,
+            vector: self.vector,
+        };
+        let mut out = VEC_WAL_V2.to_vec();
+        bincode::serialize_into(&mut out, &wire)?;
+        Ok(out)
+    }
+
+    fn decode(payload: &[u8]) -> Result<Self> {
+        if let Some(body) = payload.strip_prefix(VEC_WAL_V2) {
+            let wire: VecWalWire = bincode::deserialize(body)?;
+            return Ok(Self {
+                namespace: wire.namespace,
+                vector: wire.vector,
+                meta: crate::vector::meta_from_json(wire.meta)?,
+            });
+        }
+        let wire: VecWalWireV1 = bincode::deserialize(payload)?;
+        Ok(Self {
+            namespace: wire.namespace,
+            vector: wire.vector,
+            meta: wire.meta.map(crate::vector::meta_from_strings),
+        })
+    }
+}
+
+/// Leads the payload of an auto-link edge. User edges keep the original bare
+/// encoding, which starts with a uuid length tag, never these bytes.
+const EDGE_WAL_AUTO: &[u8] = b"PEA1";
+
+/// Graph put as logged in the WAL; `auto` travels as the payload prefix.
+#[derive(Serialize, Deserialize)]
+struct EdgeWire {
+    src: Uuid,
+    dst: Uuid,
+    weight: f32,
+}
+
+impl EdgeWire {
+    fn encode(edge: &crate::graph::Edge) -> Result<Vec<u8>> {
+        let mut out = if edge.auto {
+            EDGE_WAL_AUTO.to_vec()
+        } else {
+            Vec::new()
+        };
+        let wire = EdgeWire {
+            src: edge.src,
+            dst: edge.dst,
+            weight: edge.weight,
+        };
+        bincode::serialize_into(&mut out, &wire)?;
+        Ok(out)
+    }
+
+    fn decode(payload: &[u8]) -> Result<crate::graph::Edge> {
+        let (auto, body) = match payload.strip_prefix(EDGE_WAL_AUTO) {
+            Some(body) => (true, body),
+            None => (false, payload),
+        };
+        let wire: EdgeWire = bincode::deserialize(body)?;
+        Ok(crate::graph::Edge {
+            src: wire.src,
+            dst: wire.dst,
+            weight: wire.weight,
+            auto,
+        })
+    }
+}
+
+/// The shortest log that replays to the same data as `records`. Each doc,
+/// row and vector keeps only its latest put, placed where its current life
+/// began so `_seq` order survives; deleted ones disappear. Schemas keep their
+/// latest definition, and edges are replayed into a scratch graph and logged
+/// once each with their `auto` flag.
+fn compact_records(records: Vec<RecordKind>) -> Result<Vec<RecordKind>> {
+    // (family, namespace, collection or table, id); vectors by id alone
+    type Slot = (u8, String, String, Uuid);
+    fn slot_of(
+        family: DataFamily,
+        key: Uuid,
+        namespace: &Option<String>,
+        collection: &Option<String>,
+        table: &Option<String>,
+    ) -> Slot {
+        let name = |n: &Option<String>| n.clone().unwrap_or_else(PieskieoDb::default_ns);
+        match family {
+            DataFamily::Doc => (0, name(namespace), name(collection), key),
+            DataFamily::Row => (1, name(namespace), name(table), key),
+            DataFamily::Vec | DataFamily::Graph => (2, String::new(), String::new(), key),
+        }
+    }
+    let mut live: HashMap<Slot, (usize, RecordKind)> = HashMap::new();
+    let mut schemas: BTreeMap<Slot, RecordKind> = BTreeMap::new();
+    let graph = GraphStore::new();
+    for (pos, rec) in records.into_iter().enumerate() {
+        // (is a schema, slot) for the records that are kept
+        let (is_schema, slot) = match &rec {
+            RecordKind::Put {
+                family: DataFamily::Graph,
+                payload,
+                ..
+            } => {
+                if let Ok(edge) = EdgeWire::decode(payload) {
+                    if edge.auto {
+                        graph.add_auto_edge(edge.src, edge.dst, edge.weight);
+                    } else {
+                        graph.add_edge(edge.src, edge.dst, edge.weight);
+                    }
+                }
+                continue;
+            }
+            RecordKind::Delete {
+                family: DataFamily::Graph,
+                key,
+                ..
+            } => {
+                graph.remove_node(*key);
+                continue;
+            }
+            RecordKind::AddEdge { src, dst, weight } => {
+                graph.add_edge(*src, *dst, *weight);
+                continue;
+            }
+            RecordKind::RemoveAutoEdges { node } => {
+                graph.remove_auto_edges(*node);
+                continue;
+            }
+            RecordKind::Delete {
+                family,
+                key,
+                namespace,
+                collection,
+                table,
+            } => {
+                live.remove(&slot_of(*family, *key, namespace, collection, table));
+                continue;
+            }
+            RecordKind::Put {
+                family,
+                key,
+                namespace,
+                collection,
+                table,
+                ..
+            } => (false, slot_of(*family, *key, namespace, collection, table)),
+            RecordKind::Schema {
+                family,
+                namespace,
+                collection,
+                table,
+                ..
+            } => (
+                true,
+                slot_of(*family, Uuid::nil(), namespace, collection, table),
+            ),
+        };
+        if is_schema {
+            schemas.insert(slot, rec);
+        } else {
+            let born = live.get(&slot).map_or(pos, |(born, _)| *born);
+            live.insert(slot, (born, rec));
+        }
+    }
+    let mut live: Vec<(usize, RecordKind)> = live.into_values().collect();
+    live.sort_by_key(|(born, _)| *born);
+    let mut out: Vec<RecordKind> = schemas.into_values().collect();
+    out.extend(live.into_iter().map(|(_, rec)| rec));
+    for edge in graph.edges() {
+        out.push(RecordKind::Put {
+            family: DataFamily::Graph,
+            key: edge.src,
+            payload: EdgeWire::encode(&edge)?,
+            namespace: None,
+            collection: None,
+            table: None,
+        });
+    }
+    Ok(out)
+}
+
 impl PieskieoDb {
     fn ns(ns: Option<&str>) -> String {
         ns.unwrap_or("default").to_string()
@@ -196,10 +733,20 @@ impl PieskieoDb {
             .as_object()
             .ok_or_else(|| PieskieoError::Validation("value must be object".into()))?;
         for (field, spec) in &schema.fields {
-            if spec.required && !obj.contains_key(field) {
-                return Err(PieskieoError::Validation(format!(
-                    "field '{field}' is required"
-                )));
+            if spec.required {
+                match obj.get(field) {
+                    None => {
+                        return Err(PieskieoError::Validation(format!(
+                            "field '{field}' is required"
+                        )))
+                    }
+                    Some(Value::Null) => {
+                        return Err(PieskieoError::Validation(format!(
+                            "field '{field}' must not be null"
+                        )))
+                    }
+                    Some(_) => {}
+                }
             }
             if spec.unique {
                 if let Some(val) = obj.get(field) {
@@ -224,12 +771,13 @@ impl PieskieoDb {
                 table_name,
                 columns,
                 source,
+                on,
                 ..
-            } => (table_name, columns, source),
+            } => (table_name, columns, source, on),
             _ => return Err(PieskieoError::Internal("not insert".into())),
         };
         let (family, ns, coll) = self.split_name(insert.0)?;
-        let target_rows = Self::target_is_rows(family.as_deref(), &coll);
+        let target_rows = self.target_is_rows(family.as_deref(), &ns, &coll);
         let columns: Vec<String> = insert.1.iter().map(|c| c.value.clone()).collect();
         if columns.is_empty() {
             return Err(PieskieoError::Internal(
@@ -282,6 +830,37 @@ impl PieskieoDb {
                 }
             }
         }
+        if let Some(on) = insert.3 {
+            let (field, action) = Self::on_conflict_clause(on)?;
+            if let Some((existing, mut val)) =
+                self.find_conflict(target_rows, &ns, &coll, field, id, &obj)?
+            {
+                let assignments = match action {
+                    OnConflictAction::DoNothing => {
+                        return Ok(SqlResult::Insert { ids: Vec::new() })
+                    }
+                    OnConflictAction::DoUpdate(update) => {
+                        if update.selection.is_some() {
+                            return Err(PieskieoError::Internal(
+                                "ON CONFLICT DO UPDATE ... WHERE not supported".into(),
+                            ));
+                        }
+                        &update.assignments
+                    }
+                };
+                if let Some(target) = val.as_object_mut() {
+                    Self::apply_assignments(target, assignments, Some(&obj))?;
+                }
+                if target_rows {
+                    self.put_row_ns(Some(&ns), Some(&coll), existing, &val)?;
+                } else {
+                    self.put_doc_ns(Some(&ns), Some(&coll), existing, val)?;
+                }
+                return Ok(SqlResult::Insert {
+                    ids: vec![existing],
+                });
+            }
+        }
         let uid = id.unwrap_or_else(Uuid::new_v4);
         if target_rows {
             self.put_row_ns(Some(&ns), Some(&coll), uid, &Value::Object(obj))?;
@@ -291,6 +870,102 @@ impl PieskieoDb {
         Ok(SqlResult::Insert { ids: vec![uid] })
     }
 
+    /// The conflict column and action of `ON CONFLICT (field) DO ...`; other
+    /// `ON` forms (ON CONSTRAINT, MySQL's ON DUPLICATE KEY) are rejected.
+    fn on_conflict_clause(on: &OnInsert) -> Result<(&str, &OnConflictAction)> {
+        match on {
+            OnInsert::OnConflict(OnConflict {
+                conflict_target: Some(ConflictTarget::Columns(cols)),
+                action,
+            }) if cols.len() == 1 => Ok((cols[0].value.as_str(), action)),
+            _ => Err(PieskieoError::Internal(
+                "only ON CONFLICT (field) DO UPDATE/DO NOTHING supported".into(),
+            )),
+        }
+    }
+
+    /// Existing record that `obj` collides with on `field`: the record with
+    /// the inserted id for `id`/`_id`, otherwise the holder of the same value
+    /// in the field's unique index. The field must be declared unique.
+    fn find_conflict(
+        &self,
+        target_rows: bool,
+        ns: &str,
+        coll: &str,
+        field: &str,
+        id: Option<Uuid>,
+        obj: &serde_json::Map<String, Value>,
+    ) -> Result<Option<(Uuid, Value)>> {
+        let guard = self.data.read();
+        let (records, schema, index) = if target_rows {
+            (&guard.rows, &guard.row_schema, &guard.row_index)
+        } else {
+            (&guard.docs, &guard.doc_schema, &guard.doc_index)
+        };
+        let records = records.get(ns).and_then(|m| m.get(coll));
+        let existing = if field == "id" || field == "_id" {
+            id
+        } else {
+            let unique = schema
+                .get(ns)
+                .and_then(|m| m.get(coll))
+                .and_then(|s| s.fields.get(field))
+                .is_some_and(|f| f.unique);
+            if !unique {
+                return Err(PieskieoError::Validation(format!(
+                    "ON CONFLICT field '{field}' is not unique"
+                )));
+            }
+            obj.get(field)
+                .and_then(Self::index_key)
+                .and_then(|key| {
+                    index
+                        .get(ns)
+                        .and_then(|m| m.get(coll))
+                        .and_then(|m| m.get(field))
+                        .and_then(|m| m.get(&key))
+                })
+                .and_then(|ids| {
+                    ids.iter()
+                        .find(|i| records.is_some_and(|r| r.contains_key(i)))
+                        .copied()
+                })
+        };
+        Ok(existing.and_then(|i| Some((i, records?.get(&i)?.clone()))))
+    }
+
+    /// Applies `SET col = value` assignments to `obj`. In an upsert,
+    /// `EXCLUDED.col` reads the value the INSERT proposed.
+    fn apply_assignments(
+        obj: &mut serde_json::Map<String, Value>,
+        assignments: &[Assignment],
+        excluded: Option<&serde_json::Map<String, Value>>,
+    ) -> Result<()> {
+        for assign in assignments {
+            let key = assign
+                .id
+                .first()
+                .ok_or_else(|| PieskieoError::Internal("assignment missing column".into()))?
+                .value
+                .clone();
+            let rhs = match (&assign.value, excluded) {
+                (Expr::CompoundIdentifier(parts), Some(proposed))
+                    if parts.len() == 2 && parts[0].value.eq_ignore_ascii_case("excluded") =>
+                {
+                    proposed
+                        .get(&parts[1].value)
+                        .cloned()
+                        .unwrap_or(Value::Null)
+                }
+                (expr, _) => Self::literal_to_value(expr).ok_or_else(|| {
+                    PieskieoError::Internal("assignment literal not supported".into())
+                })?,
+            };
+            obj.insert(key, rhs);
+        }
+        Ok(())
+    }
+
     fn exec_update(&self, stmt: &Statement) -> Result<SqlResult> {
         let (table, assignments, selection) = match stmt {
             Statement::Update {
@@ -303,7 +978,7 @@ impl PieskieoDb {
         };
         let name = self.extract_name_from_table_factor(&table.relation)?;
         let (family, ns, coll) = self.split_name(name)?;
-        let target_rows = Self::target_is_rows(family.as_deref(), &coll);
+        let target_rows = self.target_is_rows(family.as_deref(), &ns, &coll);
         let conds = if let Some(expr) = selection {
             let mut c = Vec::new();
             self.walk_expr(expr, &mut c)?;
@@ -330,44 +1005,36 @@ impl PieskieoDb {
                     .unwrap_or_default()
             }
         };
-        let mut affected = 0usize;
+        let mut ids = Vec::with_capacity(matches.len());
         for (id, mut val) in matches {
             if let Some(obj) = val.as_object_mut() {
-                for assign in assignments {
-                    let key = assign
-                        .id
-                        .first()
-                        .ok_or_else(|| PieskieoError::Internal("assignment missing column".into()))?
-                        .value
-                        .clone();
-                    let rhs = Self::literal_to_value(&assign.value).ok_or_else(|| {
-                        PieskieoError::Internal("assignment literal not supported".into())
-                    })?;
-                    obj.insert(key, rhs);
-                }
+                Self::apply_assignments(obj, assignments, None)?;
             }
             if target_rows {
                 self.put_row_ns(Some(&ns), Some(&coll), id, &val)?;
             } else {
                 self.put_doc_ns(Some(&ns), Some(&coll), id, val)?;
             }
-            affected += 1;
+            ids.push(id);
         }
-        Ok(SqlResult::Update { affected })
+        Ok(SqlResult::Update {
+            affected: ids.len(),
+            ids,
+        })
     }
 
     fn exec_delete(&self, stmt: &Statement) -> Result<SqlResult> {
-        let (tables, selection) = match stmt {
+        let (table, selection) = match stmt {
             Statement::Delete {
-                tables, selection, ..
-            } => (tables, selection),
+                tables,
+                from,
+                selection,
+                ..
+            } => (self.delete_target(tables, from)?, selection),
             _ => return Err(PieskieoError::Internal("not delete".into())),
         };
-        let table = tables
-            .first()
-            .ok_or_else(|| PieskieoError::Internal("table required".into()))?;
         let (family, ns, coll) = self.split_name(table)?;
-        let target_rows = Self::target_is_rows(family.as_deref(), &coll);
+        let target_rows = self.target_is_rows(family.as_deref(), &ns, &coll);
         let conds = if let Some(expr) = selection {
             let mut c = Vec::new();
             self.walk_expr(expr, &mut c)?;
@@ -408,6 +1075,7 @@ impl PieskieoDb {
         }
         Ok(SqlResult::Delete {
             affected: matches.len(),
+            ids: matches,
         })
     }
 
@@ -422,6 +1090,7 @@ impl PieskieoDb {
                     self.default_params.ef_construction,
                     self.default_params.ef_search,
                     self.default_params.max_elements,
+                    self.default_params.initial_capacity,
                 ))
             })
             .clone()
@@ -444,145 +1113,46 @@ impl PieskieoDb {
                 params.ef_construction,
                 params.ef_search,
                 params.max_elements,
+                params.initial_capacity,
             )),
         );
         let vectors = Arc::new(RwLock::new(vecs));
         let vector_ns = Arc::new(RwLock::new(HashMap::new()));
         let graph = GraphStore::new();
 
-        for rec in wal.replay()? {
-            match rec {
-                RecordKind::Put {
-                    family,
-                    key,
-                    payload,
-                    namespace,
-                    collection,
-                    table,
-                } => match family {
-                    DataFamily::Doc => {
-                        let ns = namespace.unwrap_or_else(Self::default_ns);
-                        let col = collection.unwrap_or_else(Self::default_ns);
-                        let v: Value = serde_json::from_slice(&payload)?;
-                        let mut guard = data.write();
-                        guard
-                            .docs
-                            .entry(ns.clone())
-                            .or_default()
-                            .entry(col.clone())
-                            .or_default()
-                            .insert(key, v.clone());
-                        Self::index_upsert_doc(&mut guard, ns, col, key, &v);
-                    }
-                    DataFamily::Row => {
-                        let ns = namespace.unwrap_or_else(Self::default_ns);
-                        let table = table.unwrap_or_else(Self::default_ns);
-                        let v: Value = serde_json::from_slice(&payload)?;
-                        let mut guard = data.write();
-                        guard
-                            .rows
-                            .entry(ns.clone())
-                            .or_default()
-                            .entry(table.clone())
-                            .or_default()
-                            .insert(key, v.clone());
-                        Self::index_upsert_row(&mut guard, ns, table, key, &v);
-                    }
-                    DataFamily::Vec => match bincode::deserialize::<VecWalRecord>(&payload) {
-                        Ok(rec) => {
-                            let ns = rec.namespace.unwrap_or_else(Self::default_ns);
-                            let mut guard = vectors.write();
-                            let entry = guard.entry(ns.clone()).or_insert_with(|| {
-                                Arc::new(VectorIndex::with_params(
-                                    params.metric,
-                                    params.ef_construction,
-                                    params.ef_search,
-                                    params.max_elements,
-                                ))
-                            });
-                            let _ = entry.insert(key, rec.vector, rec.meta);
-                            vector_ns.write().insert(key, ns);
-                        }
-                        Err(_) => {
-                            let vec: Vec<f32> = bincode::deserialize(&payload)?;
-                            let guard = vectors.write();
-                            if let Some(idx) = guard.get(Self::default_ns().as_str()) {
-                                let _ = idx.insert(key, vec, None);
-                                vector_ns.write().insert(key, Self::default_ns());
-                            }
-                        }
-                    },
-                    DataFamily::Graph => {
-                        let edge: crate::graph::Edge = bincode::deserialize(&payload)?;
-                        graph.add_edge(edge.src, edge.dst, edge.weight);
-                    }
-                },
-                RecordKind::Delete {
-                    family,
-                    key,
-                    namespace,
-                    collection,
-                    table,
-                } => match family {
-                    DataFamily::Doc => {
-                        let ns = namespace.unwrap_or_else(Self::default_ns);
-                        let col = collection.unwrap_or_else(Self::default_ns);
-                        let mut guard = data.write();
-                        if let Some(map) = guard.docs.get_mut(&ns) {
-                            if let Some(c) = map.get_mut(&col) {
-                                if let Some(old) = c.remove(&key) {
-                                    Self::index_remove_doc(&mut guard, ns, col, &key, &old);
-                                }
-                            }
-                        }
-                    }
-                    DataFamily::Row => {
-                        let ns = namespace.unwrap_or_else(Self::default_ns);
-                        let tbl = table.unwrap_or_else(Self::default_ns);
-                        let mut guard = data.write();
-                        if let Some(map) = guard.rows.get_mut(&ns) {
-                            if let Some(t) = map.get_mut(&tbl) {
-                                if let Some(old) = t.remove(&key) {
-                                    Self::index_remove_row(&mut guard, ns, tbl, &key, &old);
-                                }
-                            }
-                        }
-                    }
-                    DataFamily::Vec => {
-                        let ns = namespace.unwrap_or_else(Self::default_ns);
-                        if let Some(idx) = vectors.write().get(&ns) {
-                            idx.delete(&key);
-                            vector_ns.write().remove(&key);
-                        }
-                    }
-                    DataFamily::Graph => {}
-                },
-                RecordKind::Schema {
-                    family,
-                    namespace,
-                    collection,
-                    table,
-                    schema,
-                } => {
-                    let def: SchemaDef = serde_json::from_slice(&schema)?;
-                    let mut guard = data.write();
-                    match family {
-                        DataFamily::Doc => {
-                            let ns = namespace.unwrap_or_else(Self::default_ns);
-                            let col = collection.unwrap_or_else(Self::default_ns);
-                            guard.doc_schema.entry(ns).or_default().insert(col, def);
-                        }
-                        DataFamily::Row => {
-                            let ns = namespace.unwrap_or_else(Self::default_ns);
-                            let tbl = table.unwrap_or_else(Self::default_ns);
-                            guard.row_schema.entry(ns).or_default().insert(tbl, def);
-                        }
-                        _ => {}
-                    }
-                }
-                RecordKind::AddEdge { src, dst, weight } => {
-                    graph.add_edge(src, dst, weight);
+        // Docs and rows replay per (family, namespace, collection) bucket in
+        // parallel, each bucket in log order; vectors and edges replay in
+        // order alongside, and HNSW graphs are built once at the end.
+        let started = std::time::Instant::now();
+        let records = wal.replay()?;
+        let total = records.len();
+        let mut buckets: HashMap<(bool, String, String), Vec<RecordKind>> = HashMap::new();
+        let mut ordered = Vec::new();
+        for rec in records {
+            match Self::replay_bucket(&rec) {
+                Some(key) => buckets.entry(key).or_default().push(rec),
+                None => ordered.push(rec),
+            }
+        }
+        let (colls, ordered) = rayon::join(
+            || {
+                buckets
+                    .into_par_iter()
+                    .map(|(_, recs)| Self::replay_collection(recs))
+                    .collect::<Result<Vec<Collections>>>()
+            },
+            || -> Result<()> {
+                for rec in ordered {
+                    Self::replay_vector_or_edge(rec, &params, &vectors, &vector_ns, &graph)?;
                 }
+                Ok(())
+            },
+        );
+        ordered?;
+        {
+            let mut guard = data.write();
+            for c in colls? {
+                guard.absorb(c);
             }
         }
 
@@ -605,6 +1175,7 @@ impl PieskieoDb {
                                         params.ef_construction,
                                         params.ef_search,
                                         params.max_elements,
+                                        params.initial_capacity,
                                     ))
                                 })
                                 .clone()
@@ -633,6 +1204,15 @@ impl PieskieoDb {
             }
         }
 
+        for idx in vectors.read().values() {
+            let _ = idx.finish_deferred();
+        }
+        tracing::info!(
+            records = total,
+            elapsed_ms = started.elapsed().as_millis() as u64,
+            "wal replayed"
+        );
+
         Ok(Self {
             path,
             wal: RwLock::new(wal),
@@ -641,20 +1221,238 @@ impl PieskieoDb {
             vectors,
             vector_ns,
             graph,
-            link_top_k: params.link_top_k,
+            link_top_k: AtomicUsize::new(params.link_top_k),
+            link_top_k_ns: RwLock::new(HashMap::new()),
             shard_id: params.shard_id,
             shard_total: params.shard_total.max(1),
             default_params: params,
+            wal_healthy: std::sync::atomic::AtomicBool::new(true),
+            writes: WriteCounters::default(),
+            compactions: CompactionStats::default(),
+            last_write_ms: AtomicU64::new(0),
+            snapshot_ms: AtomicU64::new(0),
         })
     }
 
+    /// Bucket of a doc/row record for parallel replay: `(is_row, ns, coll)`.
+    /// Vector, graph and other records replay in log order instead.
+    fn replay_bucket(rec: &RecordKind) -> Option<(bool, String, String)> {
+        let (family, namespace, collection, table) = match rec {
+            RecordKind::Put {
+                family,
+                namespace,
+                collection,
+                table,
+                ..
+            }
+            | RecordKind::Delete {
+                family,
+                namespace,
+                collection,
+                table,
+                ..
+            }
+            | RecordKind::Schema {
+                family,
+                namespace,
+                collection,
+                table,
+                ..
+            } => (family, namespace, collection, table),
+            _ => return None,
+        };
+        let ns = namespace.clone().unwrap_or_else(Self::default_ns);
+        match family {
+            DataFamily::Doc => Some((
+                false,
+                ns,
+                collection.clone().unwrap_or_else(Self::default_ns),
+            )),
+            DataFamily::Row => Some((true, ns, table.clone().unwrap_or_else(Self::default_ns))),
+            _ => None,
+        }
+    }
+
+    /// Replays one bucket's doc/row records, in order, into fresh collections.
+    fn replay_collection(records: Vec<RecordKind>) -> Result<Collections> {
+        let mut colls = Collections::default();
+        for rec in records {
+            match rec {
+                RecordKind::Put {
+                    family,
+                    key,
+                    payload,
+                    namespace,
+                    collection,
+                    table,
+                } => {
+                    let ns = namespace.unwrap_or_else(Self::default_ns);
+                    let v: Value = serde_json::from_slice(&payload)?;
+                    if matches!(family, DataFamily::Row) {
+                        let table = table.unwrap_or_else(Self::default_ns);
+                        colls
+                            .rows
+                            .entry(ns.clone())
+                            .or_default()
+                            .entry(table.clone())
+                            .or_default()
+                            .insert(key, v.clone());
+                        Self::index_upsert_row(&mut colls, ns, table, key, &v);
+                    } else {
+                        let col = collection.unwrap_or_else(Self::default_ns);
+                        colls
+                            .docs
+                            .entry(ns.clone())
+                            .or_default()
+                            .entry(col.clone())
+                            .or_default()
+                            .insert(key, v.clone());
+                        Self::index_upsert_doc(&mut colls, ns, col, key, &v);
+                    }
+                }
+                RecordKind::Delete {
+                    family,
+                    key,
+                    namespace,
+                    collection,
+                    table,
+                } => {
+                    let ns = namespace.unwrap_or_else(Self::default_ns);
+                    if matches!(family, DataFamily::Row) {
+                        let tbl = table.unwrap_or_else(Self::default_ns);
+                        let old = colls
+                            .rows
+                            .get_mut(&ns)
+                            .and_then(|m| m.get_mut(&tbl))
+                            .and_then(|t| t.remove(&key));
+                        if let Some(old) = old {
+                            Self::index_remove_row(&mut colls, ns, tbl, &key, &old);
+                        }
+                    } else {
+                        let col = collection.unwrap_or_else(Self::default_ns);
+                        let old = colls
+                            .docs
+                            .get_mut(&ns)
+                            .and_then(|m| m.get_mut(&col))
+                            .and_then(|c| c.remove(&key));
+                        if let Some(old) = old {
+                            Self::index_remove_doc(&mut colls, ns, col, &key, &old);
+                        }
+                    }
+                }
+                RecordKind::Schema {
+                    family,
+                    namespace,
+                    collection,
+                    table,
+                    schema,
+                } => {
+                    let def: SchemaDef = serde_json::from_slice(&schema)?;
+                    let ns = namespace.unwrap_or_else(Self::default_ns);
+                    if matches!(family, DataFamily::Row) {
+                        let tbl = table.unwrap_or_else(Self::default_ns);
+                        colls.row_schema.entry(ns).or_default().insert(tbl, def);
+                    } else {
+                        let col = collection.unwrap_or_else(Self::default_ns);
+                        colls.doc_schema.entry(ns).or_default().insert(col, def);
+                    }
+                }
+                _ => {}
+            }
+        }
+        Ok(colls)
+    }
+
+    /// Replays a vector or graph record; vectors skip HNSW until
+    /// `finish_deferred`.
+    fn replay_vector_or_edge(
+        rec: RecordKind,
+        params: &VectorParams,
+        vectors: &RwLock<HashMap<String, Arc<VectorIndex>>>,
+        vector_ns: &RwLock<HashMap<Uuid, String>>,
+        graph: &GraphStore,
+    ) -> Result<()> {
+        match rec {
+            RecordKind::Put {
+                family: DataFamily::Vec,
+                key,
+                payload,
+                ..
+            } => match VecWalRecord::decode(&payload) {
+                Ok(rec) => {
+                    let ns = rec.namespace.unwrap_or_else(Self::default_ns);
+                    let mut guard = vectors.write();
+                    let entry = guard.entry(ns.clone()).or_insert_with(|| {
+                        Arc::new(VectorIndex::with_params(
+                            params.metric,
+                            params.ef_construction,
+                            params.ef_search,
+                            params.max_elements,
+                            params.initial_capacity,
+                        ))
+                    });
+                    let _ = entry.insert_deferred(key, rec.vector, rec.meta);
+                    vector_ns.write().insert(key, ns);
+                }
+                Err(_) => {
+                    let vec: Vec<f32> = bincode::deserialize(&payload)?;
+                    let guard = vectors.write();
+                    if let Some(idx) = guard.get(Self::default_ns().as_str()) {
+                        let _ = idx.insert_deferred(key, vec, None);
+                        vector_ns.write().insert(key, Self::default_ns());
+                    }
+                }
+            },
+            RecordKind::Put {
+                family: DataFamily::Graph,
+                payload,
+                ..
+            } => {
+                let edge = EdgeWire::decode(&payload)?;
+                if edge.auto {
+                    graph.add_auto_edge(edge.src, edge.dst, edge.weight);
+                } else {
+                    graph.add_edge(edge.src, edge.dst, edge.weight);
+                }
+            }
+            RecordKind::Delete {
+                family: DataFamily::Vec,
+                key,
+                namespace,
+                ..
+            } => {
+                let ns = namespace.unwrap_or_else(Self::default_ns);
+                if let Some(idx) = vectors.write().get(&ns) {
+                    idx.delete_deferred(&key);
+                    vector_ns.write().remove(&key);
+                }
+            }
+            RecordKind::Delete {
+                family: DataFamily::Graph,
+                key,
+                ..
+            } => {
+                graph.remove_node(key);
+            }
+            RecordKind::AddEdge { src, dst, weight } => {
+                graph.add_edge(src, dst, weight);
+            }
+            RecordKind::RemoveAutoEdges { node } => {
+                graph.remove_auto_edges(node);
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// Insert or replace a doc; `Ok(true)` when `id` did not exist before.
     pub fn put_doc_ns(
         &self,
         ns: Option<&str>,
         collection: Option<&str>,
         id: Uuid,
         json: Value,
-    ) -> Result<()> {
+    ) -> Result<bool> {
         if !self.owns(&id) {
             return Err(PieskieoError::WrongShard);
         }
@@ -668,24 +1466,105 @@ impl PieskieoDb {
             collection: Some(Self::col(collection)),
             table: None,
         })?;
-        {
-            let mut guard = self.data.write();
-            let ns_key = Self::ns(ns);
-            let col_key = Self::col(collection);
-            guard
-                .docs
-                .entry(ns_key.clone())
-                .or_default()
-                .entry(col_key.clone())
-                .or_default()
-                .insert(id, json.clone());
-            Self::index_upsert_doc(&mut guard, ns_key.clone(), col_key.clone(), id, &json);
+        let created = self.store_doc(
+            &mut self.data.write(),
+            Self::ns(ns),
+            Self::col(collection),
+            id,
+            json,
+        );
+        self.writes.docs.fetch_add(1, Ordering::Relaxed);
+        Ok(created)
+    }
+
+    fn store_doc(
+        &self,
+        data: &mut Collections,
+        ns_key: String,
+        col_key: String,
+        id: Uuid,
+        json: Value,
+    ) -> bool {
+        let created = data
+            .docs
+            .entry(ns_key.clone())
+            .or_default()
+            .entry(col_key.clone())
+            .or_default()
+            .insert(id, json.clone())
+            .is_none();
+        Self::index_upsert_doc(data, ns_key.clone(), col_key.clone(), id, &json);
+        if created {
             self.bump_doc_stats(&ns_key, &col_key, 1);
         }
-        Ok(())
+        created
+    }
+
+    fn unstore_doc(&self, data: &mut Collections, ns_key: String, col_key: String, id: &Uuid) {
+        let old = data
+            .docs
+            .get_mut(&ns_key)
+            .and_then(|m| m.get_mut(&col_key))
+            .and_then(|m| m.remove(id));
+        if let Some(old) = old {
+            Self::index_remove_doc(data, ns_key.clone(), col_key.clone(), id, &old);
+            self.bump_doc_stats(&ns_key, &col_key, -1);
+        }
+    }
+
+    /// Merge `patch` into an existing doc and return the result; a missing doc
+    /// is `NotFound`, never created. `deep` follows JSON merge patch (RFC 7396),
+    /// otherwise only top-level fields are replaced. Null removes a field either
+    /// way. The read, merge and write happen under one lock, so concurrent
+    /// patches to different fields both land.
+    pub fn patch_doc_ns(
+        &self,
+        ns: Option<&str>,
+        collection: Option<&str>,
+        id: Uuid,
+        patch: Value,
+        deep: bool,
+    ) -> Result<Value> {
+        if !self.owns(&id) {
+            return Err(PieskieoError::WrongShard);
+        }
+        if !patch.is_object() {
+            return Err(PieskieoError::Validation("patch must be an object".into()));
+        }
+        let ns_key = Self::ns(ns);
+        let col_key = Self::col(collection);
+        let mut data = self.data.write();
+        let mut json = data
+            .docs
+            .get(&ns_key)
+            .and_then(|m| m.get(&col_key))
+            .and_then(|m| m.get(&id))
+            .cloned()
+            .ok_or(PieskieoError::NotFound)?;
+        merge_patch(&mut json, patch, deep);
+        if let Some(schema) = data.doc_schema.get(&ns_key).and_then(|m| m.get(&col_key)) {
+            Self::validate_object(&json)?;
+            Self::check_schema(
+                &id,
+                &json,
+                schema,
+                data.doc_index.get(&ns_key).and_then(|m| m.get(&col_key)),
+            )?;
+        }
+        self.append_record(&RecordKind::Put {
+            family: DataFamily::Doc,
+            key: id,
+            payload: serde_json::to_vec(&json)?,
+            namespace: Some(ns_key.clone()),
+            collection: Some(col_key.clone()),
+            table: None,
+        })?;
+        self.store_doc(&mut data, ns_key, col_key, id, json.clone());
+        self.writes.docs.fetch_add(1, Ordering::Relaxed);
+        Ok(json)
     }
 
-    pub fn put_doc(&self, id: Uuid, json: Value) -> Result<()> {
+    pub fn put_doc(&self, id: Uuid, json: Value) -> Result<bool> {
         self.put_doc_ns(None, None, id, json)
     }
 
@@ -705,25 +1584,13 @@ impl PieskieoDb {
             collection: Some(Self::col(collection)),
             table: None,
         })?;
-        {
-            let mut guard = self.data.write();
-            let ns_key = Self::ns(ns);
-            let col_key = Self::col(collection);
-            if let Some(ns_map) = guard.docs.get_mut(&ns_key) {
-                if let Some(col_map) = ns_map.get_mut(&col_key) {
-                    if let Some(old) = col_map.remove(id) {
-                        Self::index_remove_doc(
-                            &mut guard,
-                            ns_key.clone(),
-                            col_key.clone(),
-                            id,
-                            &old,
-                        );
-                        self.bump_doc_stats(&ns_key, &col_key, -1);
-                    }
-                }
-            }
-        }
+        self.unstore_doc(
+            &mut self.data.write(),
+            Self::ns(ns),
+            Self::col(collection),
+            id,
+        );
+        self.remove_node(*id)?;
         Ok(())
     }
 
@@ -732,16 +1599,17 @@ impl PieskieoDb {
     }
 
     pub fn update_doc(&self, id: Uuid, json: Value) -> Result<()> {
-        self.put_doc(id, json)
+        self.put_doc(id, json).map(|_| ())
     }
 
+    /// Insert or replace a row; `Ok(true)` when `id` did not exist before.
     pub fn put_row_ns<T: Serialize>(
         &self,
         ns: Option<&str>,
         table: Option<&str>,
         id: Uuid,
         row: &T,
-    ) -> Result<()> {
+    ) -> Result<bool> {
         if !self.owns(&id) {
             return Err(PieskieoError::WrongShard);
         }
@@ -756,21 +1624,50 @@ impl PieskieoDb {
             table: Some(Self::col(table)),
             collection: None,
         })?;
-        {
-            let mut guard = self.data.write();
-            let ns_key = Self::ns(ns);
-            let tbl_key = Self::col(table);
-            guard
-                .rows
-                .entry(ns_key.clone())
-                .or_default()
-                .entry(tbl_key.clone())
-                .or_default()
-                .insert(id, json.clone());
-            Self::index_upsert_row(&mut guard, ns_key.clone(), tbl_key.clone(), id, &json);
+        let created = self.store_row(
+            &mut self.data.write(),
+            Self::ns(ns),
+            Self::col(table),
+            id,
+            json,
+        );
+        self.writes.rows.fetch_add(1, Ordering::Relaxed);
+        Ok(created)
+    }
+
+    fn store_row(
+        &self,
+        data: &mut Collections,
+        ns_key: String,
+        tbl_key: String,
+        id: Uuid,
+        json: Value,
+    ) -> bool {
+        let created = data
+            .rows
+            .entry(ns_key.clone())
+            .or_default()
+            .entry(tbl_key.clone())
+            .or_default()
+            .insert(id, json.clone())
+            .is_none();
+        Self::index_upsert_row(data, ns_key.clone(), tbl_key.clone(), id, &json);
+        if created {
             self.bump_row_stats(&ns_key, &tbl_key, 1);
         }
-        Ok(())
+        created
+    }
+
+    fn unstore_row(&self, data: &mut Collections, ns_key: String, tbl_key: String, id: &Uuid) {
+        let old = data
+            .rows
+            .get_mut(&ns_key)
+            .and_then(|m| m.get_mut(&tbl_key))
+            .and_then(|m| m.remove(id));
+        if let Some(old) = old {
+            Self::index_remove_row(data, ns_key.clone(), tbl_key.clone(), id, &old);
+            self.bump_row_stats(&ns_key, &tbl_key, -1);
+        }
     }
 
     pub fn set_doc_schema(
@@ -823,7 +1720,42 @@ impl PieskieoDb {
         Ok(())
     }
 
-    pub fn put_row<T: Serialize>(&self, id: Uuid, row: &T) -> Result<()> {
+    /// Check every stored doc (or row, when `rows`) in `ns`/`collection`
+    /// against a proposed schema without applying it; the dry run for
+    /// `set_doc_schema`/`set_row_schema`. Violations come back in id order.
+    pub fn validate_against(
+        &self,
+        rows: bool,
+        ns: Option<&str>,
+        collection: Option<&str>,
+        def: &SchemaDef,
+    ) -> Vec<SchemaViolation> {
+        let ns_key = Self::ns(ns);
+        let col_key = Self::col(collection);
+        let guard = self.data.read();
+        let (store, index) = if rows {
+            (&guard.rows, &guard.row_index)
+        } else {
+            (&guard.docs, &guard.doc_index)
+        };
+        let Some(records) = store.get(&ns_key).and_then(|m| m.get(&col_key)) else {
+            return Vec::new();
+        };
+        let index = index.get(&ns_key).and_then(|m| m.get(&col_key));
+        records
+            .iter()
+            .filter_map(|(id, json)| {
+                Self::check_schema(id, json, def, index)
+                    .err()
+                    .map(|e| SchemaViolation {
+                        id: *id,
+                        reason: e.to_string(),
+                    })
+            })
+            .collect()
+    }
+
+    pub fn put_row<T: Serialize>(&self, id: Uuid, row: &T) -> Result<bool> {
         self.put_row_ns(None, None, id, row)
     }
 
@@ -838,25 +1770,7 @@ impl PieskieoDb {
             table: Some(Self::col(table)),
             collection: None,
         })?;
-        {
-            let mut guard = self.data.write();
-            let ns_key = Self::ns(ns);
-            let tbl_key = Self::col(table);
-            if let Some(ns_map) = guard.rows.get_mut(&ns_key) {
-                if let Some(tbl_map) = ns_map.get_mut(&tbl_key) {
-                    if let Some(old) = tbl_map.remove(id) {
-                        Self::index_remove_row(
-                            &mut guard,
-                            ns_key.clone(),
-                            tbl_key.clone(),
-                            id,
-                            &old,
-                        );
-                        self.bump_row_stats(&ns_key, &tbl_key, -1);
-                    }
-                }
-            }
-        }
+        self.unstore_row(&mut self.data.write(), Self::ns(ns), Self::col(table), id);
         Ok(())
     }
 
@@ -865,7 +1779,7 @@ impl PieskieoDb {
     }
 
     pub fn update_row<T: Serialize>(&self, id: Uuid, row: &T) -> Result<()> {
-        self.put_row(id, row)
+        self.put_row(id, row).map(|_| ())
     }
 
     pub fn get_doc_ns(
@@ -877,6 +1791,17 @@ impl PieskieoDb {
         if !self.owns(id) {
             return None;
         }
+        self.find_doc_ns(ns, collection, id)
+    }
+
+    /// `get_doc_ns` without the ownership check, so a doc stranded on a shard
+    /// that no longer owns its id can still be located.
+    pub fn find_doc_ns(
+        &self,
+        ns: Option<&str>,
+        collection: Option<&str>,
+        id: &Uuid,
+    ) -> Option<Value> {
         self.data
             .read()
             .docs
@@ -885,6 +1810,18 @@ impl PieskieoDb {
             .and_then(|m| m.get(id).cloned())
     }
 
+    /// Presence check behind `HEAD`; routed like `get_doc_ns` but clones nothing.
+    pub fn has_doc_ns(&self, ns: Option<&str>, collection: Option<&str>, id: &Uuid) -> bool {
+        self.owns(id)
+            && self
+                .data
+                .read()
+                .docs
+                .get(&Self::ns(ns))
+                .and_then(|m| m.get(&Self::col(collection)))
+                .is_some_and(|m| m.contains_key(id))
+    }
+
     pub fn get_doc(&self, id: &Uuid) -> Option<Value> {
         self.get_doc_ns(None, None, id)
     }
@@ -893,6 +1830,23 @@ impl PieskieoDb {
         if !self.owns(id) {
             return None;
         }
+        self.find_row_ns(ns, table, id)
+    }
+
+    /// Row counterpart of `has_doc_ns`.
+    pub fn has_row_ns(&self, ns: Option<&str>, table: Option<&str>, id: &Uuid) -> bool {
+        self.owns(id)
+            && self
+                .data
+                .read()
+                .rows
+                .get(&Self::ns(ns))
+                .and_then(|m| m.get(&Self::col(table)))
+                .is_some_and(|m| m.contains_key(id))
+    }
+
+    /// `get_row_ns` without the ownership check; see `find_doc_ns`.
+    pub fn find_row_ns(&self, ns: Option<&str>, table: Option<&str>, id: &Uuid) -> Option<Value> {
         self.data
             .read()
             .rows
@@ -922,6 +1876,20 @@ impl PieskieoDb {
         limit: usize,
         offset: usize,
     ) -> Vec<(Uuid, Value)> {
+        self.query_docs_ns_with_stats(ns, collection, filter, limit, offset)
+            .0
+    }
+
+    /// `query_docs_ns` plus whether an equality index answered it and how
+    /// many docs were examined.
+    pub fn query_docs_ns_with_stats(
+        &self,
+        ns: Option<&str>,
+        collection: Option<&str>,
+        filter: &HashMap<String, Value>,
+        limit: usize,
+        offset: usize,
+    ) -> (Vec<(Uuid, Value)>, QueryStats) {
         let guard = self.data.read();
         self.filter_map_with_index(
             &guard.docs,
@@ -935,6 +1903,76 @@ impl PieskieoDb {
         )
     }
 
+    /// How many docs match `filter`, without cloning any of them. Equality
+    /// filters count through the smallest index bucket like `query_docs_ns`;
+    /// anything else counts the owned docs directly.
+    pub fn count_docs_ns(
+        &self,
+        ns: Option<&str>,
+        collection: Option<&str>,
+        filter: &HashMap<String, Value>,
+    ) -> usize {
+        let guard = self.data.read();
+        self.count_filtered(&guard.docs, &guard.doc_index, ns, collection, filter)
+    }
+
+    /// Page through docs in ascending order of a numeric top-level `field`, ties
+    /// broken by id. Pass the last `(value, id)` of the previous page as `after`
+    /// to resume; deep pages cost O(log n + limit) rather than an OFFSET scan.
+    /// Docs whose field is missing or not a number are not returned.
+    pub fn query_range(
+        &self,
+        ns: Option<&str>,
+        collection: Option<&str>,
+        field: &str,
+        after: Option<(f64, Option<Uuid>)>,
+        limit: usize,
+    ) -> Vec<(Uuid, Value)> {
+        use std::ops::Bound;
+        let ns_key = Self::ns(ns);
+        let col_key = Self::col(collection);
+        let guard = self.data.read();
+        let (Some(index), Some(docs)) = (
+            guard
+                .doc_range
+                .get(&ns_key)
+                .and_then(|m| m.get(&col_key))
+                .and_then(|m| m.get(field)),
+            guard.docs.get(&ns_key).and_then(|m| m.get(&col_key)),
+        ) else {
+            return Vec::new();
+        };
+        let start = match after {
+            Some((v, Some(_))) => Bound::Included(RangeKey(v)),
+            Some((v, None)) => Bound::Excluded(RangeKey(v)),
+            None => Bound::Unbounded,
+        };
+        let mut out = Vec::new();
+        for (key, ids) in index.range((start, Bound::Unbounded)) {
+            let mut ids = ids.clone();
+            ids.sort();
+            for id in ids {
+                if let Some((v, Some(after_id))) = after {
+                    if key.0 == v && id <= after_id {
+                        continue;
+                    }
+                }
+                let Some(doc) = docs.get(&id) else {
+                    continue;
+                };
+                // skip entries left behind by an update to a different value
+                if doc.get(field).and_then(Value::as_f64) != Some(key.0) {
+                    continue;
+                }
+                out.push((id, doc.clone()));
+                if out.len() >= limit {
+                    return out;
+                }
+            }
+        }
+        out
+    }
+
     pub fn query_rows(
         &self,
         filter: &HashMap<String, Value>,
@@ -944,6 +1982,17 @@ impl PieskieoDb {
         self.query_rows_ns(None, None, filter, limit, offset)
     }
 
+    /// Row counterpart of `count_docs_ns`.
+    pub fn count_rows_ns(
+        &self,
+        ns: Option<&str>,
+        table: Option<&str>,
+        filter: &HashMap<String, Value>,
+    ) -> usize {
+        let guard = self.data.read();
+        self.count_filtered(&guard.rows, &guard.row_index, ns, table, filter)
+    }
+
     pub fn query_rows_ns(
         &self,
         ns: Option<&str>,
@@ -963,17 +2012,72 @@ impl PieskieoDb {
             offset,
             false,
         )
+        .0
     }
 
     /// SQL-ish over docs/rows. Supports SELECT/INSERT/UPDATE/DELETE (single statement).
-    pub fn query_sql(&self, sql: &str) -> Result<SqlResult> {
+    fn parse_statement(sql: &str) -> Result<Statement> {
         let dialect = GenericDialect {};
-        let ast = Parser::parse_sql(&dialect, sql)
+        let mut ast = Parser::parse_sql(&dialect, sql)
             .map_err(|e| PieskieoError::Internal(format!("sql parse error: {e}").into()))?;
         if ast.len() != 1 {
             return Err(PieskieoError::Internal("one statement expected".into()));
         }
-        let stmt = &ast[0];
+        Ok(ast.remove(0))
+    }
+
+    /// Parse and plan `sql` the way `query_sql` would, without reading or
+    /// writing records; unsupported syntax fails with the same error. An
+    /// `IN (SELECT ..)` subquery is still evaluated, since planning needs it.
+    pub fn analyze_sql(&self, sql: &str) -> Result<SqlAnalysis> {
+        let stmt = Self::parse_statement(sql)?;
+        let (kind, name) = match &stmt {
+            Statement::Query(q) => {
+                self.parse_select(&stmt)?;
+                let SetExpr::Select(select) = &*q.body else {
+                    unreachable!("parse_select accepts only SELECT");
+                };
+                let name = self.extract_name_from_table_factor(&select.from[0].relation)?;
+                ("select", name)
+            }
+            Statement::Insert { table_name, .. } => ("insert", table_name),
+            Statement::Update {
+                table, selection, ..
+            } => {
+                if let Some(expr) = selection {
+                    self.walk_expr(expr, &mut Vec::new())?;
+                }
+                let name = self.extract_name_from_table_factor(&table.relation)?;
+                ("update", name)
+            }
+            Statement::Delete {
+                tables,
+                from,
+                selection,
+                ..
+            } => {
+                if let Some(expr) = selection {
+                    self.walk_expr(expr, &mut Vec::new())?;
+                }
+                ("delete", self.delete_target(tables, from)?)
+            }
+            _ => return Err(PieskieoError::Internal("statement not supported".into())),
+        };
+        let (family, ns, coll) = self.split_name(name)?;
+        let family = if self.target_is_rows(family.as_deref(), &ns, &coll) {
+            "rows"
+        } else {
+            "docs"
+        };
+        Ok(SqlAnalysis {
+            kind,
+            target: format!("{family}.{ns}.{coll}"),
+            is_select: kind == "select",
+        })
+    }
+
+    pub fn query_sql(&self, sql: &str) -> Result<SqlResult> {
+        let stmt = &Self::parse_statement(sql)?;
         match stmt {
             Statement::Query(_) => self.exec_select(stmt),
             Statement::Insert { .. } => self.exec_insert(stmt),
@@ -983,6 +2087,57 @@ impl PieskieoDb {
         }
     }
 
+    /// Sort rows that several shards returned for the same SELECT by its
+    /// ORDER BY keys, so a page can be cut from the merged rows. Ties keep
+    /// their incoming order. Fails when the rows cannot be ordered the way a
+    /// shard ordered them: `_seq` is numbered per shard, and a key the
+    /// projection leaves out is not in the rows.
+    pub fn sort_merged_rows(&self, sql: &str, rows: &mut [(Uuid, Value)]) -> Result<()> {
+        let stmt = Self::parse_statement(sql)?;
+        let (_, _, _, projections, _, _, order_by, _, aggs, _) = self.parse_select(&stmt)?;
+        // aggregates come back as one row per shard, with nothing to sort by
+        if order_by.is_empty() || !aggs.is_empty() {
+            return Ok(());
+        }
+        let mut keys = Vec::with_capacity(order_by.len());
+        for key in order_by {
+            if key.field == SEQ_FIELD {
+                return Err(PieskieoError::Validation(
+                    "ORDER BY _seq cannot be merged across shards; query a single shard".into(),
+                ));
+            }
+            // the projected rows carry the key under its alias
+            let column = match &projections {
+                None => key.field.clone(),
+                Some(projs) => projs
+                    .iter()
+                    .find(|p| p.constant.is_none() && p.case.is_none() && p.source == key.field)
+                    .map(|p| p.alias.clone())
+                    .ok_or_else(|| {
+                        PieskieoError::Validation(format!(
+                            "ORDER BY {} must be selected to merge shards",
+                            key.field
+                        ))
+                    })?,
+            };
+            keys.push((column, key));
+        }
+        rows.sort_by(|a, b| {
+            for (column, key) in &keys {
+                let av = a.1.get(column).filter(|v| !v.is_null());
+                let bv = b.1.get(column).filter(|v| !v.is_null());
+                let ord = key.order(av, bv, |x, y| {
+                    cmp_values(x, y).unwrap_or(std::cmp::Ordering::Equal)
+                });
+                if ord != std::cmp::Ordering::Equal {
+                    return ord;
+                }
+            }
+            std::cmp::Ordering::Equal
+        });
+        Ok(())
+    }
+
     pub fn put_vector(&self, id: Uuid, vector: Vec<f32>) -> Result<()> {
         self.put_vector_with_meta_ns(None, id, vector, None)
     }
@@ -995,7 +2150,7 @@ impl PieskieoDb {
         &self,
         id: Uuid,
         vector: Vec<f32>,
-        meta: Option<HashMap<String, String>>,
+        meta: Option<VectorMeta>,
     ) -> Result<()> {
         self.put_vector_with_meta_ns(None, id, vector, meta)
     }
@@ -1005,17 +2160,18 @@ impl PieskieoDb {
         ns: Option<&str>,
         id: Uuid,
         vector: Vec<f32>,
-        meta: Option<HashMap<String, String>>,
+        meta: Option<VectorMeta>,
     ) -> Result<()> {
         if !self.owns(&id) {
             return Err(PieskieoError::WrongShard);
         }
         let namespace = Self::ns(ns);
-        let payload = bincode::serialize(&VecWalRecord {
+        let payload = VecWalRecord {
             namespace: Some(namespace.clone()),
             vector: vector.clone(),
             meta: meta.clone(),
-        })?;
+        }
+        .encode()?;
         self.append_record(&RecordKind::Put {
             family: DataFamily::Vec,
             key: id,
@@ -1027,12 +2183,13 @@ impl PieskieoDb {
         let idx = self.vector_index(&namespace);
         idx.insert(id, vector, meta)?;
         self.vector_ns.write().insert(id, namespace.clone());
+        self.writes.vectors.fetch_add(1, Ordering::Relaxed);
         self.auto_link_neighbors(id, &namespace);
         Ok(())
     }
 
     /// Merge or set metadata for an existing vector without changing the embedding.
-    pub fn update_vector_meta(&self, id: Uuid, meta_patch: HashMap<String, String>) -> Result<()> {
+    pub fn update_vector_meta(&self, id: Uuid, meta_patch: VectorMeta) -> Result<()> {
         let ns = {
             let map = self.vector_ns.read();
             map.get(&id).cloned().unwrap_or_else(Self::default_ns)
@@ -1055,11 +2212,12 @@ impl PieskieoDb {
             };
             (vec, merged)
         };
-        let payload = bincode::serialize(&VecWalRecord {
+        let payload = VecWalRecord {
             namespace: Some(ns.clone()),
             vector: vector.clone(),
             meta: Some(new_meta.clone()),
-        })?;
+        }
+        .encode()?;
         self.append_record(&RecordKind::Put {
             family: DataFamily::Vec,
             key: id,
@@ -1095,11 +2253,15 @@ impl PieskieoDb {
             idx.delete(id);
         }
         self.vector_ns.write().remove(id);
+        self.remove_node(*id)?;
         Ok(())
     }
 
+    /// Link `id` to its nearest vectors, first dropping the links made for
+    /// its previous embedding; user edges are left alone.
     fn auto_link_neighbors(&self, id: Uuid, ns: &str) {
-        if self.link_top_k == 0 {
+        let top_k = self.link_top_k_ns(ns);
+        if top_k == 0 || self.remove_auto_edges(id).is_err() {
             return;
         }
         let vector = self
@@ -1113,18 +2275,19 @@ impl PieskieoDb {
         let mut hits = match self.search_vector_metric_ns(
             Some(ns),
             &vector,
-            self.link_top_k + 1,
+            top_k + 1,
             self.default_params.metric,
             None,
+            None,
         ) {
             Ok(h) => h,
             Err(_) => return,
         };
         hits.retain(|h| h.id != id);
-        for h in hits.into_iter().take(self.link_top_k) {
-            let weight = 1.0 / (1.0 + h.score.abs());
-            let _ = self.add_edge(id, h.id, weight);
-            let _ = self.add_edge(h.id, id, weight);
+        for h in hits.into_iter().take(top_k) {
+            let weight = self.default_params.link_weight.weight(h.score);
+            let _ = self.add_auto_edge(id, h.id, weight);
+            let _ = self.add_auto_edge(h.id, id, weight);
         }
     }
 
@@ -1133,45 +2296,29 @@ impl PieskieoDb {
         query: &[f32],
         k: usize,
     ) -> Result<Vec<crate::vector::VectorSearchResult>> {
-        self.search_vector_metric(query, k, self.default_params.metric, None)
+        self.search_vector_metric(query, k, self.default_params.metric, None, None)
     }
 
+    /// Top-`k` over every namespace; `after` continues from a previous page
+    /// (approximate on HNSW, see `VectorIndex::search_ann_filtered`).
     pub fn search_vector_metric(
         &self,
         query: &[f32],
         k: usize,
         metric: crate::vector::VectorMetric,
-        filter_meta: Option<HashMap<String, String>>,
+        filter_meta: Option<HashMap<String, Value>>,
+        after: Option<&crate::vector::SearchAfter>,
     ) -> Result<Vec<crate::vector::VectorSearchResult>> {
         // search across all namespaces and merge top-k
         let mut all = Vec::new();
         for (_ns, idx) in self.vectors.read().iter() {
-            let local = crate::vector::VectorIndex::from_shared(
-                idx.inner.clone(),
-                idx.dim.clone(),
-                metric,
-                idx.hnsw.clone(),
-                idx.owned_store.clone(),
-                idx.id_map.clone(),
-                idx.rev_map.clone(),
-                idx.next_id.clone(),
-                idx.tombstones.clone(),
-                std::sync::atomic::AtomicUsize::new(
-                    idx.ef_construction
-                        .load(std::sync::atomic::Ordering::SeqCst),
-                ),
-                std::sync::atomic::AtomicUsize::new(
-                    idx.ef_search.load(std::sync::atomic::Ordering::SeqCst),
-                ),
-                idx.max_elements,
-                idx.meta.clone(),
-            );
-            let hits = local.search_ann_filtered(query, k, filter_meta.clone())?;
+            let local = Self::with_metric(idx, metric);
+            let hits = self.search_index(&local, query, k, filter_meta.clone(), after)?;
             for h in hits {
                 all.push(h);
             }
         }
-        all.sort_by(|a, b| b.score.partial_cmp(&a.score).unwrap());
+        all.sort_by(crate::vector::rank);
         all.truncate(k);
         Ok(all)
     }
@@ -1182,11 +2329,18 @@ impl PieskieoDb {
         query: &[f32],
         k: usize,
         metric: crate::vector::VectorMetric,
-        filter_meta: Option<HashMap<String, String>>,
+        filter_meta: Option<HashMap<String, Value>>,
+        after: Option<&crate::vector::SearchAfter>,
     ) -> Result<Vec<crate::vector::VectorSearchResult>> {
         let namespace = Self::ns(ns);
         let idx = self.vector_index(&namespace);
-        let local = crate::vector::VectorIndex::from_shared(
+        let local = Self::with_metric(&idx, metric);
+        self.search_index(&local, query, k, filter_meta, after)
+    }
+
+    /// A view of `idx` sharing its vectors and graph but scoring with `metric`.
+    fn with_metric(idx: &VectorIndex, metric: crate::vector::VectorMetric) -> VectorIndex {
+        VectorIndex::from_shared(
             idx.inner.clone(),
             idx.dim.clone(),
             metric,
@@ -1205,1493 +2359,4874 @@ impl PieskieoDb {
             ),
             idx.max_elements,
             idx.meta.clone(),
-        );
-        local.search_ann_filtered(query, k, filter_meta)
+        )
     }
 
-    pub fn add_edge(&self, src: Uuid, dst: Uuid, weight: f32) -> Result<()> {
-        if !self.owns(&src) {
-            return Err(PieskieoError::WrongShard);
+    /// Small indexes gain nothing from HNSW's single-threaded walk; score them
+    /// exactly on the rayon pool instead.
+    fn search_index(
+        &self,
+        local: &VectorIndex,
+        query: &[f32],
+        k: usize,
+        filter_meta: Option<HashMap<String, Value>>,
+        after: Option<&crate::vector::SearchAfter>,
+    ) -> Result<Vec<crate::vector::VectorSearchResult>> {
+        if local.inner.read().len() < self.default_params.exact_below {
+            return local.search_filtered(query, k, filter_meta, after);
         }
-        let payload = bincode::serialize(&crate::graph::Edge { src, dst, weight })?;
-        self.append_record(&RecordKind::Put {
-            family: DataFamily::Graph,
-            key: src,
-            payload,
-            namespace: None,
-            collection: None,
-            table: None,
-        })?;
-        self.graph.add_edge(src, dst, weight);
-        Ok(())
-    }
-
-    pub fn neighbors(&self, id: Uuid, limit: usize) -> Vec<crate::graph::Edge> {
-        self.graph.neighbors(id, limit)
+        local.search_ann_filtered(query, k, filter_meta, after)
     }
 
-    pub fn bfs(&self, start: Uuid, limit: usize) -> Vec<crate::graph::Edge> {
-        self.graph.bfs(start, limit)
+    /// Exact search over an explicit candidate set instead of ANN-then-filter.
+    /// `ns == None` searches every namespace.
+    pub fn search_vector_ids_ns(
+        &self,
+        ns: Option<&str>,
+        query: &[f32],
+        k: usize,
+        metric: crate::vector::VectorMetric,
+        ids: &HashSet<Uuid>,
+        filter_meta: Option<HashMap<String, Value>>,
+    ) -> Result<Vec<crate::vector::VectorSearchResult>> {
+        let indexes: Vec<Arc<VectorIndex>> = match ns {
+            Some(ns) => vec![self.vector_index(&Self::ns(Some(ns)))],
+            None => self.vectors.read().values().cloned().collect(),
+        };
+        let mut all = Vec::new();
+        for idx in indexes {
+            let local = Self::with_metric(&idx, metric);
+            all.extend(local.search_ids(query, k, ids, filter_meta.clone())?);
+        }
+        all.sort_by(crate::vector::rank);
+        all.truncate(k);
+        Ok(all)
     }
 
-    pub fn dfs(&self, start: Uuid, limit: usize) -> Vec<crate::graph::Edge> {
-        self.graph.dfs(start, limit)
+    /// Vector ids inside `range` across `ns` (every namespace when `None`, as
+    /// in `search_vector_ids_ns`), or `None` when there are more than `limit`.
+    pub fn vector_ids_in_range(
+        &self,
+        ns: Option<&str>,
+        range: &crate::vector::VectorIdRange,
+        limit: usize,
+    ) -> Option<HashSet<Uuid>> {
+        let indexes: Vec<Arc<VectorIndex>> = match ns {
+            Some(ns) => vec![self.vector_index(&Self::ns(Some(ns)))],
+            None => self.vectors.read().values().cloned().collect(),
+        };
+        let mut out = HashSet::new();
+        for idx in indexes {
+            out.extend(idx.ids_in_range(range, limit.saturating_sub(out.len()))?);
+        }
+        Some(out)
     }
 
-    pub fn rebuild_vectors(&self) -> Result<()> {
-        for idx in self.vectors.read().values() {
-            idx.rebuild_hnsw()?;
+    /// Adds or re-weights the `src -> dst` edge (see `GraphStore::add_edge`).
+    pub fn add_edge(&self, src: Uuid, dst: Uuid, weight: f32) -> Result<()> {
+        if !self.owns(&src) {
+            return Err(PieskieoError::WrongShard);
         }
+        self.append_edge(src, dst, weight, false)?;
+        self.graph.add_edge(src, dst, weight);
+        self.writes.edges.fetch_add(1, Ordering::Relaxed);
         Ok(())
     }
 
-    pub fn get_vector(&self, id: &Uuid) -> Option<(Vec<f32>, Option<HashMap<String, String>>)> {
-        let ns = self
-            .vector_ns
-            .read()
-            .get(id)
-            .cloned()
-            .unwrap_or_else(Self::default_ns);
-        let idx = self.vector_index(&ns);
-        let vec = idx.inner.read().get(id).cloned()?;
-        let meta = idx.meta.read().get(id).cloned();
-        Some((vec, meta))
-    }
-
-    pub fn save_vector_snapshot(&self) -> Result<()> {
-        let snap_dir = self.path.join("vectors");
-        std::fs::create_dir_all(&snap_dir)?;
-        for (ns, idx) in self.vectors.read().iter() {
-            let snap = snap_dir.join(format!("{ns}.snapshot"));
-            idx.save_snapshot(&snap)?;
-            let hnsw = snap_dir.join(format!("{ns}.hnsw"));
-            idx.save_hnsw(&hnsw)?;
+    fn add_auto_edge(&self, src: Uuid, dst: Uuid, weight: f32) -> Result<()> {
+        if !self.owns(&src) {
+            return Err(PieskieoError::WrongShard);
         }
+        self.append_edge(src, dst, weight, true)?;
+        self.graph.add_auto_edge(src, dst, weight);
+        self.writes.edges.fetch_add(1, Ordering::Relaxed);
         Ok(())
     }
 
-    pub fn set_ef_search(&self, ef: usize) {
-        for idx in self.vectors.read().values() {
-            idx.set_ef_search(ef);
-        }
+    fn append_edge(&self, src: Uuid, dst: Uuid, weight: f32, auto: bool) -> Result<()> {
+        let payload = EdgeWire::encode(&crate::graph::Edge {
+            src,
+            dst,
+            weight,
+            auto,
+        })?;
+        self.append_record(&RecordKind::Put {
+            family: DataFamily::Graph,
+            key: src,
+            payload,
+            namespace: None,
+            collection: None,
+            table: None,
+        })
     }
 
-    pub fn set_ef_construction(&self, ef: usize) {
-        for idx in self.vectors.read().values() {
-            idx.set_ef_construction(ef);
+    /// Drop the auto-link edges touching `id` held by this shard, so a
+    /// changed embedding does not keep its old neighbours.
+    fn remove_auto_edges(&self, id: Uuid) -> Result<usize> {
+        if !self.graph.has_auto_edges(&id) {
+            return Ok(0);
         }
+        self.append_record(&RecordKind::RemoveAutoEdges { node: id })?;
+        Ok(self.graph.remove_auto_edges(id))
     }
 
-    pub fn set_link_top_k(&mut self, k: usize) {
-        self.link_top_k = k;
-    }
-
-    pub fn remove_vector_meta_keys(&self, id: Uuid, keys: &[String]) -> Result<()> {
-        let ns = {
-            let map = self.vector_ns.read();
-            map.get(&id).cloned().unwrap_or_else(Self::default_ns)
-        };
-        let idx = self.vector_index(&ns);
-        let (vector, meta) = {
-            let data = idx.inner.read();
-            let meta = idx.meta.read();
-            let Some(vec) = data.get(&id).cloned() else {
-                return Err(PieskieoError::NotFound);
-            };
-            let mut m = meta.get(&id).cloned().unwrap_or_default();
-            for k in keys {
-                m.remove(k);
-            }
-            (vec, m)
-        };
-        let payload = bincode::serialize(&VecWalRecord {
-            namespace: Some(ns.clone()),
-            vector: vector.clone(),
-            meta: Some(meta.clone()),
-        })?;
-        self.append_record(&RecordKind::Put {
-            family: DataFamily::Vec,
+    /// Remove every edge touching `id` held by this shard. Incoming edges live
+    /// on the shards owning their sources, so callers broadcast to all shards.
+    pub fn remove_node(&self, id: Uuid) -> Result<usize> {
+        if !self.graph.contains(&id) {
+            return Ok(0);
+        }
+        self.append_record(&RecordKind::Delete {
+            family: DataFamily::Graph,
             key: id,
-            payload,
-            namespace: Some(ns.clone()),
+            namespace: None,
             collection: None,
             table: None,
         })?;
-        idx.insert(id, vector, Some(meta))?;
-        Ok(())
+        Ok(self.graph.remove_node(id))
     }
 
-    /// Compact tombstones and WAL by rewriting snapshot and truncating WAL.
-    pub fn vacuum(&self) -> Result<()> {
-        // drop deleted vectors from in-memory store for each namespace
-        for idx in self.vectors.read().values() {
-            let tomb = idx.tombstones.read().clone();
-            if !tomb.is_empty() {
-                let mut inner = idx.inner.write();
-                for id in tomb.keys() {
-                    inner.remove(id);
-                }
-            }
-            idx.tombstones.write().clear();
+    /// `remove_node` for many ids, with one WAL write and one graph lock.
+    pub fn remove_nodes(&self, ids: &[Uuid]) -> Result<usize> {
+        let ids: HashSet<Uuid> = ids
+            .iter()
+            .copied()
+            .filter(|id| self.graph.contains(id))
+            .collect();
+        if ids.is_empty() {
+            return Ok(0);
         }
-
-        // rebuild ANN for clean state
-        let _ = self.rebuild_vectors();
-        // persist fresh snapshots + hnsw and truncate WAL
-        self.save_vector_snapshot()?;
-        self.wal.write().truncate()?;
-        Ok(())
+        let records: Vec<RecordKind> = ids
+            .iter()
+            .map(|id| RecordKind::Delete {
+                family: DataFamily::Graph,
+                key: *id,
+                namespace: None,
+                collection: None,
+                table: None,
+            })
+            .collect();
+        self.append_records(&records)?;
+        Ok(self.graph.remove_nodes(&ids))
     }
 
-    pub fn flush_wal(&self) -> Result<()> {
-        self.wal.write().flush_sync()
+    /// Remove every doc, row and vector in `ns` plus the edges touching them,
+    /// logging a WAL delete per record so replay and replicas follow. Schemas
+    /// are kept, so a re-created namespace is validated the same way.
+    pub fn drop_namespace(&self, ns: &str) -> Result<NamespaceDrop> {
+        let mut out = NamespaceDrop::default();
+        {
+            let mut guard = self.data.write();
+            let mut deletes = Vec::new();
+            for (store, family) in [
+                (&guard.docs, DataFamily::Doc),
+                (&guard.rows, DataFamily::Row),
+            ] {
+                let rows = matches!(family, DataFamily::Row);
+                for (coll, records) in store.get(ns).into_iter().flatten() {
+                    for id in records.keys() {
+                        deletes.push(RecordKind::Delete {
+                            family,
+                            key: *id,
+                            namespace: Some(ns.to_string()),
+                            collection: (!rows).then(|| coll.clone()),
+                            table: rows.then(|| coll.clone()),
+                        });
+                        out.ids.push(*id);
+                    }
+                    if rows {
+                        out.rows += records.len();
+                    } else {
+                        out.docs += records.len();
+                    }
+                }
+            }
+            self.append_records(&deletes)?;
+            guard.docs.remove(ns);
+            guard.rows.remove(ns);
+            guard.doc_index.remove(ns);
+            guard.row_index.remove(ns);
+            guard.doc_range.remove(ns);
+            guard.doc_seq.remove(ns);
+            guard.row_seq.remove(ns);
+            let mut stats = self.stats.write();
+            stats.docs.remove(ns);
+            stats.rows.remove(ns);
+        }
+        {
+            let mut vectors = self.vectors.write();
+            if let Some(idx) = vectors.get(ns) {
+                let ids: Vec<Uuid> = idx.inner.read().keys().copied().collect();
+                let deletes: Vec<RecordKind> = ids
+                    .iter()
+                    .map(|id| RecordKind::Delete {
+                        family: DataFamily::Vec,
+                        key: *id,
+                        namespace: Some(ns.to_string()),
+                        collection: None,
+                        table: None,
+                    })
+                    .collect();
+                self.append_records(&deletes)?;
+                out.vectors += ids.len();
+                out.ids.extend(ids);
+                vectors.remove(ns);
+                // a leftover snapshot would bring the vectors back on restart
+                let snap_dir = self.path.join("vectors");
+                for ext in ["snapshot", "hnsw"] {
+                    let file = snap_dir.join(format!("{ns}.{ext}"));
+                    if file.exists() {
+                        std::fs::remove_file(file)?;
+                    }
+                }
+            }
+            self.vector_ns.write().retain(|_, n| n != ns);
+        }
+        out.edges += self.remove_nodes(&out.ids)?;
+        Ok(out)
     }
 
-    pub fn metrics(&self) -> MetricsSnapshot {
-        let mut vectors = 0usize;
-        let mut tomb = 0usize;
-        let mut hnsw_ready = true;
-        let mut ef_search = 0usize;
-        let mut ef_construction = 0usize;
-        for idx in self.vectors.read().values() {
-            vectors += idx.inner.read().len();
-            tomb += idx.tombstones.read().len();
-            hnsw_ready &= idx.hnsw.read().is_some();
-            ef_search = idx.ef_search.load(std::sync::atomic::Ordering::SeqCst);
-            ef_construction = idx
-                .ef_construction
-                .load(std::sync::atomic::Ordering::SeqCst);
-        }
-        MetricsSnapshot {
-            docs: self.data.read().docs.values().map(|m| m.len()).sum(),
-            rows: self.data.read().rows.values().map(|m| m.len()).sum(),
-            vectors,
-            vector_tombstones: tomb,
-            hnsw_ready,
-            ef_search,
-            ef_construction,
-            wal_path: self.path.join("wal.log"),
-            wal_bytes: std::fs::metadata(self.path.join("wal.log"))
-                .map(|m| m.len())
-                .unwrap_or(0),
-            snapshot_mtime: std::fs::metadata(self.path.join("vectors"))
-                .and_then(|m| m.modified())
-                .ok(),
-            link_top_k: self.link_top_k,
-            shard_id: self.shard_id,
-            shard_total: self.shard_total,
-        }
-    }
-
-    fn append_record(&self, record: &RecordKind) -> Result<()> {
-        self.wal.write().append(record)
-    }
-
-    pub fn wal_replay_since(&self, offset: u64) -> Result<(Vec<RecordKind>, u64)> {
-        self.wal.read().replay_since(offset)
-    }
-
-    pub fn wal_dump(&self) -> Result<Vec<RecordKind>> {
-        self.wal.read().replay()
-    }
-
-    pub fn apply_records(&self, records: &[RecordKind]) -> Result<()> {
-        for rec in records {
-            self.append_record(rec)?;
-            self.apply_record(rec)?;
+    /// Apply `ops` all or nothing on this shard. Everything is validated up
+    /// front (ownership, schemas, unique fields across the whole batch,
+    /// vector dimensions), then every WAL record is appended in one write
+    /// before memory changes, all under a single data write lock. Unique
+    /// fields are checked against the data as it stood before the batch.
+    /// Returns, per op, whether it created a doc or row that did not exist.
+    pub fn apply_batch(&self, ops: Vec<BatchOp>) -> Result<Vec<bool>> {
+        if ops.iter().any(|op| !self.owns(&op.key())) {
+            return Err(PieskieoError::WrongShard);
         }
-        Ok(())
-    }
-
-    fn apply_record(&self, rec: &RecordKind) -> Result<()> {
-        match rec {
-            RecordKind::Put {
-                family,
-                key,
-                payload,
-                namespace,
-                collection,
-                table,
-            } => match family {
-                DataFamily::Doc => {
-                    let ns = namespace.clone().unwrap_or_else(Self::default_ns);
-                    let col = collection.clone().unwrap_or_else(Self::default_ns);
-                    let v: Value = serde_json::from_slice(payload)?;
-                    let mut guard = self.data.write();
-                    guard
-                        .docs
-                        .entry(ns.clone())
-                        .or_default()
-                        .entry(col.clone())
-                        .or_default()
-                        .insert(*key, v.clone());
-                    Self::index_upsert_doc(&mut guard, ns, col, *key, &v);
-                }
-                DataFamily::Row => {
-                    let ns = namespace.clone().unwrap_or_else(Self::default_ns);
-                    let tbl = table.clone().unwrap_or_else(Self::default_ns);
-                    let v: Value = serde_json::from_slice(payload)?;
-                    let mut guard = self.data.write();
-                    guard
-                        .rows
-                        .entry(ns.clone())
-                        .or_default()
-                        .entry(tbl.clone())
-                        .or_default()
-                        .insert(*key, v.clone());
-                    Self::index_upsert_row(&mut guard, ns, tbl, *key, &v);
+        let mut guard = self.data.write();
+        // (rows, ns, collection, field, value) -> id holding it in this batch
+        let mut claimed: HashMap<(bool, String, String, String, String), Uuid> = HashMap::new();
+        let mut dims: HashMap<String, usize> = HashMap::new();
+        let mut records = Vec::with_capacity(ops.len());
+        for op in &ops {
+            match op {
+                BatchOp::PutDoc {
+                    namespace,
+                    collection,
+                    id,
+                    data,
                 }
-                DataFamily::Vec => match bincode::deserialize::<VecWalRecord>(payload) {
-                    Ok(rec) => {
-                        let ns = rec.namespace.unwrap_or_else(Self::default_ns);
-                        let idx = self.vector_index(&ns);
-                        idx.insert(*key, rec.vector, rec.meta)?;
-                        self.vector_ns.write().insert(*key, ns);
-                    }
-                    Err(_) => {
-                        let idx = self.vector_index(&Self::default_ns());
-                        idx.insert(*key, Vec::new(), None)?;
-                        self.vector_ns.write().insert(*key, Self::default_ns());
+                | BatchOp::PutRow {
+                    namespace,
+                    table: collection,
+                    id,
+                    data,
+                } => {
+                    let rows = matches!(op, BatchOp::PutRow { .. });
+                    let ns_key = Self::ns(namespace.as_deref());
+                    let col_key = Self::col(collection.as_deref());
+                    let (schemas, index) = if rows {
+                        (&guard.row_schema, &guard.row_index)
+                    } else {
+                        (&guard.doc_schema, &guard.doc_index)
+                    };
+                    if let Some(schema) = schemas.get(&ns_key).and_then(|m| m.get(&col_key)) {
+                        Self::validate_object(data)?;
+                        Self::check_schema(
+                            id,
+                            data,
+                            schema,
+                            index.get(&ns_key).and_then(|m| m.get(&col_key)),
+                        )?;
+                        for (field, spec) in &schema.fields {
+                            if !spec.unique {
+                                continue;
+                            }
+                            let Some(key) = data.get(field).and_then(Self::index_key) else {
+                                continue;
+                            };
+                            let owner = claimed
+                                .entry((rows, ns_key.clone(), col_key.clone(), field.clone(), key))
+                                .or_insert(*id);
+                            if *owner != *id {
+                                return Err(PieskieoError::UniqueViolation(field.clone()));
+                            }
+                        }
                     }
-                },
-                DataFamily::Graph => {
-                    if let Ok(edge) = bincode::deserialize::<crate::graph::Edge>(payload) {
-                        let _ = self.graph.add_edge(edge.src, edge.dst, edge.weight);
+                    let (family, collection, table) = if rows {
+                        (DataFamily::Row, None, Some(col_key))
+                    } else {
+                        (DataFamily::Doc, Some(col_key), None)
+                    };
+                    records.push(RecordKind::Put {
+                        family,
+                        key: *id,
+                        payload: serde_json::to_vec(data)?,
+                        namespace: Some(ns_key),
+                        collection,
+                        table,
+                    });
+                }
+                BatchOp::Delete {
+                    family,
+                    namespace,
+                    collection,
+                    id,
+                } => {
+                    let col_key = Self::col(collection.as_deref());
+                    let (data_family, namespace, collection, table) = match family {
+                        BatchFamily::Doc => (
+                            DataFamily::Doc,
+                            Self::ns(namespace.as_deref()),
+                            Some(col_key),
+                            None,
+                        ),
+                        BatchFamily::Row => (
+                            DataFamily::Row,
+                            Self::ns(namespace.as_deref()),
+                            None,
+                            Some(col_key),
+                        ),
+                        BatchFamily::Vector => (
+                            DataFamily::Vec,
+                            self.vector_ns
+                                .read()
+                                .get(id)
+                                .cloned()
+                                .unwrap_or_else(|| Self::ns(namespace.as_deref())),
+                            None,
+                            None,
+                        ),
+                    };
+                    records.push(RecordKind::Delete {
+                        family: data_family,
+                        key: *id,
+                        namespace: Some(namespace),
+                        collection,
+                        table,
+                    });
+                    if *family != BatchFamily::Row {
+                        records.push(RecordKind::Delete {
+                            family: DataFamily::Graph,
+                            key: *id,
+                            namespace: None,
+                            collection: None,
+                            table: None,
+                        });
                     }
                 }
-            },
-            RecordKind::Delete {
-                family,
-                key,
-                namespace,
-                collection,
-                table,
-            } => match family {
-                DataFamily::Doc => {
-                    let ns = namespace.clone().unwrap_or_else(Self::default_ns);
-                    let col = collection.clone().unwrap_or_else(Self::default_ns);
-                    let mut guard = self.data.write();
-                    if let Some(map) = guard.docs.get_mut(&ns).and_then(|m| m.get_mut(&col)) {
-                        map.remove(key);
+                BatchOp::PutVector {
+                    namespace,
+                    id,
+                    vector,
+                    meta,
+                } => {
+                    let ns_key = Self::ns(namespace.as_deref());
+                    let dim = match dims.get(&ns_key) {
+                        Some(dim) => Some(*dim),
+                        None => self
+                            .vectors
+                            .read()
+                            .get(&ns_key)
+                            .and_then(|idx| *idx.dim.read()),
+                    };
+                    if dim.is_some_and(|dim| dim != vector.len()) {
+                        return Err(PieskieoError::Validation(format!(
+                            "vector {id} has {} dimensions, namespace '{ns_key}' expects {}",
+                            vector.len(),
+                            dim.unwrap_or_default()
+                        )));
                     }
-                    if let Some(idx) = guard.doc_index.get_mut(&ns).and_then(|m| m.get_mut(&col)) {
-                        for (_field, valmap) in idx.iter_mut() {
-                            for (_v, ids) in valmap.iter_mut() {
-                                ids.retain(|id| id != key);
-                            }
-                        }
+                    dims.insert(ns_key.clone(), vector.len());
+                    let payload = VecWalRecord {
+                        namespace: Some(ns_key.clone()),
+                        vector: vector.clone(),
+                        meta: meta.clone(),
                     }
+                    .encode()?;
+                    records.push(RecordKind::Put {
+                        family: DataFamily::Vec,
+                        key: *id,
+                        payload,
+                        namespace: Some(ns_key),
+                        collection: None,
+                        table: None,
+                    });
                 }
-                DataFamily::Row => {
-                    let ns = namespace.clone().unwrap_or_else(Self::default_ns);
-                    let tbl = table.clone().unwrap_or_else(Self::default_ns);
-                    let mut guard = self.data.write();
-                    if let Some(map) = guard.rows.get_mut(&ns).and_then(|m| m.get_mut(&tbl)) {
-                        map.remove(key);
-                    }
-                    if let Some(idx) = guard.row_index.get_mut(&ns).and_then(|m| m.get_mut(&tbl)) {
-                        for (_field, valmap) in idx.iter_mut() {
-                            for (_v, ids) in valmap.iter_mut() {
-                                ids.retain(|id| id != key);
+                BatchOp::AddEdge { src, dst, weight } => {
+                    records.push(RecordKind::Put {
+                        family: DataFamily::Graph,
+                        key: *src,
+                        payload: EdgeWire::encode(&crate::graph::Edge {
+                            src: *src,
+                            dst: *dst,
+                            weight: weight.unwrap_or(1.0),
+                            auto: false,
+                        })?,
+                        namespace: None,
+                        collection: None,
+                        table: None,
+                    });
+                }
+            }
+        }
+        self.append_records(&records)?;
+
+        let mut created = Vec::with_capacity(ops.len());
+        let mut linked = Vec::new();
+        for op in ops {
+            let fresh = match op {
+                BatchOp::PutDoc {
+                    namespace,
+                    collection,
+                    id,
+                    data,
+                } => {
+                    self.writes.docs.fetch_add(1, Ordering::Relaxed);
+                    self.store_doc(
+                        &mut guard,
+                        Self::ns(namespace.as_deref()),
+                        Self::col(collection.as_deref()),
+                        id,
+                        data,
+                    )
+                }
+                BatchOp::PutRow {
+                    namespace,
+                    table,
+                    id,
+                    data,
+                } => {
+                    self.writes.rows.fetch_add(1, Ordering::Relaxed);
+                    self.store_row(
+                        &mut guard,
+                        Self::ns(namespace.as_deref()),
+                        Self::col(table.as_deref()),
+                        id,
+                        data,
+                    )
+                }
+                BatchOp::Delete {
+                    family,
+                    namespace,
+                    collection,
+                    id,
+                } => {
+                    let ns_key = Self::ns(namespace.as_deref());
+                    let col_key = Self::col(collection.as_deref());
+                    match family {
+                        BatchFamily::Doc => self.unstore_doc(&mut guard, ns_key, col_key, &id),
+                        BatchFamily::Row => self.unstore_row(&mut guard, ns_key, col_key, &id),
+                        BatchFamily::Vector => {
+                            let ns = self.vector_ns.write().remove(&id);
+                            let idx = ns.and_then(|ns| self.vectors.read().get(&ns).cloned());
+                            if let Some(idx) = idx {
+                                idx.delete(&id);
                             }
                         }
                     }
-                }
-                DataFamily::Vec => {
-                    if let Some(ns) = self.vector_ns.read().get(key).cloned() {
-                        let idx = self.vector_index(&ns);
-                        idx.delete(key);
-                        self.vector_ns.write().remove(key);
+                    if family != BatchFamily::Row {
+                        self.graph.remove_node(id);
                     }
+                    false
                 }
-                DataFamily::Graph => {}
-            },
-            RecordKind::Schema {
-                family,
-                namespace,
-                collection,
-                table,
-                schema,
-            } => {
-                let ns = namespace.clone().unwrap_or_else(Self::default_ns);
-                let def: SchemaDef = serde_json::from_slice(schema)?;
-                match family {
-                    DataFamily::Doc => {
-                        let name = collection.clone().unwrap_or_else(Self::default_ns);
-                        self.data
-                            .write()
-                            .doc_schema
-                            .entry(ns)
-                            .or_default()
-                            .insert(name, def);
-                    }
-                    DataFamily::Row => {
-                        let name = table.clone().unwrap_or_else(Self::default_ns);
-                        self.data
-                            .write()
-                            .row_schema
-                            .entry(ns)
-                            .or_default()
-                            .insert(name, def);
-                    }
-                    _ => {}
+                BatchOp::PutVector {
+                    namespace,
+                    id,
+                    vector,
+                    meta,
+                } => {
+                    let ns_key = Self::ns(namespace.as_deref());
+                    self.vector_index(&ns_key).insert(id, vector, meta)?;
+                    self.vector_ns.write().insert(id, ns_key.clone());
+                    self.writes.vectors.fetch_add(1, Ordering::Relaxed);
+                    linked.push((id, ns_key));
+                    false
                 }
-            }
-            RecordKind::AddEdge { src, dst, weight } => {
-                self.graph.add_edge(*src, *dst, *weight);
-            }
+                BatchOp::AddEdge { src, dst, weight } => {
+                    self.graph.add_edge(src, dst, weight.unwrap_or(1.0));
+                    self.writes.edges.fetch_add(1, Ordering::Relaxed);
+                    false
+                }
+            };
+            created.push(fresh);
         }
-        Ok(())
+        drop(guard);
+        for (id, ns) in linked {
+            self.auto_link_neighbors(id, &ns);
+        }
+        Ok(created)
     }
 
-    fn owns(&self, id: &Uuid) -> bool {
-        if self.shard_total <= 1 {
-            return true;
+    /// Delete every id in `ids` that exists as `family` (vectors in any
+    /// namespace; `collection` names the table for rows), with one WAL write
+    /// via `apply_batch`. Missing and repeated ids are skipped; returns the
+    /// ids actually deleted, in request order.
+    pub fn delete_many(
+        &self,
+        family: BatchFamily,
+        ns: Option<&str>,
+        collection: Option<&str>,
+        ids: &[Uuid],
+    ) -> Result<Vec<Uuid>> {
+        if ids.iter().any(|id| !self.owns(id)) {
+            return Err(PieskieoError::WrongShard);
+        }
+        let mut seen = HashSet::new();
+        let present: Vec<Uuid> = ids
+            .iter()
+            .copied()
+            .filter(|id| seen.insert(*id))
+            .filter(|id| match family {
+                BatchFamily::Doc => self.has_doc_ns(ns, collection, id),
+                BatchFamily::Row => self.has_row_ns(ns, collection, id),
+                BatchFamily::Vector => self.has_vector(id),
+            })
+            .collect();
+        let ops = present
+            .iter()
+            .map(|id| BatchOp::Delete {
+                family,
+                namespace: ns.map(str::to_string),
+                collection: collection.map(str::to_string),
+                id: *id,
+            })
+            .collect::<Vec<_>>();
+        if !ops.is_empty() {
+            self.apply_batch(ops)?;
         }
-        (shard_hash(id) % self.shard_total) == self.shard_id
+        Ok(present)
     }
-}
 
-fn value_matches(doc: &Value, filter: &HashMap<String, Value>) -> bool {
-    for (k, v) in filter {
-        if let Some(field) = doc.get(k) {
-            if !value_compare(field, v) {
-                return false;
+    pub fn neighbors(&self, id: Uuid, limit: usize) -> Vec<crate::graph::Edge> {
+        self.graph.neighbors(id, limit)
+    }
+
+    pub fn edge_weight(&self, src: Uuid, dst: Uuid) -> Option<f32> {
+        self.graph.edge_weight(src, dst)
+    }
+
+    /// Every edge held by this shard, i.e. those whose source it owns.
+    pub fn export_edges(&self) -> Vec<crate::graph::Edge> {
+        self.graph.edges()
+    }
+
+    /// Load edges in one WAL write, keeping their `auto` flag; all sources
+    /// must belong to this shard or nothing is written.
+    pub fn import_edges(&self, edges: &[crate::graph::Edge]) -> Result<()> {
+        if edges.iter().any(|e| !self.owns(&e.src)) {
+            return Err(PieskieoError::WrongShard);
+        }
+        let records = edges
+            .iter()
+            .map(|e| {
+                Ok(RecordKind::Put {
+                    family: DataFamily::Graph,
+                    key: e.src,
+                    payload: EdgeWire::encode(e)?,
+                    namespace: None,
+                    collection: None,
+                    table: None,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        self.append_records(&records)?;
+        for e in edges {
+            if e.auto {
+                self.graph.add_auto_edge(e.src, e.dst, e.weight);
+            } else {
+                self.graph.add_edge(e.src, e.dst, e.weight);
             }
-        } else {
-            return false;
         }
+        self.writes
+            .edges
+            .fetch_add(edges.len() as u64, Ordering::Relaxed);
+        Ok(())
     }
-    true
-}
 
-fn value_compare(field: &Value, cond: &Value) -> bool {
-    if !cond.is_object() {
-        return field == cond;
+    /// Connected components of this shard's edges; see `GraphStore::components`.
+    pub fn graph_components(&self) -> Vec<Vec<Uuid>> {
+        self.graph.components()
     }
-    let obj = cond.as_object().unwrap();
-    for (op, val) in obj {
-        match op.as_str() {
-            "$gt" => {
-                if !cmp_values(field, val).map(|o| o.is_gt()).unwrap_or(false) {
-                    return false;
-                }
-            }
-            "$gte" => {
-                if !cmp_values(field, val).map(|o| o.is_ge()).unwrap_or(false) {
-                    return false;
-                }
-            }
-            "$lt" => {
-                if !cmp_values(field, val).map(|o| o.is_lt()).unwrap_or(false) {
-                    return false;
-                }
-            }
-            "$lte" => {
-                if !cmp_values(field, val).map(|o| o.is_le()).unwrap_or(false) {
-                    return false;
-                }
-            }
-            "$ne" => {
-                if field == val {
-                    return false;
-                }
-            }
-            "$in" => {
-                if let Some(arr) = val.as_array() {
-                    if !arr.iter().any(|x| x == field) {
-                        return false;
-                    }
-                } else {
-                    return false;
-                }
-            }
-            "$nin" => {
-                if let Some(arr) = val.as_array() {
-                    if arr.iter().any(|x| x == field) {
-                        return false;
-                    }
-                } else {
-                    return false;
-                }
-            }
-            _ => return false,
+
+    pub fn bfs(&self, start: Uuid, limit: usize) -> Vec<crate::graph::Edge> {
+        self.graph.bfs(start, limit)
+    }
+
+    pub fn dfs(&self, start: Uuid, limit: usize) -> Vec<crate::graph::Edge> {
+        self.graph.dfs(start, limit)
+    }
+
+    pub fn rebuild_vectors(&self) -> Result<()> {
+        for idx in self.vectors.read().values() {
+            idx.rebuild_hnsw()?;
         }
+        Ok(())
     }
-    true
-}
 
-fn cmp_values(a: &Value, b: &Value) -> Option<std::cmp::Ordering> {
-    match (a, b) {
-        (Value::Number(x), Value::Number(y)) => {
-            let xf = x.as_f64()?;
-            let yf = y.as_f64()?;
-            xf.partial_cmp(&yf)
+    /// Rebuild a single namespace's HNSW; `NotFound` if it has no index.
+    pub fn rebuild_vectors_ns(&self, ns: Option<&str>) -> Result<()> {
+        let namespace = Self::ns(ns);
+        let Some(idx) = self.vectors.read().get(&namespace).cloned() else {
+            return Err(PieskieoError::NotFound);
+        };
+        idx.rebuild_hnsw()
+    }
+
+    /// Rebuild only namespaces written to since their last rebuild; returns
+    /// the namespaces that were rebuilt.
+    pub fn rebuild_dirty_vectors(&self) -> Result<Vec<String>> {
+        let dirty = self.dirty_vector_namespaces();
+        for ns in &dirty {
+            self.rebuild_vectors_ns(Some(ns))?;
         }
-        (Value::String(x), Value::String(y)) => Some(x.cmp(y)),
-        (Value::Bool(x), Value::Bool(y)) => Some(x.cmp(y)),
-        _ => None,
+        Ok(dirty)
     }
-}
 
-#[derive(Clone)]
-struct Projection {
-    source: String,
-    alias: String,
-}
+    /// Namespaces written to since their last rebuild, for callers that
+    /// schedule `rebuild_vectors_ns` themselves.
+    pub fn dirty_vector_namespaces(&self) -> Vec<String> {
+        let mut dirty: Vec<String> = self
+            .vectors
+            .read()
+            .iter()
+            .filter(|(_, idx)| idx.dirty.load(std::sync::atomic::Ordering::SeqCst))
+            .map(|(ns, _)| ns.clone())
+            .collect();
+        dirty.sort();
+        dirty
+    }
 
-#[derive(Clone)]
-struct JoinSpec {
-    right_ns: String,
-    right_coll: String,
-    right_is_rows: bool,
-    on_left: String,
-    on_right: String,
-}
+    /// Whether `id` has a live vector, without copying the embedding.
+    pub fn has_vector(&self, id: &Uuid) -> bool {
+        let Some(ns) = self.vector_ns.read().get(id).cloned() else {
+            return false;
+        };
+        self.vectors
+            .read()
+            .get(&ns)
+            .is_some_and(|idx| idx.inner.read().contains_key(id))
+    }
 
-#[derive(Clone)]
-enum AggKind {
-    Count,
-    Sum,
-    Avg,
-    Min,
-    Max,
-}
+    pub fn get_vector(&self, id: &Uuid) -> Option<(Vec<f32>, Option<VectorMeta>)> {
+        let ns = self
+            .vector_ns
+            .read()
+            .get(id)
+            .cloned()
+            .unwrap_or_else(Self::default_ns);
+        let idx = self.vector_index(&ns);
+        let vec = idx.inner.read().get(id).cloned()?;
+        let meta = idx.meta.read().get(id).cloned();
+        Some((vec, meta))
+    }
 
-#[derive(Clone)]
-struct AggExpr {
-    alias: String,
-    field: Option<String>, // None for count(*)
-    kind: AggKind,
-}
+    pub fn save_vector_snapshot(&self) -> Result<()> {
+        // a write racing the save lands at or after `started` and keeps the
+        // shard marked as changed
+        let started = now_ms();
+        let snap_dir = self.path.join("vectors");
+        std::fs::create_dir_all(&snap_dir)?;
+        for (ns, idx) in self.vectors.read().iter() {
+            Self::save_ns_snapshot(&snap_dir, ns, idx)?;
+        }
+        self.snapshot_ms.store(started, Ordering::Relaxed);
+        Ok(())
+    }
 
-#[derive(Clone)]
-struct Condition {
-    field: String,
-    op: Op,
-    value: Value,
-}
+    fn save_ns_snapshot(snap_dir: &Path, ns: &str, idx: &VectorIndex) -> Result<()> {
+        idx.save_snapshot(&snap_dir.join(format!("{ns}.snapshot")))?;
+        idx.save_hnsw(&snap_dir.join(format!("{ns}.hnsw")))?;
+        Ok(())
+    }
 
-#[derive(Clone)]
-enum Op {
-    Eq,
-    Ne,
-    Gt,
-    Gte,
-    Lt,
-    Lte,
-    In,
-    Nin,
-}
+    /// This shard's vectors in `ns`, with metadata, as one blob in the format
+    /// `save_vector_snapshot` writes; an empty snapshot when it holds none.
+    pub fn export_vectors_ns(&self, ns: &str) -> Result<Vec<u8>> {
+        match self.vectors.read().get(ns).cloned() {
+            Some(idx) => idx.snapshot_bytes(),
+            None => encode_snapshot(Vec::new()),
+        }
+    }
 
-impl PieskieoDb {
-    fn filter_conditions(
-        &self,
-        inner: &BTreeMap<Uuid, Value>,
-        conds: &[Condition],
-        limit: usize,
-        offset: usize,
-    ) -> Vec<(Uuid, Value)> {
-        let mut out = Vec::new();
-        let mut skipped = 0usize;
-        'outer: for (id, v) in inner.iter() {
-            if !self.owns(id) {
-                continue;
+    /// Replace this shard's vectors in `ns` with those in `snapshot` and
+    /// rebuild the namespace's HNSW graph. The vectors dropped and loaded are
+    /// logged to the WAL in one write so restarts and replicas follow, and the
+    /// namespace's snapshot on disk is rewritten to match; edges are not part
+    /// of a snapshot and are left alone. Vectors must match the namespace's
+    /// dimension and may not already live in another namespace. Returns how
+    /// many vectors were loaded.
+    pub fn import_vectors_ns(&self, ns: &str, snapshot: &[u8]) -> Result<usize> {
+        let entries = decode_snapshot(snapshot)?;
+        if entries.iter().any(|(id, ..)| !self.owns(id)) {
+            return Err(PieskieoError::WrongShard);
+        }
+        let dim = self
+            .vectors
+            .read()
+            .get(ns)
+            .and_then(|idx| *idx.dim.read())
+            .or_else(|| entries.first().map(|(_, v, _)| v.len()));
+        if let Some((id, v, _)) = entries
+            .iter()
+            .find(|(_, v, _)| v.is_empty() || Some(v.len()) != dim)
+        {
+            return Err(PieskieoError::Validation(format!(
+                "vector {id} has {} dimensions, namespace '{ns}' expects {}",
+                v.len(),
+                dim.unwrap_or_default()
+            )));
+        }
+        {
+            let owners = self.vector_ns.read();
+            let elsewhere = entries.iter().find_map(|(id, ..)| {
+                owners
+                    .get(id)
+                    .filter(|other| other.as_str() != ns)
+                    .map(|other| (id, other))
+            });
+            if let Some((id, other)) = elsewhere {
+                return Err(PieskieoError::Validation(format!(
+                    "vector {id} already exists in namespace '{other}'"
+                )));
             }
-            for c in conds {
-                let Some(field_val) = v.get(&c.field) else {
-                    continue 'outer;
-                };
-                let pass = match c.op {
-                    Op::Eq => field_val == &c.value,
-                    Op::Ne => field_val != &c.value,
-                    Op::Gt => cmp_values(field_val, &c.value)
-                        .map(|o| o.is_gt())
-                        .unwrap_or(false),
-                    Op::Gte => cmp_values(field_val, &c.value)
-                        .map(|o| o.is_ge())
-                        .unwrap_or(false),
-                    Op::Lt => cmp_values(field_val, &c.value)
-                        .map(|o| o.is_lt())
-                        .unwrap_or(false),
-                    Op::Lte => cmp_values(field_val, &c.value)
-                        .map(|o| o.is_le())
-                        .unwrap_or(false),
-                    Op::In => c
-                        .value
-                        .as_array()
-                        .map(|arr| arr.iter().any(|x| x == field_val))
-                        .unwrap_or(false),
-                    Op::Nin => c
-                        .value
-                        .as_array()
-                        .map(|arr| arr.iter().all(|x| x != field_val))
-                        .unwrap_or(false),
-                };
-                if !pass {
-                    continue 'outer;
-                }
+        }
+        let idx = self.vector_index(ns);
+        let incoming: HashSet<Uuid> = entries.iter().map(|(id, ..)| *id).collect();
+        let dropped: Vec<Uuid> = idx
+            .inner
+            .read()
+            .keys()
+            .filter(|id| !incoming.contains(id))
+            .copied()
+            .collect();
+        let mut records: Vec<RecordKind> = dropped
+            .iter()
+            .map(|id| RecordKind::Delete {
+                family: DataFamily::Vec,
+                key: *id,
+                namespace: Some(ns.to_string()),
+                collection: None,
+                table: None,
+            })
+            .collect();
+        for (id, vector, meta) in &entries {
+            let payload = VecWalRecord {
+                namespace: Some(ns.to_string()),
+                vector: vector.clone(),
+                meta: meta.clone(),
             }
-            if skipped < offset {
-                skipped += 1;
-                continue;
+            .encode()?;
+            records.push(RecordKind::Put {
+                family: DataFamily::Vec,
+                key: *id,
+                payload,
+                namespace: Some(ns.to_string()),
+                collection: None,
+                table: None,
+            });
+        }
+        self.append_records(&records)?;
+        idx.load_snapshot_bytes(snapshot)?;
+        idx.rebuild_hnsw()?;
+        // open() loads snapshots after the WAL, so an older one left on disk
+        // would undo the import on restart
+        let snap_dir = self.path.join("vectors");
+        std::fs::create_dir_all(&snap_dir)?;
+        Self::save_ns_snapshot(&snap_dir, ns, &idx)?;
+        {
+            let mut owners = self.vector_ns.write();
+            for id in &dropped {
+                owners.remove(id);
             }
-            out.push((*id, v.clone()));
-            if out.len() >= limit {
-                break;
+            for id in incoming {
+                owners.insert(id, ns.to_string());
             }
         }
-        out
+        self.writes
+            .vectors
+            .fetch_add(entries.len() as u64, Ordering::Relaxed);
+        Ok(entries.len())
     }
 
-    fn exec_select(&self, stmt: &Statement) -> Result<SqlResult> {
-        let (ns, coll, conds, projections, limit, offset, order_by, join_spec, aggs, target_rows) =
-            self.parse_select(stmt)?;
-        let mut rows = self.collect_filtered_ns(&ns, &coll, target_rows, &conds);
-        if let Some(join) = join_spec {
-            let right =
-                self.collect_filtered_ns(&join.right_ns, &join.right_coll, join.right_is_rows, &[]);
-            let mut joined = Vec::new();
-            for (lid, lv) in &rows {
-                if let Some(lobj) = lv.as_object() {
-                    let lv_on = lobj.get(&join.on_left);
-                    for (_, rv) in &right {
-                        if let Some(robj) = rv.as_object() {
-                            if robj.get(&join.on_right) == lv_on {
-                                let mut merged = serde_json::Map::new();
-                                for (k, v) in lobj {
-                                    merged.insert(k.clone(), v.clone());
-                                }
-                                for (k, v) in robj {
-                                    let key = if merged.contains_key(k) {
-                                        format!("right_{k}")
-                                    } else {
-                                        k.clone()
-                                    };
-                                    merged.insert(key, v.clone());
-                                }
-                                joined.push((*lid, Value::Object(merged)));
-                            }
-                        }
-                    }
-                }
-            }
-            rows = joined;
+    pub fn set_ef_search(&self, ef: usize) {
+        for idx in self.vectors.read().values() {
+            idx.set_ef_search(ef);
         }
+    }
 
-        if !order_by.is_empty() {
-            rows.sort_by(|a, b| {
-                for (field, asc) in order_by.iter() {
-                    let av = a.1.get(field);
-                    let bv = b.1.get(field);
-                    let ord = match (av, bv) {
-                        (Some(x), Some(y)) => cmp_values(x, y).unwrap_or(std::cmp::Ordering::Equal),
-                        (Some(_), None) => std::cmp::Ordering::Greater,
-                        (None, Some(_)) => std::cmp::Ordering::Less,
-                        _ => std::cmp::Ordering::Equal,
-                    };
-                    if ord != std::cmp::Ordering::Equal {
-                        return if *asc { ord } else { ord.reverse() };
-                    }
-                }
-                std::cmp::Ordering::Equal
-            });
+    pub fn set_ef_construction(&self, ef: usize) {
+        for idx in self.vectors.read().values() {
+            idx.set_ef_construction(ef);
         }
+    }
 
-        // aggregation (global only, no group by)
-        if !aggs.is_empty() {
-            let mut out_obj = serde_json::Map::new();
-            for agg in aggs {
-                let val = match agg.kind {
-                    AggKind::Count => Value::Number((rows.len() as u64).into()),
-                    AggKind::Sum => {
-                        let nums = Self::collect_nums(&rows, agg.field.as_deref().unwrap_or(""));
-                        let sum: f64 = nums.iter().sum();
-                        Self::num_or_null(sum)
-                    }
-                    AggKind::Avg => {
-                        let nums = Self::collect_nums(&rows, agg.field.as_deref().unwrap_or(""));
-                        if nums.is_empty() {
-                            Value::Null
-                        } else {
-                            let avg = nums.iter().sum::<f64>() / nums.len() as f64;
-                            Self::num_or_null(avg)
-                        }
-                    }
-                    AggKind::Min => {
-                        let nums = Self::collect_nums(&rows, agg.field.as_deref().unwrap_or(""));
-                        nums.into_iter()
-                            .fold(None, |acc, v| Some(acc.map_or(v, |a: f64| a.min(v))))
-                            .map(Self::num_or_null)
-                            .unwrap_or(Value::Null)
-                    }
-                    AggKind::Max => {
-                        let nums = Self::collect_nums(&rows, agg.field.as_deref().unwrap_or(""));
-                        nums.into_iter()
-                            .fold(None, |acc, v| Some(acc.map_or(v, |a: f64| a.max(v))))
-                            .map(Self::num_or_null)
-                            .unwrap_or(Value::Null)
-                    }
-                };
-                out_obj.insert(agg.alias, val);
-            }
-            return Ok(SqlResult::Select(vec![(
-                Uuid::nil(),
-                Value::Object(out_obj),
-            )]));
-        }
+    /// Neighbours linked per inserted vector in namespaces without their own
+    /// setting.
+    pub fn set_link_top_k(&self, k: usize) {
+        self.link_top_k.store(k, Ordering::Relaxed);
+    }
 
-        let start = offset.min(rows.len());
-        let end = (start + limit).min(rows.len());
-        let slice = &rows[start..end];
+    /// Overrides `link_top_k` for vectors inserted into `ns`.
+    pub fn set_link_top_k_ns(&self, ns: Option<&str>, k: usize) {
+        self.link_top_k_ns.write().insert(Self::ns(ns), k);
+    }
 
-        let out = if let Some(projs) = projections {
-            let mut projected = Vec::with_capacity(slice.len());
-            for (id, v) in slice {
-                let mut obj = serde_json::Map::new();
-                for p in projs.iter() {
-                    if p.source == "_id" {
-                        obj.insert(p.alias.clone(), Value::String(id.to_string()));
-                    } else if let Some(val) = v.get(&p.source) {
-                        obj.insert(p.alias.clone(), val.clone());
-                    }
-                }
-                projected.push((*id, Value::Object(obj)));
+    /// `link_top_k` in effect for `ns`: its own setting, else the global one.
+    pub fn link_top_k_ns(&self, ns: &str) -> usize {
+        self.link_top_k_ns
+            .read()
+            .get(ns)
+            .copied()
+            .unwrap_or_else(|| self.link_top_k.load(Ordering::Relaxed))
+    }
+
+    pub fn remove_vector_meta_keys(&self, id: Uuid, keys: &[String]) -> Result<()> {
+        let ns = {
+            let map = self.vector_ns.read();
+            map.get(&id).cloned().unwrap_or_else(Self::default_ns)
+        };
+        let idx = self.vector_index(&ns);
+        let (vector, meta) = {
+            let data = idx.inner.read();
+            let meta = idx.meta.read();
+            let Some(vec) = data.get(&id).cloned() else {
+                return Err(PieskieoError::NotFound);
+            };
+            let mut m = meta.get(&id).cloned().unwrap_or_default();
+            for k in keys {
+                m.remove(k);
             }
-            projected
-        } else {
-            slice.to_vec()
+            (vec, m)
         };
-        Ok(SqlResult::Select(out))
+        let payload = VecWalRecord {
+            namespace: Some(ns.clone()),
+            vector: vector.clone(),
+            meta: Some(meta.clone()),
+        }
+        .encode()?;
+        self.append_record(&RecordKind::Put {
+            family: DataFamily::Vec,
+            key: id,
+            payload,
+            namespace: Some(ns.clone()),
+            collection: None,
+            table: None,
+        })?;
+        idx.insert(id, vector, Some(meta))?;
+        Ok(())
     }
 
-    fn collect_filtered_ns(
-        &self,
-        ns: &str,
-        coll: &str,
-        target_rows: bool,
-        conds: &[Condition],
-    ) -> Vec<(Uuid, Value)> {
-        let guard = self.data.read();
-        if target_rows {
-            guard
-                .rows
-                .get(ns)
-                .and_then(|m| m.get(coll))
-                .map(|map| self.filter_conditions(map, conds, usize::MAX, 0))
-                .unwrap_or_default()
-        } else {
-            guard
-                .docs
-                .get(ns)
-                .and_then(|m| m.get(coll))
-                .map(|map| self.filter_conditions(map, conds, usize::MAX, 0))
-                .unwrap_or_default()
+    /// Compact tombstones and WAL by rewriting snapshot and compacting WAL.
+    pub fn vacuum(&self) -> Result<()> {
+        let started = std::time::Instant::now();
+        // drop deleted vectors from in-memory store for each namespace
+        for idx in self.vectors.read().values() {
+            Self::drop_tombstones(idx);
         }
+
+        // rebuild ANN for clean state
+        let _ = self.rebuild_vectors();
+        // persist fresh snapshots + hnsw and compact the WAL
+        self.save_vector_snapshot()?;
+        self.compact_wal(started)
     }
 
-    fn parse_select(
-        &self,
-        stmt: &Statement,
-    ) -> Result<(
-        String,
-        String,
-        Vec<Condition>,
-        Option<Vec<Projection>>,
-        usize,
-        usize,
-        Vec<(String, bool)>,
-        Option<JoinSpec>,
-        Vec<AggExpr>,
-        bool,
-    )> {
-        let (select, query) = match stmt {
-            Statement::Query(q) => match &*q.body {
-                SetExpr::Select(s) => (s.as_ref(), q),
-                _ => return Err(PieskieoError::Internal("only SELECT supported".into())),
-            },
-            _ => return Err(PieskieoError::Internal("only SELECT supported".into())),
+    /// Vacuum a single namespace, leaving other indexes untouched. The WAL is
+    /// only compacted once every namespace is clean; until then just this
+    /// namespace's snapshot is rewritten.
+    pub fn vacuum_ns(&self, ns: Option<&str>) -> Result<()> {
+        let started = std::time::Instant::now();
+        let namespace = Self::ns(ns);
+        let Some(idx) = self.vectors.read().get(&namespace).cloned() else {
+            return Err(PieskieoError::NotFound);
         };
-        // projections: None == wildcard
-        let mut projections: Option<Vec<Projection>> = None;
-        let mut aggs: Vec<AggExpr> = Vec::new();
-        let mut saw_wildcard = false;
-        for item in &select.projection {
-            match item {
-                SelectItem::Wildcard(_) => {
-                    saw_wildcard = true;
-                }
-                SelectItem::UnnamedExpr(Expr::Identifier(id)) => {
-                    projections.get_or_insert_with(Vec::new).push(Projection {
-                        source: id.value.clone(),
-                        alias: id.value.clone(),
-                    });
-                }
-                SelectItem::ExprWithAlias {
-                    expr: Expr::Identifier(id),
-                    alias,
-                } => {
-                    projections.get_or_insert_with(Vec::new).push(Projection {
-                        source: id.value.clone(),
-                        alias: alias.value.clone(),
-                    });
-                }
-                SelectItem::UnnamedExpr(Expr::Function(f)) => {
-                    aggs.push(Self::parse_agg(f, None)?);
-                }
-                SelectItem::ExprWithAlias {
-                    expr: Expr::Function(f),
-                    alias,
-                } => {
-                    aggs.push(Self::parse_agg(f, Some(&alias.value))?);
-                }
-                SelectItem::QualifiedWildcard(name, _) => {
-                    // Handle table.* projections - treat as wildcard for now
-                    tracing::debug!("treating qualified wildcard {:?}.* as wildcard", name);
-                    saw_wildcard = true;
-                }
-                _ => {
-                    return Err(PieskieoError::Internal(
-                        "projection item not supported".into(),
-                    ))
-                }
-            }
-        }
-        if saw_wildcard && projections.is_some() {
-            return Err(PieskieoError::Internal(
-                "mixing * with explicit projections not supported".into(),
-            ));
-        }
-        if saw_wildcard {
-            projections = None;
-        }
-        let tbl = select
-            .from
-            .get(0)
-            .and_then(|t| match &t.relation {
-                TableFactor::Table { name, .. } => Some(name.clone()),
-                _ => None,
-            })
-            .ok_or_else(|| PieskieoError::Internal("FROM required".into()))?;
-        let (family, ns, coll) = self.split_name(&tbl)?;
-        let mut conds = Vec::new();
-        if let Some(selection) = &select.selection {
-            self.walk_expr(selection, &mut conds)?;
-        }
-        let limit = query
-            .limit
-            .as_ref()
-            .and_then(|l| match l {
-                Expr::Value(sqlparser::ast::Value::Number(n, _)) => n.parse().ok(),
-                _ => None,
-            })
-            .unwrap_or(100);
-        let offset = query
-            .offset
-            .as_ref()
-            .and_then(|o| match &o.value {
-                Expr::Value(sqlparser::ast::Value::Number(n, _)) => n.parse().ok(),
-                _ => None,
-            })
-            .unwrap_or(0);
-        let mut order_by: Vec<(String, bool)> = Vec::new();
-        for ob in &query.order_by {
-            order_by.push(self.parse_order_by(ob)?);
+        Self::drop_tombstones(&idx);
+        idx.rebuild_hnsw()?;
+        let all_clean = self
+            .vectors
+            .read()
+            .values()
+            .all(|idx| idx.tombstones.read().is_empty());
+        if all_clean {
+            self.save_vector_snapshot()?;
+            self.compact_wal(started)?;
+        } else {
+            let snap_dir = self.path.join("vectors");
+            std::fs::create_dir_all(&snap_dir)?;
+            Self::save_ns_snapshot(&snap_dir, &namespace, &idx)?;
         }
-        let join_spec = self.parse_join(select)?;
-        // family hint or prefix heuristic
-        let target_rows = match family.as_deref() {
-            Some("rows") | Some("tables") | Some("table") | Some("row") => true,
-            Some("docs") | Some("collections") | Some("doc") => false,
-            _ => {
-                coll.starts_with("rows_") || coll.starts_with("table_") || coll.starts_with("tbl_")
-            }
-        };
-        Ok((
-            ns,
-            coll,
-            conds,
-            projections,
-            limit,
-            offset,
-            order_by,
-            join_spec,
-            aggs,
-            target_rows,
-        ))
+        Ok(())
     }
 
-    fn parse_order_by(&self, ob: &OrderByExpr) -> Result<(String, bool)> {
-        let field = match &ob.expr {
-            Expr::Identifier(id) => id.value.clone(),
-            _ => {
-                return Err(PieskieoError::Internal(
-                    "ORDER BY supports only identifiers".into(),
-                ))
-            }
+    /// Saves the vector snapshot and compacts the WAL without touching
+    /// tombstones, so the next open replays only the records still live.
+    pub fn checkpoint(&self) -> Result<()> {
+        let started = std::time::Instant::now();
+        self.save_vector_snapshot()?;
+        self.compact_wal(started)
+    }
+
+    /// Rewrites the WAL down to the records that still matter (see
+    /// `compact_records`) and records the compaction; `started` is when the
+    /// vacuum that led to it began. The log is read from disk rather than
+    /// rebuilt from memory, and its lock is held throughout, so a write racing
+    /// the vacuum waits and lands in the new log.
+    fn compact_wal(&self, started: std::time::Instant) -> Result<()> {
+        let records = {
+            let mut wal = self.wal.write();
+            wal.flush_sync()?;
+            let records = wal.replay()?;
+            let before = records.len();
+            let kept = compact_records(records)?;
+            wal.rewrite(&kept)?;
+            (before - kept.len()) as u64
         };
-        let asc = ob.asc.unwrap_or(true);
-        Ok((field, asc))
+        let c = &self.compactions;
+        c.runs.fetch_add(1, Ordering::Relaxed);
+        c.records.fetch_add(records, Ordering::Relaxed);
+        c.last_us
+            .store(started.elapsed().as_micros() as u64, Ordering::Relaxed);
+        c.last_ms.store(now_ms(), Ordering::Relaxed);
+        Ok(())
     }
 
-    fn parse_join(&self, select: &Select) -> Result<Option<JoinSpec>> {
-        if select.from.len() != 1 {
-            return Err(PieskieoError::Internal(
-                "only single FROM item supported".into(),
-            ));
+    fn drop_tombstones(idx: &VectorIndex) {
+        let tomb = idx.tombstones.read().clone();
+        if !tomb.is_empty() {
+            let mut inner = idx.inner.write();
+            for id in tomb.keys() {
+                inner.remove(id);
+            }
         }
-        let joins = &select.from[0].joins;
-        if joins.is_empty() {
-            return Ok(None);
+        idx.tombstones.write().clear();
+    }
+
+    pub fn flush_wal(&self) -> Result<()> {
+        let res = self.wal.write().flush_sync();
+        self.wal_healthy
+            .store(res.is_ok(), std::sync::atomic::Ordering::SeqCst);
+        res
+    }
+
+    /// False while the most recent WAL flush has failed.
+    pub fn wal_healthy(&self) -> bool {
+        self.wal_healthy.load(std::sync::atomic::Ordering::SeqCst)
+    }
+
+    /// Bytes written to the WAL buffer that have not been fsynced yet.
+    pub fn wal_pending_bytes(&self) -> u64 {
+        self.wal.read().pending_bytes()
+    }
+
+    /// Id of every stored doc, row and live vector, one entry per record, so an
+    /// id used by both a doc and a vector appears twice.
+    pub fn record_ids(&self) -> Vec<Uuid> {
+        let mut out = Vec::new();
+        {
+            let data = self.data.read();
+            for family in [&data.docs, &data.rows] {
+                for colls in family.values() {
+                    for records in colls.values() {
+                        out.extend(records.keys().copied());
+                    }
+                }
+            }
         }
-        if joins.len() > 1 {
-            return Err(PieskieoError::Internal("only one JOIN supported".into()));
+        for idx in self.vectors.read().values() {
+            out.extend(idx.inner.read().keys().copied());
         }
-        let j = &joins[0];
-        // only INNER JOIN
-        let on = match &j.join_operator {
-            JoinOperator::Inner(constraint) => constraint,
-            _ => return Err(PieskieoError::Internal("only INNER JOIN supported".into())),
-        };
-        let right_name = self.extract_name_from_table_factor(&j.relation)?;
-        let (rfam, rns, rcoll) = self.split_name(right_name)?;
-        let right_is_rows = Self::target_is_rows(rfam.as_deref(), &rcoll);
-        let (on_left, on_right) = match on {
-            JoinConstraint::On(expr) => {
-                if let Expr::BinaryOp {
-                    left,
-                    op: BinaryOperator::Eq,
-                    right,
-                } = expr
-                {
-                    let l = Self::ident_name(left)?;
-                    let r = Self::ident_name(right)?;
-                    (l, r)
-                } else {
-                    return Err(PieskieoError::Internal(
-                        "JOIN ON must be equality of identifiers".into(),
-                    ));
-                }
-            }
-            _ => return Err(PieskieoError::Internal("JOIN requires ON clause".into())),
-        };
-        Ok(Some(JoinSpec {
-            right_ns: rns,
-            right_coll: rcoll,
-            right_is_rows,
-            on_left,
-            on_right,
-        }))
+        out
     }
 
-    fn parse_agg(f: &Function, alias: Option<&str>) -> Result<AggExpr> {
-        let name = f.name.to_string().to_lowercase();
-        let kind = match name.as_str() {
-            "count" => AggKind::Count,
-            "sum" => AggKind::Sum,
-            "avg" => AggKind::Avg,
-            "min" => AggKind::Min,
-            "max" => AggKind::Max,
-            _ => {
-                return Err(PieskieoError::Internal(
-                    "aggregate function not supported".into(),
-                ))
-            }
-        };
-        let mut field: Option<String> = None;
-        if let Some(arg) = f.args.first() {
-            match arg {
-                FunctionArg::Unnamed(FunctionArgExpr::Wildcard) => {
-                    if !matches!(kind, AggKind::Count) {
-                        return Err(PieskieoError::Internal(
-                            "only count(*) supports wildcard".into(),
-                        ));
-                    }
-                }
-                FunctionArg::Unnamed(FunctionArgExpr::Expr(Expr::Identifier(id))) => {
-                    field = Some(id.value.clone());
-                }
-                _ => {
-                    return Err(PieskieoError::Internal(
-                        "aggregate argument not supported".into(),
-                    ))
+    /// Per-namespace live/tombstoned vector counts, sorted by namespace.
+    pub fn vector_ns_stats(&self) -> Vec<VectorNsStats> {
+        let mut out: Vec<VectorNsStats> = self
+            .vectors
+            .read()
+            .iter()
+            .map(|(ns, idx)| {
+                let mem = idx.memory_estimate();
+                VectorNsStats {
+                    namespace: ns.clone(),
+                    vectors: idx.inner.read().len(),
+                    tombstones: idx.tombstones.read().len(),
+                    last_rebuild_us: idx.last_rebuild_us.load(Ordering::Relaxed),
+                    memory_bytes: mem.bytes,
+                    owned_bytes: mem.owned_bytes,
+                    leaked_bytes: mem.leaked_bytes,
                 }
-            }
-        }
-        Ok(AggExpr {
-            alias: alias.unwrap_or(&name).to_string(),
-            field,
-            kind,
-        })
+            })
+            .collect();
+        out.sort_by(|a, b| a.namespace.cmp(&b.namespace));
+        out
     }
 
-    fn collect_nums(rows: &[(Uuid, Value)], field: &str) -> Vec<f64> {
-        rows.iter()
-            .filter_map(|(_, v)| v.get(field))
-            .filter_map(|n| n.as_f64())
-            .collect()
+    pub fn metrics(&self) -> MetricsSnapshot {
+        let mut vectors = 0usize;
+        let mut tomb = 0usize;
+        let mut hnsw_ready = true;
+        let mut ef_search = 0usize;
+        let mut ef_construction = 0usize;
+        for idx in self.vectors.read().values() {
+            vectors += idx.inner.read().len();
+            tomb += idx.tombstones.read().len();
+            hnsw_ready &= idx.hnsw.read().is_some();
+            ef_search = idx.ef_search.load(std::sync::atomic::Ordering::SeqCst);
+            ef_construction = idx
+                .ef_construction
+                .load(std::sync::atomic::Ordering::SeqCst);
+        }
+        MetricsSnapshot {
+            docs: self.data.read().docs.values().map(|m| m.len()).sum(),
+            rows: self.data.read().rows.values().map(|m| m.len()).sum(),
+            vectors,
+            vector_tombstones: tomb,
+            hnsw_ready,
+            ef_search,
+            ef_construction,
+            wal_path: self.path.join("wal.log"),
+            wal_bytes: std::fs::metadata(self.path.join("wal.log"))
+                .map(|m| m.len())
+                .unwrap_or(0),
+            snapshot_mtime: std::fs::metadata(self.path.join("vectors"))
+                .and_then(|m| m.modified())
+                .ok(),
+            link_top_k: self.link_top_k.load(Ordering::Relaxed),
+            shard_id: self.shard_id,
+            shard_total: self.shard_total,
+            docs_written: self.writes.docs.load(Ordering::Relaxed),
+            rows_written: self.writes.rows.load(Ordering::Relaxed),
+            vectors_written: self.writes.vectors.load(Ordering::Relaxed),
+            edges_written: self.writes.edges.load(Ordering::Relaxed),
+            compactions: self.compactions.runs.load(Ordering::Relaxed),
+            wal_records_compacted: self.compactions.records.load(Ordering::Relaxed),
+            last_compaction_ms: self.compactions.last_ms.load(Ordering::Relaxed),
+            last_compaction_us: self.compactions.last_us.load(Ordering::Relaxed),
+        }
     }
 
-    fn num_or_null(x: f64) -> Value {
-        serde_json::Number::from_f64(x)
-            .map(Value::Number)
-            .unwrap_or(Value::Null)
+    fn append_record(&self, record: &RecordKind) -> Result<()> {
+        self.ensure_wal_healthy()?;
+        self.wal.write().append(record)?;
+        self.last_write_ms.store(now_ms(), Ordering::Relaxed);
+        Ok(())
     }
 
-    fn split_name(
-        &self,
-        name: &sqlparser::ast::ObjectName,
-    ) -> Result<(Option<String>, String, String)> {
-        let parts: Vec<String> = name.0.iter().map(|i| i.value.clone()).collect();
-        match parts.len() {
-            3 => Ok((Some(parts[0].clone()), parts[1].clone(), parts[2].clone())),
-            2 => Ok((None, parts[0].clone(), parts[1].clone())),
-            1 => Ok((None, "default".into(), parts[0].clone())),
-            _ => Err(PieskieoError::Internal(
-                "table name must be [family.]ns.coll".into(),
-            )),
+    fn append_records(&self, records: &[RecordKind]) -> Result<()> {
+        if records.is_empty() {
+            return Ok(());
         }
+        self.ensure_wal_healthy()?;
+        self.wal.write().append_batch(records)?;
+        self.last_write_ms.store(now_ms(), Ordering::Relaxed);
+        Ok(())
     }
 
-    fn extract_name_from_table_factor<'a>(
-        &self,
-        tf: &'a TableFactor,
-    ) -> Result<&'a sqlparser::ast::ObjectName> {
-        match tf {
-            TableFactor::Table { name, .. } => Ok(name),
-            _ => Err(PieskieoError::Internal("only base tables supported".into())),
-        }
+    /// Unix ms of the last write logged since open, `None` before the first.
+    pub fn last_write_ms(&self) -> Option<u64> {
+        Some(self.last_write_ms.load(Ordering::Relaxed)).filter(|ms| *ms > 0)
     }
 
-    fn target_is_rows(family: Option<&str>, coll: &str) -> bool {
-        match family {
-            Some("rows") | Some("tables") | Some("table") | Some("row") => true,
-            Some("docs") | Some("collections") | Some("doc") => false,
-            _ => {
-                coll.starts_with("rows_") || coll.starts_with("table_") || coll.starts_with("tbl_")
-            }
-        }
+    /// Whether anything was written since the last vector snapshot began (or
+    /// since open, when the on-disk state was loaded); periodic snapshot
+    /// tasks skip shards that are not.
+    pub fn changed_since_snapshot(&self) -> bool {
+        self.last_write_ms()
+            .is_some_and(|w| w >= self.snapshot_ms.load(Ordering::Relaxed))
     }
 
-    fn walk_expr(&self, expr: &Expr, out: &mut Vec<Condition>) -> Result<()> {
-        match expr {
-            Expr::BinaryOp { left, op, right } => match op {
-                BinaryOperator::And => {
-                    self.walk_expr(left, out)?;
-                    self.walk_expr(right, out)?;
-                    Ok(())
-                }
-                BinaryOperator::Eq
-                | BinaryOperator::NotEq
-                | BinaryOperator::Gt
-                | BinaryOperator::GtEq
-                | BinaryOperator::Lt
-                | BinaryOperator::LtEq => {
-                    let (field, value) = self.extract_field_value(left, right)?;
-                    let op = match op {
-                        BinaryOperator::Eq => Op::Eq,
-                        BinaryOperator::NotEq => Op::Ne,
-                        BinaryOperator::Gt => Op::Gt,
-                        BinaryOperator::GtEq => Op::Gte,
-                        BinaryOperator::Lt => Op::Lt,
-                        BinaryOperator::LtEq => Op::Lte,
-                        _ => unreachable!(),
-                    };
-                    out.push(Condition { field, op, value });
-                    Ok(())
-                }
-                _ => Err(PieskieoError::Internal("operator not supported".into())),
-            },
-            Expr::InList {
-                expr,
-                list,
-                negated,
-                ..
-            } => {
-                let field = Self::ident_name(expr)?;
-                let values: Vec<Value> = list.iter().filter_map(Self::literal_to_value).collect();
-                let op = if *negated { Op::Nin } else { Op::In };
-                out.push(Condition {
-                    field,
-                    op,
-                    value: Value::Array(values),
-                });
-                Ok(())
-            }
-            _ => Err(PieskieoError::Internal("expression not supported".into())),
+    fn ensure_wal_healthy(&self) -> Result<()> {
+        if self.default_params.wal_fail_fast && !self.wal_healthy() {
+            return Err(PieskieoError::Internal(
+                "WAL unhealthy: last flush failed, refusing non-durable write".into(),
+            ));
         }
+        Ok(())
     }
 
-    fn ident_name(expr: &Expr) -> Result<String> {
-        if let Expr::Identifier(id) = expr {
-            Ok(id.value.clone())
-        } else {
-            Err(PieskieoError::Internal("field must be identifier".into()))
-        }
+    pub fn wal_replay_since(&self, offset: u64) -> Result<(Vec<RecordKind>, u64)> {
+        self.wal.read().replay_since(offset)
     }
 
-    fn literal_to_value(expr: &Expr) -> Option<Value> {
-        match expr {
-            Expr::Value(sqlparser::ast::Value::Number(n, _)) => n
-                .parse::<f64>()
-                .ok()
-                .and_then(|f| serde_json::Number::from_f64(f))
-                .map(Value::Number),
-            Expr::Value(sqlparser::ast::Value::SingleQuotedString(s)) => {
-                Some(Value::String(s.clone()))
-            }
-            Expr::Value(sqlparser::ast::Value::Boolean(b)) => Some(Value::Bool(*b)),
-            _ => None,
-        }
+    /// Current WAL head offset without reading any records.
+    pub fn wal_current_offset(&self) -> Result<u64> {
+        self.wal.read().current_offset()
     }
 
-    fn extract_field_value(&self, left: &Expr, right: &Expr) -> Result<(String, Value)> {
-        let field = Self::ident_name(left)?;
-        let value = Self::literal_to_value(right)
-            .ok_or_else(|| PieskieoError::Internal("unsupported literal".into()))?;
-        Ok((field, value))
+    pub fn wal_dump(&self) -> Result<Vec<RecordKind>> {
+        self.wal.read().replay()
     }
-}
 
-impl PieskieoDb {
-    fn filter_map(
-        &self,
-        map: &HashMap<String, HashMap<String, BTreeMap<Uuid, Value>>>,
-        ns: Option<&str>,
-        coll: Option<&str>,
-        filter: &HashMap<String, Value>,
-        limit: usize,
-        offset: usize,
-    ) -> Vec<(Uuid, Value)> {
-        let mut out = Vec::new();
-        let mut skipped = 0usize;
-        match (ns, coll) {
-            (Some(ns), Some(c)) => {
-                if let Some(ns_map) = map.get(ns) {
-                    if let Some(inner) = ns_map.get(c) {
-                        Self::collect_filtered_inner(
-                            self,
-                            inner,
-                            filter,
-                            limit,
-                            offset,
-                            &mut out,
-                            &mut skipped,
-                        );
-                    }
-                }
-            }
-            (Some(ns), None) => {
-                if let Some(ns_map) = map.get(ns) {
-                    for inner in ns_map.values() {
-                        Self::collect_filtered_inner(
-                            self,
-                            inner,
-                            filter,
-                            limit,
-                            offset,
-                            &mut out,
-                            &mut skipped,
-                        );
-                        if out.len() >= limit {
-                            break;
-                        }
-                    }
-                }
-            }
-            (None, Some(c)) => {
-                for ns_map in map.values() {
-                    if let Some(inner) = ns_map.get(c) {
-                        Self::collect_filtered_inner(
-                            self,
-                            inner,
-                            filter,
-                            limit,
-                            offset,
-                            &mut out,
-                            &mut skipped,
-                        );
-                        if out.len() >= limit {
-                            break;
+    pub fn apply_records(&self, records: &[RecordKind]) -> Result<()> {
+        for rec in records {
+            self.append_record(rec)?;
+            self.apply_record(rec)?;
+        }
+        Ok(())
+    }
+
+    fn apply_record(&self, rec: &RecordKind) -> Result<()> {
+        match rec {
+            RecordKind::Put {
+                family,
+                key,
+                payload,
+                namespace,
+                collection,
+                table,
+            } => match family {
+                DataFamily::Doc => {
+                    let ns = namespace.clone().unwrap_or_else(Self::default_ns);
+                    let col = collection.clone().unwrap_or_else(Self::default_ns);
+                    let v: Value = serde_json::from_slice(payload)?;
+                    let mut guard = self.data.write();
+                    guard
+                        .docs
+                        .entry(ns.clone())
+                        .or_default()
+                        .entry(col.clone())
+                        .or_default()
+                        .insert(*key, v.clone());
+                    Self::index_upsert_doc(&mut guard, ns, col, *key, &v);
+                }
+                DataFamily::Row => {
+                    let ns = namespace.clone().unwrap_or_else(Self::default_ns);
+                    let tbl = table.clone().unwrap_or_else(Self::default_ns);
+                    let v: Value = serde_json::from_slice(payload)?;
+                    let mut guard = self.data.write();
+                    guard
+                        .rows
+                        .entry(ns.clone())
+                        .or_default()
+                        .entry(tbl.clone())
+                        .or_default()
+                        .insert(*key, v.clone());
+                    Self::index_upsert_row(&mut guard, ns, tbl, *key, &v);
+                }
+                DataFamily::Vec => match VecWalRecord::decode(payload) {
+                    Ok(rec) => {
+                        let ns = rec.namespace.unwrap_or_else(Self::default_ns);
+                        let idx = self.vector_index(&ns);
+                        idx.insert(*key, rec.vector, rec.meta)?;
+                        self.vector_ns.write().insert(*key, ns);
+                    }
+                    Err(_) => {
+                        let idx = self.vector_index(&Self::default_ns());
+                        idx.insert(*key, Vec::new(), None)?;
+                        self.vector_ns.write().insert(*key, Self::default_ns());
+                    }
+                },
+                DataFamily::Graph => {
+                    if let Ok(edge) = EdgeWire::decode(payload) {
+                        if edge.auto {
+                            self.graph.add_auto_edge(edge.src, edge.dst, edge.weight);
+                        } else {
+                            self.graph.add_edge(edge.src, edge.dst, edge.weight);
                         }
                     }
                 }
-            }
-            (None, None) => {
-                for ns_map in map.values() {
-                    for inner in ns_map.values() {
-                        Self::collect_filtered_inner(
-                            self,
-                            inner,
-                            filter,
-                            limit,
-                            offset,
-                            &mut out,
-                            &mut skipped,
-                        );
-                        if out.len() >= limit {
-                            break;
+            },
+            RecordKind::Delete {
+                family,
+                key,
+                namespace,
+                collection,
+                table,
+            } => match family {
+                DataFamily::Doc => {
+                    let ns = namespace.clone().unwrap_or_else(Self::default_ns);
+                    let col = collection.clone().unwrap_or_else(Self::default_ns);
+                    let mut guard = self.data.write();
+                    if let Some(map) = guard.docs.get_mut(&ns).and_then(|m| m.get_mut(&col)) {
+                        map.remove(key);
+                    }
+                    guard.drop_seq(false, &ns, &col, key);
+                    if let Some(idx) = guard.doc_index.get_mut(&ns).and_then(|m| m.get_mut(&col)) {
+                        for (_field, valmap) in idx.iter_mut() {
+                            for (_v, ids) in valmap.iter_mut() {
+                                ids.retain(|id| id != key);
+                            }
+                        }
+                    }
+                    if let Some(idx) = guard.doc_range.get_mut(&ns).and_then(|m| m.get_mut(&col)) {
+                        for ranges in idx.values_mut() {
+                            for ids in ranges.values_mut() {
+                                ids.retain(|id| id != key);
+                            }
+                        }
+                    }
+                }
+                DataFamily::Row => {
+                    let ns = namespace.clone().unwrap_or_else(Self::default_ns);
+                    let tbl = table.clone().unwrap_or_else(Self::default_ns);
+                    let mut guard = self.data.write();
+                    if let Some(map) = guard.rows.get_mut(&ns).and_then(|m| m.get_mut(&tbl)) {
+                        map.remove(key);
+                    }
+                    guard.drop_seq(true, &ns, &tbl, key);
+                    if let Some(idx) = guard.row_index.get_mut(&ns).and_then(|m| m.get_mut(&tbl)) {
+                        for (_field, valmap) in idx.iter_mut() {
+                            for (_v, ids) in valmap.iter_mut() {
+                                ids.retain(|id| id != key);
+                            }
                         }
                     }
                 }
+                DataFamily::Vec => {
+                    if let Some(ns) = self.vector_ns.read().get(key).cloned() {
+                        let idx = self.vector_index(&ns);
+                        idx.delete(key);
+                        self.vector_ns.write().remove(key);
+                    }
+                }
+                DataFamily::Graph => {
+                    self.graph.remove_node(*key);
+                }
+            },
+            RecordKind::Schema {
+                family,
+                namespace,
+                collection,
+                table,
+                schema,
+            } => {
+                let ns = namespace.clone().unwrap_or_else(Self::default_ns);
+                let def: SchemaDef = serde_json::from_slice(schema)?;
+                match family {
+                    DataFamily::Doc => {
+                        let name = collection.clone().unwrap_or_else(Self::default_ns);
+                        self.data
+                            .write()
+                            .doc_schema
+                            .entry(ns)
+                            .or_default()
+                            .insert(name, def);
+                    }
+                    DataFamily::Row => {
+                        let name = table.clone().unwrap_or_else(Self::default_ns);
+                        self.data
+                            .write()
+                            .row_schema
+                            .entry(ns)
+                            .or_default()
+                            .insert(name, def);
+                    }
+                    _ => {}
+                }
             }
-        };
-        out
+            RecordKind::AddEdge { src, dst, weight } => {
+                self.graph.add_edge(*src, *dst, *weight);
+            }
+            RecordKind::RemoveAutoEdges { node } => {
+                self.graph.remove_auto_edges(*node);
+            }
+        }
+        Ok(())
     }
 
-    fn collect_filtered_inner(
-        &self,
-        inner: &BTreeMap<Uuid, Value>,
-        filter: &HashMap<String, Value>,
-        limit: usize,
-        offset: usize,
-        out: &mut Vec<(Uuid, Value)>,
-        skipped: &mut usize,
-    ) {
-        for (id, v) in inner.iter() {
-            if !self.owns(id) {
-                continue;
+    fn owns(&self, id: &Uuid) -> bool {
+        shard_index(id, self.shard_total) == self.shard_id
+    }
+}
+
+fn value_matches(doc: &Value, filter: &HashMap<String, Value>) -> bool {
+    for (k, v) in filter {
+        if let Some(field) = doc.get(k) {
+            if !value_compare(field, v) {
+                return false;
             }
-            if value_matches(v, filter) {
-                if *skipped < offset {
-                    *skipped += 1;
-                    continue;
+        } else {
+            return false;
+        }
+    }
+    true
+}
+
+pub(crate) fn value_compare(field: &Value, cond: &Value) -> bool {
+    if !cond.is_object() {
+        return field == cond;
+    }
+    let obj = cond.as_object().unwrap();
+    for (op, val) in obj {
+        match op.as_str() {
+            "$gt" => {
+                if !cmp_values(field, val).map(|o| o.is_gt()).unwrap_or(false) {
+                    return false;
                 }
-                if out.len() < limit {
-                    out.push((*id, v.clone()));
-                    if out.len() >= limit {
-                        return;
+            }
+            "$gte" => {
+                if !cmp_values(field, val).map(|o| o.is_ge()).unwrap_or(false) {
+                    return false;
+                }
+            }
+            "$lt" => {
+                if !cmp_values(field, val).map(|o| o.is_lt()).unwrap_or(false) {
+                    return false;
+                }
+            }
+            "$lte" => {
+                if !cmp_values(field, val).map(|o| o.is_le()).unwrap_or(false) {
+                    return false;
+                }
+            }
+            "$ne" => {
+                if field == val {
+                    return false;
+                }
+            }
+            "$in" => {
+                if let Some(arr) = val.as_array() {
+                    if !arr.iter().any(|x| x == field) {
+                        return false;
+                    }
+                } else {
+                    return false;
+                }
+            }
+            "$nin" => {
+                if let Some(arr) = val.as_array() {
+                    if arr.iter().any(|x| x == field) {
+                        return false;
                     }
+                } else {
+                    return false;
                 }
             }
+            "$contains" => {
+                if !field.as_array().is_some_and(|arr| arr.contains(val)) {
+                    return false;
+                }
+            }
+            _ => return false,
         }
     }
+    true
+}
 
-    /// Filter with optional equality index shortcut.
-    fn filter_map_with_index(
+fn cmp_values(a: &Value, b: &Value) -> Option<std::cmp::Ordering> {
+    match (a, b) {
+        (Value::Number(x), Value::Number(y)) => {
+            let xf = x.as_f64()?;
+            let yf = y.as_f64()?;
+            xf.partial_cmp(&yf)
+        }
+        (Value::String(x), Value::String(y)) => Some(x.cmp(y)),
+        (Value::Bool(x), Value::Bool(y)) => Some(x.cmp(y)),
+        _ => None,
+    }
+}
+
+/// Drop null-valued keys from objects, recursing into nested objects and arrays.
+/// Array elements keep their positions, so null items inside arrays stay.
+pub fn strip_nulls(value: &mut Value) {
+    match value {
+        Value::Object(map) => {
+            map.retain(|_, v| !v.is_null());
+            map.values_mut().for_each(strip_nulls);
+        }
+        Value::Array(items) => items.iter_mut().for_each(strip_nulls),
+        _ => {}
+    }
+}
+
+/// Apply `patch` to `target`: null deletes a field and anything else replaces
+/// it. With `deep`, objects merge recursively as in JSON merge patch (RFC 7396),
+/// including dropping nulls from newly added objects; a non-object patch
+/// replaces `target` outright.
+pub fn merge_patch(target: &mut Value, patch: Value, deep: bool) {
+    let Value::Object(fields) = patch else {
+        *target = patch;
+        return;
+    };
+    if !target.is_object() {
+        *target = Value::Object(Default::default());
+    }
+    let Value::Object(map) = target else {
+        return;
+    };
+    for (key, value) in fields {
+        if value.is_null() {
+            map.remove(&key);
+        } else if deep {
+            merge_patch(map.entry(key).or_insert(Value::Null), value, true);
+        } else {
+            map.insert(key, value);
+        }
+    }
+}
+
+#[derive(Clone)]
+struct Projection {
+    source: String,
+    alias: String,
+    /// literal emitted verbatim instead of reading `source`
+    constant: Option<Value>,
+    /// `CASE` evaluated per row instead of reading `source`
+    case: Option<CaseExpr>,
+}
+
+/// `CASE WHEN <conds> THEN <literal> ... [ELSE <literal>] END`; the first
+/// branch whose conditions all hold wins, otherwise `else_value` (NULL if absent).
+#[derive(Clone)]
+struct CaseExpr {
+    branches: Vec<(Vec<Condition>, Value)>,
+    else_value: Value,
+}
+
+/// Largest result an `IN (SELECT ...)` subquery may produce.
+const SUBQUERY_MAX_ROWS: usize = 10_000;
+
+/// Pseudo-column naming a record's own key in SQL projections and filters.
+const ID_FIELD: &str = "_id";
+
+/// Pseudo-column for ORDER BY: the record's insertion order on its shard.
+const SEQ_FIELD: &str = "_seq";
+
+#[derive(Clone)]
+struct OrderKey {
+    field: String,
+    asc: bool,
+    nulls_first: bool,
+}
+
+impl OrderKey {
+    /// Orders two sort values, `None` being NULL. Null placement is
+    /// absolute, so it is applied after asc/desc.
+    fn order<T>(
         &self,
-        map: &HashMap<String, HashMap<String, BTreeMap<Uuid, Value>>>,
-        index: &HashMap<String, HashMap<String, HashMap<String, HashMap<String, Vec<Uuid>>>>>,
-        ns: Option<&str>,
-        coll: Option<&str>,
-        filter: &HashMap<String, Value>,
+        a: Option<T>,
+        b: Option<T>,
+        cmp: impl Fn(T, T) -> std::cmp::Ordering,
+    ) -> std::cmp::Ordering {
+        match (a, b) {
+            (Some(x), Some(y)) => {
+                let ord = cmp(x, y);
+                if self.asc {
+                    ord
+                } else {
+                    ord.reverse()
+                }
+            }
+            (Some(_), None) if self.nulls_first => std::cmp::Ordering::Greater,
+            (Some(_), None) => std::cmp::Ordering::Less,
+            (None, Some(_)) if self.nulls_first => std::cmp::Ordering::Less,
+            (None, Some(_)) => std::cmp::Ordering::Greater,
+            _ => std::cmp::Ordering::Equal,
+        }
+    }
+}
+
+#[derive(Clone)]
+struct JoinSpec {
+    right_ns: String,
+    right_coll: String,
+    right_is_rows: bool,
+    on_left: String,
+    on_right: String,
+    /// `(left, right)` column prefixes when either table is aliased; every
+    /// output column is then `prefix.field` and WHERE runs on the joined row
+    prefixes: Option<(String, String)>,
+}
+
+#[derive(Clone)]
+enum AggKind {
+    Count,
+    CountDistinct,
+    Sum,
+    Avg,
+    Min,
+    Max,
+}
+
+#[derive(Clone)]
+struct AggExpr {
+    alias: String,
+    field: Option<String>, // None for count(*)
+    kind: AggKind,
+}
+
+#[derive(Clone)]
+struct Condition {
+    field: String,
+    op: Op,
+    value: Value,
+}
+
+#[derive(Clone)]
+enum Op {
+    Eq,
+    Ne,
+    Gt,
+    Gte,
+    Lt,
+    Lte,
+    In,
+    Nin,
+    /// array field holding the value
+    Contains,
+}
+
+impl PieskieoDb {
+    fn filter_conditions(
+        &self,
+        inner: &BTreeMap<Uuid, Value>,
+        conds: &[Condition],
         limit: usize,
         offset: usize,
-        is_doc: bool,
     ) -> Vec<(Uuid, Value)> {
-        if let (Some(ns), Some(coll)) = (ns, coll) {
-            if let (Some(ns_map), Some(inner)) =
-                (index.get(ns), map.get(ns).and_then(|m| m.get(coll)))
-            {
-                let stats_guard = self.stats.read();
-                let total_rows = if is_doc {
-                    stats_guard
-                        .docs
-                        .get(ns)
-                        .and_then(|m| m.get(coll))
-                        .cloned()
-                        .unwrap_or_else(|| inner.len())
-                } else {
-                    stats_guard
-                        .rows
-                        .get(ns)
-                        .and_then(|m| m.get(coll))
-                        .cloned()
-                        .unwrap_or_else(|| inner.len())
+        let mut out = Vec::new();
+        let mut skipped = 0usize;
+        // `_id = ..` / `_id IN (..)` name the candidates, so skip the scan
+        let picked = conds.iter().find_map(Self::id_candidates);
+        let candidates: Box<dyn Iterator<Item = (&Uuid, &Value)> + '_> = match &picked {
+            Some(ids) => Box::new(ids.iter().filter_map(|id| inner.get_key_value(id))),
+            None => Box::new(inner.iter()),
+        };
+        for (id, v) in candidates {
+            if !self.owns(id) {
+                continue;
+            }
+            if !conds.iter().all(|c| Self::condition_holds(id, v, c)) {
+                continue;
+            }
+            if skipped < offset {
+                skipped += 1;
+                continue;
+            }
+            out.push((*id, v.clone()));
+            if out.len() >= limit {
+                break;
+            }
+        }
+        out
+    }
+
+    /// Ids an `_id` equality or `IN` list allows, in key order; literals that
+    /// are not UUIDs match nothing.
+    fn id_candidates(c: &Condition) -> Option<BTreeSet<Uuid>> {
+        if c.field != ID_FIELD {
+            return None;
+        }
+        let values = match (&c.op, &c.value) {
+            (Op::Eq, v) => std::slice::from_ref(v),
+            (Op::In, Value::Array(vs)) => vs.as_slice(),
+            _ => return None,
+        };
+        Some(values.iter().filter_map(Self::value_uuid).collect())
+    }
+
+    fn value_uuid(v: &Value) -> Option<Uuid> {
+        v.as_str().and_then(|s| Uuid::parse_str(s).ok())
+    }
+
+    /// A missing field fails every operator, including `!=` and `NOT IN`.
+    /// `_id` is the record's key, whatever the body holds under that name;
+    /// (in)equality parses the literals as UUIDs, so any spelling matches.
+    fn condition_holds(id: &Uuid, v: &Value, c: &Condition) -> bool {
+        if c.field == ID_FIELD {
+            let is_id = |x: &Value| Self::value_uuid(x) == Some(*id);
+            match c.op {
+                Op::Eq => return is_id(&c.value),
+                Op::Ne => return !is_id(&c.value),
+                Op::In => return c.value.as_array().is_some_and(|arr| arr.iter().any(is_id)),
+                Op::Nin => return c.value.as_array().is_some_and(|arr| !arr.iter().any(is_id)),
+                _ => {}
+            }
+        }
+        let key;
+        let field_val = if c.field == ID_FIELD {
+            key = Value::String(id.to_string());
+            &key
+        } else {
+            let Some(field_val) = v.get(&c.field) else {
+                return false;
+            };
+            field_val
+        };
+        match c.op {
+            Op::Eq => field_val == &c.value,
+            Op::Ne => field_val != &c.value,
+            Op::Gt => cmp_values(field_val, &c.value)
+                .map(|o| o.is_gt())
+                .unwrap_or(false),
+            Op::Gte => cmp_values(field_val, &c.value)
+                .map(|o| o.is_ge())
+                .unwrap_or(false),
+            Op::Lt => cmp_values(field_val, &c.value)
+                .map(|o| o.is_lt())
+                .unwrap_or(false),
+            Op::Lte => cmp_values(field_val, &c.value)
+                .map(|o| o.is_le())
+                .unwrap_or(false),
+            Op::In => c
+                .value
+                .as_array()
+                .map(|arr| arr.iter().any(|x| x == field_val))
+                .unwrap_or(false),
+            Op::Nin => c
+                .value
+                .as_array()
+                .map(|arr| arr.iter().all(|x| x != field_val))
+                .unwrap_or(false),
+            Op::Contains => field_val
+                .as_array()
+                .is_some_and(|arr| arr.contains(&c.value)),
+        }
+    }
+
+    /// Insertion sequence of each of `rows` in `ns.coll`, for ORDER BY _seq.
+    fn insertion_seqs(
+        &self,
+        target_rows: bool,
+        ns: &str,
+        coll: &str,
+        rows: &[(Uuid, Value)],
+    ) -> HashMap<Uuid, u64> {
+        let guard = self.data.read();
+        let seqs = if target_rows {
+            &guard.row_seq
+        } else {
+            &guard.doc_seq
+        };
+        let Some(seqs) = seqs.get(ns).and_then(|m| m.get(coll)) else {
+            return HashMap::new();
+        };
+        rows.iter()
+            .filter_map(|(id, _)| Some((*id, *seqs.get(id)?)))
+            .collect()
+    }
+
+    fn exec_select(&self, stmt: &Statement) -> Result<SqlResult> {
+        let (ns, coll, conds, projections, limit, offset, order_by, join_spec, aggs, target_rows) =
+            self.parse_select(stmt)?;
+        let qualified = join_spec.as_ref().is_some_and(|j| j.prefixes.is_some());
+        let left_conds: &[Condition] = if qualified { &[] } else { &conds };
+        let mut rows = self.collect_filtered_ns(&ns, &coll, target_rows, left_conds);
+        if let Some(join) = join_spec {
+            let right =
+                self.collect_filtered_ns(&join.right_ns, &join.right_coll, join.right_is_rows, &[]);
+            let mut joined = Vec::new();
+            for (lid, lv) in &rows {
+                if let Some(lobj) = lv.as_object() {
+                    let lv_on = lobj.get(&join.on_left);
+                    for (_, rv) in &right {
+                        if let Some(robj) = rv.as_object() {
+                            if robj.get(&join.on_right) == lv_on {
+                                let mut merged = serde_json::Map::new();
+                                if let Some((lp, rp)) = &join.prefixes {
+                                    for (k, v) in lobj {
+                                        merged.insert(format!("{lp}.{k}"), v.clone());
+                                    }
+                                    for (k, v) in robj {
+                                        merged.insert(format!("{rp}.{k}"), v.clone());
+                                    }
+                                } else {
+                                    for (k, v) in lobj {
+                                        merged.insert(k.clone(), v.clone());
+                                    }
+                                    for (k, v) in robj {
+                                        let key = if merged.contains_key(k) {
+                                            format!("right_{k}")
+                                        } else {
+                                            k.clone()
+                                        };
+                                        merged.insert(key, v.clone());
+                                    }
+                                }
+                                joined.push((*lid, Value::Object(merged)));
+                            }
+                        }
+                    }
+                }
+            }
+            if qualified {
+                joined.retain(|(id, v)| conds.iter().all(|c| Self::condition_holds(id, v, c)));
+            }
+            rows = joined;
+        }
+
+        if !order_by.is_empty() {
+            let seqs = if order_by.iter().any(|k| k.field == SEQ_FIELD) {
+                self.insertion_seqs(target_rows, &ns, &coll, &rows)
+            } else {
+                HashMap::new()
+            };
+            rows.sort_by(|a, b| {
+                for key in order_by.iter() {
+                    let ord = if key.field == SEQ_FIELD {
+                        key.order(seqs.get(&a.0), seqs.get(&b.0), |x, y| x.cmp(y))
+                    } else {
+                        // missing fields and JSON null both count as NULL
+                        let av = a.1.get(&key.field).filter(|v| !v.is_null());
+                        let bv = b.1.get(&key.field).filter(|v| !v.is_null());
+                        key.order(av, bv, |x, y| {
+                            cmp_values(x, y).unwrap_or(std::cmp::Ordering::Equal)
+                        })
+                    };
+                    if ord != std::cmp::Ordering::Equal {
+                        return ord;
+                    }
+                }
+                std::cmp::Ordering::Equal
+            });
+        }
+
+        // aggregation (global only, no group by)
+        if !aggs.is_empty() {
+            let mut out_obj = serde_json::Map::new();
+            for agg in aggs {
+                let val = match agg.kind {
+                    AggKind::Count => Value::Number((rows.len() as u64).into()),
+                    AggKind::CountDistinct => {
+                        let field = agg.field.as_deref().unwrap_or("");
+                        let distinct: HashSet<String> = rows
+                            .iter()
+                            .filter_map(|(_, v)| v.get(field).filter(|x| !x.is_null()))
+                            .map(|x| x.to_string())
+                            .collect();
+                        Value::Number((distinct.len() as u64).into())
+                    }
+                    AggKind::Sum => {
+                        let field = agg.field.as_deref().unwrap_or("");
+                        Self::collect_ints(&rows, field)
+                            .and_then(|ints| Self::exact_int(ints.iter().sum()))
+                            .unwrap_or_else(|| {
+                                let sum: f64 = Self::collect_nums(&rows, field).iter().sum();
+                                Self::num_or_null(sum)
+                            })
+                    }
+                    AggKind::Avg => {
+                        let nums = Self::collect_nums(&rows, agg.field.as_deref().unwrap_or(""));
+                        if nums.is_empty() {
+                            Value::Null
+                        } else {
+                            let avg = nums.iter().sum::<f64>() / nums.len() as f64;
+                            Self::num_or_null(avg)
+                        }
+                    }
+                    AggKind::Min => {
+                        let field = agg.field.as_deref().unwrap_or("");
+                        Self::collect_ints(&rows, field)
+                            .and_then(|ints| ints.into_iter().min())
+                            .and_then(Self::exact_int)
+                            .unwrap_or_else(|| {
+                                Self::collect_nums(&rows, field)
+                                    .into_iter()
+                                    .fold(None, |acc, v| Some(acc.map_or(v, |a: f64| a.min(v))))
+                                    .map(Self::num_or_null)
+                                    .unwrap_or(Value::Null)
+                            })
+                    }
+                    AggKind::Max => {
+                        let field = agg.field.as_deref().unwrap_or("");
+                        Self::collect_ints(&rows, field)
+                            .and_then(|ints| ints.into_iter().max())
+                            .and_then(Self::exact_int)
+                            .unwrap_or_else(|| {
+                                Self::collect_nums(&rows, field)
+                                    .into_iter()
+                                    .fold(None, |acc, v| Some(acc.map_or(v, |a: f64| a.max(v))))
+                                    .map(Self::num_or_null)
+                                    .unwrap_or(Value::Null)
+                            })
+                    }
                 };
+                out_obj.insert(agg.alias, val);
+            }
+            return Ok(SqlResult::Select(vec![(
+                Uuid::nil(),
+                Value::Object(out_obj),
+            )]));
+        }
+
+        let start = offset.min(rows.len());
+        let end = (start + limit).min(rows.len());
+        let slice = &rows[start..end];
+
+        let out = if let Some(projs) = projections {
+            let mut projected = Vec::with_capacity(slice.len());
+            for (id, v) in slice {
+                let mut obj = serde_json::Map::new();
+                for p in projs.iter() {
+                    if let Some(c) = &p.constant {
+                        obj.insert(p.alias.clone(), c.clone());
+                    } else if let Some(case) = &p.case {
+                        let val = case
+                            .branches
+                            .iter()
+                            .find(|(conds, _)| {
+                                conds.iter().all(|c| Self::condition_holds(id, v, c))
+                            })
+                            .map(|(_, val)| val)
+                            .unwrap_or(&case.else_value);
+                        obj.insert(p.alias.clone(), val.clone());
+                    } else if p.source == ID_FIELD {
+                        obj.insert(p.alias.clone(), Value::String(id.to_string()));
+                    } else if let Some(val) = v.get(&p.source) {
+                        obj.insert(p.alias.clone(), val.clone());
+                    }
+                }
+                projected.push((*id, Value::Object(obj)));
+            }
+            projected
+        } else {
+            slice.to_vec()
+        };
+        Ok(SqlResult::Select(out))
+    }
+
+    fn collect_filtered_ns(
+        &self,
+        ns: &str,
+        coll: &str,
+        target_rows: bool,
+        conds: &[Condition],
+    ) -> Vec<(Uuid, Value)> {
+        let guard = self.data.read();
+        if target_rows {
+            guard
+                .rows
+                .get(ns)
+                .and_then(|m| m.get(coll))
+                .map(|map| self.filter_conditions(map, conds, usize::MAX, 0))
+                .unwrap_or_default()
+        } else {
+            guard
+                .docs
+                .get(ns)
+                .and_then(|m| m.get(coll))
+                .map(|map| self.filter_conditions(map, conds, usize::MAX, 0))
+                .unwrap_or_default()
+        }
+    }
+
+    fn parse_select(
+        &self,
+        stmt: &Statement,
+    ) -> Result<(
+        String,
+        String,
+        Vec<Condition>,
+        Option<Vec<Projection>>,
+        usize,
+        usize,
+        Vec<OrderKey>,
+        Option<JoinSpec>,
+        Vec<AggExpr>,
+        bool,
+    )> {
+        let (select, query) = match stmt {
+            Statement::Query(q) => match &*q.body {
+                SetExpr::Select(s) => (s.as_ref(), q),
+                _ => return Err(PieskieoError::Internal("only SELECT supported".into())),
+            },
+            _ => return Err(PieskieoError::Internal("only SELECT supported".into())),
+        };
+        // projections: None == wildcard
+        let mut projections: Option<Vec<Projection>> = None;
+        let mut aggs: Vec<AggExpr> = Vec::new();
+        let mut saw_wildcard = false;
+        for item in &select.projection {
+            match item {
+                SelectItem::Wildcard(_) => {
+                    saw_wildcard = true;
+                }
+                SelectItem::UnnamedExpr(
+                    expr @ (Expr::Identifier(_) | Expr::CompoundIdentifier(_)),
+                ) => {
+                    let source = Self::ident_name(expr)?;
+                    projections.get_or_insert_with(Vec::new).push(Projection {
+                        alias: source.clone(),
+                        source,
+                        constant: None,
+                        case: None,
+                    });
+                }
+                SelectItem::ExprWithAlias {
+                    expr: expr @ (Expr::Identifier(_) | Expr::CompoundIdentifier(_)),
+                    alias,
+                } => {
+                    projections.get_or_insert_with(Vec::new).push(Projection {
+                        source: Self::ident_name(expr)?,
+                        alias: alias.value.clone(),
+                        constant: None,
+                        case: None,
+                    });
+                }
+                SelectItem::ExprWithAlias {
+                    expr: expr @ Expr::Value(_),
+                    alias,
+                } => {
+                    let constant = Self::literal_to_value(expr)
+                        .filter(|v| !v.is_null())
+                        .ok_or_else(|| {
+                            PieskieoError::Internal("unsupported constant projection".into())
+                        })?;
+                    projections.get_or_insert_with(Vec::new).push(Projection {
+                        source: String::new(),
+                        alias: alias.value.clone(),
+                        constant: Some(constant),
+                        case: None,
+                    });
+                }
+                SelectItem::UnnamedExpr(expr @ Expr::Case { .. }) => {
+                    projections.get_or_insert_with(Vec::new).push(Projection {
+                        source: String::new(),
+                        alias: "case".into(),
+                        constant: None,
+                        case: Some(self.parse_case(expr)?),
+                    });
+                }
+                SelectItem::ExprWithAlias {
+                    expr: expr @ Expr::Case { .. },
+                    alias,
+                } => {
+                    projections.get_or_insert_with(Vec::new).push(Projection {
+                        source: String::new(),
+                        alias: alias.value.clone(),
+                        constant: None,
+                        case: Some(self.parse_case(expr)?),
+                    });
+                }
+                SelectItem::UnnamedExpr(Expr::Function(f)) => {
+                    aggs.push(Self::parse_agg(f, None)?);
+                }
+                SelectItem::ExprWithAlias {
+                    expr: Expr::Function(f),
+                    alias,
+                } => {
+                    aggs.push(Self::parse_agg(f, Some(&alias.value))?);
+                }
+                SelectItem::QualifiedWildcard(name, _) => {
+                    // Handle table.* projections - treat as wildcard for now
+                    tracing::debug!("treating qualified wildcard {:?}.* as wildcard", name);
+                    saw_wildcard = true;
+                }
+                _ => {
+                    return Err(PieskieoError::Internal(
+                        "projection item not supported".into(),
+                    ))
+                }
+            }
+        }
+        if saw_wildcard && projections.is_some() {
+            return Err(PieskieoError::Internal(
+                "mixing * with explicit projections not supported".into(),
+            ));
+        }
+        if saw_wildcard {
+            projections = None;
+        }
+        let tbl = select
+            .from
+            .get(0)
+            .and_then(|t| match &t.relation {
+                TableFactor::Table { name, .. } => Some(name.clone()),
+                _ => None,
+            })
+            .ok_or_else(|| PieskieoError::Internal("FROM required".into()))?;
+        let (family, ns, coll) = self.split_name(&tbl)?;
+        let mut conds = Vec::new();
+        if let Some(selection) = &select.selection {
+            self.walk_expr(selection, &mut conds)?;
+        }
+        let limit = query
+            .limit
+            .as_ref()
+            .and_then(|l| match l {
+                Expr::Value(sqlparser::ast::Value::Number(n, _)) => n.parse().ok(),
+                _ => None,
+            })
+            .unwrap_or(100);
+        let offset = query
+            .offset
+            .as_ref()
+            .and_then(|o| match &o.value {
+                Expr::Value(sqlparser::ast::Value::Number(n, _)) => n.parse().ok(),
+                _ => None,
+            })
+            .unwrap_or(0);
+        let mut order_by: Vec<OrderKey> = Vec::new();
+        for ob in &query.order_by {
+            order_by.push(self.parse_order_by(ob)?);
+        }
+        let join_spec = self.parse_join(select)?;
+        let target_rows = self.target_is_rows(family.as_deref(), &ns, &coll);
+        Ok((
+            ns,
+            coll,
+            conds,
+            projections,
+            limit,
+            offset,
+            order_by,
+            join_spec,
+            aggs,
+            target_rows,
+        ))
+    }
+
+    /// Both `CASE WHEN cond THEN ..` and `CASE x WHEN v THEN ..` forms; conditions
+    /// use the WHERE subset and results must be literals.
+    fn parse_case(&self, expr: &Expr) -> Result<CaseExpr> {
+        let Expr::Case {
+            operand,
+            conditions,
+            results,
+            else_result,
+        } = expr
+        else {
+            return Err(PieskieoError::Internal("expected CASE".into()));
+        };
+        let literal = |e: &Expr| {
+            Self::literal_to_value(e)
+                .ok_or_else(|| PieskieoError::Internal("CASE values must be literals".into()))
+        };
+        let operand = operand.as_deref().map(Self::ident_name).transpose()?;
+        let mut branches = Vec::with_capacity(conditions.len());
+        for (cond, result) in conditions.iter().zip(results) {
+            let mut conds = Vec::new();
+            match &operand {
+                Some(field) => conds.push(Condition {
+                    field: field.clone(),
+                    op: Op::Eq,
+                    value: literal(cond)?,
+                }),
+                None => self.walk_expr(cond, &mut conds)?,
+            }
+            branches.push((conds, literal(result)?));
+        }
+        let else_value = else_result.as_deref().map(literal).transpose()?;
+        Ok(CaseExpr {
+            branches,
+            else_value: else_value.unwrap_or(Value::Null),
+        })
+    }
+
+    fn parse_order_by(&self, ob: &OrderByExpr) -> Result<OrderKey> {
+        let field = match &ob.expr {
+            expr @ (Expr::Identifier(_) | Expr::CompoundIdentifier(_)) => Self::ident_name(expr)?,
+            _ => {
+                return Err(PieskieoError::Internal(
+                    "ORDER BY supports only identifiers".into(),
+                ))
+            }
+        };
+        let asc = ob.asc.unwrap_or(true);
+        // Postgres default: NULL sorts as the largest value
+        let nulls_first = ob.nulls_first.unwrap_or(!asc);
+        Ok(OrderKey {
+            field,
+            asc,
+            nulls_first,
+        })
+    }
+
+    fn parse_join(&self, select: &Select) -> Result<Option<JoinSpec>> {
+        if select.from.len() != 1 {
+            return Err(PieskieoError::Internal(
+                "only single FROM item supported".into(),
+            ));
+        }
+        let joins = &select.from[0].joins;
+        if joins.is_empty() {
+            return Ok(None);
+        }
+        if joins.len() > 1 {
+            return Err(PieskieoError::Internal("only one JOIN supported".into()));
+        }
+        let j = &joins[0];
+        // only INNER JOIN
+        let on = match &j.join_operator {
+            JoinOperator::Inner(constraint) => constraint,
+            _ => return Err(PieskieoError::Internal("only INNER JOIN supported".into())),
+        };
+        let right_name = self.extract_name_from_table_factor(&j.relation)?;
+        let (rfam, rns, rcoll) = self.split_name(right_name)?;
+        let right_is_rows = self.target_is_rows(rfam.as_deref(), &rns, &rcoll);
+        let (on_left, on_right) = match on {
+            JoinConstraint::On(expr) => {
+                if let Expr::BinaryOp {
+                    left,
+                    op: BinaryOperator::Eq,
+                    right,
+                } = expr
+                {
+                    (Self::ident_name(left)?, Self::ident_name(right)?)
+                } else {
+                    return Err(PieskieoError::Internal(
+                        "JOIN ON must be equality of identifiers".into(),
+                    ));
+                }
+            }
+            _ => return Err(PieskieoError::Internal("JOIN requires ON clause".into())),
+        };
+        let alias = |tf: &TableFactor| match tf {
+            TableFactor::Table { alias: Some(a), .. } => Some(a.name.value.clone()),
+            _ => None,
+        };
+        let (left_alias, right_alias) = (alias(&select.from[0].relation), alias(&j.relation));
+        if left_alias.is_none() && right_alias.is_none() {
+            return Ok(Some(JoinSpec {
+                right_ns: rns,
+                right_coll: rcoll,
+                right_is_rows,
+                on_left,
+                on_right,
+                prefixes: None,
+            }));
+        }
+        // an unaliased side goes by its collection name
+        let left_name = self.extract_name_from_table_factor(&select.from[0].relation)?;
+        let lp = left_alias.unwrap_or(self.split_name(left_name)?.2);
+        let rp = right_alias.unwrap_or_else(|| rcoll.clone());
+        // ON columns may be qualified and in either order
+        let side = |col: &str| -> Result<(Option<bool>, String)> {
+            match col.split_once('.') {
+                None => Ok((None, col.to_string())),
+                Some((p, field)) if p == lp => Ok((Some(true), field.to_string())),
+                Some((p, field)) if p == rp => Ok((Some(false), field.to_string())),
+                Some((p, _)) => Err(PieskieoError::Internal(format!(
+                    "unknown table alias {p} in JOIN ON"
+                ))),
+            }
+        };
+        let (a, b) = (side(&on_left)?, side(&on_right)?);
+        let (on_left, on_right) = match (a, b) {
+            ((Some(false), r), (_, l)) | ((_, r), (Some(true), l)) => (l, r),
+            ((_, l), (_, r)) => (l, r),
+        };
+        Ok(Some(JoinSpec {
+            right_ns: rns,
+            right_coll: rcoll,
+            right_is_rows,
+            on_left,
+            on_right,
+            prefixes: Some((lp, rp)),
+        }))
+    }
+
+    fn parse_agg(f: &Function, alias: Option<&str>) -> Result<AggExpr> {
+        let name = f.name.to_string().to_lowercase();
+        let kind = match name.as_str() {
+            "count" => AggKind::Count,
+            "sum" => AggKind::Sum,
+            "avg" => AggKind::Avg,
+            "min" => AggKind::Min,
+            "max" => AggKind::Max,
+            _ => {
+                return Err(PieskieoError::Internal(
+                    "aggregate function not supported".into(),
+                ))
+            }
+        };
+        let kind = match (kind, f.distinct) {
+            (AggKind::Count, true) => AggKind::CountDistinct,
+            (_, true) => {
+                return Err(PieskieoError::Internal(
+                    "DISTINCT is only supported in count".into(),
+                ))
+            }
+            (kind, false) => kind,
+        };
+        let mut field: Option<String> = None;
+        if let Some(arg) = f.args.first() {
+            match arg {
+                FunctionArg::Unnamed(FunctionArgExpr::Wildcard) => {
+                    if !matches!(kind, AggKind::Count) {
+                        return Err(PieskieoError::Internal(
+                            "only count(*) supports wildcard".into(),
+                        ));
+                    }
+                }
+                FunctionArg::Unnamed(FunctionArgExpr::Expr(Expr::Identifier(id))) => {
+                    field = Some(id.value.clone());
+                }
+                _ => {
+                    return Err(PieskieoError::Internal(
+                        "aggregate argument not supported".into(),
+                    ))
+                }
+            }
+        }
+        if matches!(kind, AggKind::CountDistinct) && field.is_none() {
+            return Err(PieskieoError::Internal(
+                "count(DISTINCT ...) needs a column".into(),
+            ));
+        }
+        Ok(AggExpr {
+            alias: alias.unwrap_or(&name).to_string(),
+            field,
+            kind,
+        })
+    }
+
+    fn collect_nums(rows: &[(Uuid, Value)], field: &str) -> Vec<f64> {
+        rows.iter()
+            .filter_map(|(_, v)| v.get(field))
+            .filter_map(|n| n.as_f64())
+            .collect()
+    }
+
+    /// Every numeric `field` as an exact integer, or `None` once any of them
+    /// is fractional, so SUM/MIN/MAX over integer columns skip f64 rounding.
+    fn collect_ints(rows: &[(Uuid, Value)], field: &str) -> Option<Vec<i128>> {
+        rows.iter()
+            .filter_map(|(_, v)| v.get(field))
+            .filter(|n| n.is_number())
+            .map(|n| {
+                n.as_i64()
+                    .map(i128::from)
+                    .or_else(|| n.as_u64().map(i128::from))
+            })
+            .collect()
+    }
+
+    fn exact_int(x: i128) -> Option<Value> {
+        i64::try_from(x)
+            .map(Value::from)
+            .or_else(|_| u64::try_from(x).map(Value::from))
+            .ok()
+    }
+
+    fn num_or_null(x: f64) -> Value {
+        serde_json::Number::from_f64(x)
+            .map(Value::Number)
+            .unwrap_or(Value::Null)
+    }
+
+    fn split_name(
+        &self,
+        name: &sqlparser::ast::ObjectName,
+    ) -> Result<(Option<String>, String, String)> {
+        let parts: Vec<String> = name.0.iter().map(|i| i.value.clone()).collect();
+        for part in &parts {
+            Self::check_ident(part)?;
+        }
+        match parts.len() {
+            3 => Ok((Some(parts[0].clone()), parts[1].clone(), parts[2].clone())),
+            2 => Ok((None, parts[0].clone(), parts[1].clone())),
+            1 => Ok((None, "default".into(), parts[0].clone())),
+            _ => Err(PieskieoError::Validation(
+                "table name must be [family.]ns.coll".into(),
+            )),
+        }
+    }
+
+    /// Quoted identifiers can hold anything, including dots that would shift
+    /// the `family.ns.coll` split, so each part is held to a plain charset.
+    fn check_ident(part: &str) -> Result<()> {
+        if part.is_empty() || part.len() > 128 {
+            return Err(PieskieoError::Validation(
+                "name parts must be 1-128 characters".into(),
+            ));
+        }
+        if !part
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+        {
+            return Err(PieskieoError::Validation(format!(
+                "invalid name '{part}': use letters, digits, '_' or '-'"
+            )));
+        }
+        Ok(())
+    }
+
+    /// `DELETE FROM t` parses into `from`; the MySQL `DELETE t FROM ..` form
+    /// names it in `tables`.
+    fn delete_target<'a>(
+        &self,
+        tables: &'a [sqlparser::ast::ObjectName],
+        from: &'a FromTable,
+    ) -> Result<&'a sqlparser::ast::ObjectName> {
+        if let Some(table) = tables.first() {
+            return Ok(table);
+        }
+        let (FromTable::WithFromKeyword(from) | FromTable::WithoutKeyword(from)) = from;
+        let table = from
+            .first()
+            .ok_or_else(|| PieskieoError::Internal("table required".into()))?;
+        self.extract_name_from_table_factor(&table.relation)
+    }
+
+    fn extract_name_from_table_factor<'a>(
+        &self,
+        tf: &'a TableFactor,
+    ) -> Result<&'a sqlparser::ast::ObjectName> {
+        match tf {
+            TableFactor::Table { name, .. } => Ok(name),
+            _ => Err(PieskieoError::Internal("only base tables supported".into())),
+        }
+    }
+
+    /// Explicit family prefix wins. Without one, a schema registered for that
+    /// name decides, so a doc collection called `table_events` with a doc
+    /// schema stays docs; other (or ambiguous) names fall back to the
+    /// `rows_`/`table_`/`tbl_` guess, which can be switched off. Stored data
+    /// is never consulted: it differs between shards, schemas do not.
+    fn target_is_rows(&self, family: Option<&str>, ns: &str, coll: &str) -> bool {
+        match family {
+            Some("rows") | Some("tables") | Some("table") | Some("row") => true,
+            Some("docs") | Some("collections") | Some("doc") => false,
+            _ => {
+                let data = self.data.read();
+                let is_table = data
+                    .row_schema
+                    .get(ns)
+                    .is_some_and(|m| m.contains_key(coll));
+                let is_coll = data
+                    .doc_schema
+                    .get(ns)
+                    .is_some_and(|m| m.contains_key(coll));
+                match (is_table, is_coll) {
+                    (true, false) => true,
+                    (false, true) => false,
+                    _ => {
+                        self.default_params.sql_prefix_family
+                            && (coll.starts_with("rows_")
+                                || coll.starts_with("table_")
+                                || coll.starts_with("tbl_"))
+                    }
+                }
+            }
+        }
+    }
+
+    fn walk_expr(&self, expr: &Expr, out: &mut Vec<Condition>) -> Result<()> {
+        match expr {
+            Expr::BinaryOp { left, op, right } => match op {
+                BinaryOperator::And => {
+                    self.walk_expr(left, out)?;
+                    self.walk_expr(right, out)?;
+                    Ok(())
+                }
+                BinaryOperator::Eq
+                | BinaryOperator::NotEq
+                | BinaryOperator::Gt
+                | BinaryOperator::GtEq
+                | BinaryOperator::Lt
+                | BinaryOperator::LtEq => {
+                    let (field, value) = self.extract_field_value(left, right)?;
+                    let op = match op {
+                        BinaryOperator::Eq => Op::Eq,
+                        BinaryOperator::NotEq => Op::Ne,
+                        BinaryOperator::Gt => Op::Gt,
+                        BinaryOperator::GtEq => Op::Gte,
+                        BinaryOperator::Lt => Op::Lt,
+                        BinaryOperator::LtEq => Op::Lte,
+                        _ => unreachable!(),
+                    };
+                    out.push(Condition { field, op, value });
+                    Ok(())
+                }
+                _ => Err(PieskieoError::Internal("operator not supported".into())),
+            },
+            Expr::InList {
+                expr,
+                list,
+                negated,
+                ..
+            } => {
+                let field = Self::ident_name(expr)?;
+                let values: Vec<Value> = list.iter().filter_map(Self::literal_to_value).collect();
+                let op = if *negated { Op::Nin } else { Op::In };
+                out.push(Condition {
+                    field,
+                    op,
+                    value: Value::Array(values),
+                });
+                Ok(())
+            }
+            Expr::InSubquery {
+                expr,
+                subquery,
+                negated,
+            } => {
+                let field = Self::ident_name(expr)?;
+                let values = self.subquery_values(subquery)?;
+                let op = if *negated { Op::Nin } else { Op::In };
+                out.push(Condition {
+                    field,
+                    op,
+                    value: Value::Array(values),
+                });
+                Ok(())
+            }
+            // 'rust' = ANY(tags)
+            Expr::AnyOp {
+                left,
+                compare_op: BinaryOperator::Eq,
+                right,
+            } => {
+                let field = Self::ident_name(right)?;
+                let value = Self::literal_to_value(left).ok_or_else(|| {
+                    PieskieoError::Internal("ANY expects a literal on the left".into())
+                })?;
+                out.push(Condition {
+                    field,
+                    op: Op::Contains,
+                    value,
+                });
+                Ok(())
+            }
+            // ARRAY_CONTAINS(tags, 'rust')
+            Expr::Function(f) if f.name.to_string().eq_ignore_ascii_case("array_contains") => {
+                let arg = |i: usize| match f.args.get(i) {
+                    Some(FunctionArg::Unnamed(FunctionArgExpr::Expr(e))) => Some(e),
+                    _ => None,
+                };
+                let (2, Some(field), Some(value)) = (
+                    f.args.len(),
+                    arg(0),
+                    arg(1).and_then(Self::literal_to_value),
+                ) else {
+                    return Err(PieskieoError::Internal(
+                        "ARRAY_CONTAINS expects (field, literal)".into(),
+                    ));
+                };
+                out.push(Condition {
+                    field: Self::ident_name(field)?,
+                    op: Op::Contains,
+                    value,
+                });
+                Ok(())
+            }
+            _ => Err(PieskieoError::Internal("expression not supported".into())),
+        }
+    }
+
+    /// Run an `IN (SELECT col ...)` subquery and return the distinct values of
+    /// its single column. Rows missing the column are skipped (NULL never
+    /// matches). The implicit `LIMIT 100` does not apply; instead the result
+    /// is capped at `SUBQUERY_MAX_ROWS` and anything larger is an error.
+    /// Refused on a sharded instance, where this shard only sees its own rows
+    /// and the answer would silently depend on where the data landed.
+    fn subquery_values(&self, subquery: &Query) -> Result<Vec<Value>> {
+        if self.shard_total > 1 {
+            return Err(PieskieoError::Validation(
+                "IN subqueries are not supported with more than one shard".into(),
+            ));
+        }
+        let single_column = match &*subquery.body {
+            SetExpr::Select(s) => {
+                s.projection.len() == 1
+                    && matches!(
+                        s.projection[0],
+                        SelectItem::UnnamedExpr(_) | SelectItem::ExprWithAlias { .. }
+                    )
+            }
+            _ => false,
+        };
+        if !single_column {
+            return Err(PieskieoError::Internal(
+                "IN subquery must select exactly one column".into(),
+            ));
+        }
+        let mut q = subquery.clone();
+        if q.limit.is_none() {
+            q.limit = Some(Expr::Value(sqlparser::ast::Value::Number(
+                (SUBQUERY_MAX_ROWS + 1).to_string(),
+                false,
+            )));
+        }
+        let rows = match self.exec_select(&Statement::Query(Box::new(q)))? {
+            SqlResult::Select(rows) => rows,
+            _ => return Err(PieskieoError::Internal("subquery must be SELECT".into())),
+        };
+        if rows.len() > SUBQUERY_MAX_ROWS {
+            return Err(PieskieoError::Internal(format!(
+                "IN subquery returned more than {SUBQUERY_MAX_ROWS} rows"
+            )));
+        }
+        let mut seen = HashSet::new();
+        Ok(rows
+            .into_iter()
+            .filter_map(|(_, v)| v.as_object().and_then(|o| o.values().next().cloned()))
+            .filter(|v| seen.insert(v.to_string()))
+            .collect())
+    }
+
+    /// `field`, or `alias.field` as one name, which only an aliased JOIN's
+    /// output columns carry.
+    fn ident_name(expr: &Expr) -> Result<String> {
+        match expr {
+            Expr::Identifier(id) => Ok(id.value.clone()),
+            Expr::CompoundIdentifier(parts) if parts.len() == 2 => {
+                Ok(format!("{}.{}", parts[0].value, parts[1].value))
+            }
+            _ => Err(PieskieoError::Internal("field must be identifier".into())),
+        }
+    }
+
+    fn literal_to_value(expr: &Expr) -> Option<Value> {
+        match expr {
+            // keep integer literals integral so equality against JSON ints holds
+            Expr::Value(sqlparser::ast::Value::Number(n, _)) => {
+                if let Ok(i) = n.parse::<i64>() {
+                    Some(Value::Number(i.into()))
+                } else if let Ok(u) = n.parse::<u64>() {
+                    Some(Value::Number(u.into()))
+                } else {
+                    n.parse::<f64>()
+                        .ok()
+                        .and_then(serde_json::Number::from_f64)
+                        .map(Value::Number)
+                }
+            }
+            Expr::Value(sqlparser::ast::Value::SingleQuotedString(s)) => {
+                Some(Value::String(s.clone()))
+            }
+            Expr::Value(sqlparser::ast::Value::Boolean(b)) => Some(Value::Bool(*b)),
+            Expr::Value(sqlparser::ast::Value::Null) => Some(Value::Null),
+            _ => None,
+        }
+    }
+
+    fn extract_field_value(&self, left: &Expr, right: &Expr) -> Result<(String, Value)> {
+        let field = Self::ident_name(left)?;
+        let value = Self::literal_to_value(right)
+            .ok_or_else(|| PieskieoError::Internal("unsupported literal".into()))?;
+        Ok((field, value))
+    }
+}
+
+impl PieskieoDb {
+    fn filter_map(
+        &self,
+        map: &HashMap<String, HashMap<String, BTreeMap<Uuid, Value>>>,
+        ns: Option<&str>,
+        coll: Option<&str>,
+        filter: &HashMap<String, Value>,
+        limit: usize,
+        offset: usize,
+    ) -> (Vec<(Uuid, Value)>, QueryStats) {
+        let mut out = Vec::new();
+        let mut skipped = 0usize;
+        let mut scanned = 0usize;
+        match (ns, coll) {
+            (Some(ns), Some(c)) => {
+                if let Some(ns_map) = map.get(ns) {
+                    if let Some(inner) = ns_map.get(c) {
+                        scanned += Self::collect_filtered_inner(
+                            self,
+                            inner,
+                            filter,
+                            limit,
+                            offset,
+                            &mut out,
+                            &mut skipped,
+                        );
+                    }
+                }
+            }
+            (Some(ns), None) => {
+                if let Some(ns_map) = map.get(ns) {
+                    for inner in ns_map.values() {
+                        scanned += Self::collect_filtered_inner(
+                            self,
+                            inner,
+                            filter,
+                            limit,
+                            offset,
+                            &mut out,
+                            &mut skipped,
+                        );
+                        if out.len() >= limit {
+                            break;
+                        }
+                    }
+                }
+            }
+            (None, Some(c)) => {
+                for ns_map in map.values() {
+                    if let Some(inner) = ns_map.get(c) {
+                        scanned += Self::collect_filtered_inner(
+                            self,
+                            inner,
+                            filter,
+                            limit,
+                            offset,
+                            &mut out,
+                            &mut skipped,
+                        );
+                        if out.len() >= limit {
+                            break;
+                        }
+                    }
+                }
+            }
+            (None, None) => {
+                for ns_map in map.values() {
+                    for inner in ns_map.values() {
+                        scanned += Self::collect_filtered_inner(
+                            self,
+                            inner,
+                            filter,
+                            limit,
+                            offset,
+                            &mut out,
+                            &mut skipped,
+                        );
+                        if out.len() >= limit {
+                            break;
+                        }
+                    }
+                }
+            }
+        };
+        let stats = QueryStats {
+            used_index: false,
+            scanned,
+            matched: skipped + out.len(),
+        };
+        (out, stats)
+    }
+
+    fn count_filtered(
+        &self,
+        map: &HashMap<String, HashMap<String, BTreeMap<Uuid, Value>>>,
+        index: &HashMap<String, HashMap<String, HashMap<String, HashMap<String, Vec<Uuid>>>>>,
+        ns: Option<&str>,
+        coll: Option<&str>,
+        filter: &HashMap<String, Value>,
+    ) -> usize {
+        let count_in = |inner: &BTreeMap<Uuid, Value>| {
+            inner
+                .iter()
+                .filter(|(id, v)| self.owns(id) && value_matches(v, filter))
+                .count()
+        };
+        let (Some(ns), Some(coll)) = (ns, coll) else {
+            return map
+                .iter()
+                .filter(|(n, _)| ns.is_none_or(|ns| ns == n.as_str()))
+                .flat_map(|(_, m)| m.iter())
+                .filter(|(c, _)| coll.is_none_or(|coll| coll == c.as_str()))
+                .map(|(_, inner)| count_in(inner))
+                .sum();
+        };
+        let Some(inner) = map.get(ns).and_then(|m| m.get(coll)) else {
+            return 0;
+        };
+        // even an empty filter counts the map, not `Stats`, which replay and
+        // replica applies leave untouched; as in `filter_map_with_index`: the smallest equality bucket bounds
+        // the candidates, and a value without a bucket matches nothing
+        let coll_index = index.get(ns).and_then(|m| m.get(coll));
+        let mut best: Option<&Vec<Uuid>> = None;
+        for (field, val) in filter {
+            let Some(key) = Self::index_key(val) else {
+                continue;
+            };
+            let ids = coll_index
+                .and_then(|m| m.get(field))
+                .and_then(|m| m.get(&key))
+                .filter(|ids| !ids.is_empty());
+            let Some(ids) = ids else {
+                return 0;
+            };
+            if best.is_none_or(|b| ids.len() < b.len()) {
+                best = Some(ids);
+            }
+        }
+        match best {
+            Some(bucket) => bucket
+                .iter()
+                .filter(|id| self.owns(id))
+                .filter(|id| inner.get(id).is_some_and(|v| value_matches(v, filter)))
+                .count(),
+            None => count_in(inner),
+        }
+    }
+
+    /// Returns how many owned records it examined.
+    fn collect_filtered_inner(
+        &self,
+        inner: &BTreeMap<Uuid, Value>,
+        filter: &HashMap<String, Value>,
+        limit: usize,
+        offset: usize,
+        out: &mut Vec<(Uuid, Value)>,
+        skipped: &mut usize,
+    ) -> usize {
+        let mut scanned = 0;
+        for (id, v) in inner.iter() {
+            if !self.owns(id) {
+                continue;
+            }
+            scanned += 1;
+            if value_matches(v, filter) {
+                if *skipped < offset {
+                    *skipped += 1;
+                    continue;
+                }
+                if out.len() < limit {
+                    out.push((*id, v.clone()));
+                    if out.len() >= limit {
+                        return scanned;
+                    }
+                }
+            }
+        }
+        scanned
+    }
+
+    /// Filter with optional equality index shortcut.
+    fn filter_map_with_index(
+        &self,
+        map: &HashMap<String, HashMap<String, BTreeMap<Uuid, Value>>>,
+        index: &HashMap<String, HashMap<String, HashMap<String, HashMap<String, Vec<Uuid>>>>>,
+        ns: Option<&str>,
+        coll: Option<&str>,
+        filter: &HashMap<String, Value>,
+        limit: usize,
+        offset: usize,
+        is_doc: bool,
+    ) -> (Vec<(Uuid, Value)>, QueryStats) {
+        if let (Some(ns), Some(coll)) = (ns, coll) {
+            if let (Some(ns_map), Some(inner)) =
+                (index.get(ns), map.get(ns).and_then(|m| m.get(coll)))
+            {
+                let stats_guard = self.stats.read();
+                let total_rows = if is_doc {
+                    stats_guard
+                        .docs
+                        .get(ns)
+                        .and_then(|m| m.get(coll))
+                        .cloned()
+                        .unwrap_or_else(|| inner.len())
+                } else {
+                    stats_guard
+                        .rows
+                        .get(ns)
+                        .and_then(|m| m.get(coll))
+                        .cloned()
+                        .unwrap_or_else(|| inner.len())
+                };
+
+                // Every indexed equality predicate has a bucket whose length is
+                // that value's cardinality. The smallest one drives the scan and
+                // `value_matches` checks the rest per candidate, so no bucket is
+                // copied or hashed. The index covers every stored scalar, so a
+                // value without a bucket (or an empty one) cannot match at all.
+                let coll_index = ns_map.get(coll);
+                let mut best: Option<(&String, &Vec<Uuid>)> = None;
+                for (field, val) in filter.iter() {
+                    let Some(key) = Self::index_key(val) else {
+                        continue;
+                    };
+                    let ids = coll_index
+                        .and_then(|m| m.get(field))
+                        .and_then(|m| m.get(&key))
+                        .filter(|ids| !ids.is_empty());
+                    let Some(ids) = ids else {
+                        tracing::debug!(
+                            target: "planner",
+                            namespace=%ns,
+                            collection=%coll,
+                            field=%field,
+                            "equality value not indexed, empty result"
+                        );
+                        let stats = QueryStats {
+                            used_index: true,
+                            ..QueryStats::default()
+                        };
+                        return (Vec::new(), stats);
+                    };
+                    match best {
+                        Some((_, b)) if b.len() <= ids.len() => {}
+                        _ => best = Some((field, ids)),
+                    }
+                }
+
+                if let Some((field, bucket)) = best.filter(|(_, b)| b.len() < total_rows) {
+                    tracing::debug!(
+                        target: "planner",
+                        namespace=%ns,
+                        collection=%coll,
+                        field=%field,
+                        bucket=bucket.len(),
+                        total=total_rows,
+                        "chosen equality index path"
+                    );
+
+                    let mut out = Vec::new();
+                    let mut skipped = 0usize;
+                    let mut scanned = 0usize;
+                    for id in bucket {
+                        if !self.owns(id) {
+                            continue;
+                        }
+                        if let Some(v) = inner.get(id) {
+                            scanned += 1;
+                            if !value_matches(v, filter) {
+                                continue;
+                            }
+                            if skipped < offset {
+                                skipped += 1;
+                                continue;
+                            }
+                            out.push((*id, v.clone()));
+                            if out.len() >= limit {
+                                break;
+                            }
+                        }
+                    }
+                    let stats = QueryStats {
+                        used_index: true,
+                        scanned,
+                        matched: skipped + out.len(),
+                    };
+                    return (out, stats);
+                } else {
+                    tracing::debug!(
+                        target: "planner",
+                        namespace=%ns,
+                        collection=%coll,
+                        total=total_rows,
+                        "no selective index found, full scan"
+                    );
+                }
+            }
+        }
+        self.filter_map(map, ns, coll, filter, limit, offset)
+    }
+
+    fn index_key(v: &Value) -> Option<String> {
+        match v {
+            Value::String(s) => Some(s.clone()),
+            Value::Number(n) => Some(n.to_string()),
+            Value::Bool(b) => Some(b.to_string()),
+            _ => None,
+        }
+    }
+
+    fn index_upsert_doc(colls: &mut Collections, ns: String, col: String, id: Uuid, json: &Value) {
+        colls.assign_seq(false, &ns, &col, id);
+        if let Some(obj) = json.as_object() {
+            for (k, v) in obj {
+                if let Some(key) = Self::index_key(v) {
+                    let entry = colls
+                        .doc_index
+                        .entry(ns.clone())
+                        .or_default()
+                        .entry(col.clone())
+                        .or_default()
+                        .entry(k.clone())
+                        .or_default()
+                        .entry(key)
+                        .or_default();
+                    if !entry.contains(&id) {
+                        entry.push(id);
+                    }
+                }
+                if let Some(n) = v.as_f64() {
+                    let entry = colls
+                        .doc_range
+                        .entry(ns.clone())
+                        .or_default()
+                        .entry(col.clone())
+                        .or_default()
+                        .entry(k.clone())
+                        .or_default()
+                        .entry(RangeKey(n))
+                        .or_default();
+                    if !entry.contains(&id) {
+                        entry.push(id);
+                    }
+                }
+            }
+        }
+    }
+
+    fn index_remove_doc(colls: &mut Collections, ns: String, col: String, id: &Uuid, json: &Value) {
+        colls.drop_seq(false, &ns, &col, id);
+        if let Some(obj) = json.as_object() {
+            for (k, v) in obj {
+                if let Some(key) = Self::index_key(v) {
+                    if let Some(ns_map) = colls.doc_index.get_mut(&ns) {
+                        if let Some(col_map) = ns_map.get_mut(&col) {
+                            if let Some(field_map) = col_map.get_mut(k) {
+                                if let Some(ids) = field_map.get_mut(&key) {
+                                    ids.retain(|x| x != id);
+                                }
+                            }
+                        }
+                    }
+                }
+                if let Some(n) = v.as_f64() {
+                    if let Some(ranges) = colls
+                        .doc_range
+                        .get_mut(&ns)
+                        .and_then(|m| m.get_mut(&col))
+                        .and_then(|m| m.get_mut(k))
+                    {
+                        if let Some(ids) = ranges.get_mut(&RangeKey(n)) {
+                            ids.retain(|x| x != id);
+                            if ids.is_empty() {
+                                ranges.remove(&RangeKey(n));
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    fn index_upsert_row(
+        colls: &mut Collections,
+        ns: String,
+        table: String,
+        id: Uuid,
+        json: &Value,
+    ) {
+        colls.assign_seq(true, &ns, &table, id);
+        if let Some(obj) = json.as_object() {
+            for (k, v) in obj {
+                if let Some(key) = Self::index_key(v) {
+                    let entry = colls
+                        .row_index
+                        .entry(ns.clone())
+                        .or_default()
+                        .entry(table.clone())
+                        .or_default()
+                        .entry(k.clone())
+                        .or_default()
+                        .entry(key)
+                        .or_default();
+                    if !entry.contains(&id) {
+                        entry.push(id);
+                    }
+                }
+            }
+        }
+    }
+
+    fn index_remove_row(
+        colls: &mut Collections,
+        ns: String,
+        table: String,
+        id: &Uuid,
+        json: &Value,
+    ) {
+        colls.drop_seq(true, &ns, &table, id);
+        if let Some(obj) = json.as_object() {
+            for (k, v) in obj {
+                if let Some(key) = Self::index_key(v) {
+                    if let Some(ns_map) = colls.row_index.get_mut(&ns) {
+                        if let Some(tbl_map) = ns_map.get_mut(&table) {
+                            if let Some(field_map) = tbl_map.get_mut(k) {
+                                if let Some(ids) = field_map.get_mut(&key) {
+                                    ids.retain(|x| x != id);
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+#[derive(Clone, Copy)]
+pub struct VectorParams {
+    pub metric: VectorMetric,
+    pub ef_construction: usize,
+    pub ef_search: usize,
+    pub max_elements: usize,
+    pub link_top_k: usize,
+    pub shard_id: usize,
+    pub shard_total: usize,
+    pub link_weight: LinkWeight,
+    /// reject writes while the last WAL flush is failing
+    pub wal_fail_fast: bool,
+    /// indexes with fewer live vectors are searched exactly instead of via HNSW
+    pub exact_below: usize,
+    /// guess rows for unqualified SQL names starting `rows_`/`table_`/`tbl_`
+    pub sql_prefix_family: bool,
+    /// HNSW preallocation for a new namespace; grows towards `max_elements`
+    pub initial_capacity: usize,
+}
+
+/// How auto-link turns a search score into an edge weight. Scores follow the
+/// search metric: similarity for cosine/dot, negated distance for l2.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum LinkWeight {
+    /// `1 / (1 + |score|)`; the original formula, bounded but compresses differences.
+    #[default]
+    Damped,
+    /// The score itself; meaningful for cosine/dot where higher means closer.
+    Similarity,
+    /// `1 / (1 + distance)` reading the score as a negated distance (l2).
+    InverseDistance,
+}
+
+impl LinkWeight {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "damped" => Some(Self::Damped),
+            "similarity" => Some(Self::Similarity),
+            "inverse_distance" => Some(Self::InverseDistance),
+            _ => None,
+        }
+    }
+
+    pub fn weight(self, score: f32) -> f32 {
+        match self {
+            Self::Damped => 1.0 / (1.0 + score.abs()),
+            Self::Similarity => score,
+            Self::InverseDistance => 1.0 / (1.0 + (-score).max(0.0)),
+        }
+    }
+}
+
+pub struct MetricsSnapshot {
+    pub docs: usize,
+    pub rows: usize,
+    pub vectors: usize,
+    pub vector_tombstones: usize,
+    pub hnsw_ready: bool,
+    pub ef_search: usize,
+    pub ef_construction: usize,
+    pub wal_path: std::path::PathBuf,
+    pub wal_bytes: u64,
+    pub snapshot_mtime: Option<std::time::SystemTime>,
+    pub link_top_k: usize,
+    pub shard_id: usize,
+    pub shard_total: usize,
+    /// monotonic write counters since open (see `WriteCounters`)
+    pub docs_written: u64,
+    pub rows_written: u64,
+    pub vectors_written: u64,
+    pub edges_written: u64,
+    /// WAL compactions by vacuum since open and the records they dropped
+    pub compactions: u64,
+    pub wal_records_compacted: u64,
+    /// unix ms / duration of the last compaction; 0 if none ran yet
+    pub last_compaction_ms: u64,
+    pub last_compaction_us: u64,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct VectorNsStats {
+    pub namespace: String,
+    pub vectors: usize,
+    pub tombstones: usize,
+    /// duration of the namespace's last HNSW rebuild; 0 if none ran yet
+    pub last_rebuild_us: u64,
+    /// estimated resident bytes, see `VectorIndex::memory_estimate`
+    pub memory_bytes: u64,
+    /// HNSW backing slices still in use / leaked by earlier graphs
+    pub owned_bytes: u64,
+    pub leaked_bytes: u64,
+}
+
+impl VectorNsStats {
+    /// Share of HNSW slots held by deleted vectors; 0 for an empty index.
+    pub fn tombstone_ratio(&self) -> f64 {
+        let total = self.vectors + self.tombstones;
+        if total == 0 {
+            0.0
+        } else {
+            self.tombstones as f64 / total as f64
+        }
+    }
+}
+
+impl Default for VectorParams {
+    fn default() -> Self {
+        Self {
+            metric: VectorMetric::L2,
+            ef_construction: 200,
+            ef_search: 50,
+            max_elements: 100_000,
+            link_top_k: 0,
+            shard_id: 0,
+            shard_total: 1,
+            link_weight: LinkWeight::default(),
+            wal_fail_fast: true,
+            exact_below: 0,
+            sql_prefix_family: true,
+            initial_capacity: crate::vector::DEFAULT_INITIAL_CAPACITY,
+        }
+    }
+}
+
+impl Drop for PieskieoDb {
+    fn drop(&mut self) {
+        let _ = self.save_vector_snapshot();
+    }
+}
+
+fn now_ms() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+/// Stable id for a client natural key: UUIDv5 of namespace, collection/table and
+/// key under `space`, so re-sending the same key upserts instead of duplicating.
+pub fn key_id(space: &Uuid, ns: Option<&str>, collection: Option<&str>, key: &str) -> Uuid {
+    let name = format!(
+        "{}\0{}\0{}",
+        ns.unwrap_or("default"),
+        collection.unwrap_or("default"),
+        key
+    );
+    Uuid::new_v5(space, name.as_bytes())
+}
+
+/// Shard that owns `id` out of `total`. The single routing rule shared by
+/// `owns` and the server's pool; anything else risks writes landing on a shard
+/// that will never serve them.
+pub fn shard_index(id: &Uuid, total: usize) -> usize {
+    if total <= 1 {
+        return 0;
+    }
+    shard_hash(id) % total
+}
+
+/// FNV-1a over all 16 bytes, so time-ordered (v1/v7) ids spread as evenly as v4.
+fn shard_hash(id: &Uuid) -> usize {
+    const OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+    const PRIME: u64 = 0x0000_0100_0000_01b3;
+    let mut hash = OFFSET;
+    for b in id.as_bytes() {
+        hash ^= u64::from(*b);
+        hash = hash.wrapping_mul(PRIME);
+    }
+    hash as usize
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[tokio::test]
+    async fn round_trip_doc_and_vector() -> Result<()> {
+        let dir = tempdir().unwrap();
+        let db = PieskieoDb::open(dir.path())?;
+        let id = Uuid::new_v4();
+        db.put_doc(id, serde_json::json!({"hello": "world"}))?;
+        db.put_vector(id, vec![0.1, 0.2, 0.3])?;
+
+        assert_eq!(db.get_doc(&id).unwrap()["hello"], "world");
+        let hits = db.search_vector(&[0.1, 0.2, 0.3], 1)?;
+        assert_eq!(hits.len(), 1);
+        assert_eq!(hits[0].id, id);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn remove_node_drops_edges_and_survives_replay() -> Result<()> {
+        let dir = tempdir().unwrap();
+        let a = Uuid::new_v4();
+        let b = Uuid::new_v4();
+        let c = Uuid::new_v4();
+        {
+            let db = PieskieoDb::open(dir.path())?;
+            db.add_edge(a, b, 1.0)?;
+            db.add_edge(b, c, 1.0)?;
+            db.add_edge(c, b, 1.0)?;
+            assert_eq!(db.remove_node(b)?, 3);
+            assert!(db.neighbors(a, 10).is_empty());
+            assert!(db.neighbors(c, 10).is_empty());
+            assert_eq!(db.remove_node(b)?, 0);
+            // incoming edges are found through the reverse index, self-loops once
+            db.add_edge(a, b, 2.0)?;
+            db.add_edge(b, b, 1.0)?;
+            assert_eq!(db.remove_node(b)?, 2);
+            assert_eq!(db.remove_node(b)?, 0);
+
+            db.put_doc(a, serde_json::json!({"k": 1}))?;
+            db.add_edge(a, c, 1.0)?;
+            db.delete_doc(&a)?;
+            assert!(db.neighbors(a, 10).is_empty());
+            db.flush_wal()?;
+        }
+        let db = PieskieoDb::open(dir.path())?;
+        assert!(db.neighbors(a, 10).is_empty());
+        assert!(db.neighbors(b, 10).is_empty());
+        assert!(db.neighbors(c, 10).is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn find_doc_ignores_shard_ownership() -> Result<()> {
+        let dir = tempdir().unwrap();
+        let id = Uuid::new_v4();
+        {
+            let db = PieskieoDb::open(dir.path())?;
+            db.put_doc(id, serde_json::json!({"k": 1}))?;
+            db.flush_wal()?;
+        }
+        // reopen as the shard that does not own `id`, as after a bad reshard
+        let db = PieskieoDb::open_with_params(
+            dir.path(),
+            VectorParams {
+                shard_id: 1 - shard_index(&id, 2),
+                shard_total: 2,
+                ..Default::default()
+            },
+        )?;
+        assert!(db.get_doc(&id).is_none());
+        assert_eq!(db.find_doc_ns(None, None, &id).unwrap()["k"], 1);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn add_edge_updates_weight_in_place() -> Result<()> {
+        let dir = tempdir().unwrap();
+        let a = Uuid::new_v4();
+        let b = Uuid::new_v4();
+        let c = Uuid::new_v4();
+        {
+            let db = PieskieoDb::open(dir.path())?;
+            db.add_edge(a, b, 1.0)?;
+            db.add_edge(a, c, 2.0)?;
+            db.add_edge(a, b, 0.5)?;
+            let edges = db.neighbors(a, 10);
+            assert_eq!(edges.len(), 2);
+            assert_eq!((edges[0].dst, edges[0].weight), (b, 0.5));
+            assert_eq!(db.edge_weight(a, b), Some(0.5));
+            assert_eq!(db.edge_weight(b, a), None);
+            db.flush_wal()?;
+        }
+        // replay applies both records in order; the last weight wins
+        let db = PieskieoDb::open(dir.path())?;
+        assert_eq!(db.neighbors(a, 10).len(), 2);
+        assert_eq!(db.edge_weight(a, b), Some(0.5));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn vector_search_restricted_to_ids() -> Result<()> {
+        let dir = tempdir().unwrap();
+        let db = PieskieoDb::open(dir.path())?;
+        let near = Uuid::new_v4();
+        let far = Uuid::new_v4();
+        db.put_vector(near, vec![1.0, 0.0, 0.0])?;
+        db.put_vector(far, vec![0.0, 9.0, 0.0])?;
+
+        let only_far: HashSet<Uuid> = [far].into_iter().collect();
+        let hits =
+            db.search_vector_ids_ns(None, &[1.0, 0.0, 0.0], 1, VectorMetric::L2, &only_far, None)?;
+        assert_eq!(hits.len(), 1);
+        assert_eq!(hits[0].id, far);
+        Ok(())
+    }
+
+    #[test]
+    fn shard_hash_spreads_time_ordered_ids() {
+        let shards = 4;
+        let n = 8000;
+        let mut buckets = vec![0usize; shards];
+        for i in 0..n as u64 {
+            // v7-style: 48-bit millisecond timestamp up front, random tail
+            let mut bytes = *Uuid::new_v4().as_bytes();
+            bytes[..6].copy_from_slice(&(1_700_000_000_000u64 + i).to_be_bytes()[2..]);
+            buckets[shard_index(&Uuid::from_bytes(bytes), shards)] += 1;
+        }
+        let expected = n / shards;
+        for count in buckets {
+            assert!(
+                count.abs_diff(expected) < expected / 5,
+                "skewed bucket: {count} vs {expected}"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn owns_agrees_with_shard_index() -> Result<()> {
+        let dir = tempdir().unwrap();
+        let total = 3;
+        let mut shards = Vec::new();
+        for i in 0..total {
+            let params = VectorParams {
+                shard_id: i,
+                shard_total: total,
+                ..VectorParams::default()
+            };
+            shards.push(PieskieoDb::open_with_params(
+                dir.path().join(format!("shard{i}")),
+                params,
+            )?);
+        }
+        for _ in 0..200 {
+            let id = Uuid::new_v4();
+            let owner = shard_index(&id, total);
+            for (i, shard) in shards.iter().enumerate() {
+                assert_eq!(shard.owns(&id), i == owner);
+            }
+            assert!(matches!(
+                shards[(owner + 1) % total].put_doc(id, serde_json::json!({})),
+                Err(PieskieoError::WrongShard)
+            ));
+            shards[owner].put_doc(id, serde_json::json!({}))?;
+        }
+        assert_eq!(shard_index(&Uuid::new_v4(), 1), 0);
+        assert_eq!(shard_index(&Uuid::new_v4(), 0), 0);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn graph_neighbors() -> Result<()> {
+        let dir = tempdir().unwrap();
+        let db = PieskieoDb::open(dir.path())?;
+        let a = Uuid::new_v4();
+        let b = Uuid::new_v4();
+        db.add_edge(a, b, 1.0)?;
+        let neighbors = db.neighbors(a, 10);
+        assert_eq!(neighbors.len(), 1);
+        assert_eq!(neighbors[0].dst, b);
+        Ok(())
+    }
+
+    #[test]
+    fn key_id_is_stable_and_scoped() {
+        let space = Uuid::NAMESPACE_OID;
+        let key = "alice@example.com";
+        let a = key_id(&space, None, Some("users"), key);
+        assert_eq!(a, key_id(&space, Some("default"), Some("users"), key));
+        assert_ne!(a, key_id(&space, None, Some("admins"), key));
+        assert_ne!(a, key_id(&Uuid::NAMESPACE_URL, None, Some("users"), key));
+    }
+
+    #[test]
+    fn strip_nulls_is_recursive() {
+        let mut v = serde_json::json!({
+            "a": null,
+            "b": {"c": null, "d": 1},
+            "e": [null, {"f": null}],
+        });
+        strip_nulls(&mut v);
+        assert_eq!(v, serde_json::json!({"b": {"d": 1}, "e": [null, {}]}));
+    }
+
+    #[test]
+    fn link_weight_functions() {
+        assert_eq!(
+            LinkWeight::parse("Inverse_Distance"),
+            Some(LinkWeight::InverseDistance)
+        );
+        assert_eq!(LinkWeight::parse("bogus"), None);
+        assert_eq!(LinkWeight::Damped.weight(-3.0), 0.25);
+        assert_eq!(LinkWeight::Similarity.weight(0.8), 0.8);
+        assert_eq!(LinkWeight::InverseDistance.weight(-1.0), 0.5);
+        // a positive score is not a distance; treat it as touching
+        assert_eq!(LinkWeight::InverseDistance.weight(0.3), 1.0);
+    }
+
+    #[tokio::test]
+    async fn small_index_searched_exactly_below_threshold() -> Result<()> {
+        let dir = tempdir().unwrap();
+        let db = PieskieoDb::open_with_params(
+            dir.path(),
+            VectorParams {
+                exact_below: 100,
+                ..Default::default()
+            },
+        )?;
+        let ids: Vec<Uuid> = (0..10).map(|_| Uuid::new_v4()).collect();
+        for (i, id) in ids.iter().enumerate() {
+            db.put_vector(*id, vec![i as f32, 0.0])?;
+        }
+        let hits = db.search_vector(&[9.0, 0.0], 10)?;
+        let got: Vec<Uuid> = hits.iter().map(|h| h.id).collect();
+        let want: Vec<Uuid> = ids.iter().rev().copied().collect();
+        assert_eq!(got, want);
+        // exact scores are the metric's own: negated squared l2
+        assert_eq!(hits[1].score, -1.0);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn auto_links_neighbors_when_enabled() -> Result<()> {
+        let dir = tempdir().unwrap();
+        let db = PieskieoDb::open_with_params(
+            dir.path(),
+            VectorParams {
+                link_top_k: 1,
+                ..Default::default()
+            },
+        )?;
+        let a = Uuid::new_v4();
+        let b = Uuid::new_v4();
+        db.put_vector(a, vec![0.0, 0.0])?;
+        db.put_vector(b, vec![0.0, 0.1])?;
+
+        let neighbors = db.neighbors(a, 10);
+        assert!(
+            neighbors.iter().any(|e| e.dst == b),
+            "expected auto-linked neighbor"
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn vacuum_clears_tombstones_and_wal() -> Result<()> {
+        let dir = tempdir().unwrap();
+        let db = PieskieoDb::open(dir.path())?;
+        let a = Uuid::new_v4();
+        let b = Uuid::new_v4();
+        db.put_vector(a, vec![0.0, 0.0])?;
+        db.put_vector(b, vec![0.0, 0.1])?;
+        db.delete_vector(&a)?;
+        let idx = db.vector_index("default");
+        assert!(idx.tombstones.read().contains_key(&a));
+        db.flush_wal()?;
+        assert_eq!(db.metrics().compactions, 0);
+        db.vacuum()?;
+        assert!(!idx.tombstones.read().contains_key(&a));
+        let m = db.metrics();
+        assert_eq!(m.compactions, 1);
+        // a's put and delete go, b's put stays
+        assert_eq!(m.wal_records_compacted, 2);
+        assert!(m.last_compaction_ms > 0);
+        assert_eq!(db.wal_replay_since(0)?.0.len(), 1);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn vacuum_keeps_docs_rows_edges_and_schemas() -> Result<()> {
+        let dir = tempdir()?;
+        let (a, b, gone, v) = (
+            Uuid::new_v4(),
+            Uuid::new_v4(),
+            Uuid::new_v4(),
+            Uuid::new_v4(),
+        );
+        {
+            let db = PieskieoDb::open(dir.path())?;
+            let mut fields = HashMap::new();
+            fields.insert(
+                "n".to_string(),
+                SchemaField {
+                    required: true,
+                    unique: false,
+                    r#type: None,
+                },
+            );
+            db.set_doc_schema(None, Some("c"), SchemaDef { fields })?;
+            db.put_doc_ns(None, Some("c"), a, serde_json::json!({"n": 1}))?;
+            db.put_doc_ns(None, Some("c"), b, serde_json::json!({"n": 2}))?;
+            db.put_doc_ns(None, Some("c"), a, serde_json::json!({"n": 3}))?;
+            db.put_doc_ns(None, Some("c"), gone, serde_json::json!({"n": 4}))?;
+            db.delete_doc_ns(None, Some("c"), &gone)?;
+            db.put_row_ns(None, Some("t"), a, &serde_json::json!({"r": 1}))?;
+            db.add_edge(a, b, 0.5)?;
+            db.add_edge(a, gone, 1.0)?;
+            db.remove_node(gone)?;
+            db.put_vector(v, vec![1.0, 0.0])?;
+            db.delete_vector(&v)?;
+            db.vacuum()?;
+            db.flush_wal()?;
+        }
+        let db = PieskieoDb::open(dir.path())?;
+        assert_eq!(
+            db.get_doc_ns(None, Some("c"), &a),
+            Some(serde_json::json!({"n": 3}))
+        );
+        assert!(db.get_doc_ns(None, Some("c"), &gone).is_none());
+        assert!(db.get_row_ns(None, Some("t"), &a).is_some());
+        assert_eq!(db.edge_weight(a, b), Some(0.5));
+        assert_eq!(db.edge_weight(a, gone), None);
+        assert!(!db.has_vector(&v));
+        // the schema came back too: a doc without `n` is still refused
+        assert!(db
+            .put_doc_ns(None, Some("c"), Uuid::new_v4(), serde_json::json!({}))
+            .is_err());
+        // insertion order is kept: `a` was first inserted before `b`
+        match db.query_sql("SELECT * FROM docs.default.c ORDER BY _seq")? {
+            SqlResult::Select(rows) => {
+                assert_eq!(rows.iter().map(|(id, _)| *id).collect::<Vec<_>>(), [a, b]);
+            }
+            _ => panic!("expected select"),
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn vector_ns_stats_report_tombstone_ratio() -> Result<()> {
+        let dir = tempdir().unwrap();
+        let db = PieskieoDb::open(dir.path())?;
+        let ids: Vec<Uuid> = (0..4).map(|_| Uuid::new_v4()).collect();
+        for (i, id) in ids.iter().enumerate() {
+            db.put_vector(*id, vec![i as f32, 0.0])?;
+        }
+        db.delete_vector(&ids[0])?;
+        let stats = db.vector_ns_stats();
+        assert_eq!(stats.len(), 1);
+        assert_eq!((stats[0].vectors, stats[0].tombstones), (3, 1));
+        assert_eq!(stats[0].tombstone_ratio(), 0.25);
+        db.vacuum()?;
+        assert_eq!(db.vector_ns_stats()[0].tombstone_ratio(), 0.0);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn checkpoint_shrinks_wal_and_keeps_data() -> Result<()> {
+        let dir = tempdir()?;
+        let id = Uuid::new_v4();
+        {
+            let db = PieskieoDb::open(dir.path())?;
+            for n in 0..10 {
+                db.put_doc(id, serde_json::json!({"n": n}))?;
+            }
+            db.put_vector(id, vec![1.0, 0.0])?;
+            db.flush_wal()?;
+            let before = db.wal_current_offset()?;
+            db.checkpoint()?;
+            assert!(db.wal_current_offset()? < before);
+            assert_eq!(db.wal_replay_since(0)?.0.len(), 2);
+        }
+        let db = PieskieoDb::open(dir.path())?;
+        assert_eq!(db.get_doc(&id), Some(serde_json::json!({"n": 9})));
+        assert!(db.has_vector(&id));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn vacuum_ns_only_touches_one_namespace() -> Result<()> {
+        let dir = tempdir().unwrap();
+        let db = PieskieoDb::open(dir.path())?;
+        let a = Uuid::new_v4();
+        let b = Uuid::new_v4();
+        db.put_vector_ns(Some("hot"), a, vec![0.0, 0.0])?;
+        db.put_vector_ns(Some("cold"), b, vec![0.0, 0.1])?;
+        db.delete_vector(&a)?;
+        db.delete_vector(&b)?;
+        db.vacuum_ns(Some("hot"))?;
+        db.flush_wal()?;
+        assert!(db.vector_index("hot").tombstones.read().is_empty());
+        assert!(db.vector_index("cold").tombstones.read().contains_key(&b));
+        // cold still dirty, so the WAL must survive
+        assert!(!db.wal_replay_since(0)?.0.is_empty());
+        assert!(matches!(
+            db.vacuum_ns(Some("missing")),
+            Err(PieskieoError::NotFound)
+        ));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn rebuild_dirty_vectors_skips_untouched_namespaces() -> Result<()> {
+        let dir = tempdir().unwrap();
+        let db = PieskieoDb::open(dir.path())?;
+        db.put_vector_ns(Some("hot"), Uuid::new_v4(), vec![0.0, 0.0])?;
+        db.put_vector_ns(Some("cold"), Uuid::new_v4(), vec![0.0, 0.1])?;
+        let mut rebuilt = db.rebuild_dirty_vectors()?;
+        rebuilt.sort();
+        assert_eq!(rebuilt, vec!["cold".to_string(), "hot".to_string()]);
+        assert!(db.vector_ns_stats().iter().all(|s| s.last_rebuild_us > 0));
+        assert!(db.rebuild_dirty_vectors()?.is_empty());
+        db.put_vector_ns(Some("hot"), Uuid::new_v4(), vec![1.0, 0.0])?;
+        assert_eq!(db.dirty_vector_namespaces(), ["hot"]);
+        assert_eq!(db.rebuild_dirty_vectors()?, vec!["hot".to_string()]);
+        db.rebuild_vectors_ns(Some("cold"))?;
+        assert!(matches!(
+            db.rebuild_vectors_ns(Some("missing")),
+            Err(PieskieoError::NotFound)
+        ));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn query_range_pages_by_value_then_id() -> Result<()> {
+        let dir = tempdir().unwrap();
+        let db = PieskieoDb::open(dir.path())?;
+        let mut ids: Vec<Uuid> = (0..4).map(|_| Uuid::new_v4()).collect();
+        ids[1..3].sort();
+        db.put_doc(ids[0], serde_json::json!({"ts": 1}))?;
+        db.put_doc(ids[1], serde_json::json!({"ts": 2.5}))?;
+        db.put_doc(ids[2], serde_json::json!({"ts": 2.5}))?;
+        db.put_doc(ids[3], serde_json::json!({"ts": 9}))?;
+        db.put_doc(Uuid::new_v4(), serde_json::json!({"ts": "late"}))?;
+        let page: Vec<Uuid> = db
+            .query_range(None, None, "ts", None, 2)
+            .into_iter()
+            .map(|(id, _)| id)
+            .collect();
+        assert_eq!(page, ids[..2]);
+        let page: Vec<Uuid> = db
+            .query_range(None, None, "ts", Some((2.5, Some(ids[1]))), 10)
+            .into_iter()
+            .map(|(id, _)| id)
+            .collect();
+        assert_eq!(page, ids[2..]);
+        // moved docs are found only at their new value
+        db.put_doc(ids[0], serde_json::json!({"ts": 10}))?;
+        let page: Vec<Uuid> = db
+            .query_range(None, None, "ts", Some((2.5, None)), 10)
+            .into_iter()
+            .map(|(id, _)| id)
+            .collect();
+        assert_eq!(page, vec![ids[3], ids[0]]);
+        db.delete_doc(&ids[3])?;
+        let rest = db.query_range(None, None, "ts", Some((2.5, None)), 10);
+        assert_eq!(rest.len(), 1);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn write_counters_only_grow() -> Result<()> {
+        let dir = tempdir().unwrap();
+        let db = PieskieoDb::open(dir.path())?;
+        let id = Uuid::new_v4();
+        db.put_doc(id, serde_json::json!({"a": 1}))?;
+        db.put_doc(id, serde_json::json!({"a": 2}))?;
+        db.delete_doc(&id)?;
+        db.put_row(Uuid::new_v4(), &serde_json::json!({"b": 1}))?;
+        db.put_vector(Uuid::new_v4(), vec![0.0, 1.0])?;
+        db.add_edge(Uuid::new_v4(), Uuid::new_v4(), 1.0)?;
+        let m = db.metrics();
+        assert_eq!(m.docs_written, 2);
+        assert_eq!(m.rows_written, 1);
+        assert_eq!(m.vectors_written, 1);
+        assert_eq!(m.edges_written, 1);
+        drop(db);
+        // replayed records are not new writes
+        let db = PieskieoDb::open(dir.path())?;
+        assert_eq!(db.metrics().docs_written, 0);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn sql_case_projection_buckets_rows() -> Result<()> {
+        let dir = tempdir().unwrap();
+        let db = PieskieoDb::open(dir.path())?;
+        db.put_doc(Uuid::new_v4(), serde_json::json!({"name": "a", "age": 30}))?;
+        db.put_doc(Uuid::new_v4(), serde_json::json!({"name": "b", "age": 12}))?;
+        db.put_doc(Uuid::new_v4(), serde_json::json!({"name": "c"}))?;
+        let rows = match db.query_sql(
+            "SELECT name, CASE WHEN age >= 18 THEN 'adult' WHEN age < 18 THEN 'minor' END AS bucket, \
+             CASE name WHEN 'a' THEN 1 ELSE 0 END AS is_a FROM docs.default.default ORDER BY name",
+        )? {
+            SqlResult::Select(r) => r,
+            _ => panic!("expected select"),
+        };
+        let got: Vec<(Value, Value)> = rows
+            .into_iter()
+            .map(|(_, v)| (v["bucket"].clone(), v["is_a"].clone()))
+            .collect();
+        assert_eq!(
+            got,
+            vec![
+                (serde_json::json!("adult"), serde_json::json!(1)),
+                (serde_json::json!("minor"), serde_json::json!(0)),
+                (Value::Null, serde_json::json!(0)),
+            ]
+        );
+        assert!(db
+            .query_sql("SELECT CASE WHEN age > 1 THEN age END AS x FROM docs.default.default")
+            .is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn analyze_sql_reports_target_without_writing() -> Result<()> {
+        let dir = tempdir().unwrap();
+        let db = PieskieoDb::open(dir.path())?;
+        let a = db.analyze_sql("SELECT name FROM docs.app.users WHERE age > 3")?;
+        assert_eq!(a.kind, "select");
+        assert_eq!(a.target, "docs.app.users");
+        assert!(a.is_select);
+        let a = db.analyze_sql("DELETE FROM rows.app.orders WHERE id = 'x'")?;
+        assert_eq!((a.kind, a.target.as_str()), ("delete", "rows.app.orders"));
+        let a = db.analyze_sql("INSERT INTO docs.app.users (name) VALUES ('a')")?;
+        assert!(!a.is_select);
+        let stored = db.query_docs_ns(Some("app"), Some("users"), &HashMap::new(), 10, 0);
+        assert!(stored.is_empty());
+        assert!(db.analyze_sql("SELEC nope").is_err());
+        let or_filter = "SELECT * FROM docs.app.users WHERE a = 1 OR b = 2";
+        assert!(db.analyze_sql(or_filter).is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn put_reports_created_only_for_new_ids() -> Result<()> {
+        let dir = tempdir().unwrap();
+        let db = PieskieoDb::open(dir.path())?;
+        let id = Uuid::new_v4();
+        assert!(db.put_doc(id, serde_json::json!({"a": 1}))?);
+        assert!(!db.put_doc(id, serde_json::json!({"a": 2}))?);
+        // same id in another collection is a separate entity
+        assert!(db.put_doc_ns(None, Some("other"), id, serde_json::json!({}))?);
+        assert!(db.put_row(id, &serde_json::json!({"b": 1}))?);
+        assert!(!db.put_row(id, &serde_json::json!({"b": 2}))?);
+        db.delete_doc(&id)?;
+        assert!(db.put_doc(id, serde_json::json!({"a": 3}))?);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn multi_field_filter_uses_smallest_bucket() -> Result<()> {
+        let dir = tempdir().unwrap();
+        let db = PieskieoDb::open(dir.path())?;
+        let coll = Some("people");
+        let mut target = None;
+        for i in 0..20 {
+            let id = Uuid::new_v4();
+            let city = if i % 2 == 0 { "oslo" } else { "rome" };
+            db.put_doc_ns(None, coll, id, serde_json::json!({"city": city, "age": i}))?;
+            if i == 4 {
+                target = Some(id);
+            }
+        }
+        let filter: HashMap<String, Value> = [
+            ("city".to_string(), serde_json::json!("oslo")),
+            ("age".to_string(), serde_json::json!(4)),
+        ]
+        .into();
+        let hits = db.query_docs_ns(None, coll, &filter, 10, 0);
+        assert_eq!(hits.len(), 1);
+        assert_eq!(Some(hits[0].0), target);
+
+        // a value no doc holds short-circuits to nothing
+        let filter: HashMap<String, Value> = [
+            ("city".to_string(), serde_json::json!("oslo")),
+            ("age".to_string(), serde_json::json!(99)),
+        ]
+        .into();
+        assert!(db.query_docs_ns(None, coll, &filter, 10, 0).is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn drop_namespace_removes_every_family_durably() -> Result<()> {
+        let dir = tempdir().unwrap();
+        let ns = Some("tenant");
+        let (doc, row, vid, kept) = (
+            Uuid::new_v4(),
+            Uuid::new_v4(),
+            Uuid::new_v4(),
+            Uuid::new_v4(),
+        );
+        {
+            let db = PieskieoDb::open(dir.path())?;
+            db.put_doc_ns(ns, Some("people"), doc, serde_json::json!({"a": 1}))?;
+            db.put_row_ns(ns, Some("orders"), row, &serde_json::json!({"b": 2}))?;
+            db.put_vector_ns(ns, vid, vec![0.0, 1.0])?;
+            db.put_doc_ns(Some("other"), None, kept, serde_json::json!({}))?;
+            db.add_edge(doc, kept, 1.0)?;
+            db.save_vector_snapshot()?;
+            let dropped = db.drop_namespace("tenant")?;
+            assert_eq!((dropped.docs, dropped.rows, dropped.vectors), (1, 1, 1));
+            assert!(dropped.edges >= 1);
+            assert!(db.neighbors(doc, 10).is_empty());
+        }
+        let db = PieskieoDb::open(dir.path())?;
+        assert!(db.get_doc_ns(ns, Some("people"), &doc).is_none());
+        assert!(db.get_row_ns(ns, Some("orders"), &row).is_none());
+        assert!(db.get_vector(&vid).is_none());
+        assert!(db.get_doc_ns(Some("other"), None, &kept).is_some());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn vector_meta_keeps_json_types() -> Result<()> {
+        let dir = tempdir().unwrap();
+        let (old, new) = (Uuid::new_v4(), Uuid::new_v4());
+        let meta = |year: i64| -> VectorMeta {
+            [
+                ("year".to_string(), serde_json::json!(year)),
+                ("draft".to_string(), serde_json::json!(false)),
+            ]
+            .into()
+        };
+        let range: HashMap<String, Value> =
+            [("year".to_string(), serde_json::json!({"$gte": 2020}))].into();
+        {
+            let db = PieskieoDb::open(dir.path())?;
+            db.put_vector_with_meta(old, vec![1.0, 0.0], Some(meta(2019)))?;
+            db.put_vector_with_meta(new, vec![0.9, 0.1], Some(meta(2024)))?;
+            let hits = db.search_vector_metric(
+                &[1.0, 0.0],
+                5,
+                VectorMetric::L2,
+                Some(range.clone()),
+                None,
+            )?;
+            assert_eq!(hits.iter().map(|h| h.id).collect::<Vec<_>>(), [new]);
+        }
+        // WAL replay keeps the types
+        let db = PieskieoDb::open(dir.path())?;
+        assert_eq!(db.get_vector(&old).unwrap().1, Some(meta(2019)));
+        let hits = db.search_vector_metric(&[1.0, 0.0], 5, VectorMetric::L2, Some(range), None)?;
+        assert_eq!(hits.len(), 1);
+        // and so does a snapshot
+        db.save_vector_snapshot()?;
+        let idx = VectorIndex::new(VectorMetric::L2);
+        idx.load_snapshot(dir.path().join("vectors").join("default.snapshot"))?;
+        assert_eq!(idx.meta.read().get(&new), Some(&meta(2024)));
+
+        // payloads from before typed metadata decode as strings
+        let legacy: HashMap<String, String> = [("year".to_string(), "2019".to_string())].into();
+        let bytes = bincode::serialize(&(Some("default"), vec![1.0f32], Some(legacy)))?;
+        let rec = VecWalRecord::decode(&bytes)?;
+        assert_eq!(rec.meta.unwrap()["year"], serde_json::json!("2019"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn has_checks_follow_namespace_and_deletes() -> Result<()> {
+        let dir = tempdir().unwrap();
+        let db = PieskieoDb::open(dir.path())?;
+        let id = Uuid::new_v4();
+        db.put_doc_ns(None, Some("people"), id, serde_json::json!({}))?;
+        assert!(db.has_doc_ns(None, Some("people"), &id));
+        assert!(!db.has_doc_ns(None, None, &id));
+        assert!(!db.has_row_ns(None, Some("people"), &id));
+        db.put_vector(id, vec![1.0, 0.0])?;
+        assert!(db.has_vector(&id));
+        db.delete_vector(&id)?;
+        assert!(!db.has_vector(&id));
+        assert!(!db.has_vector(&Uuid::new_v4()));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn batch_applies_all_or_nothing() -> Result<()> {
+        let dir = tempdir().unwrap();
+        let (a, b, vid, gone) = (
+            Uuid::new_v4(),
+            Uuid::new_v4(),
+            Uuid::new_v4(),
+            Uuid::new_v4(),
+        );
+        let put = |id: Uuid, email: &str| BatchOp::PutDoc {
+            namespace: None,
+            collection: Some("users".into()),
+            id,
+            data: serde_json::json!({ "email": email }),
+        };
+        {
+            let db = PieskieoDb::open(dir.path())?;
+            let mut fields = HashMap::new();
+            fields.insert(
+                "email".to_string(),
+                SchemaField {
+                    required: true,
+                    unique: true,
+                    r#type: None,
+                },
+            );
+            db.set_doc_schema(None, Some("users"), SchemaDef { fields })?;
+            db.put_row(gone, &serde_json::json!({"n": 1}))?;
+
+            // a unique clash inside the batch rejects every op
+            let err = db.apply_batch(vec![put(a, "x@y"), put(b, "x@y")]);
+            assert!(matches!(err, Err(PieskieoError::UniqueViolation(_))));
+            // so does a vector that disagrees with the batch's dimension
+            let err = db.apply_batch(vec![
+                put(a, "a@y"),
+                BatchOp::PutVector {
+                    namespace: None,
+                    id: vid,
+                    vector: vec![1.0, 0.0],
+                    meta: None,
+                },
+                BatchOp::PutVector {
+                    namespace: None,
+                    id: b,
+                    vector: vec![1.0],
+                    meta: None,
+                },
+            ]);
+            assert!(matches!(err, Err(PieskieoError::Validation(_))));
+            assert!(db.get_doc_ns(None, Some("users"), &a).is_none());
+            assert!(!db.has_vector(&vid));
+
+            let created = db.apply_batch(vec![
+                put(a, "a@y"),
+                put(b, "b@y"),
+                BatchOp::PutVector {
+                    namespace: None,
+                    id: vid,
+                    vector: vec![1.0, 0.0],
+                    meta: None,
+                },
+                BatchOp::AddEdge {
+                    src: a,
+                    dst: b,
+                    weight: None,
+                },
+                BatchOp::Delete {
+                    family: BatchFamily::Row,
+                    namespace: None,
+                    collection: None,
+                    id: gone,
+                },
+            ])?;
+            assert_eq!(created, [true, true, false, false, false]);
+        }
+        // everything came back from the WAL
+        let db = PieskieoDb::open(dir.path())?;
+        assert!(db.get_doc_ns(None, Some("users"), &b).is_some());
+        assert!(db.has_vector(&vid));
+        assert_eq!(db.edge_weight(a, b), Some(1.0));
+        assert!(db.get_row(&gone).is_none());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn wal_current_offset_matches_replay_end() -> Result<()> {
+        let dir = tempdir().unwrap();
+        let db = PieskieoDb::open(dir.path())?;
+        db.put_doc(Uuid::new_v4(), serde_json::json!({"a": 1}))?;
+        db.flush_wal()?;
+        let head = db.wal_current_offset()?;
+        assert!(head > 0);
+        let (records, end) = db.wal_replay_since(0)?;
+        assert_eq!((records.len(), end), (1, head));
+        // a follower at the head has nothing left to fetch
+        assert!(db.wal_replay_since(head)?.0.is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn sql_names_are_checked_per_part() -> Result<()> {
+        let dir = tempdir().unwrap();
+        let db = PieskieoDb::open(dir.path())?;
+        for sql in [
+            r#"INSERT INTO docs."a.b".c (x) VALUES (1)"#,
+            r#"SELECT * FROM docs.default."bad name""#,
+            "SELECT * FROM a.b.c.d",
+        ] {
+            assert!(
+                matches!(db.query_sql(sql), Err(PieskieoError::Validation(_))),
+                "{sql}"
+            );
+        }
+        assert!(db
+            .query_docs_ns(Some("a.b"), Some("c"), &HashMap::new(), 10, 0)
+            .is_empty());
+        db.query_sql(r#"INSERT INTO docs."tenant-1".people (x) VALUES (1)"#)?;
+        assert_eq!(
+            db.query_docs_ns(Some("tenant-1"), Some("people"), &HashMap::new(), 10, 0)
+                .len(),
+            1
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn re_embedding_replaces_auto_links_only() -> Result<()> {
+        let dir = tempdir().unwrap();
+        let params = VectorParams {
+            link_top_k: 1,
+            ..Default::default()
+        };
+        let (a, b, c, d) = (
+            Uuid::new_v4(),
+            Uuid::new_v4(),
+            Uuid::new_v4(),
+            Uuid::new_v4(),
+        );
+        let check = |db: &PieskieoDb| {
+            assert!(db.edge_weight(b, a).is_none());
+            assert!(db.edge_weight(a, b).is_none());
+            assert!(db.edge_weight(b, c).is_some());
+            assert!(db.edge_weight(c, a).is_some());
+            let user: Vec<_> = db
+                .neighbors(a, 10)
+                .into_iter()
+                .filter(|e| !e.auto)
+                .collect();
+            assert_eq!(user.len(), 1);
+            assert_eq!((user[0].dst, user[0].weight), (d, 2.0));
+        };
+        {
+            let db = PieskieoDb::open_with_params(dir.path(), params)?;
+            db.put_vector(a, vec![1.0, 0.0])?;
+            db.put_vector(c, vec![0.0, 1.0])?;
+            db.put_vector(b, vec![1.0, 0.1])?;
+            assert!(db.neighbors(b, 10).iter().all(|e| e.dst == a && e.auto));
+            db.add_edge(a, d, 2.0)?;
+            // b moves next to c: its links to a go, c's older link to a stays
+            db.put_vector(b, vec![0.1, 1.0])?;
+            check(&db);
+        }
+        let db = PieskieoDb::open_with_params(dir.path(), params)?;
+        check(&db);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn array_contains_in_filters_and_sql() -> Result<()> {
+        let dir = tempdir().unwrap();
+        let db = PieskieoDb::open(dir.path())?;
+        let coll = Some("posts");
+        let tagged = Uuid::new_v4();
+        db.put_doc_ns(
+            None,
+            coll,
+            tagged,
+            serde_json::json!({"tags": ["rust", "db"]}),
+        )?;
+        db.put_doc_ns(
+            None,
+            coll,
+            Uuid::new_v4(),
+            serde_json::json!({"tags": ["go"]}),
+        )?;
+        db.put_doc_ns(
+            None,
+            coll,
+            Uuid::new_v4(),
+            serde_json::json!({"tags": "rust"}),
+        )?;
+
+        let filter: HashMap<String, Value> =
+            [("tags".to_string(), serde_json::json!({"$contains": "rust"}))].into();
+        let hits = db.query_docs_ns(None, coll, &filter, 10, 0);
+        assert_eq!(hits.iter().map(|h| h.0).collect::<Vec<_>>(), [tagged]);
+
+        for sql in [
+            "SELECT * FROM docs.default.posts WHERE ARRAY_CONTAINS(tags, 'rust')",
+            "SELECT * FROM docs.default.posts WHERE 'rust' = ANY(tags)",
+        ] {
+            match db.query_sql(sql)? {
+                SqlResult::Select(rows) => {
+                    assert_eq!(
+                        rows.iter().map(|r| r.0).collect::<Vec<_>>(),
+                        [tagged],
+                        "{sql}"
+                    )
+                }
+                _ => panic!("expected select"),
+            }
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn record_ids_cover_docs_rows_and_vectors() -> Result<()> {
+        let dir = tempdir()?;
+        let db = PieskieoDb::open(dir.path())?;
+        let shared = Uuid::new_v4();
+        let row = Uuid::new_v4();
+        db.put_doc(shared, serde_json::json!({"a": 1}))?;
+        db.put_row(row, &serde_json::json!({"b": 2}))?;
+        db.put_vector(shared, vec![0.1, 0.2])?;
+        let gone = Uuid::new_v4();
+        db.put_vector(gone, vec![0.3, 0.4])?;
+        db.delete_vector(&gone)?;
+
+        let mut ids = db.record_ids();
+        ids.sort();
+        let mut want = vec![shared, shared, row];
+        want.sort();
+        assert_eq!(ids, want);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn graph_components_ignore_direction_and_merge() -> Result<()> {
+        let dir = tempdir()?;
+        let db = PieskieoDb::open(dir.path())?;
+        let ids: Vec<Uuid> = (0..5).map(|_| Uuid::new_v4()).collect();
+        db.add_edge(ids[0], ids[1], 1.0)?;
+        db.add_edge(ids[2], ids[1], 1.0)?;
+        db.add_edge(ids[3], ids[4], 1.0)?;
+
+        let comps = db.graph_components();
+        assert_eq!(comps.iter().map(Vec::len).collect::<Vec<_>>(), vec![3, 2]);
+        let mut first = comps[0].clone();
+        first.sort();
+        let mut want = vec![ids[0], ids[1], ids[2]];
+        want.sort();
+        assert_eq!(first, want);
+
+        // components from separate shards join through shared nodes
+        let extra = vec![vec![ids[2], ids[3]]];
+        let merged = crate::graph::connected(
+            comps
+                .iter()
+                .chain(&extra)
+                .flat_map(|c| c.iter().map(move |id| (c[0], *id))),
+        );
+        assert_eq!(merged.len(), 1);
+        assert_eq!(merged[0].len(), 5);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn patch_doc_merges_in_place() -> Result<()> {
+        let dir = tempdir()?;
+        let db = PieskieoDb::open(dir.path())?;
+        let id = Uuid::new_v4();
+        assert!(matches!(
+            db.patch_doc_ns(None, None, id, serde_json::json!({"a": 1}), true),
+            Err(PieskieoError::NotFound)
+        ));
+        assert!(db.get_doc(&id).is_none());
+
+        db.put_doc(
+            id,
+            serde_json::json!({"a": 1, "b": {"x": 1, "y": 2}, "c": 3}),
+        )?;
+        let merged = db.patch_doc_ns(
+            None,
+            None,
+            id,
+            serde_json::json!({"b": {"y": null, "z": {"k": null}}, "c": null}),
+            true,
+        )?;
+        let want = serde_json::json!({"a": 1, "b": {"x": 1, "z": {}}});
+        assert_eq!(merged, want);
+        assert_eq!(db.get_doc(&id), Some(want));
+
+        let shallow = db.patch_doc_ns(None, None, id, serde_json::json!({"b": {"w": 1}}), false)?;
+        assert_eq!(shallow, serde_json::json!({"a": 1, "b": {"w": 1}}));
+
+        // the merged doc is what the WAL replays
+        drop(db);
+        let db = PieskieoDb::open(dir.path())?;
+        assert_eq!(db.get_doc(&id), Some(shallow));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn exported_edges_import_into_a_fresh_db() -> Result<()> {
+        let dir = tempdir()?;
+        let db = PieskieoDb::open(dir.path())?;
+        let (a, b, c) = (Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4());
+        db.add_edge(a, b, 0.5)?;
+        db.add_edge(b, c, 2.0)?;
+        db.add_auto_edge(a, c, 0.9)?;
+        let edges = db.export_edges();
+        assert_eq!(edges.len(), 3);
+
+        let other = tempdir()?;
+        let copy = PieskieoDb::open(other.path())?;
+        copy.import_edges(&edges)?;
+        drop(copy);
+        let copy = PieskieoDb::open(other.path())?;
+        assert_eq!(copy.edge_weight(a, b), Some(0.5));
+        assert_eq!(copy.edge_weight(b, c), Some(2.0));
+        let auto = copy.neighbors(a, 10).into_iter().find(|e| e.dst == c);
+        assert!(auto.is_some_and(|e| e.auto && e.weight == 0.9));
+        Ok(())
+    }
+
+    #[test]
+    fn metric_scores_rank_higher_as_closer() {
+        let q = [3.0, 4.0];
+        for name in ["l2", "cosine", "dot"] {
+            let m = VectorMetric::parse(name).unwrap();
+            assert_eq!(m.name(), name);
+        }
+        assert!(VectorMetric::parse("manhattan").is_none());
+        assert_eq!(VectorMetric::L2.score(&q, &[3.0, 2.0]), -4.0);
+        assert_eq!(VectorMetric::Dot.score(&q, &[1.0, 1.0]), 7.0);
+        // cosine ignores magnitude, unlike dot
+        let cos = VectorMetric::Cosine.score(&q, &[6.0, 8.0]);
+        assert!((cos - 1.0).abs() < 1e-6);
+        assert_eq!(VectorMetric::Cosine.score(&q, &[0.0, 0.0]), 0.0);
+    }
+
+    #[tokio::test]
+    async fn sql_filters_and_deletes_by_id() -> Result<()> {
+        let dir = tempdir()?;
+        let db = PieskieoDb::open(dir.path())?;
+        let coll = Some("people");
+        let ids: Vec<Uuid> = (0..3).map(|_| Uuid::new_v4()).collect();
+        for (i, id) in ids.iter().enumerate() {
+            db.put_doc_ns(None, coll, *id, serde_json::json!({"n": i}))?;
+        }
+        // a body field called `_id` does not shadow the key
+        db.put_doc_ns(
+            None,
+            coll,
+            Uuid::new_v4(),
+            serde_json::json!({"_id": ids[0].to_string(), "n": 9}),
+        )?;
+        let select = |sql: String| -> Result<Vec<Uuid>> {
+            match db.query_sql(&sql)? {
+                SqlResult::Select(rows) => Ok(rows.into_iter().map(|r| r.0).collect()),
+                _ => panic!("expected select"),
+            }
+        };
+
+        let upper = ids[0].to_string().to_uppercase();
+        assert_eq!(
+            select(format!(
+                "SELECT * FROM docs.default.people WHERE _id = '{upper}'"
+            ))?,
+            [ids[0]]
+        );
+        let mut want = vec![ids[1], ids[2]];
+        want.sort();
+        assert_eq!(
+            select(format!(
+                "SELECT * FROM docs.default.people WHERE _id IN ('{}', '{}', 'nope')",
+                ids[2], ids[1]
+            ))?,
+            want
+        );
+        assert_eq!(
+            select(format!(
+                "SELECT * FROM docs.default.people WHERE _id IN ('{}', '{}') AND n = 2",
+                ids[1], ids[2]
+            ))?,
+            [ids[2]]
+        );
+        assert_eq!(
+            select(format!(
+                "SELECT * FROM docs.default.people WHERE _id != '{}' AND n < 3",
+                ids[0]
+            ))?
+            .len(),
+            2
+        );
+
+        db.query_sql(&format!(
+            "DELETE FROM docs.default.people WHERE _id = '{}'",
+            ids[1]
+        ))?;
+        assert!(db.get_doc_ns(None, coll, &ids[1]).is_none());
+        assert!(db.get_doc_ns(None, coll, &ids[2]).is_some());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn aliased_join_prefixes_columns() -> Result<()> {
+        let dir = tempdir()?;
+        let db = PieskieoDb::open(dir.path())?;
+        let (ann, bob) = (Uuid::new_v4(), Uuid::new_v4());
+        db.put_doc_ns(
+            None,
+            Some("users"),
+            ann,
+            serde_json::json!({"id": 1, "name": "ann"}),
+        )?;
+        db.put_doc_ns(
+            None,
+            Some("users"),
+            bob,
+            serde_json::json!({"id": 2, "name": "bob"}),
+        )?;
+        for (user, total) in [(1, 10), (1, 30), (2, 20)] {
+            db.put_doc_ns(
+                None,
+                Some("orders"),
+                Uuid::new_v4(),
+                serde_json::json!({"id": 100 + total, "user_id": user, "total": total}),
+            )?;
+        }
+        let select = |sql: &str| -> Result<Vec<Value>> {
+            match db.query_sql(sql)? {
+                SqlResult::Select(rows) => Ok(rows.into_iter().map(|r| r.1).collect()),
+                _ => panic!("expected select"),
+            }
+        };
 
-                // choose smallest equality-bucket among predicates
-                let mut best: Option<(String, usize, Vec<Uuid>)> = None;
-                for (field, val) in filter.iter() {
-                    if let Some(key) = Self::index_key(val) {
-                        if let Some(field_map) = ns_map.get(coll).and_then(|m| m.get(field)) {
-                            if let Some(ids) = field_map.get(&key) {
-                                let bucket = ids.clone();
-                                let cost = bucket.len();
-                                if cost < total_rows {
-                                    match &best {
-                                        None => best = Some((field.clone(), cost, bucket)),
-                                        Some((_, best_cost, _)) if cost < *best_cost => {
-                                            best = Some((field.clone(), cost, bucket))
-                                        }
-                                        _ => {}
-                                    }
-                                }
-                            }
-                        }
-                    }
-                }
+        // ON sides reversed; WHERE sees the joined, prefixed row
+        let rows = select(
+            "SELECT u.name, o.total FROM docs.default.users u \
+             JOIN docs.default.orders o ON o.user_id = u.id \
+             WHERE o.total > 15 ORDER BY o.total",
+        )?;
+        assert_eq!(
+            rows,
+            [
+                serde_json::json!({"u.name": "bob", "o.total": 20}),
+                serde_json::json!({"u.name": "ann", "o.total": 30}),
+            ]
+        );
 
-                if let Some((field, cost, bucket)) = best {
-                    tracing::debug!(
-                        target: "planner",
-                        namespace=%ns,
-                        collection=%coll,
-                        field=%field,
-                        bucket=cost,
-                        total=total_rows,
-                        "chosen equality index path"
-                    );
+        // one alias is enough; the other side goes by its collection name
+        let rows = select(
+            "SELECT * FROM docs.default.users \
+             JOIN docs.default.orders o ON users.id = o.user_id WHERE users.name = 'bob'",
+        )?;
+        assert_eq!(rows.len(), 1);
+        assert_eq!(rows[0]["users.id"], 2);
+        assert_eq!(rows[0]["o.id"], 120);
 
-                    let mut out = Vec::new();
-                    let mut skipped = 0usize;
-                    for id in bucket {
-                        if !self.owns(&id) {
-                            continue;
-                        }
-                        if let Some(v) = inner.get(&id) {
-                            if !value_matches(v, filter) {
-                                continue;
-                            }
-                            if skipped < offset {
-                                skipped += 1;
-                                continue;
-                            }
-                            out.push((id, v.clone()));
-                            if out.len() >= limit {
-                                return out;
-                            }
-                        }
-                    }
-                    return out;
-                } else {
-                    tracing::debug!(
-                        target: "planner",
-                        namespace=%ns,
-                        collection=%coll,
-                        total=total_rows,
-                        "no selective index found, full scan"
-                    );
-                }
-            }
-        }
-        self.filter_map(map, ns, coll, filter, limit, offset)
+        // no aliases keeps the flat right_ layout
+        let rows = select(
+            "SELECT * FROM docs.default.users \
+             JOIN docs.default.orders ON id = user_id WHERE name = 'bob'",
+        )?;
+        assert_eq!(rows[0]["id"], 2);
+        assert_eq!(rows[0]["right_id"], 120);
+
+        assert!(db
+            .query_sql(
+                "SELECT * FROM docs.default.users u JOIN docs.default.orders o ON x.id = o.user_id"
+            )
+            .is_err());
+        Ok(())
     }
 
-    fn index_key(v: &Value) -> Option<String> {
-        match v {
-            Value::String(s) => Some(s.clone()),
-            Value::Number(n) => Some(n.to_string()),
-            Value::Bool(b) => Some(b.to_string()),
-            _ => None,
+    #[tokio::test]
+    async fn vector_memory_estimate_tracks_leaked_backing() -> Result<()> {
+        let dir = tempdir()?;
+        let db = PieskieoDb::open(dir.path())?;
+        let stats = |db: &PieskieoDb| db.vector_ns_stats().remove(0);
+        db.put_vector_ns(Some("mem"), Uuid::new_v4(), vec![0.5; 64])?;
+        let one = stats(&db);
+        assert_eq!(one.owned_bytes, 64 * 4);
+        assert_eq!(one.leaked_bytes, 0);
+        db.put_vector_ns(Some("mem"), Uuid::new_v4(), vec![0.5; 64])?;
+        let two = stats(&db);
+        assert!(two.memory_bytes >= one.memory_bytes + 2 * 64 * 4);
+
+        // a rebuild swaps in fresh backing and strands the old slices
+        db.rebuild_dirty_vectors()?;
+        let rebuilt = stats(&db);
+        assert_eq!(rebuilt.owned_bytes, 2 * 64 * 4);
+        assert_eq!(rebuilt.leaked_bytes, 2 * 64 * 4);
+        assert!(rebuilt.memory_bytes > two.memory_bytes);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn replay_rebuilds_every_bucket_and_vector_graph() -> Result<()> {
+        let dir = tempdir()?;
+        let db = PieskieoDb::open(dir.path())?;
+        let (a, b, r) = (Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4());
+        db.put_doc_ns(None, Some("one"), a, serde_json::json!({"tag": "old"}))?;
+        db.put_doc_ns(None, Some("one"), a, serde_json::json!({"tag": "new"}))?;
+        db.put_doc_ns(
+            Some("other"),
+            Some("two"),
+            b,
+            serde_json::json!({"tag": "x"}),
+        )?;
+        db.delete_doc_ns(Some("other"), Some("two"), &b)?;
+        db.put_row_ns(None, Some("t"), r, &serde_json::json!({"n": 1}))?;
+        let mut fields = HashMap::new();
+        fields.insert(
+            "tag".to_string(),
+            SchemaField {
+                required: true,
+                unique: false,
+                r#type: None,
+            },
+        );
+        db.set_doc_schema(None, Some("one"), SchemaDef { fields })?;
+        let (keep, gone) = (Uuid::new_v4(), Uuid::new_v4());
+        db.put_vector_ns(Some("v"), keep, vec![1.0, 0.0])?;
+        db.put_vector_ns(Some("v"), gone, vec![0.0, 1.0])?;
+        db.delete_vector(&gone)?;
+        db.flush_wal()?;
+        drop(db);
+        // without snapshots every vector comes back through the WAL
+        std::fs::remove_dir_all(dir.path().join("vectors"))?;
+
+        let db = PieskieoDb::open(dir.path())?;
+        let filter = HashMap::from([("tag".to_string(), serde_json::json!("new"))]);
+        assert_eq!(db.query_docs_ns(None, Some("one"), &filter, 10, 0).len(), 1);
+        let filter = HashMap::from([("tag".to_string(), serde_json::json!("x"))]);
+        assert!(db
+            .query_docs_ns(Some("other"), Some("two"), &filter, 10, 0)
+            .is_empty());
+        assert_eq!(
+            db.get_row_ns(None, Some("t"), &r),
+            Some(serde_json::json!({"n": 1}))
+        );
+        assert!(matches!(
+            db.put_doc_ns(None, Some("one"), Uuid::new_v4(), serde_json::json!({})),
+            Err(PieskieoError::Validation(_))
+        ));
+        let idx = db.vector_index("v");
+        assert!(idx.hnsw.read().is_some());
+        let hits = db.search_vector(&[0.0, 1.0], 5)?;
+        assert_eq!(hits.iter().map(|h| h.id).collect::<Vec<_>>(), [keep]);
+        Ok(())
+    }
+
+    #[test]
+    fn normalized_scores_are_bounded_and_keep_order() {
+        let q = [1.0, 0.0];
+        for m in [VectorMetric::L2, VectorMetric::Cosine, VectorMetric::Dot] {
+            let near = m.normalize(m.score(&q, &[2.0, 0.1]));
+            let far = m.normalize(m.score(&q, &[-3.0, 4.0]));
+            assert!((0.0..=1.0).contains(&near) && (0.0..=1.0).contains(&far));
+            assert!(near > far, "{}", m.name());
         }
+        assert_eq!(VectorMetric::L2.normalize(0.0), 1.0);
+        assert_eq!(VectorMetric::Cosine.normalize(-1.0), 0.0);
+        assert_eq!(VectorMetric::Dot.normalize(0.0), 0.5);
     }
 
-    fn index_upsert_doc(colls: &mut Collections, ns: String, col: String, id: Uuid, json: &Value) {
-        if let Some(obj) = json.as_object() {
-            for (k, v) in obj {
-                if let Some(key) = Self::index_key(v) {
-                    let entry = colls
-                        .doc_index
-                        .entry(ns.clone())
-                        .or_default()
-                        .entry(col.clone())
-                        .or_default()
-                        .entry(k.clone())
-                        .or_default()
-                        .entry(key)
-                        .or_default();
-                    if !entry.contains(&id) {
-                        entry.push(id);
-                    }
-                }
+    #[tokio::test]
+    async fn snapshot_clears_changed_until_next_write() -> Result<()> {
+        let dir = tempdir()?;
+        let db = PieskieoDb::open(dir.path())?;
+        assert!(!db.changed_since_snapshot());
+        assert_eq!(db.last_write_ms(), None);
+        db.put_doc(Uuid::new_v4(), serde_json::json!({"a": 1}))?;
+        assert!(db.changed_since_snapshot());
+        assert!(db.last_write_ms().is_some());
+        std::thread::sleep(std::time::Duration::from_millis(2));
+        db.save_vector_snapshot()?;
+        assert!(!db.changed_since_snapshot());
+        db.put_vector(Uuid::new_v4(), vec![1.0, 0.0])?;
+        assert!(db.changed_since_snapshot());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn id_range_selects_tenant_prefix() -> Result<()> {
+        let dir = tempdir()?;
+        let db = PieskieoDb::open(dir.path())?;
+        let id = |tenant: u128, n: u128| Uuid::from_u128((tenant << 64) | n);
+        for tenant in 1..=3 {
+            for n in 0..4 {
+                db.put_vector(id(tenant, n), vec![tenant as f32, n as f32])?;
             }
         }
+        db.delete_vector(&id(2, 3))?;
+        let range = crate::vector::VectorIdRange {
+            from: id(2, 0),
+            to: id(2, u64::MAX as u128),
+        };
+        assert!(range.contains(&id(2, 7)) && !range.contains(&id(3, 0)));
+        let ids = db.vector_ids_in_range(None, &range, 10).unwrap();
+        assert_eq!(ids, (0..3).map(|n| id(2, n)).collect::<HashSet<_>>());
+        assert!(db.vector_ids_in_range(None, &range, 2).is_none());
+        let hits = db.search_vector_ids_ns(None, &[9.0, 9.0], 10, VectorMetric::L2, &ids, None)?;
+        assert_eq!(hits.len(), 3);
+        assert!(hits.iter().all(|h| range.contains(&h.id)));
+        Ok(())
     }
 
-    fn index_remove_doc(colls: &mut Collections, ns: String, col: String, id: &Uuid, json: &Value) {
-        if let Some(obj) = json.as_object() {
-            for (k, v) in obj {
-                if let Some(key) = Self::index_key(v) {
-                    if let Some(ns_map) = colls.doc_index.get_mut(&ns) {
-                        if let Some(col_map) = ns_map.get_mut(&col) {
-                            if let Some(field_map) = col_map.get_mut(k) {
-                                if let Some(ids) = field_map.get_mut(&key) {
-                                    ids.retain(|x| x != id);
-                                }
-                            }
-                        }
-                    }
-                }
-            }
+    #[test]
+    fn json_schema_translates_or_names_unsupported_keyword() -> Result<()> {
+        let def = SchemaDef::from_json_schema(&serde_json::json!({
+            "$schema": "https://json-schema.org/draft/2020-12/schema",
+            "title": "user",
+            "type": "object",
+            "properties": {
+                "email": {"type": "string", "format": "email", "x-unique": true},
+                "age": {"type": "integer", "description": "years"},
+                "tags": true
+            },
+            "required": ["email", "name"]
+        }))?;
+        let email = &def.fields["email"];
+        assert!(email.required && email.unique);
+        assert_eq!(email.r#type.as_deref(), Some("string"));
+        assert!(!def.fields["age"].required);
+        assert_eq!(def.fields["age"].r#type.as_deref(), Some("integer"));
+        assert!(def.fields["name"].required && def.fields["name"].r#type.is_none());
+        assert!(def.fields.contains_key("tags"));
+
+        let err = SchemaDef::from_json_schema(&serde_json::json!({
+            "properties": {"age": {"type": "integer", "minimum": 0}}
+        }))
+        .unwrap_err();
+        assert!(
+            err.to_string().contains("'minimum' at properties.age"),
+            "{err}"
+        );
+        for bad in [
+            serde_json::json!({"type": "array"}),
+            serde_json::json!({"additionalProperties": false}),
+            serde_json::json!({"properties": {"a": {"type": ["string", "null"]}}}),
+        ] {
+            assert!(matches!(
+                SchemaDef::from_json_schema(&bad),
+                Err(PieskieoError::Validation(_))
+            ));
         }
+        Ok(())
     }
 
-    fn index_upsert_row(
-        colls: &mut Collections,
-        ns: String,
-        table: String,
-        id: Uuid,
-        json: &Value,
-    ) {
-        if let Some(obj) = json.as_object() {
-            for (k, v) in obj {
-                if let Some(key) = Self::index_key(v) {
-                    let entry = colls
-                        .row_index
-                        .entry(ns.clone())
-                        .or_default()
-                        .entry(table.clone())
-                        .or_default()
-                        .entry(k.clone())
-                        .or_default()
-                        .entry(key)
-                        .or_default();
-                    if !entry.contains(&id) {
-                        entry.push(id);
-                    }
-                }
+    #[tokio::test]
+    async fn search_after_pages_split_ties_by_id() -> Result<()> {
+        let dir = tempdir()?;
+        let db = PieskieoDb::open(dir.path())?;
+        // four vectors per distance, so pages of three cut through ties
+        for n in 0..20u128 {
+            db.put_vector(Uuid::from_u128(n + 1), vec![(n % 5) as f32, 0.0])?;
+        }
+        let all = db.search_vector_metric(&[0.0, 0.0], 20, VectorMetric::L2, None, None)?;
+        assert_eq!(all.len(), 20);
+        assert!(all
+            .windows(2)
+            .all(|w| crate::vector::rank(&w[0], &w[1]).is_lt()));
+
+        let mut paged = Vec::new();
+        let mut after = None;
+        loop {
+            let page =
+                db.search_vector_metric(&[0.0, 0.0], 3, VectorMetric::L2, None, after.as_ref())?;
+            paged.extend(page.iter().map(|h| h.id));
+            match page.last() {
+                Some(last) if page.len() == 3 => after = Some(crate::vector::SearchAfter::of(last)),
+                _ => break,
             }
         }
+        assert_eq!(paged, all.iter().map(|h| h.id).collect::<Vec<_>>());
+
+        let cursor = crate::vector::SearchAfter::of(&all[5]);
+        assert!(!cursor.admits(&all[5]) && !cursor.admits(&all[4]));
+        assert!(cursor.admits(&all[6]));
+        Ok(())
     }
 
-    fn index_remove_row(
-        colls: &mut Collections,
-        ns: String,
-        table: String,
-        id: &Uuid,
-        json: &Value,
-    ) {
-        if let Some(obj) = json.as_object() {
-            for (k, v) in obj {
-                if let Some(key) = Self::index_key(v) {
-                    if let Some(ns_map) = colls.row_index.get_mut(&ns) {
-                        if let Some(tbl_map) = ns_map.get_mut(&table) {
-                            if let Some(field_map) = tbl_map.get_mut(k) {
-                                if let Some(ids) = field_map.get_mut(&key) {
-                                    ids.retain(|x| x != id);
-                                }
-                            }
-                        }
-                    }
-                }
-            }
+    #[tokio::test]
+    async fn sql_insert_on_conflict_updates_existing() -> Result<()> {
+        let dir = tempdir()?;
+        let db = PieskieoDb::open(dir.path())?;
+        let mut fields = HashMap::new();
+        fields.insert(
+            "email".to_string(),
+            SchemaField {
+                unique: true,
+                ..Default::default()
+            },
+        );
+        db.set_doc_schema(Some("default"), Some("users"), SchemaDef { fields })?;
+        let first = match db.query_sql(
+            "INSERT INTO docs.default.users (email, name, visits) VALUES ('a@x', 'alice', 1)",
+        )? {
+            SqlResult::Insert { ids } => ids[0],
+            other => panic!("unexpected {other:?}"),
+        };
+        let err = db
+            .query_sql("INSERT INTO docs.default.users (email) VALUES ('a@x')")
+            .unwrap_err();
+        assert!(matches!(err, PieskieoError::UniqueViolation(_)));
+
+        let upsert = db.query_sql(
+            "INSERT INTO docs.default.users (email, name) VALUES ('a@x', 'alicia') \
+             ON CONFLICT (email) DO UPDATE SET name = EXCLUDED.name, visits = 2",
+        )?;
+        assert!(matches!(upsert, SqlResult::Insert { ids } if ids == vec![first]));
+        let doc = db
+            .get_doc_ns(Some("default"), Some("users"), &first)
+            .unwrap();
+        assert_eq!(doc["name"], "alicia");
+        assert_eq!(doc["visits"], 2);
+        assert_eq!(db.metrics().docs, 1);
+
+        let skipped = db.query_sql(
+            "INSERT INTO docs.default.users (email, name) VALUES ('a@x', 'x') \
+             ON CONFLICT (email) DO NOTHING",
+        )?;
+        assert!(matches!(skipped, SqlResult::Insert { ids } if ids.is_empty()));
+        // no collision: a plain insert
+        db.query_sql(
+            "INSERT INTO docs.default.users (email) VALUES ('b@x') \
+             ON CONFLICT (email) DO UPDATE SET name = 'unused'",
+        )?;
+        assert_eq!(db.metrics().docs, 2);
+
+        let err = db
+            .query_sql(
+                "INSERT INTO docs.default.users (name) VALUES ('a') \
+                 ON CONFLICT (name) DO UPDATE SET visits = 0",
+            )
+            .unwrap_err();
+        assert!(matches!(err, PieskieoError::Validation(_)));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn query_stats_report_index_or_scan() -> Result<()> {
+        let dir = tempdir()?;
+        let db = PieskieoDb::open(dir.path())?;
+        for n in 0..10 {
+            let color = if n < 2 { "red" } else { "blue" };
+            db.put_doc_ns(
+                Some("app"),
+                Some("items"),
+                Uuid::new_v4(),
+                serde_json::json!({"color": color, "n": n}),
+            )?;
         }
+        let query = |filter: serde_json::Value, offset| {
+            let filter: HashMap<String, Value> = serde_json::from_value(filter).unwrap();
+            db.query_docs_ns_with_stats(Some("app"), Some("items"), &filter, 10, offset)
+        };
+
+        let (hits, stats) = query(serde_json::json!({"color": "red"}), 1);
+        assert_eq!(hits.len(), 1);
+        assert_eq!(
+            stats,
+            QueryStats {
+                used_index: true,
+                scanned: 2,
+                matched: 2
+            }
+        );
+        // range predicates have no equality bucket to drive the scan
+        let (_, stats) = query(serde_json::json!({"n": {"$gt": 5}}), 0);
+        assert!(!stats.used_index);
+        assert_eq!((stats.scanned, stats.matched), (10, 4));
+        let (hits, stats) = query(serde_json::json!({"color": "green"}), 0);
+        assert!(hits.is_empty() && stats.used_index && stats.scanned == 0);
+        Ok(())
     }
-}
-#[derive(Clone, Copy)]
-pub struct VectorParams {
-    pub metric: VectorMetric,
-    pub ef_construction: usize,
-    pub ef_search: usize,
-    pub max_elements: usize,
-    pub link_top_k: usize,
-    pub shard_id: usize,
-    pub shard_total: usize,
-}
 
-pub struct MetricsSnapshot {
-    pub docs: usize,
-    pub rows: usize,
-    pub vectors: usize,
-    pub vector_tombstones: usize,
-    pub hnsw_ready: bool,
-    pub ef_search: usize,
-    pub ef_construction: usize,
-    pub wal_path: std::path::PathBuf,
-    pub wal_bytes: u64,
-    pub snapshot_mtime: Option<std::time::SystemTime>,
-    pub link_top_k: usize,
-    pub shard_id: usize,
-    pub shard_total: usize,
-}
+    #[tokio::test]
+    async fn link_top_k_per_namespace_overrides_global() -> Result<()> {
+        let dir = tempdir()?;
+        let db = PieskieoDb::open(dir.path())?;
+        db.set_link_top_k_ns(Some("images"), 2);
+        assert_eq!(db.link_top_k_ns("images"), 2);
+        assert_eq!(db.link_top_k_ns("tags"), 0);
+        let put = |ns, n: usize| -> Result<Vec<Uuid>> {
+            let ids: Vec<Uuid> = (0..n).map(|_| Uuid::new_v4()).collect();
+            for (i, id) in ids.iter().enumerate() {
+                db.put_vector_ns(Some(ns), *id, vec![i as f32, 0.0])?;
+            }
+            Ok(ids)
+        };
+        let images = put("images", 4)?;
+        let tags = put("tags", 4)?;
+        assert_eq!(db.neighbors(images[3], 10).len(), 2);
+        assert!(db.neighbors(tags[3], 10).is_empty());
+
+        db.set_link_top_k(1);
+        let late = Uuid::new_v4();
+        db.put_vector_ns(Some("tags"), late, vec![9.0, 0.0])?;
+        assert_eq!(db.neighbors(late, 10).len(), 1);
+        assert_eq!(db.link_top_k_ns("images"), 2);
+        Ok(())
+    }
 
-impl Default for VectorParams {
-    fn default() -> Self {
-        Self {
-            metric: VectorMetric::L2,
-            ef_construction: 200,
-            ef_search: 50,
-            max_elements: 100_000,
-            link_top_k: 0,
-            shard_id: 0,
-            shard_total: 1,
+    #[tokio::test]
+    async fn sql_order_by_seq_follows_insertion() -> Result<()> {
+        let dir = tempdir()?;
+        let db = PieskieoDb::open(dir.path())?;
+        // ids descend, so id order is the reverse of insertion order
+        let ids: Vec<Uuid> = (0..4u128).map(|n| Uuid::from_u128(100 - n)).collect();
+        for (n, id) in ids.iter().enumerate() {
+            db.put_doc_ns(None, Some("events"), *id, serde_json::json!({"n": n}))?;
         }
+        // an update keeps its place; a delete and re-insert moves to the end
+        db.put_doc_ns(None, Some("events"), ids[0], serde_json::json!({"n": 0}))?;
+        db.delete_doc_ns(None, Some("events"), &ids[1])?;
+        db.put_doc_ns(None, Some("events"), ids[1], serde_json::json!({"n": 1}))?;
+        let order = |db: &PieskieoDb, dir: &str| -> Result<Vec<u64>> {
+            let sql = format!("SELECT n FROM docs.default.events ORDER BY _seq {dir}");
+            match db.query_sql(&sql)? {
+                SqlResult::Select(rows) => {
+                    Ok(rows.iter().map(|(_, v)| v["n"].as_u64().unwrap()).collect())
+                }
+                _ => panic!("expected select"),
+            }
+        };
+        assert_eq!(order(&db, "ASC")?, [0, 2, 3, 1]);
+        assert_eq!(order(&db, "DESC")?, [1, 3, 2, 0]);
+
+        db.flush_wal()?;
+        drop(db);
+        let db = PieskieoDb::open(dir.path())?;
+        assert_eq!(order(&db, "ASC")?, [0, 2, 3, 1]);
+        Ok(())
     }
-}
 
-impl Drop for PieskieoDb {
-    fn drop(&mut self) {
-        let _ = self.save_vector_snapshot();
+    #[tokio::test]
+    async fn delete_many_reports_deleted_ids_and_survives_replay() -> Result<()> {
+        let dir = tempdir()?;
+        let (a, b, missing, vid) = (
+            Uuid::new_v4(),
+            Uuid::new_v4(),
+            Uuid::new_v4(),
+            Uuid::new_v4(),
+        );
+        {
+            let db = PieskieoDb::open(dir.path())?;
+            db.put_doc_ns(None, Some("c"), a, serde_json::json!({"n": 1}))?;
+            db.put_doc_ns(None, Some("c"), b, serde_json::json!({"n": 2}))?;
+            db.put_row_ns(None, Some("t"), a, &serde_json::json!({"n": 3}))?;
+            db.put_vector(vid, vec![1.0, 0.0])?;
+
+            let deleted = db.delete_many(BatchFamily::Doc, None, Some("c"), &[b, missing, a, b])?;
+            assert_eq!(deleted, vec![b, a]);
+            assert!(!db.has_doc_ns(None, Some("c"), &a));
+            // other families and collections are untouched
+            assert!(db.has_row_ns(None, Some("t"), &a));
+
+            let deleted = db.delete_many(BatchFamily::Row, None, Some("t"), &[a])?;
+            assert_eq!(deleted, vec![a]);
+            let deleted = db.delete_many(BatchFamily::Vector, None, None, &[vid, missing])?;
+            assert_eq!(deleted, vec![vid]);
+            assert!(db
+                .delete_many(BatchFamily::Doc, None, Some("c"), &[a])?
+                .is_empty());
+        }
+        let db = PieskieoDb::open(dir.path())?;
+        assert!(!db.has_doc_ns(None, Some("c"), &b));
+        assert!(!db.has_row_ns(None, Some("t"), &a));
+        assert!(!db.has_vector(&vid));
+        Ok(())
     }
-}
-
-fn shard_hash(id: &Uuid) -> usize {
-    let bytes = id.as_bytes();
-    let mut arr = [0u8; 8];
-    arr.copy_from_slice(&bytes[..8]);
-    u64::from_le_bytes(arr) as usize
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use tempfile::tempdir;
+    #[tokio::test]
+    async fn count_matches_query_without_rows() -> Result<()> {
+        let dir = tempdir()?;
+        let db = PieskieoDb::open(dir.path())?;
+        for n in 0..10 {
+            let color = if n % 3 == 0 { "red" } else { "blue" };
+            db.put_doc_ns(
+                None,
+                Some("c"),
+                Uuid::new_v4(),
+                serde_json::json!({"n": n, "color": color}),
+            )?;
+        }
+        db.put_row_ns(
+            None,
+            Some("t"),
+            Uuid::new_v4(),
+            &serde_json::json!({"n": 1}),
+        )?;
+        let filter = |v: Value| -> HashMap<String, Value> { serde_json::from_value(v).unwrap() };
+
+        assert_eq!(db.count_docs_ns(None, Some("c"), &HashMap::new()), 10);
+        assert_eq!(
+            db.count_docs_ns(
+                None,
+                Some("c"),
+                &filter(serde_json::json!({"color": "red"}))
+            ),
+            4
+        );
+        assert_eq!(
+            db.count_docs_ns(
+                None,
+                Some("c"),
+                &filter(serde_json::json!({"color": "blue", "n": {"$gt": 5}}))
+            ),
+            2
+        );
+        assert_eq!(
+            db.count_docs_ns(
+                None,
+                Some("c"),
+                &filter(serde_json::json!({"color": "green"}))
+            ),
+            0
+        );
+        // without a namespace or collection every one is counted
+        assert_eq!(db.count_docs_ns(None, None, &HashMap::new()), 10);
+        assert_eq!(db.count_rows_ns(None, Some("t"), &HashMap::new()), 1);
+        assert_eq!(db.count_docs_ns(None, Some("missing"), &HashMap::new()), 0);
+        Ok(())
+    }
 
     #[tokio::test]
-    async fn round_trip_doc_and_vector() -> Result<()> {
-        let dir = tempdir().unwrap();
+    async fn count_survives_reopen() -> Result<()> {
+        let dir = tempdir()?;
+        let first = Uuid::new_v4();
+        {
+            let db = PieskieoDb::open(dir.path())?;
+            db.put_doc_ns(None, Some("c"), first, serde_json::json!({"n": 0}))?;
+            for n in 1..5 {
+                db.put_doc_ns(None, Some("c"), Uuid::new_v4(), serde_json::json!({"n": n}))?;
+            }
+            db.flush_wal()?;
+        }
         let db = PieskieoDb::open(dir.path())?;
-        let id = Uuid::new_v4();
-        db.put_doc(id, serde_json::json!({"hello": "world"}))?;
-        db.put_vector(id, vec![0.1, 0.2, 0.3])?;
+        assert_eq!(db.count_docs_ns(None, Some("c"), &HashMap::new()), 5);
+        db.put_doc_ns(None, Some("c"), Uuid::new_v4(), serde_json::json!({"n": 5}))?;
+        assert_eq!(db.count_docs_ns(None, Some("c"), &HashMap::new()), 6);
+        db.delete_doc_ns(None, Some("c"), &first)?;
+        assert_eq!(db.count_docs_ns(None, Some("c"), &HashMap::new()), 5);
+        Ok(())
+    }
 
-        assert_eq!(db.get_doc(&id).unwrap()["hello"], "world");
-        let hits = db.search_vector(&[0.1, 0.2, 0.3], 1)?;
-        assert_eq!(hits.len(), 1);
-        assert_eq!(hits[0].id, id);
+    #[tokio::test]
+    async fn vector_namespace_moves_between_instances() -> Result<()> {
+        let (src_dir, dst_dir) = (tempdir()?, tempdir()?);
+        let (a, b, stale) = (Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4());
+        let meta: VectorMeta = serde_json::from_value(serde_json::json!({"lang": "en"}))?;
+        let blob = {
+            let src = PieskieoDb::open(src_dir.path())?;
+            src.put_vector_with_meta_ns(Some("t1"), a, vec![1.0, 0.0], Some(meta.clone()))?;
+            src.put_vector_with_meta_ns(Some("t1"), b, vec![0.0, 1.0], None)?;
+            src.put_vector_with_meta_ns(Some("t2"), Uuid::new_v4(), vec![1.0, 1.0], None)?;
+            src.export_vectors_ns("t1")?
+        };
+        {
+            let dst = PieskieoDb::open(dst_dir.path())?;
+            dst.put_vector_with_meta_ns(Some("t1"), stale, vec![0.5, 0.5], None)?;
+            assert_eq!(dst.import_vectors_ns("t1", &blob)?, 2);
+            // the import replaces what the namespace held
+            assert!(!dst.has_vector(&stale));
+            assert_eq!(
+                dst.get_vector(&a),
+                Some((vec![1.0, 0.0], Some(meta.clone())))
+            );
+            let hits = dst.search_vector_metric_ns(
+                Some("t1"),
+                &[0.0, 1.0],
+                1,
+                VectorMetric::L2,
+                None,
+                None,
+            )?;
+            assert_eq!(hits[0].id, b);
+            // a mismatched dimension is rejected before anything is written
+            let wide = encode_snapshot(vec![(Uuid::new_v4(), vec![1.0, 2.0, 3.0], None)])?;
+            assert!(matches!(
+                dst.import_vectors_ns("t1", &wide),
+                Err(PieskieoError::Validation(_))
+            ));
+        }
+        let dst = PieskieoDb::open(dst_dir.path())?;
+        assert!(dst.has_vector(&a) && dst.has_vector(&b));
+        assert!(!dst.has_vector(&stale));
+        assert_eq!(dst.get_vector(&a).and_then(|(_, m)| m), Some(meta));
         Ok(())
     }
 
     #[tokio::test]
-    async fn graph_neighbors() -> Result<()> {
-        let dir = tempdir().unwrap();
+    async fn vector_import_replaces_an_older_snapshot() -> Result<()> {
+        let dir = tempdir()?;
+        let (old, new) = (Uuid::new_v4(), Uuid::new_v4());
+        {
+            let db = PieskieoDb::open(dir.path())?;
+            db.put_vector_with_meta_ns(Some("t1"), old, vec![1.0, 0.0], None)?;
+            db.save_vector_snapshot()?;
+            let blob = encode_snapshot(vec![(new, vec![0.0, 1.0], None)])?;
+            assert_eq!(db.import_vectors_ns("t1", &blob)?, 1);
+            db.flush_wal()?;
+        }
         let db = PieskieoDb::open(dir.path())?;
-        let a = Uuid::new_v4();
-        let b = Uuid::new_v4();
-        db.add_edge(a, b, 1.0)?;
-        let neighbors = db.neighbors(a, 10);
-        assert_eq!(neighbors.len(), 1);
-        assert_eq!(neighbors[0].dst, b);
+        assert!(db.has_vector(&new));
+        assert!(!db.has_vector(&old));
         Ok(())
     }
 
+    #[test]
+    fn hnsw_capacity_grows_from_initial_to_max() {
+        let idx = VectorIndex::with_params(VectorMetric::L2, 200, 50, 100_000, 16);
+        assert_eq!(idx.hnsw_capacity(0), 16);
+        assert_eq!(idx.hnsw_capacity(100), 200);
+        assert_eq!(idx.hnsw_capacity(1_000_000), 100_000);
+    }
+
     #[tokio::test]
-    async fn auto_links_neighbors_when_enabled() -> Result<()> {
+    async fn writes_fail_fast_while_wal_unhealthy() -> Result<()> {
         let dir = tempdir().unwrap();
-        let db = PieskieoDb::open_with_params(
-            dir.path(),
-            VectorParams {
-                link_top_k: 1,
-                ..Default::default()
-            },
-        )?;
-        let a = Uuid::new_v4();
-        let b = Uuid::new_v4();
-        db.put_vector(a, vec![0.0, 0.0])?;
-        db.put_vector(b, vec![0.0, 0.1])?;
-
-        let neighbors = db.neighbors(a, 10);
-        assert!(
-            neighbors.iter().any(|e| e.dst == b),
-            "expected auto-linked neighbor"
-        );
+        let db = PieskieoDb::open(dir.path())?;
+        assert!(db.wal_healthy());
+        db.wal_healthy
+            .store(false, std::sync::atomic::Ordering::SeqCst);
+        assert!(matches!(
+            db.put_doc(Uuid::new_v4(), serde_json::json!({"a": 1})),
+            Err(PieskieoError::Internal(_))
+        ));
+        // a successful flush restores write access
+        db.flush_wal()?;
+        assert!(db.wal_healthy());
+        db.put_doc(Uuid::new_v4(), serde_json::json!({"a": 1}))?;
         Ok(())
     }
 
     #[tokio::test]
-    async fn vacuum_clears_tombstones_and_wal() -> Result<()> {
+    async fn wal_pending_bytes_reset_on_flush() -> Result<()> {
         let dir = tempdir().unwrap();
         let db = PieskieoDb::open(dir.path())?;
-        let a = Uuid::new_v4();
-        let b = Uuid::new_v4();
-        db.put_vector(a, vec![0.0, 0.0])?;
-        db.put_vector(b, vec![0.0, 0.1])?;
-        db.delete_vector(&a)?;
-        let idx = db.vector_index("default");
-        assert!(idx.tombstones.read().contains_key(&a));
-        db.vacuum()?;
-        assert!(!idx.tombstones.read().contains_key(&a));
+        assert_eq!(db.wal_pending_bytes(), 0);
+        db.put_doc(Uuid::new_v4(), serde_json::json!({"a": 1}))?;
+        assert!(db.wal_pending_bytes() > 0);
+        db.flush_wal()?;
+        assert_eq!(db.wal_pending_bytes(), 0);
         Ok(())
     }
 
@@ -2774,6 +7309,111 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn sql_order_by_null_placement() -> Result<()> {
+        let dir = tempdir().unwrap();
+        let db = PieskieoDb::open(dir.path())?;
+        for doc in [
+            serde_json::json!({"name": "a", "rank": 1}),
+            serde_json::json!({"name": "b", "rank": null}),
+            serde_json::json!({"name": "c", "rank": 2}),
+            serde_json::json!({"name": "d"}),
+        ] {
+            db.put_doc_ns(Some("default"), Some("people"), Uuid::new_v4(), doc)?;
+        }
+        let order = |clause: &str| -> Result<Vec<String>> {
+            let sql = format!("SELECT name FROM docs.default.people ORDER BY rank {clause}, name");
+            match db.query_sql(&sql)? {
+                SqlResult::Select(rows) => Ok(rows
+                    .iter()
+                    .map(|(_, v)| v["name"].as_str().unwrap().to_string())
+                    .collect()),
+                _ => panic!("expected select"),
+            }
+        };
+        assert_eq!(order("ASC")?, ["a", "c", "b", "d"]);
+        assert_eq!(order("DESC")?, ["b", "d", "c", "a"]);
+        assert_eq!(order("ASC NULLS FIRST")?, ["b", "d", "a", "c"]);
+        assert_eq!(order("ASC NULLS LAST")?, ["a", "c", "b", "d"]);
+        assert_eq!(order("DESC NULLS FIRST")?, ["b", "d", "c", "a"]);
+        assert_eq!(order("DESC NULLS LAST")?, ["c", "a", "b", "d"]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn sort_merged_rows_orders_across_shards() -> Result<()> {
+        let (dir_a, dir_b) = (tempdir().unwrap(), tempdir().unwrap());
+        let (a, b) = (
+            PieskieoDb::open(dir_a.path())?,
+            PieskieoDb::open(dir_b.path())?,
+        );
+        for (db, ranks) in [(&a, [1, 4, 5]), (&b, [2, 3, 6])] {
+            for rank in ranks {
+                db.put_doc_ns(
+                    Some("default"),
+                    Some("people"),
+                    Uuid::new_v4(),
+                    serde_json::json!({"name": format!("p{rank}"), "rank": rank}),
+                )?;
+            }
+        }
+        let merged = |sql: &str| -> Result<Vec<Value>> {
+            let mut rows = Vec::new();
+            for db in [&a, &b] {
+                match db.query_sql(sql)? {
+                    SqlResult::Select(part) => rows.extend(part),
+                    _ => panic!("expected select"),
+                }
+            }
+            a.sort_merged_rows(sql, &mut rows)?;
+            Ok(rows.into_iter().map(|(_, v)| v).collect())
+        };
+
+        let rows = merged("SELECT * FROM docs.default.people ORDER BY rank DESC")?;
+        let ranks: Vec<i64> = rows.iter().map(|v| v["rank"].as_i64().unwrap()).collect();
+        assert_eq!(ranks, [6, 5, 4, 3, 2, 1]);
+
+        // the key is read under its alias
+        let rows = merged("SELECT name, rank AS r FROM docs.default.people ORDER BY rank")?;
+        let names: Vec<&str> = rows.iter().map(|v| v["name"].as_str().unwrap()).collect();
+        assert_eq!(names, ["p1", "p2", "p3", "p4", "p5", "p6"]);
+
+        assert!(matches!(
+            merged("SELECT name FROM docs.default.people ORDER BY rank"),
+            Err(PieskieoError::Validation(_))
+        ));
+        assert!(matches!(
+            merged("SELECT * FROM docs.default.people ORDER BY _seq"),
+            Err(PieskieoError::Validation(_))
+        ));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn sql_constant_projection_columns() -> Result<()> {
+        let dir = tempdir().unwrap();
+        let db = PieskieoDb::open(dir.path())?;
+        db.put_doc_ns(
+            Some("default"),
+            Some("people"),
+            Uuid::new_v4(),
+            serde_json::json!({"name": "alice", "age": 30}),
+        )?;
+        let res = db.query_sql(
+            "SELECT name, 'people' AS source, 2 AS version, true AS live FROM docs.default.people",
+        )?;
+        let rows = match res {
+            SqlResult::Select(r) => r,
+            _ => panic!("expected select"),
+        };
+        assert_eq!(rows.len(), 1);
+        assert_eq!(
+            rows[0].1,
+            serde_json::json!({"name": "alice", "source": "people", "version": 2, "live": true})
+        );
+        Ok(())
+    }
+
     #[tokio::test]
     async fn sql_targets_rows_family() -> Result<()> {
         let dir = tempdir().unwrap();
@@ -2798,6 +7438,308 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn sql_family_comes_from_prefix_or_schema() -> Result<()> {
+        let dir = tempdir().unwrap();
+        let db = PieskieoDb::open(dir.path())?;
+        db.put_doc_ns(
+            None,
+            Some("table_events"),
+            Uuid::new_v4(),
+            serde_json::json!({"kind": "click"}),
+        )?;
+        // stored docs alone do not decide; the explicit prefix does
+        match db.query_sql("SELECT * FROM default.table_events")? {
+            SqlResult::Select(rows) => assert!(rows.is_empty()),
+            _ => panic!("expected select"),
+        }
+        match db.query_sql("SELECT * FROM docs.default.table_events")? {
+            SqlResult::Select(rows) => assert_eq!(rows.len(), 1),
+            _ => panic!("expected select"),
+        }
+        // and so does a doc schema
+        db.set_doc_schema(
+            None,
+            Some("table_events"),
+            SchemaDef {
+                fields: HashMap::new(),
+            },
+        )?;
+        match db.query_sql("SELECT * FROM default.table_events")? {
+            SqlResult::Select(rows) => assert_eq!(rows.len(), 1),
+            _ => panic!("expected select"),
+        }
+
+        let dir = tempdir().unwrap();
+        let db = PieskieoDb::open_with_params(
+            dir.path(),
+            VectorParams {
+                sql_prefix_family: false,
+                ..Default::default()
+            },
+        )?;
+        db.query_sql("INSERT INTO default.tbl_log (msg) VALUES ('hi')")?;
+        let docs = db.query_docs_ns(None, Some("tbl_log"), &HashMap::new(), 10, 0);
+        assert_eq!(docs.len(), 1);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn sql_in_subquery_filters_by_membership() -> Result<()> {
+        let dir = tempdir().unwrap();
+        let db = PieskieoDb::open(dir.path())?;
+        let alice = Uuid::new_v4();
+        let bob = Uuid::new_v4();
+        db.put_doc_ns(
+            None,
+            Some("users"),
+            alice,
+            serde_json::json!({"name": "alice", "active": true}),
+        )?;
+        db.put_doc_ns(
+            None,
+            Some("users"),
+            bob,
+            serde_json::json!({"name": "bob", "active": false}),
+        )?;
+        for (user, total) in [(alice, 10), (bob, 20), (alice, 30)] {
+            db.put_doc_ns(
+                None,
+                Some("orders"),
+                Uuid::new_v4(),
+                serde_json::json!({"user_id": user.to_string(), "total": total}),
+            )?;
+        }
+        let totals = |sql: &str| -> Result<Vec<i64>> {
+            match db.query_sql(sql)? {
+                SqlResult::Select(rows) => Ok(rows
+                    .iter()
+                    .map(|(_, v)| v["total"].as_i64().unwrap())
+                    .collect()),
+                _ => panic!("expected select"),
+            }
+        };
+        assert_eq!(
+            totals(
+                "SELECT total FROM docs.default.orders WHERE user_id IN \
+                 (SELECT _id FROM docs.default.users WHERE active = true) ORDER BY total"
+            )?,
+            [10, 30]
+        );
+        assert_eq!(
+            totals(
+                "SELECT total FROM docs.default.orders WHERE user_id NOT IN \
+                 (SELECT _id FROM docs.default.users WHERE active = true)"
+            )?,
+            [20]
+        );
+        let wildcard = "SELECT total FROM docs.default.orders \
+                        WHERE user_id IN (SELECT * FROM docs.default.users)";
+        assert!(db.query_sql(wildcard).is_err());
+
+        // a shard cannot see the other shards' users, so it refuses
+        let dir = tempdir().unwrap();
+        let shard = PieskieoDb::open_with_params(
+            dir.path(),
+            VectorParams {
+                shard_total: 2,
+                ..Default::default()
+            },
+        )?;
+        assert!(matches!(
+            shard.query_sql(
+                "SELECT total FROM docs.default.orders WHERE user_id IN \
+                 (SELECT _id FROM docs.default.users)"
+            ),
+            Err(PieskieoError::Validation(_))
+        ));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn sql_count_distinct_skips_nulls() -> Result<()> {
+        let dir = tempdir().unwrap();
+        let db = PieskieoDb::open(dir.path())?;
+        for doc in [
+            serde_json::json!({"city": "ny"}),
+            serde_json::json!({"city": "sf"}),
+            serde_json::json!({"city": "ny"}),
+            serde_json::json!({"city": null}),
+            serde_json::json!({}),
+        ] {
+            db.put_doc_ns(None, Some("people"), Uuid::new_v4(), doc)?;
+        }
+        let res = db.query_sql(
+            "SELECT COUNT(DISTINCT city) AS cities, COUNT(*) AS n FROM docs.default.people",
+        )?;
+        let rows = match res {
+            SqlResult::Select(r) => r,
+            _ => panic!("expected select"),
+        };
+        assert_eq!(rows[0].1["cities"], 2);
+        assert_eq!(rows[0].1["n"], 5);
+        assert!(db
+            .query_sql("SELECT SUM(DISTINCT city) FROM docs.default.people")
+            .is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn sql_integer_aggregates_stay_exact() -> Result<()> {
+        let dir = tempdir().unwrap();
+        let db = PieskieoDb::open(dir.path())?;
+        for doc in [
+            serde_json::json!({"amount": 50, "big": 9007199254740993_u64}),
+            serde_json::json!({"amount": 100, "big": 1, "price": 1.5}),
+        ] {
+            db.put_doc_ns(None, Some("orders"), Uuid::new_v4(), doc)?;
+        }
+        let res = db.query_sql(
+            "SELECT SUM(amount) AS s, AVG(amount) AS a, SUM(big) AS b, MAX(big) AS m, \
+             SUM(price) AS p FROM docs.default.orders",
+        )?;
+        let rows = match res {
+            SqlResult::Select(r) => r,
+            _ => panic!("expected select"),
+        };
+        let out = &rows[0].1;
+        assert_eq!(out["s"].as_i64(), Some(150));
+        assert_eq!(out["a"].as_f64(), Some(75.0));
+        assert!(out["a"].is_f64());
+        assert_eq!(out["b"].as_u64(), Some(9007199254740994));
+        assert_eq!(out["m"].as_u64(), Some(9007199254740993));
+        assert!(out["p"].is_f64());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn validate_against_reports_without_applying() -> Result<()> {
+        let dir = tempdir().unwrap();
+        let db = PieskieoDb::open(dir.path())?;
+        let ok = Uuid::new_v4();
+        let missing = Uuid::new_v4();
+        let dup_a = Uuid::new_v4();
+        let dup_b = Uuid::new_v4();
+        for (id, doc) in [
+            (ok, serde_json::json!({"email": "a@x"})),
+            (missing, serde_json::json!({"name": "b"})),
+            (dup_a, serde_json::json!({"email": "c@x"})),
+            (dup_b, serde_json::json!({"email": "c@x"})),
+        ] {
+            db.put_doc_ns(None, Some("users"), id, doc)?;
+        }
+
+        let mut fields = HashMap::new();
+        fields.insert(
+            "email".to_string(),
+            SchemaField {
+                required: true,
+                unique: true,
+                r#type: None,
+            },
+        );
+        let def = SchemaDef { fields };
+        let mut bad: Vec<Uuid> = db
+            .validate_against(false, None, Some("users"), &def)
+            .iter()
+            .map(|v| v.id)
+            .collect();
+        bad.sort();
+        let mut want = vec![missing, dup_a, dup_b];
+        want.sort();
+        assert_eq!(bad, want);
+        // nothing was applied: a doc without email is still accepted
+        db.put_doc_ns(None, Some("users"), Uuid::new_v4(), serde_json::json!({}))?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn sql_insert_and_update_null_literals() -> Result<()> {
+        let dir = tempdir().unwrap();
+        let db = PieskieoDb::open(dir.path())?;
+        let id = Uuid::new_v4();
+        db.query_sql(&format!(
+            "INSERT INTO docs.default.people (_id, name, nickname) VALUES ('{id}', 'alice', NULL)"
+        ))?;
+        let doc = db.get_doc_ns(Some("default"), Some("people"), &id).unwrap();
+        assert!(doc["nickname"].is_null());
+
+        db.query_sql("UPDATE docs.default.people SET name = NULL WHERE nickname = NULL")?;
+        let doc = db.get_doc_ns(Some("default"), Some("people"), &id).unwrap();
+        assert!(doc["name"].is_null());
+
+        let mut fields = HashMap::new();
+        fields.insert(
+            "name".to_string(),
+            SchemaField {
+                required: true,
+                unique: false,
+                r#type: None,
+            },
+        );
+        db.set_doc_schema(Some("default"), Some("strict"), SchemaDef { fields })?;
+        let err = db
+            .query_sql("INSERT INTO docs.default.strict (name) VALUES (NULL)")
+            .unwrap_err();
+        assert!(matches!(err, PieskieoError::Validation(_)));
+        let err = db
+            .query_sql("INSERT INTO docs.default.strict (other) VALUES (1)")
+            .unwrap_err();
+        assert!(matches!(err, PieskieoError::Validation(_)));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn sql_update_reports_ids() -> Result<()> {
+        let dir = tempdir().unwrap();
+        let db = PieskieoDb::open(dir.path())?;
+        let a = Uuid::new_v4();
+        db.put_doc_ns(None, Some("people"), a, serde_json::json!({"age": 30}))?;
+        db.put_doc_ns(
+            None,
+            Some("people"),
+            Uuid::new_v4(),
+            serde_json::json!({"age": 20}),
+        )?;
+
+        match db.query_sql("UPDATE docs.default.people SET seen = true WHERE age > 25")? {
+            SqlResult::Update { affected, ids } => {
+                assert_eq!(affected, 1);
+                assert_eq!(ids, vec![a]);
+            }
+            _ => panic!("expected update"),
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn sql_integer_literals_stay_integral() -> Result<()> {
+        let dir = tempdir().unwrap();
+        let db = PieskieoDb::open(dir.path())?;
+        let a = Uuid::new_v4();
+        let b = Uuid::new_v4();
+        db.query_sql(&format!(
+            "INSERT INTO docs.default.items (_id, qty, price) VALUES ('{a}', 42, 9.5)"
+        ))?;
+        db.put_doc_ns(
+            Some("default"),
+            Some("items"),
+            b,
+            serde_json::json!({"qty": 7}),
+        )?;
+        let doc = db.get_doc_ns(Some("default"), Some("items"), &a).unwrap();
+        assert_eq!(doc["qty"].as_i64(), Some(42));
+        assert!(doc["price"].is_f64());
+
+        let rows = match db.query_sql("SELECT * FROM docs.default.items WHERE qty = 7")? {
+            SqlResult::Select(r) => r,
+            _ => panic!("expected select"),
+        };
+        assert_eq!(rows.len(), 1);
+        assert_eq!(rows[0].0, b);
+        Ok(())
+    }
+
     #[tokio::test]
     async fn pql_join_and_aggregate() -> Result<()> {
         let dir = tempdir().unwrap();
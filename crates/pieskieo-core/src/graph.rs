@@ -1,6 +1,6 @@
 use parking_lot::RwLock;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
 use uuid::Uuid;
 
@@ -9,11 +9,69 @@ pub struct Edge {
     pub src: Uuid,
     pub dst: Uuid,
     pub weight: f32,
+    /// made by vector auto-linking; replaced when the embedding changes
+    #[serde(default)]
+    pub auto: bool,
+}
+
+/// Edges by source, plus the sources pointing at each target so a node's
+/// incoming edges are found without scanning the whole graph.
+#[derive(Default)]
+struct Adjacency {
+    out: HashMap<Uuid, Vec<Edge>>,
+    incoming: HashMap<Uuid, HashSet<Uuid>>,
+}
+
+impl Adjacency {
+    fn push(&mut self, edge: Edge) {
+        self.incoming.entry(edge.dst).or_default().insert(edge.src);
+        self.out.entry(edge.src).or_default().push(edge);
+    }
+
+    /// Forget that `src` points at `dst`, once `src`'s edge to it is gone.
+    fn unlink(&mut self, src: Uuid, dst: Uuid) {
+        if let Some(srcs) = self.incoming.get_mut(&dst) {
+            srcs.remove(&src);
+            if srcs.is_empty() {
+                self.incoming.remove(&dst);
+            }
+        }
+    }
+
+    /// Drop the edges leaving `src` that `remove` selects; returns how many.
+    fn retain_out(&mut self, src: Uuid, remove: impl Fn(&Edge) -> bool) -> usize {
+        let Some(edges) = self.out.get_mut(&src) else {
+            return 0;
+        };
+        let mut gone = Vec::new();
+        edges.retain(|e| {
+            let hit = remove(e);
+            if hit {
+                gone.push(e.dst);
+            }
+            !hit
+        });
+        if edges.is_empty() {
+            self.out.remove(&src);
+        }
+        for dst in &gone {
+            self.unlink(src, *dst);
+        }
+        gone.len()
+    }
+
+    fn remove_node(&mut self, id: Uuid) -> usize {
+        let mut removed = self.retain_out(id, |_| true);
+        for src in self.incoming.remove(&id).unwrap_or_default() {
+            removed += self.retain_out(src, |e| e.dst == id);
+        }
+        removed
+    }
 }
 
 #[derive(Default, Clone)]
 pub struct GraphStore {
-    adj: Arc<RwLock<HashMap<Uuid, Vec<Edge>>>>,
+    adj: Arc<RwLock<Adjacency>>,
 }
 
 impl GraphStore {
@@ -21,19 +79,126 @@ impl GraphStore {
         Self::default()
     }
 
+    /// Upsert: a `(src, dst)` pair holds at most one edge, so adding it again
+    /// replaces the weight in place and keeps its position among `src`'s edges.
+    /// An auto-link edge re-added here becomes a user edge.
     pub fn add_edge(&self, src: Uuid, dst: Uuid, weight: f32) {
         let mut adj = self.adj.write();
-        let entry = adj.entry(src).or_insert_with(Vec::new);
-        if let Some(existing) = entry.iter_mut().find(|e| e.dst == dst) {
+        let existing = adj
+            .out
+            .get_mut(&src)
+            .and_then(|edges| edges.iter_mut().find(|e| e.dst == dst));
+        if let Some(existing) = existing {
             existing.weight = weight;
+            existing.auto = false;
         } else {
-            entry.push(Edge { src, dst, weight });
+            adj.push(Edge {
+                src,
+                dst,
+                weight,
+                auto: false,
+            });
+        }
+    }
+
+    /// Like `add_edge` for auto-link edges, but never touches a user edge
+    /// already joining the pair.
+    pub fn add_auto_edge(&self, src: Uuid, dst: Uuid, weight: f32) {
+        let mut adj = self.adj.write();
+        let existing = adj
+            .out
+            .get_mut(&src)
+            .and_then(|edges| edges.iter_mut().find(|e| e.dst == dst));
+        match existing {
+            Some(existing) if existing.auto => existing.weight = weight,
+            Some(_) => {}
+            None => adj.push(Edge {
+                src,
+                dst,
+                weight,
+                auto: true,
+            }),
+        }
+    }
+
+    pub fn edge_weight(&self, src: Uuid, dst: Uuid) -> Option<f32> {
+        self.adj
+            .read()
+            .out
+            .get(&src)
+            .and_then(|edges| edges.iter().find(|e| e.dst == dst))
+            .map(|e| e.weight)
+    }
+
+    /// True if `id` is the source or target of any edge.
+    pub fn contains(&self, id: &Uuid) -> bool {
+        let adj = self.adj.read();
+        adj.out.contains_key(id) || adj.incoming.contains_key(id)
+    }
+
+    /// Drop every edge where `id` is src or dst; returns how many were removed.
+    pub fn remove_node(&self, id: Uuid) -> usize {
+        self.adj.write().remove_node(id)
+    }
+
+    /// `remove_node` for each of `ids` under one lock; returns how many edges
+    /// were removed in total.
+    pub fn remove_nodes(&self, ids: &HashSet<Uuid>) -> usize {
+        let mut adj = self.adj.write();
+        ids.iter().map(|id| adj.remove_node(*id)).sum()
+    }
+
+    /// True if an auto-link edge starts or ends at `id`.
+    pub fn has_auto_edges(&self, id: &Uuid) -> bool {
+        let adj = self.adj.read();
+        adj.out
+            .get(id)
+            .is_some_and(|edges| edges.iter().any(|e| e.auto))
+            || adj.incoming.get(id).is_some_and(|srcs| {
+                srcs.iter().any(|src| {
+                    adj.out
+                        .get(src)
+                        .is_some_and(|edges| edges.iter().any(|e| e.auto && e.dst == *id))
+                })
+            })
+    }
+
+    /// Drop the auto-link edges starting or ending at `id`, keeping user
+    /// edges; returns how many were removed.
+    pub fn remove_auto_edges(&self, id: Uuid) -> usize {
+        let mut adj = self.adj.write();
+        let mut removed = adj.retain_out(id, |e| e.auto);
+        let srcs: Vec<Uuid> = adj
+            .incoming
+            .get(&id)
+            .map(|srcs| srcs.iter().copied().collect())
+            .unwrap_or_default();
+        for src in srcs {
+            removed += adj.retain_out(src, |e| e.auto && e.dst == id);
         }
+        removed
+    }
+
+    /// Every edge, grouped by source.
+    pub fn edges(&self) -> Vec<Edge> {
+        self.adj.read().out.values().flatten().cloned().collect()
+    }
+
+    /// Weakly connected components (edge direction ignored), largest first;
+    /// only nodes touching an edge are counted.
+    pub fn components(&self) -> Vec<Vec<Uuid>> {
+        let adj = self.adj.read();
+        connected(
+            adj.out
+                .iter()
+                .flat_map(|(src, edges)| edges.iter().map(move |e| (*src, e.dst))),
+        )
     }
 
     pub fn neighbors(&self, id: Uuid, limit: usize) -> Vec<Edge> {
         let adj = self.adj.read();
-        adj.get(&id)
+        adj.out
+            .get(&id)
             .map(|edges| edges.iter().take(limit).cloned().collect())
             .unwrap_or_default()
     }
@@ -78,3 +243,39 @@ impl GraphStore {
         out
     }
 }
+
+/// Group the endpoints of `pairs` into connected components, largest first.
+/// Also merges components computed separately, e.g. on each shard, by linking
+/// every member to the first one.
+pub fn connected(pairs: impl IntoIterator<Item = (Uuid, Uuid)>) -> Vec<Vec<Uuid>> {
+    fn root(parent: &mut [usize], mut i: usize) -> usize {
+        while parent[i] != i {
+            parent[i] = parent[parent[i]];
+            i = parent[i];
+        }
+        i
+    }
+    let mut slot: HashMap<Uuid, usize> = HashMap::new();
+    let mut nodes: Vec<Uuid> = Vec::new();
+    let mut parent: Vec<usize> = Vec::new();
+    for (a, b) in pairs {
+        let [a, b] = [a, b].map(|id| {
+            *slot.entry(id).or_insert_with(|| {
+                nodes.push(id);
+                parent.push(parent.len());
+                parent.len() - 1
+            })
+        });
+        let (ra, rb) = (root(&mut parent, a), root(&mut parent, b));
+        if ra != rb {
+            parent[rb] = ra;
+        }
+    }
+    let mut groups: HashMap<usize, Vec<Uuid>> = HashMap::new();
+    for (i, id) in nodes.into_iter().enumerate() {
+        groups.entry(root(&mut parent, i)).or_default().push(id);
+    }
+    let mut out: Vec<Vec<Uuid>> = groups.into_values().collect();
+    out.sort_by(|a, b| b.len().cmp(&a.len()).then_with(|| a[0].cmp(&b[0])));
+    out
+}
@@ -4,7 +4,14 @@ pub mod graph;
 pub mod vector;
 pub mod wal;
 
-pub use engine::{PieskieoDb, SchemaDef, SchemaField, SqlResult, VectorParams};
+pub use engine::{
+    key_id, merge_patch, shard_index, strip_nulls, BatchFamily, BatchOp, LinkWeight, NamespaceDrop,
+    PieskieoDb, QueryStats, SchemaDef, SchemaField, SchemaViolation, SqlAnalysis, SqlResult,
+    VectorParams,
+};
 pub use error::PieskieoError;
 pub use graph::{Edge, GraphStore};
-pub use vector::{VectorIndex, VectorSearchResult};
+pub use vector::{
+    decode_snapshot, encode_snapshot, SearchAfter, SnapshotEntry, VectorIdRange, VectorIndex,
+    VectorMemory, VectorMeta, VectorSearchResult,
+};
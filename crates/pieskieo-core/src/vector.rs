@@ -3,13 +3,14 @@ use hnsw_rs::{hnswio::HnswIo, prelude::*};
 use parking_lot::RwLock;
 use rayon::prelude::*;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use serde_json::Value;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::fs::File;
 use std::io::{BufWriter, Write};
 use std::mem::transmute;
 use std::path::Path;
 use std::sync::{
-    atomic::{AtomicUsize, Ordering},
+    atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
     Arc,
 };
 use uuid::Uuid;
@@ -18,15 +19,213 @@ use uuid::Uuid;
 pub struct VectorSearchResult {
     pub id: Uuid,
     pub score: f32,
+    /// filled in on request after ranking; never set by the index itself
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub vector: Option<Vec<f32>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub meta: Option<VectorMeta>,
+    /// extra per-metric scores, keyed by metric name; filled in on request
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scores: Option<BTreeMap<String, f32>>,
 }
 
-#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+/// Per-vector metadata; values keep their JSON type so numbers can be
+/// range-filtered.
+pub type VectorMeta = HashMap<String, Value>;
+
+/// Leads snapshots written since metadata became typed JSON; older files
+/// start with a bincode length and carry string-only metadata.
+const SNAPSHOT_V3: &[u8] = b"PVS3";
+
+/// Metadata as JSON text, for the bincode formats that cannot carry `Value`.
+pub(crate) fn meta_to_json(meta: Option<&VectorMeta>) -> Result<Option<String>> {
+    meta.map(serde_json::to_string)
+        .transpose()
+        .map_err(PieskieoError::from)
+}
+
+pub(crate) fn meta_from_json(text: Option<String>) -> Result<Option<VectorMeta>> {
+    text.map(|t| serde_json::from_str(&t))
+        .transpose()
+        .map_err(PieskieoError::from)
+}
+
+/// One vector as carried by a snapshot.
+pub type SnapshotEntry = (Uuid, Vec<f32>, Option<VectorMeta>);
+
+/// Encode `entries` in the current snapshot format.
+pub fn encode_snapshot(entries: Vec<SnapshotEntry>) -> Result<Vec<u8>> {
+    let data: Vec<(Uuid, Vec<f32>, Option<String>)> = entries
+        .into_iter()
+        .map(|(id, v, meta)| Ok((id, v, meta_to_json(meta.as_ref())?)))
+        .collect::<Result<_>>()?;
+    let mut out = SNAPSHOT_V3.to_vec();
+    bincode::serialize_into(&mut out, &data)?;
+    Ok(out)
+}
+
+/// Decode a snapshot of any version. V3 carries typed metadata; V2 (string
+/// metadata) and V1 (none) are migrated.
+pub fn decode_snapshot(bytes: &[u8]) -> Result<Vec<SnapshotEntry>> {
+    if let Some(body) = bytes.strip_prefix(SNAPSHOT_V3) {
+        let v3: Vec<(Uuid, Vec<f32>, Option<String>)> = bincode::deserialize(body)?;
+        v3.into_iter()
+            .map(|(id, vec, meta)| Ok((id, vec, meta_from_json(meta)?)))
+            .collect()
+    } else if let Ok(v2) =
+        bincode::deserialize::<Vec<(Uuid, Vec<f32>, Option<HashMap<String, String>>)>>(bytes)
+    {
+        Ok(v2
+            .into_iter()
+            .map(|(id, vec, meta)| (id, vec, meta.map(meta_from_strings)))
+            .collect())
+    } else if let Ok(v1) = bincode::deserialize::<Vec<(Uuid, Vec<f32>)>>(bytes) {
+        Ok(v1.into_iter().map(|(id, vec)| (id, vec, None)).collect())
+    } else {
+        Err(PieskieoError::NotFound)
+    }
+}
+
+/// Metadata stored before values were typed: every value becomes a JSON string.
+pub(crate) fn meta_from_strings(meta: HashMap<String, String>) -> VectorMeta {
+    meta.into_iter()
+        .map(|(k, v)| (k, Value::String(v)))
+        .collect()
+}
+
+/// Same rules as doc filters: plain values match by typed equality and
+/// `{"$gt": ..}`-style objects compare.
+fn meta_matches(meta: Option<&VectorMeta>, filters: &HashMap<String, Value>) -> bool {
+    let Some(m) = meta else {
+        return false;
+    };
+    filters.iter().all(|(k, cond)| {
+        m.get(k)
+            .map(|v| crate::engine::value_compare(v, cond))
+            .unwrap_or(false)
+    })
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum VectorMetric {
     L2,
     Cosine,
     Dot,
 }
 
+impl VectorMetric {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "l2" => Some(VectorMetric::L2),
+            "cosine" => Some(VectorMetric::Cosine),
+            "dot" => Some(VectorMetric::Dot),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            VectorMetric::L2 => "l2",
+            VectorMetric::Cosine => "cosine",
+            VectorMetric::Dot => "dot",
+        }
+    }
+
+    /// Exact score of `v` against `query`, higher is closer: negated squared
+    /// distance for l2, cosine similarity, or the raw inner product.
+    pub fn score(self, query: &[f32], v: &[f32]) -> f32 {
+        match self {
+            VectorMetric::L2 => -l2(query, v),
+            VectorMetric::Dot => dot(query, v),
+            VectorMetric::Cosine => {
+                let norms = dot(query, query).sqrt() * dot(v, v).sqrt();
+                if norms > 0.0 {
+                    dot(query, v) / norms
+                } else {
+                    0.0
+                }
+            }
+        }
+    }
+
+    /// Maps a `score` from this metric into [0, 1], higher still closer:
+    /// cosine `(1 + s) / 2`, l2 `1 / (1 + d)` for the negated squared
+    /// distance `-d`, and a logistic for dot products, which are unbounded.
+    pub fn normalize(self, score: f32) -> f32 {
+        match self {
+            VectorMetric::L2 => 1.0 / (1.0 + (-score).max(0.0)),
+            VectorMetric::Cosine => ((1.0 + score) / 2.0).clamp(0.0, 1.0),
+            VectorMetric::Dot => 1.0 / (1.0 + (-score).exp()),
+        }
+    }
+}
+
+/// Where the previous page of a search ended. Hits rank by score, highest
+/// first, then by id (see `rank`), so the next page is every hit after this.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct SearchAfter {
+    pub score: f32,
+    pub id: Uuid,
+}
+
+impl SearchAfter {
+    pub fn of(hit: &VectorSearchResult) -> Self {
+        Self {
+            score: hit.score,
+            id: hit.id,
+        }
+    }
+
+    /// Whether `hit` ranks strictly after the cursor.
+    pub fn admits(&self, hit: &VectorSearchResult) -> bool {
+        hit.score < self.score || (hit.score == self.score && hit.id > self.id)
+    }
+}
+
+/// Search order: higher score first, ties by id so pages split them the same
+/// way every time.
+pub fn rank(a: &VectorSearchResult, b: &VectorSearchResult) -> std::cmp::Ordering {
+    b.score
+        .partial_cmp(&a.score)
+        .unwrap_or(std::cmp::Ordering::Equal)
+        .then_with(|| a.id.cmp(&b.id))
+}
+
+/// Inclusive `from..=to` range of vector ids, compared byte by byte, so ids
+/// that encode a tenant or shard in their high bytes can be searched per range.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct VectorIdRange {
+    pub from: Uuid,
+    pub to: Uuid,
+}
+
+impl VectorIdRange {
+    pub fn contains(&self, id: &Uuid) -> bool {
+        let id = id.as_bytes();
+        self.from.as_bytes() <= id && id <= self.to.as_bytes()
+    }
+}
+
+/// HNSW preallocation for new indexes unless `VectorParams::initial_capacity` says otherwise.
+pub const DEFAULT_INITIAL_CAPACITY: usize = 1024;
+
+/// Per-point HNSW cost: a layer-0 neighbour list of `2 * M` (M = 16) links
+/// at ~24 bytes each plus the point header; upper layers add little.
+const HNSW_POINT_BYTES: u64 = 2 * 16 * 24 + 64;
+/// Uuid key, `Vec` header and hash-table slack per primary-store entry.
+const VECTOR_ENTRY_BYTES: u64 = 48;
+
+/// Approximate resident size of one index, from counts rather than the allocator.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct VectorMemory {
+    /// everything: vectors, id maps, metadata, HNSW links and both kinds of backing
+    pub bytes: u64,
+    /// leaked backing slices the current HNSW graph still points into
+    pub owned_bytes: u64,
+    /// backing slices left behind by replaced graphs; never freed
+    pub leaked_bytes: u64,
+}
+
 /// In-memory vector store + optional HNSW ANN accelerator.
 pub struct VectorIndex {
     pub(crate) inner: Arc<RwLock<HashMap<Uuid, Vec<f32>>>>,
@@ -40,8 +239,17 @@ pub struct VectorIndex {
     pub(crate) tombstones: Arc<RwLock<HashMap<Uuid, ()>>>,
     pub(crate) ef_construction: AtomicUsize,
     pub(crate) ef_search: AtomicUsize,
+    /// largest HNSW preallocation; the graph still grows past it when full
     pub(crate) max_elements: usize,
-    pub(crate) meta: Arc<RwLock<HashMap<Uuid, HashMap<String, String>>>>,
+    /// HNSW preallocation for a fresh index; rebuilds size to twice the live count
+    pub(crate) initial_capacity: usize,
+    pub(crate) meta: Arc<RwLock<HashMap<Uuid, VectorMeta>>>,
+    /// set by inserts/deletes, cleared when HNSW is rebuilt
+    pub(crate) dirty: AtomicBool,
+    /// how long the last HNSW rebuild took; 0 until one has run
+    pub(crate) last_rebuild_us: AtomicU64,
+    /// bytes of backing slices dropped from `owned_store` but still leaked
+    pub(crate) leaked_bytes: AtomicU64,
 }
 
 impl VectorIndex {
@@ -59,7 +267,11 @@ impl VectorIndex {
             ef_construction: AtomicUsize::new(200),
             ef_search: AtomicUsize::new(50),
             max_elements: 100_000,
+            initial_capacity: DEFAULT_INITIAL_CAPACITY,
             meta: Arc::new(RwLock::new(HashMap::new())),
+            dirty: AtomicBool::new(false),
+            last_rebuild_us: AtomicU64::new(0),
+            leaked_bytes: AtomicU64::new(0),
         }
     }
 
@@ -68,15 +280,25 @@ impl VectorIndex {
         ef_construction: usize,
         ef_search: usize,
         max_elements: usize,
+        initial_capacity: usize,
     ) -> Self {
         let mut v = Self::new(metric);
         v.ef_construction
             .store(ef_construction.max(4), Ordering::SeqCst);
         v.ef_search.store(ef_search.max(4), Ordering::SeqCst);
         v.max_elements = max_elements.max(1_000);
+        v.initial_capacity = initial_capacity.max(1);
         v
     }
 
+    /// Elements to preallocate for an HNSW holding `live` vectors: room to
+    /// double, never below `initial_capacity` nor above `max_elements`.
+    pub(crate) fn hnsw_capacity(&self, live: usize) -> usize {
+        live.saturating_mul(2)
+            .max(self.initial_capacity)
+            .min(self.max_elements)
+    }
+
     pub fn from_shared(
         inner: Arc<RwLock<HashMap<Uuid, Vec<f32>>>>,
         dim: Arc<RwLock<Option<usize>>>,
@@ -90,7 +312,7 @@ impl VectorIndex {
         ef_construction: AtomicUsize,
         ef_search: AtomicUsize,
         max_elements: usize,
-        meta: Arc<RwLock<HashMap<Uuid, HashMap<String, String>>>>,
+        meta: Arc<RwLock<HashMap<Uuid, VectorMeta>>>,
     ) -> Self {
         Self {
             inner,
@@ -105,38 +327,16 @@ impl VectorIndex {
             ef_construction,
             ef_search,
             max_elements,
+            initial_capacity: DEFAULT_INITIAL_CAPACITY,
             meta,
+            dirty: AtomicBool::new(false),
+            last_rebuild_us: AtomicU64::new(0),
+            leaked_bytes: AtomicU64::new(0),
         }
     }
 
-    pub fn insert(
-        &self,
-        id: Uuid,
-        mut vector: Vec<f32>,
-        meta: Option<HashMap<String, String>>,
-    ) -> Result<()> {
-        // Enforce consistent dimensionality.
-        {
-            let mut dim_guard = self.dim.write();
-            if let Some(dim) = *dim_guard {
-                if vector.len() != dim {
-                    return Err(PieskieoError::NotFound);
-                }
-            } else {
-                *dim_guard = Some(vector.len());
-            }
-        }
-
-        if matches!(self.metric, VectorMetric::Cosine) {
-            normalize(&mut vector);
-        }
-
-        // Update primary store.
-        self.inner.write().insert(id, vector.clone());
-        if let Some(m) = meta {
-            self.meta.write().insert(id, m);
-        }
-        self.tombstones.write().remove(&id);
+    pub fn insert(&self, id: Uuid, vector: Vec<f32>, meta: Option<VectorMeta>) -> Result<()> {
+        let vector = self.store_vector(id, vector, meta)?;
 
         // Assign stable internal ID.
         let internal = {
@@ -167,7 +367,7 @@ impl VectorIndex {
                 let max_layer = 16;
                 let hnsw = Hnsw::<f32, DistL2>::new(
                     16,
-                    self.max_elements,
+                    self.hnsw_capacity(1),
                     max_layer,
                     self.ef_construction.load(Ordering::SeqCst),
                     DistL2 {},
@@ -178,24 +378,128 @@ impl VectorIndex {
                 hnsw.insert((leaked, internal));
             }
         }
+        self.dirty.store(true, Ordering::SeqCst);
         Ok(())
     }
 
+    /// `insert` without the HNSW step, for WAL replay: the graph is built
+    /// once by `finish_deferred` instead of point by point.
+    pub(crate) fn insert_deferred(
+        &self,
+        id: Uuid,
+        vector: Vec<f32>,
+        meta: Option<VectorMeta>,
+    ) -> Result<()> {
+        self.store_vector(id, vector, meta)?;
+        self.dirty.store(true, Ordering::SeqCst);
+        Ok(())
+    }
+
+    /// `delete` for WAL replay; never rebuilds mid-replay.
+    pub(crate) fn delete_deferred(&self, id: &Uuid) {
+        self.inner.write().remove(id);
+        self.tombstones.write().insert(*id, ());
+        self.dirty.store(true, Ordering::SeqCst);
+    }
+
+    /// Builds the graph for vectors replayed with `insert_deferred`; a no-op
+    /// when one already exists (e.g. loaded from a snapshot).
+    pub(crate) fn finish_deferred(&self) -> Result<()> {
+        if self.hnsw.read().is_some() {
+            return Ok(());
+        }
+        self.rebuild_hnsw()
+    }
+
+    /// Dimension check, cosine normalisation and the primary store; returns
+    /// the vector as stored.
+    fn store_vector(
+        &self,
+        id: Uuid,
+        mut vector: Vec<f32>,
+        meta: Option<VectorMeta>,
+    ) -> Result<Vec<f32>> {
+        // Enforce consistent dimensionality.
+        {
+            let mut dim_guard = self.dim.write();
+            if let Some(dim) = *dim_guard {
+                if vector.len() != dim {
+                    return Err(PieskieoError::NotFound);
+                }
+            } else {
+                *dim_guard = Some(vector.len());
+            }
+        }
+
+        if matches!(self.metric, VectorMetric::Cosine) {
+            normalize(&mut vector);
+        }
+
+        // Update primary store.
+        self.inner.write().insert(id, vector.clone());
+        if let Some(m) = meta {
+            self.meta.write().insert(id, m);
+        }
+        self.tombstones.write().remove(&id);
+        Ok(vector)
+    }
+
     pub fn delete(&self, id: &Uuid) {
         self.inner.write().remove(id);
         self.tombstones.write().insert(*id, ());
+        self.dirty.store(true, Ordering::SeqCst);
         self.maybe_rebuild();
     }
 
     pub fn search(&self, query: &[f32], k: usize) -> Result<Vec<VectorSearchResult>> {
-        self.search_filtered(query, k, None)
+        self.search_filtered(query, k, None, None)
     }
 
+    /// Exact top-`k`, starting after `after` when paging.
     pub fn search_filtered(
         &self,
         query: &[f32],
         k: usize,
-        filter_meta: Option<HashMap<String, String>>,
+        filter_meta: Option<HashMap<String, Value>>,
+        after: Option<&SearchAfter>,
+    ) -> Result<Vec<VectorSearchResult>> {
+        self.search_exact(query, k, filter_meta, None, after)
+    }
+
+    /// Exact search restricted to `ids`; cheap when the candidate set is small.
+    pub fn search_ids(
+        &self,
+        query: &[f32],
+        k: usize,
+        ids: &HashSet<Uuid>,
+        filter_meta: Option<HashMap<String, Value>>,
+    ) -> Result<Vec<VectorSearchResult>> {
+        self.search_exact(query, k, filter_meta, Some(ids), None)
+    }
+
+    /// Live ids inside `range`, or `None` once there are more than `limit`,
+    /// i.e. too many to be worth scoring exactly instead of through HNSW.
+    pub fn ids_in_range(&self, range: &VectorIdRange, limit: usize) -> Option<HashSet<Uuid>> {
+        let tomb = self.tombstones.read();
+        let mut out = HashSet::new();
+        for id in self.inner.read().keys() {
+            if range.contains(id) && !tomb.contains_key(id) {
+                if out.len() == limit {
+                    return None;
+                }
+                out.insert(*id);
+            }
+        }
+        Some(out)
+    }
+
+    fn search_exact(
+        &self,
+        query: &[f32],
+        k: usize,
+        filter_meta: Option<HashMap<String, Value>>,
+        ids: Option<&HashSet<Uuid>>,
+        after: Option<&SearchAfter>,
     ) -> Result<Vec<VectorSearchResult>> {
         if query.is_empty() {
             return Err(PieskieoError::NotFound);
@@ -218,7 +522,13 @@ impl VectorIndex {
         // Snapshot to minimize lock hold during compute-heavy loop.
         let snapshot: Vec<(Uuid, Vec<f32>)> = {
             let guard = self.inner.read();
-            guard.iter().map(|(id, v)| (*id, v.clone())).collect()
+            match ids {
+                Some(ids) => ids
+                    .iter()
+                    .filter_map(|id| guard.get(id).map(|v| (*id, v.clone())))
+                    .collect(),
+                None => guard.iter().map(|(id, v)| (*id, v.clone())).collect(),
+            }
         };
 
         // Parallel distance computation for better throughput on large collections.
@@ -230,21 +540,22 @@ impl VectorIndex {
                     VectorMetric::Cosine => dot(&qbuf, v),
                     VectorMetric::Dot => dot(&qbuf, v),
                 };
-                VectorSearchResult { id: *id, score: d }
+                VectorSearchResult {
+                    id: *id,
+                    score: d,
+                    vector: None,
+                    meta: None,
+                    scores: None,
+                }
             })
             .collect();
-        scores.sort_by(|a, b| b.score.partial_cmp(&a.score).unwrap());
+        if let Some(after) = after {
+            scores.retain(|hit| after.admits(hit));
+        }
+        scores.sort_by(rank);
         if let Some(filters) = filter_meta {
-            scores.retain(|hit| {
-                let meta = self.meta.read();
-                if let Some(m) = meta.get(&hit.id) {
-                    filters
-                        .iter()
-                        .all(|(k, v)| m.get(k).map(|mv| mv == v).unwrap_or(false))
-                } else {
-                    false
-                }
-            });
+            let meta = self.meta.read();
+            scores.retain(|hit| meta_matches(meta.get(&hit.id), &filters));
         }
 
         scores.truncate(k);
@@ -253,52 +564,65 @@ impl VectorIndex {
 
     /// Attempt ANN search using HNSW; fall back to exact if unavailable.
     pub fn search_ann(&self, query: &[f32], k: usize) -> Result<Vec<VectorSearchResult>> {
-        self.search_ann_filtered(query, k, None)
+        self.search_ann_filtered(query, k, None, None)
     }
 
+    /// ANN top-`k`. With `after`, the graph is searched for ever more
+    /// neighbours until `k` of them rank past the cursor, so later pages cost
+    /// more and, being approximate, may skip or repeat a hit near the boundary.
     pub fn search_ann_filtered(
         &self,
         query: &[f32],
         k: usize,
-        filter_meta: Option<HashMap<String, String>>,
+        filter_meta: Option<HashMap<String, Value>>,
+        after: Option<&SearchAfter>,
     ) -> Result<Vec<VectorSearchResult>> {
         let mut qbuf: Vec<f32> = query.to_vec();
         if matches!(self.metric, VectorMetric::Cosine) {
             normalize(&mut qbuf);
         }
         if let Some(ref hnsw) = *self.hnsw.read() {
-            let results = hnsw.search(&qbuf, k, self.ef_search.load(Ordering::SeqCst));
-            let hits = results
-                .iter()
-                .filter_map(|r| {
-                    let rev = self.rev_map.read();
-                    rev.get(r.d_id).copied().map(|uid| VectorSearchResult {
-                        id: uid,
-                        score: -(r.distance as f32),
+            let ef = self.ef_search.load(Ordering::SeqCst);
+            let live = self.inner.read().len().max(k);
+            let mut want = if after.is_some() { k * 2 } else { k };
+            loop {
+                let results = hnsw.search(&qbuf, want, ef.max(want));
+                let exhausted = results.len() < want || want >= live;
+                let hits = results
+                    .iter()
+                    .filter_map(|r| {
+                        let rev = self.rev_map.read();
+                        rev.get(r.d_id).copied().map(|uid| VectorSearchResult {
+                            id: uid,
+                            score: -(r.distance as f32),
+                            vector: None,
+                            meta: None,
+                            scores: None,
+                        })
                     })
-                })
-                .filter(|r| !self.tombstones.read().contains_key(&r.id))
-                .collect();
-            let mut filtered: Vec<_> = match filter_meta {
-                None => hits,
-                Some(filters) => hits
-                    .into_iter()
-                    .filter(|hit| {
+                    .filter(|r| !self.tombstones.read().contains_key(&r.id))
+                    .filter(|r| after.is_none_or(|a| a.admits(r)))
+                    .collect();
+                let mut filtered: Vec<_> = match &filter_meta {
+                    None => hits,
+                    Some(filters) => {
                         let meta = self.meta.read();
-                        if let Some(m) = meta.get(&hit.id) {
-                            filters
-                                .iter()
-                                .all(|(k, v)| m.get(k).map(|mv| mv == v).unwrap_or(false))
-                        } else {
-                            false
-                        }
-                    })
-                    .collect(),
-            };
-            filtered.truncate(k);
-            return Ok(filtered);
+                        hits.into_iter()
+                            .filter(|hit| meta_matches(meta.get(&hit.id), filters))
+                            .collect()
+                    }
+                };
+                if after.is_none() || filtered.len() >= k || exhausted {
+                    if after.is_some() {
+                        filtered.sort_by(rank);
+                    }
+                    filtered.truncate(k);
+                    return Ok(filtered);
+                }
+                want = (want * 2).min(live);
+            }
         }
-        self.search_filtered(query, k, filter_meta)
+        self.search_filtered(query, k, filter_meta, after)
     }
 
     fn maybe_rebuild(&self) {
@@ -311,13 +635,16 @@ impl VectorIndex {
 
     /// Rebuild HNSW from current live vectors (drops tombstoned ids).
     pub fn rebuild_hnsw(&self) -> Result<()> {
+        // cleared up front so writes racing the rebuild mark the index again
+        self.dirty.store(false, Ordering::SeqCst);
         if self.dim.read().is_none() {
             return Ok(()); // nothing to rebuild
         }
+        let start = std::time::Instant::now();
         let max_layer = 16;
         let hnsw = Hnsw::<f32, DistL2>::new(
             16,
-            self.max_elements,
+            self.hnsw_capacity(self.inner.read().len()),
             max_layer,
             self.ef_construction.load(Ordering::SeqCst),
             DistL2 {},
@@ -358,15 +685,18 @@ impl VectorIndex {
         // replace owned_store and hnsw atomically
         {
             let mut store = self.owned_store.write();
+            self.retire_owned(&mut store);
             *store = owned;
         }
         *self.hnsw.write() = Some(hnsw);
+        self.last_rebuild_us
+            .store(start.elapsed().as_micros() as u64, Ordering::Relaxed);
         Ok(())
     }
 
-    /// Persist vectors (ids + optional metadata) to a snapshot file for fast reload.
-    pub fn save_snapshot(&self, path: impl AsRef<Path>) -> Result<()> {
-        let data: Vec<(Uuid, Vec<f32>, Option<HashMap<String, String>>)> = {
+    /// Every live vector with its metadata, encoded as a snapshot.
+    pub fn snapshot_bytes(&self) -> Result<Vec<u8>> {
+        let entries = {
             let guard = self.inner.read();
             let meta = self.meta.read();
             guard
@@ -374,9 +704,15 @@ impl VectorIndex {
                 .map(|(id, v)| (*id, v.clone(), meta.get(id).cloned()))
                 .collect()
         };
+        encode_snapshot(entries)
+    }
+
+    /// Persist vectors (ids + optional metadata) to a snapshot file for fast reload.
+    pub fn save_snapshot(&self, path: impl AsRef<Path>) -> Result<()> {
+        let bytes = self.snapshot_bytes()?;
         let file = File::create(path)?;
         let mut w = BufWriter::new(file);
-        bincode::serialize_into(&mut w, &data)?;
+        w.write_all(&bytes)?;
         w.flush()?;
         if let Some(f) = w.get_ref().try_clone().ok() {
             f.sync_all()?;
@@ -386,37 +722,27 @@ impl VectorIndex {
 
     /// Load vectors from snapshot, rebuilding in-memory and HNSW state.
     pub fn load_snapshot(&self, path: impl AsRef<Path>) -> Result<()> {
-        let bytes = std::fs::read(path)?;
-        // Prefer V2 (with metadata); fallback to V1 for backward compatibility.
-        let entries_v2: Result<Vec<(Uuid, Vec<f32>, Option<HashMap<String, String>>)>> =
-            bincode::deserialize(&bytes).map_err(PieskieoError::from);
-        let entries_v1: Option<Vec<(Uuid, Vec<f32>)>> = if entries_v2.is_err() {
-            bincode::deserialize(&bytes).ok()
-        } else {
-            None
-        };
+        self.load_snapshot_bytes(&std::fs::read(path)?)
+    }
+
+    /// `load_snapshot` from an in-memory snapshot; replaces every vector.
+    pub fn load_snapshot_bytes(&self, bytes: &[u8]) -> Result<()> {
+        let entries = decode_snapshot(bytes)?;
 
         // Clear existing state.
         {
             self.inner.write().clear();
+            self.meta.write().clear();
             self.id_map.write().clear();
             self.rev_map.write().clear();
             self.tombstones.write().clear();
             self.next_id.store(0, Ordering::SeqCst);
-            self.owned_store.write().clear();
+            self.retire_owned(&mut self.owned_store.write());
             *self.hnsw.write() = None;
         }
 
-        if let Ok(entries) = entries_v2 {
-            for (id, vec, meta) in entries {
-                self.insert(id, vec, meta)?;
-            }
-        } else if let Some(entries) = entries_v1 {
-            for (id, vec) in entries {
-                self.insert(id, vec, None)?;
-            }
-        } else {
-            return Err(PieskieoError::NotFound);
+        for (id, vec, meta) in entries {
+            self.insert(id, vec, meta)?;
         }
         Ok(())
     }
@@ -475,12 +801,61 @@ impl VectorIndex {
                 }
             }
             *self.hnsw.write() = Some(hnsw);
-            self.owned_store.write().clear();
+            self.retire_owned(&mut self.owned_store.write());
             return Ok(true);
         }
         Ok(false)
     }
 
+    /// Empties `store`, counting its slices as leaked: they were `Box::leak`ed
+    /// for the graph and nothing frees them once it is gone.
+    fn retire_owned(&self, store: &mut Vec<&'static [f32]>) {
+        let bytes: usize = store.iter().map(|s| std::mem::size_of_val(*s)).sum();
+        self.leaked_bytes.fetch_add(bytes as u64, Ordering::Relaxed);
+        store.clear();
+    }
+
+    /// Rough bytes held by this index, for capacity planning. HNSW slots
+    /// count live and tombstoned vectors alike; a graph loaded from disk owns
+    /// a copy of its data instead of pointing into `owned_store`.
+    pub fn memory_estimate(&self) -> VectorMemory {
+        let dim = self.dim.read().unwrap_or(0) as u64;
+        let live = self.inner.read().len() as u64;
+        let slots = live + self.tombstones.read().len() as u64;
+        let owned_bytes: u64 = self
+            .owned_store
+            .read()
+            .iter()
+            .map(|s| std::mem::size_of_val(*s) as u64)
+            .sum();
+        let leaked_bytes = self.leaked_bytes.load(Ordering::Relaxed);
+        let hnsw = if self.hnsw.read().is_none() {
+            0
+        } else if owned_bytes == 0 {
+            slots * (HNSW_POINT_BYTES + dim * 4)
+        } else {
+            slots * HNSW_POINT_BYTES
+        };
+        let ids = self.id_map.read().len() as u64 * 32 + self.rev_map.read().len() as u64 * 16;
+        let meta: u64 = self
+            .meta
+            .read()
+            .values()
+            .flat_map(|m| m.iter())
+            .map(|(k, v)| VECTOR_ENTRY_BYTES + k.len() as u64 + value_bytes(v))
+            .sum();
+        VectorMemory {
+            bytes: live * (dim * 4 + VECTOR_ENTRY_BYTES)
+                + ids
+                + meta
+                + hnsw
+                + owned_bytes
+                + leaked_bytes,
+            owned_bytes,
+            leaked_bytes,
+        }
+    }
+
     pub fn set_ef_search(&self, ef: usize) {
         self.ef_search.store(ef.max(1), Ordering::SeqCst);
     }
@@ -490,6 +865,17 @@ impl VectorIndex {
     }
 }
 
+/// Heap plus inline size of a JSON value, roughly.
+fn value_bytes(v: &Value) -> u64 {
+    let heap = match v {
+        Value::String(s) => s.len() as u64,
+        Value::Array(a) => a.iter().map(value_bytes).sum(),
+        Value::Object(o) => o.iter().map(|(k, v)| k.len() as u64 + value_bytes(v)).sum(),
+        _ => 0,
+    };
+    std::mem::size_of::<Value>() as u64 + heap
+}
+
 fn l2(a: &[f32], b: &[f32]) -> f32 {
     a.iter()
         .zip(b.iter())
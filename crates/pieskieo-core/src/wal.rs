@@ -43,6 +43,10 @@ pub enum RecordKind {
         table: Option<String>,
         schema: Vec<u8>,
     },
+    /// Drop the auto-link edges touching `node` before it is re-linked.
+    RemoveAutoEdges {
+        node: Uuid,
+    },
 }
 
 #[derive(Debug, Clone, Copy, Serialize, Deserialize)]
@@ -56,6 +60,8 @@ pub enum DataFamily {
 pub struct Wal {
     path: PathBuf,
     writer: BufWriter<File>,
+    // bytes appended since the last flush_sync
+    pending: u64,
 }
 
 impl Wal {
@@ -68,7 +74,11 @@ impl Wal {
             .read(true)
             .open(&path)?;
         let writer = BufWriter::new(file);
-        Ok(Self { path, writer })
+        Ok(Self {
+            path,
+            writer,
+            pending: 0,
+        })
     }
 
     pub fn append(&mut self, record: &RecordKind) -> Result<()> {
@@ -76,18 +86,44 @@ impl Wal {
         let len = bytes.len() as u32;
         self.writer.write_all(&len.to_le_bytes())?;
         self.writer.write_all(&bytes)?;
+        self.pending += 4 + bytes.len() as u64;
+        Ok(())
+    }
+
+    /// Append several records with one buffered write; every record is
+    /// encoded first, so an encoding error leaves the log untouched.
+    pub fn append_batch(&mut self, records: &[RecordKind]) -> Result<()> {
+        let buf = Self::encode(records)?;
+        self.writer.write_all(&buf)?;
+        self.pending += buf.len() as u64;
         Ok(())
     }
 
+    fn encode(records: &[RecordKind]) -> Result<Vec<u8>> {
+        let mut buf = Vec::new();
+        for record in records {
+            let bytes = bincode::serialize(record)?;
+            buf.extend_from_slice(&(bytes.len() as u32).to_le_bytes());
+            buf.extend_from_slice(&bytes);
+        }
+        Ok(buf)
+    }
+
     /// Flush buffered WAL data to disk, including fsync for durability.
     pub fn flush_sync(&mut self) -> Result<()> {
         self.writer.flush()?;
         if let Some(inner) = self.writer.get_ref().try_clone().ok() {
             inner.sync_all()?;
         }
+        self.pending = 0;
         Ok(())
     }
 
+    /// Bytes appended since the last successful `flush_sync`.
+    pub fn pending_bytes(&self) -> u64 {
+        self.pending
+    }
+
     pub fn replay(&self) -> Result<Vec<RecordKind>> {
         let mut res = Vec::new();
         let file = OpenOptions::new().read(true).open(&self.path)?;
@@ -138,12 +174,57 @@ impl Wal {
         Ok((res, end))
     }
 
-    pub fn truncate(&mut self) -> Result<()> {
-        let mut file = OpenOptions::new().write(true).open(&self.path)?;
-        file.set_len(0)?;
-        file.seek(SeekFrom::Start(0))?;
-        file.sync_all()?;
+    /// Bytes on disk, i.e. the `end` a full `replay_since` would report.
+    /// Records still sitting in the write buffer are not counted.
+    pub fn current_offset(&self) -> Result<u64> {
+        Ok(std::fs::metadata(&self.path)?.len())
+    }
+
+    /// Records on disk, counted from their length prefixes without decoding.
+    pub fn record_count(&self) -> Result<u64> {
+        let file = OpenOptions::new().read(true).open(&self.path)?;
+        let end = file.metadata()?.len();
+        let mut reader = BufReader::new(file);
+        let mut pos = 0u64;
+        let mut count = 0u64;
+        while pos + 4 <= end {
+            let mut len_buf = [0u8; 4];
+            reader.read_exact(&mut len_buf)?;
+            let len = u32::from_le_bytes(len_buf) as u64;
+            if pos + 4 + len > end {
+                break;
+            }
+            reader.seek_relative(len as i64)?;
+            pos += 4 + len;
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    /// Replace the log with `records`. They are written and fsynced to a side
+    /// file that is then renamed over `wal.log`, so a crash leaves either the
+    /// old log or the new one, never a mix.
+    pub fn rewrite(&mut self, records: &[RecordKind]) -> Result<()> {
+        self.writer.flush()?;
+        let tmp = self.path.with_extension("log.compact");
+        {
+            let mut file = File::create(&tmp)?;
+            file.write_all(&Self::encode(records)?)?;
+            file.sync_all()?;
+        }
+        std::fs::rename(&tmp, &self.path)?;
+        if let Some(dir) = self.path.parent() {
+            // persist the rename itself; not every platform can open a directory
+            if let Ok(dir) = File::open(dir) {
+                let _ = dir.sync_all();
+            }
+        }
+        let file = OpenOptions::new()
+            .append(true)
+            .read(true)
+            .open(&self.path)?;
         self.writer = BufWriter::new(file);
+        self.pending = 0;
         Ok(())
     }
 }
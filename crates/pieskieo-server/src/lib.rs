@@ -1,9 +1,9 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::fs::OpenOptions;
 use std::io::Write;
 use std::net::{IpAddr, SocketAddr};
 use std::sync::atomic::AtomicBool;
-use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
 use std::sync::{Arc, Mutex};
 use std::time::{Duration, Instant};
 
@@ -18,21 +18,25 @@ use axum::{
     http::Request,
     middleware::{self, Next},
     response::IntoResponse,
-    routing::{delete, get, post},
+    routing::{delete, get, head, patch, post},
     Extension, Json, Router,
 };
 use base64::engine::general_purpose::STANDARD as B64;
 use base64::Engine;
 use futures::future::join_all;
 use pieskieo_core::{
-    PieskieoDb, PieskieoError, SchemaDef, SchemaField, SqlResult,
-    VectorParams as PieskieoVectorParams,
+    BatchFamily, BatchOp, NamespaceDrop, PieskieoDb, PieskieoError, QueryStats, SchemaDef,
+    SchemaField, SqlAnalysis, SqlResult, VectorMeta, VectorParams as PieskieoVectorParams,
 };
 use rand_core::OsRng;
 use serde::{Deserialize, Serialize};
 use sqlparser::{dialect::GenericDialect, parser::Parser};
 use std::path::PathBuf;
 use tokio::{net::TcpListener, sync::RwLock};
+use tower_http::compression::{
+    predicate::{DefaultPredicate, Predicate, SizeAbove},
+    CompressionLayer,
+};
 use tracing_appender::rolling;
 use tracing_subscriber::fmt::writer::MakeWriterExt;
 use tracing_subscriber::{prelude::*, EnvFilter};
@@ -52,10 +56,67 @@ struct AppState {
     pool: Arc<RwLock<DbPool>>,
     auth: Arc<RwLock<AuthConfig>>,
     limiter: Arc<RateLimiter>,
+    concurrency: Arc<ConcurrencyLimiter>,
+    search_limiter: Arc<SearchLimiter>,
     audit: Arc<AuditLog>,
     data_dir: String,
     pause_writes: Arc<AtomicBool>,
     reshard_status: Arc<RwLock<Option<ReshardReport>>>,
+    wal_flush: Arc<WalFlushStats>,
+    metric_prefix: Arc<str>,
+    // UUIDv5 namespace for ids derived from natural keys
+    key_space: Uuid,
+    // retry missed point reads on every shard
+    scatter_get: bool,
+    // most ids one bulk delete may name
+    bulk_delete_max: usize,
+    // largest candidate set a vector search scores exactly
+    prefilter_max: usize,
+    // vector search k when a request omits it, and the most it may ask for
+    default_k: usize,
+    max_k: usize,
+    // most query vectors one batch search may carry
+    batch_queries_max: usize,
+    // base URL of each shard node, by shard index, for client routing
+    shard_addrs: Arc<[String]>,
+    started: Instant,
+    // auto-vacuum (WAL compaction) task; None when it is not enabled
+    compaction_task: Option<Arc<TaskHeartbeat>>,
+}
+
+/// Liveness of a periodic background task: each tick records the time it
+/// finished, so a task that died or hangs stops advancing it.
+struct TaskHeartbeat {
+    interval: Duration,
+    last_ms: AtomicU64,
+}
+
+impl TaskHeartbeat {
+    fn new(interval: Duration) -> Self {
+        Self {
+            interval,
+            last_ms: AtomicU64::new(unix_ms(std::time::SystemTime::now())),
+        }
+    }
+
+    fn beat(&self) {
+        self.last_ms
+            .store(unix_ms(std::time::SystemTime::now()), Ordering::Relaxed);
+    }
+
+    /// No tick has finished for three intervals.
+    fn stale(&self) -> bool {
+        let now = unix_ms(std::time::SystemTime::now());
+        let since = now.saturating_sub(self.last_ms.load(Ordering::Relaxed));
+        since > 3 * self.interval.as_millis() as u64
+    }
+}
+
+#[derive(Default)]
+struct WalFlushStats {
+    last_us: AtomicU64,
+    max_us: AtomicU64,
+    runs: AtomicU64,
 }
 
 #[derive(Clone, Serialize, Deserialize)]
@@ -70,12 +131,30 @@ struct ReshardReport {
 #[derive(Clone)]
 struct AuthConfig {
     users: Vec<UserRec>,
-    bearer: Option<String>,
+    tokens: Vec<TokenRec>,
     path: PathBuf,
     attempts: Arc<Mutex<HashMap<String, Attempt>>>,
+    ip_attempts: Arc<Mutex<HashMap<IpAddr, Attempt>>>,
+    // failure counters and lockouts survive restarts here
+    attempts_path: PathBuf,
+    /// set when a counter or lock changes, cleared when a save takes it
+    attempts_dirty: Arc<AtomicBool>,
+    /// one writer at a time, so an older snapshot never replaces a newer one
+    attempts_writing: Arc<Mutex<()>>,
     max_failures: u32,
     lockout: Duration,
+    ip_max_failures: u32,
+    ip_lockout: Duration,
     window: Duration,
+    max_attempt_entries: usize,
+    policy: PasswordPolicy,
+}
+
+#[derive(Clone, Copy)]
+struct PasswordPolicy {
+    min_len: usize,
+    /// strict upper+lower+digit+symbol rule; when off only length and variety are checked
+    require_classes: bool,
 }
 
 struct RateLimiter {
@@ -85,14 +164,51 @@ struct RateLimiter {
     rejected: AtomicU64,
 }
 
+/// Caps in-flight requests per client IP; complements the request-count limiter,
+/// which cannot stop one client from running many slow searches at once.
+struct ConcurrencyLimiter {
+    max: u32,
+    in_flight: Arc<Mutex<HashMap<IpAddr, u32>>>,
+    rejected: AtomicU64,
+}
+
+/// Caps concurrent vector searches per shard; see `SearchLimiter::from_env`.
+struct SearchLimiter {
+    max: usize,
+    queue: usize,
+    // by shard index, grown as shards are first searched
+    gates: Mutex<Vec<Arc<SearchGate>>>,
+    rejected: AtomicU64,
+}
+
+struct SearchGate {
+    permits: Arc<tokio::sync::Semaphore>,
+    waiting: AtomicUsize,
+    in_flight: AtomicUsize,
+}
+
 #[derive(Clone)]
 struct AuditLog {
     path: PathBuf,
+    // fraction of successful reads written; writes and failures always are
+    sample_rate: f64,
 }
 
 impl AuditLog {
     fn new(path: PathBuf) -> Self {
-        Self { path }
+        let sample_rate = std::env::var("PIESKIEO_AUDIT_SAMPLE_RATE")
+            .ok()
+            .and_then(|s| s.parse::<f64>().ok())
+            .unwrap_or(1.0)
+            .clamp(0.0, 1.0);
+        Self { path, sample_rate }
+    }
+
+    fn should_log(&self, method: &str, path: &str, status: u16) -> bool {
+        if self.sample_rate >= 1.0 || status >= 400 || !is_read_path(path, method) {
+            return true;
+        }
+        rand::random::<f64>() < self.sample_rate
     }
 
     fn write(
@@ -158,12 +274,75 @@ impl AuditLog {
     }
 }
 
+fn unix_ms(t: std::time::SystemTime) -> u64 {
+    t.duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
 fn ensure_parent(path: &PathBuf) -> std::io::Result<()> {
     if let Some(parent) = path.parent() {
         std::fs::create_dir_all(parent)?;
     }
     Ok(())
 }
+impl PasswordPolicy {
+    fn from_env() -> Self {
+        let min_len = std::env::var("PIESKIEO_AUTH_MIN_PASSWORD_LEN")
+            .ok()
+            .and_then(|s| s.parse::<usize>().ok())
+            .unwrap_or(8)
+            .max(1);
+        let require_classes = std::env::var("PIESKIEO_AUTH_PASSWORD_CLASSES")
+            .map(|s| !matches!(s.to_ascii_lowercase().as_str(), "0" | "false" | "off"))
+            .unwrap_or(true);
+        Self {
+            min_len,
+            require_classes,
+        }
+    }
+
+    fn validate(&self, pass: &str) -> Result<(), String> {
+        if pass.chars().count() < self.min_len {
+            return Err(format!(
+                "password must be at least {} characters",
+                self.min_len
+            ));
+        }
+        if !self.require_classes {
+            // length-only mode still rejects trivially repetitive secrets like "aaaaaaaaaaaa";
+            // a minimum length below 5 lowers the bar so such passwords stay possible
+            let need = self.min_len.min(5);
+            let distinct: std::collections::HashSet<char> = pass.chars().collect();
+            if distinct.len() < need {
+                return Err(format!(
+                    "password must contain at least {need} distinct characters"
+                ));
+            }
+            return Ok(());
+        }
+        let mut has_upper = false;
+        let mut has_lower = false;
+        let mut has_digit = false;
+        let mut has_symbol = false;
+        for ch in pass.chars() {
+            if ch.is_ascii_uppercase() {
+                has_upper = true;
+            } else if ch.is_ascii_lowercase() {
+                has_lower = true;
+            } else if ch.is_ascii_digit() {
+                has_digit = true;
+            } else {
+                has_symbol = true;
+            }
+        }
+        if !(has_upper && has_lower && has_digit && has_symbol) {
+            return Err("password must contain upper, lower, digit, and symbol characters".into());
+        }
+        Ok(())
+    }
+}
+
 #[derive(Clone)]
 struct UserRec {
     user: String,
@@ -171,6 +350,12 @@ struct UserRec {
     role: Role,
 }
 
+#[derive(Clone)]
+struct TokenRec {
+    token: String,
+    role: Role,
+}
+
 #[derive(Clone, Debug)]
 struct Attempt {
     count: u32,
@@ -178,6 +363,21 @@ struct Attempt {
     locked_until: Option<Instant>,
 }
 
+/// On-disk form of `Attempt`; `Instant` is process-local, so times are
+/// stored as unix milliseconds.
+#[derive(Serialize, Deserialize)]
+struct AttemptDisk {
+    count: u32,
+    first_ms: u64,
+    locked_until_ms: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+struct AttemptsDisk {
+    users: HashMap<String, AttemptDisk>,
+    ips: HashMap<String, AttemptDisk>,
+}
+
 #[derive(Clone, Copy, PartialEq, Eq, Debug)]
 enum Role {
     Admin,
@@ -204,6 +404,21 @@ impl AuthConfig {
             .and_then(|s| s.parse::<u64>().ok())
             .map(Duration::from_secs)
             .unwrap_or_else(|| Duration::from_secs(900));
+        let ip_max_failures = std::env::var("PIESKIEO_AUTH_IP_MAX_FAILURES")
+            .ok()
+            .and_then(|s| s.parse().ok())
+            .unwrap_or(20);
+        let ip_lockout = std::env::var("PIESKIEO_AUTH_IP_LOCKOUT_SECS")
+            .ok()
+            .and_then(|s| s.parse::<u64>().ok())
+            .map(Duration::from_secs)
+            .unwrap_or(lockout);
+        let max_attempt_entries = std::env::var("PIESKIEO_AUTH_ATTEMPTS_MAX")
+            .ok()
+            .and_then(|s| s.parse::<usize>().ok())
+            .unwrap_or(10_000)
+            .max(1);
+        let policy = PasswordPolicy::from_env();
         if let Ok(json) = std::env::var("PIESKIEO_USERS") {
             if let Ok(val) = serde_json::from_str::<serde_json::Value>(&json) {
                 if let Some(arr) = val.as_array() {
@@ -212,7 +427,7 @@ impl AuthConfig {
                             item.get("user").and_then(|v| v.as_str()),
                             item.get("pass").and_then(|v| v.as_str()),
                         ) {
-                            if let Err(msg) = Self::validate_password(p) {
+                            if let Err(msg) = policy.validate(p) {
                                 tracing::warn!("skipping user {} from PIESKIEO_USERS: {}", u, msg);
                                 continue;
                             }
@@ -235,7 +450,7 @@ impl AuthConfig {
         if users.is_empty() {
             if let Ok(u) = std::env::var("PIESKIEO_AUTH_USER") {
                 if let Ok(p) = std::env::var("PIESKIEO_AUTH_PASSWORD") {
-                    if let Err(msg) = Self::validate_password(&p) {
+                    if let Err(msg) = policy.validate(&p) {
                         tracing::warn!("skipping PIESKIEO_AUTH_USER {}: {}", u, msg);
                     } else {
                         users.push(UserRec {
@@ -268,9 +483,38 @@ impl AuthConfig {
                 }
             }
         }
-        let bearer = std::env::var("PIESKIEO_TOKEN").ok();
+        // PIESKIEO_TOKEN is the admin token; PIESKIEO_TOKENS adds [{token,role}]
+        let mut tokens = Vec::new();
+        if let Ok(token) = std::env::var("PIESKIEO_TOKEN") {
+            tokens.push(TokenRec {
+                token,
+                role: Role::Admin,
+            });
+        }
+        if let Ok(json) = std::env::var("PIESKIEO_TOKENS") {
+            match serde_json::from_str::<Vec<serde_json::Value>>(&json) {
+                Ok(arr) => {
+                    for item in arr {
+                        let Some(token) = item.get("token").and_then(|v| v.as_str()) else {
+                            tracing::warn!("skipping PIESKIEO_TOKENS entry without token");
+                            continue;
+                        };
+                        let role = item
+                            .get("role")
+                            .and_then(|v| v.as_str())
+                            .map(Self::parse_role)
+                            .unwrap_or(Role::Read);
+                        tokens.push(TokenRec {
+                            token: token.to_string(),
+                            role,
+                        });
+                    }
+                }
+                Err(e) => tracing::warn!("ignoring PIESKIEO_TOKENS: {}", e),
+            }
+        }
         // default admin if nothing configured (even if auth_users.json exists but was empty/invalid)
-        if users.is_empty() && bearer.is_none() {
+        if users.is_empty() && tokens.is_empty() {
             tracing::info!("No users configured; creating default admin user (Pieskieo/pieskieo)");
             users.push(UserRec {
                 user: "Pieskieo".into(),
@@ -278,19 +522,29 @@ impl AuthConfig {
                 role: Role::Admin,
             });
         }
-        Self {
+        let cfg = Self {
             users,
-            bearer,
+            tokens,
             path,
             attempts: Arc::new(Mutex::new(HashMap::new())),
+            ip_attempts: Arc::new(Mutex::new(HashMap::new())),
+            attempts_path: PathBuf::from(data_dir).join("auth_attempts.json"),
+            attempts_dirty: Arc::new(AtomicBool::new(false)),
+            attempts_writing: Arc::new(Mutex::new(())),
             max_failures,
             lockout,
+            ip_max_failures,
+            ip_lockout,
             window,
-        }
+            max_attempt_entries,
+            policy,
+        };
+        cfg.load_attempts();
+        cfg
     }
 
     fn enabled(&self) -> bool {
-        !self.users.is_empty() || self.bearer.is_some()
+        !self.users.is_empty() || !self.tokens.is_empty()
     }
 
     fn parse_role(s: &str) -> Role {
@@ -301,31 +555,6 @@ impl AuthConfig {
         }
     }
 
-    fn validate_password(pass: &str) -> Result<(), String> {
-        if pass.len() < 8 {
-            return Err("password must be at least 8 characters".into());
-        }
-        let mut has_upper = false;
-        let mut has_lower = false;
-        let mut has_digit = false;
-        let mut has_symbol = false;
-        for ch in pass.chars() {
-            if ch.is_ascii_uppercase() {
-                has_upper = true;
-            } else if ch.is_ascii_lowercase() {
-                has_lower = true;
-            } else if ch.is_ascii_digit() {
-                has_digit = true;
-            } else {
-                has_symbol = true;
-            }
-        }
-        if !(has_upper && has_lower && has_digit && has_symbol) {
-            return Err("password must contain upper, lower, digit, and symbol characters".into());
-        }
-        Ok(())
-    }
-
     fn persist(&self) {
         let disk: Vec<UserDisk> = self
             .users
@@ -351,6 +580,114 @@ impl AuthConfig {
         }
     }
 
+    /// Persist the attempt records on a blocking thread if anything changed
+    /// since the last save. Must be called inside the runtime.
+    fn save_attempts(&self) {
+        if !self.attempts_dirty.swap(false, Ordering::Relaxed) {
+            return;
+        }
+        let auth = self.clone();
+        tokio::task::spawn_blocking(move || auth.write_attempts());
+    }
+
+    /// Write failure counters and lockouts so a restart cannot be used to
+    /// reset brute-force protection. Blocks on the file system.
+    fn write_attempts(&self) {
+        let _writing = self.attempts_writing.lock().unwrap();
+        let now = Instant::now();
+        let now_ms = unix_ms(std::time::SystemTime::now());
+        let to_ms = |t: Instant| {
+            if t >= now {
+                now_ms + (t - now).as_millis() as u64
+            } else {
+                now_ms.saturating_sub((now - t).as_millis() as u64)
+            }
+        };
+        let to_disk = |a: &Attempt| AttemptDisk {
+            count: a.count,
+            first_ms: to_ms(a.first),
+            locked_until_ms: a.locked_until.map(to_ms),
+        };
+        let disk = AttemptsDisk {
+            users: self
+                .attempts
+                .lock()
+                .unwrap()
+                .iter()
+                .map(|(u, a)| (u.clone(), to_disk(a)))
+                .collect(),
+            ips: self
+                .ip_attempts
+                .lock()
+                .unwrap()
+                .iter()
+                .map(|(ip, a)| (ip.to_string(), to_disk(a)))
+                .collect(),
+        };
+        let Ok(txt) = serde_json::to_string(&disk) else {
+            return;
+        };
+        let _ = ensure_parent(&self.attempts_path);
+        let tmp = self.attempts_path.with_extension("json.tmp");
+        if let Err(e) =
+            std::fs::write(&tmp, txt).and_then(|_| std::fs::rename(&tmp, &self.attempts_path))
+        {
+            tracing::warn!("failed to persist auth attempts: {e}");
+        }
+    }
+
+    fn load_attempts(&self) {
+        let Ok(txt) = std::fs::read_to_string(&self.attempts_path) else {
+            return;
+        };
+        let Ok(disk) = serde_json::from_str::<AttemptsDisk>(&txt) else {
+            tracing::warn!("auth_attempts.json is invalid; starting with no lockouts");
+            return;
+        };
+        let now = Instant::now();
+        let now_ms = unix_ms(std::time::SystemTime::now());
+        // a time too far ahead for `Instant` drops the record rather than panicking
+        let from_ms = |ms: u64| {
+            if ms >= now_ms {
+                now.checked_add(Duration::from_millis(ms - now_ms))
+            } else {
+                Some(
+                    now.checked_sub(Duration::from_millis(now_ms - ms))
+                        .unwrap_or(now),
+                )
+            }
+        };
+        let from_disk = |a: AttemptDisk| {
+            Some(Attempt {
+                count: a.count,
+                first: from_ms(a.first_ms)?,
+                locked_until: match a.locked_until_ms {
+                    Some(ms) => Some(from_ms(ms)?),
+                    None => None,
+                },
+            })
+        };
+        {
+            let mut map = self.attempts.lock().unwrap();
+            map.extend(
+                disk.users
+                    .into_iter()
+                    .filter_map(|(u, a)| Some((u, from_disk(a)?))),
+            );
+            Self::evict_oldest(&mut map, self.max_attempt_entries);
+        }
+        let mut map = self.ip_attempts.lock().unwrap();
+        map.extend(
+            disk.ips
+                .into_iter()
+                .filter_map(|(ip, a)| Some((ip.parse().ok()?, from_disk(a)?))),
+        );
+        Self::evict_oldest(&mut map, self.max_attempt_entries);
+        drop(map);
+        let removed = self.prune_attempts();
+        tracing::info!(removed, "restored auth attempt records");
+    }
+
     fn hash_password(pass: &str) -> String {
         let salt = argon2::password_hash::SaltString::generate(&mut OsRng);
         let argon = Argon2::new(
@@ -374,10 +711,23 @@ impl AuthConfig {
         }
     }
 
-    fn check_lockout(&self, user: &str) -> bool {
-        let mut map = self.attempts.lock().unwrap();
-        if let Some(state) = map.get_mut(user) {
-            let now = Instant::now();
+    /// A request is refused when its username is locked, from any IP, or when
+    /// its source IP is locked, for any username. The per-IP lock stops one
+    /// address spraying guesses across many accounts.
+    fn check_lockout(&self, user: &str, ip: IpAddr) -> bool {
+        let now = Instant::now();
+        let mut ips = self.ip_attempts.lock().unwrap();
+        if Self::refresh_attempt(ips.get_mut(&ip), now, self.window) {
+            return true;
+        }
+        drop(ips);
+        let mut users = self.attempts.lock().unwrap();
+        Self::refresh_attempt(users.get_mut(user), now, self.window)
+    }
+
+    /// Clear expired lock/window state; returns whether the entry is still locked.
+    fn refresh_attempt(state: Option<&mut Attempt>, now: Instant, window: Duration) -> bool {
+        if let Some(state) = state {
             if let Some(until) = state.locked_until {
                 if now < until {
                     return true;
@@ -387,7 +737,7 @@ impl AuthConfig {
                     state.first = now;
                 }
             }
-            if now.duration_since(state.first) > self.window {
+            if now.duration_since(state.first) > window {
                 state.count = 0;
                 state.first = now;
             }
@@ -395,33 +745,126 @@ impl AuthConfig {
         false
     }
 
-    fn record_failure(&self, user: &str) {
-        let mut map = self.attempts.lock().unwrap();
+    fn record_failure(&self, user: &str, ip: IpAddr) {
         let now = Instant::now();
-        let entry = map.entry(user.to_string()).or_insert(Attempt {
+        let mut locked = false;
+        {
+            let mut map = self.attempts.lock().unwrap();
+            if !map.contains_key(user) && map.len() >= self.max_attempt_entries {
+                Self::evict_oldest(&mut map, self.max_attempt_entries - 1);
+            }
+            let entry = map.entry(user.to_string()).or_insert(Attempt {
+                count: 0,
+                first: now,
+                locked_until: None,
+            });
+            if Self::bump_attempt(entry, now, self.window, self.max_failures, self.lockout) {
+                tracing::warn!(
+                    "user {} locked out for {:?} after {} failures",
+                    user,
+                    self.lockout,
+                    entry.count
+                );
+                locked = true;
+            }
+        }
+        let mut map = self.ip_attempts.lock().unwrap();
+        if !map.contains_key(&ip) && map.len() >= self.max_attempt_entries {
+            Self::evict_oldest(&mut map, self.max_attempt_entries - 1);
+        }
+        let entry = map.entry(ip).or_insert(Attempt {
             count: 0,
             first: now,
             locked_until: None,
         });
-        if now.duration_since(entry.first) > self.window {
+        if Self::bump_attempt(
+            entry,
+            now,
+            self.window,
+            self.ip_max_failures,
+            self.ip_lockout,
+        ) {
+            tracing::warn!(
+                "ip {} locked out for {:?} after {} failures",
+                ip,
+                self.ip_lockout,
+                entry.count
+            );
+            locked = true;
+        }
+        drop(map);
+        self.attempts_dirty.store(true, Ordering::Relaxed);
+        // new locks are persisted right away; counters ride the periodic save
+        if locked {
+            self.save_attempts();
+        }
+    }
+
+    /// Count one failure; returns true when this failure triggered a lock.
+    fn bump_attempt(
+        entry: &mut Attempt,
+        now: Instant,
+        window: Duration,
+        max_failures: u32,
+        lockout: Duration,
+    ) -> bool {
+        if now.duration_since(entry.first) > window {
             entry.count = 0;
             entry.first = now;
         }
         entry.count += 1;
-        if entry.count >= self.max_failures {
-            entry.locked_until = Some(now + self.lockout);
-            tracing::warn!(
-                "user {} locked out for {:?} after {} failures",
-                user,
-                self.lockout,
-                entry.count
-            );
+        if entry.count >= max_failures {
+            entry.locked_until = Some(now + lockout);
+            return true;
         }
+        false
     }
 
     fn record_success(&self, user: &str) {
+        // IP counters are left to expire so a valid login cannot reset a spraying source
         let mut map = self.attempts.lock().unwrap();
-        map.remove(user);
+        if map.remove(user).is_some() {
+            self.attempts_dirty.store(true, Ordering::Relaxed);
+        }
+    }
+
+    /// Drop attempt records whose window and lockout have both lapsed, then
+    /// enforce the size cap. Removing any marks the records for saving.
+    fn prune_attempts(&self) -> usize {
+        let now = Instant::now();
+        let keep = |a: &Attempt| {
+            let locked = a.locked_until.map(|until| now < until).unwrap_or(false);
+            locked || now.duration_since(a.first) <= self.window
+        };
+        let mut removed = 0;
+        {
+            let mut map = self.attempts.lock().unwrap();
+            let before = map.len();
+            map.retain(|_, a| keep(a));
+            Self::evict_oldest(&mut map, self.max_attempt_entries);
+            removed += before - map.len();
+        }
+        let mut map = self.ip_attempts.lock().unwrap();
+        let before = map.len();
+        map.retain(|_, a| keep(a));
+        Self::evict_oldest(&mut map, self.max_attempt_entries);
+        removed += before - map.len();
+        if removed > 0 {
+            self.attempts_dirty.store(true, Ordering::Relaxed);
+        }
+        removed
+    }
+
+    fn evict_oldest<K: Clone + Eq + std::hash::Hash>(map: &mut HashMap<K, Attempt>, keep: usize) {
+        if map.len() <= keep {
+            return;
+        }
+        let mut by_age: Vec<(Instant, K)> = map.iter().map(|(k, a)| (a.first, k.clone())).collect();
+        by_age.sort_by_key(|(first, _)| *first);
+        let excess = map.len() - keep;
+        for (_, key) in by_age.into_iter().take(excess) {
+            map.remove(&key);
+        }
     }
 }
 
@@ -444,7 +887,7 @@ impl RateLimiter {
         }
     }
 
-    fn allow(&self, ip: IpAddr) -> Result<(), Duration> {
+    fn allow(&self, ip: IpAddr) -> RateDecision {
         let now = Instant::now();
         let mut hits = self.hits.lock().unwrap();
         let entry = hits.entry(ip).or_insert((0, now));
@@ -454,11 +897,180 @@ impl RateLimiter {
             *start = now;
         }
         *count += 1;
-        if *count <= self.max {
-            Ok(())
-        } else {
+        let allowed = *count <= self.max;
+        if !allowed {
+            self.rejected.fetch_add(1, Ordering::Relaxed);
+        }
+        RateDecision {
+            allowed,
+            limit: self.max,
+            remaining: self.max.saturating_sub(*count),
+            reset: self.window.saturating_sub(now.duration_since(*start)),
+        }
+    }
+}
+
+impl ConcurrencyLimiter {
+    fn from_env() -> Self {
+        let max = std::env::var("PIESKIEO_CONCURRENCY_PER_IP")
+            .ok()
+            .and_then(|s| s.parse().ok())
+            .unwrap_or(64);
+        Self {
+            max,
+            in_flight: Arc::new(Mutex::new(HashMap::new())),
+            rejected: AtomicU64::new(0),
+        }
+    }
+
+    /// Claim a slot for `ip`; `None` once it already has `max` requests running.
+    /// A cap of 0 disables the limit.
+    fn acquire(&self, ip: IpAddr) -> Option<ConcurrencySlot> {
+        if self.max == 0 {
+            return Some(ConcurrencySlot { slot: None });
+        }
+        let mut in_flight = self.in_flight.lock().unwrap();
+        let count = in_flight.entry(ip).or_insert(0);
+        if *count >= self.max {
             self.rejected.fetch_add(1, Ordering::Relaxed);
-            Err(self.window.saturating_sub(now.duration_since(*start)))
+            return None;
+        }
+        *count += 1;
+        Some(ConcurrencySlot {
+            slot: Some((self.in_flight.clone(), ip)),
+        })
+    }
+}
+
+impl SearchLimiter {
+    /// `PIESKIEO_SEARCH_CONCURRENCY_PER_SHARD` searches run on a shard at once
+    /// (default 0: unlimited); up to `PIESKIEO_SEARCH_QUEUE_PER_SHARD` more
+    /// (default 0) wait for a slot and any beyond that fail fast with 503.
+    fn from_env() -> Self {
+        let env = |key: &str| {
+            std::env::var(key)
+                .ok()
+                .and_then(|s| s.parse().ok())
+                .unwrap_or(0)
+        };
+        Self {
+            max: env("PIESKIEO_SEARCH_CONCURRENCY_PER_SHARD"),
+            queue: env("PIESKIEO_SEARCH_QUEUE_PER_SHARD"),
+            gates: Mutex::new(Vec::new()),
+            rejected: AtomicU64::new(0),
+        }
+    }
+
+    fn gate(&self, shard: usize) -> Arc<SearchGate> {
+        let mut gates = self.gates.lock().unwrap();
+        while gates.len() <= shard {
+            gates.push(Arc::new(SearchGate {
+                permits: Arc::new(tokio::sync::Semaphore::new(self.max)),
+                waiting: AtomicUsize::new(0),
+                in_flight: AtomicUsize::new(0),
+            }));
+        }
+        gates[shard].clone()
+    }
+
+    /// A slot to search `shard`, waiting in its queue while that has room.
+    async fn acquire(&self, shard: usize) -> Result<SearchSlot, ApiError> {
+        let gate = self.gate(shard);
+        let permit = if self.max == 0 {
+            None
+        } else if let Ok(permit) = gate.permits.clone().try_acquire_owned() {
+            Some(permit)
+        } else {
+            if gate.waiting.fetch_add(1, Ordering::SeqCst) >= self.queue {
+                gate.waiting.fetch_sub(1, Ordering::SeqCst);
+                self.rejected.fetch_add(1, Ordering::Relaxed);
+                return Err(ApiError::Unavailable(format!(
+                    "shard {shard} has too many searches running"
+                )));
+            }
+            let _queued = QueuedSearch(&gate.waiting);
+            let permit = gate.permits.clone().acquire_owned().await;
+            Some(permit.expect("search semaphores are never closed"))
+        };
+        gate.in_flight.fetch_add(1, Ordering::Relaxed);
+        Ok(SearchSlot {
+            gate,
+            _permit: permit,
+        })
+    }
+
+    /// Searches running on each shard seen so far, by shard index.
+    fn in_flight(&self) -> Vec<usize> {
+        self.gates
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|g| g.in_flight.load(Ordering::Relaxed))
+            .collect()
+    }
+}
+
+/// Each live shard with the slot a search holds on it; the slot is shared
+/// with the blocking tasks so it stays taken until the last one finishes.
+type ShardSlots = Vec<(Arc<PieskieoDb>, Arc<SearchSlot>)>;
+
+/// Leaves the queue on drop, including when the waiting request is cancelled.
+struct QueuedSearch<'a>(&'a AtomicUsize);
+
+impl Drop for QueuedSearch<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Held while a search, or a whole batch of them, runs on one shard; frees
+/// the slot on drop.
+struct SearchSlot {
+    gate: Arc<SearchGate>,
+    _permit: Option<tokio::sync::OwnedSemaphorePermit>,
+}
+
+impl Drop for SearchSlot {
+    fn drop(&mut self) {
+        self.gate.in_flight.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Releases its IP's slot on drop, including when the request future is cancelled.
+struct ConcurrencySlot {
+    slot: Option<(Arc<Mutex<HashMap<IpAddr, u32>>>, IpAddr)>,
+}
+
+impl Drop for ConcurrencySlot {
+    fn drop(&mut self) {
+        if let Some((in_flight, ip)) = self.slot.take() {
+            let mut in_flight = in_flight.lock().unwrap();
+            if let Some(count) = in_flight.get_mut(&ip) {
+                *count -= 1;
+                if *count == 0 {
+                    in_flight.remove(&ip);
+                }
+            }
+        }
+    }
+}
+
+/// Outcome of a rate-limit check, surfaced to clients as `X-RateLimit-*` headers.
+struct RateDecision {
+    allowed: bool,
+    limit: u32,
+    remaining: u32,
+    reset: Duration,
+}
+
+impl RateDecision {
+    fn apply_headers(&self, headers: &mut axum::http::HeaderMap) {
+        let reset = self.reset.as_secs().max(1);
+        headers.insert("x-ratelimit-limit", self.limit.into());
+        headers.insert("x-ratelimit-remaining", self.remaining.into());
+        headers.insert("x-ratelimit-reset", reset.into());
+        if !self.allowed {
+            headers.insert(axum::http::header::RETRY_AFTER, reset.into());
         }
     }
 }
@@ -473,6 +1085,8 @@ struct UserDisk {
 #[derive(Deserialize)]
 struct DocInput {
     id: Option<Uuid>,
+    /// natural key mapped to a stable UUIDv5 when `id` is omitted
+    key: Option<String>,
     data: serde_json::Value,
     namespace: Option<String>,
     collection: Option<String>,
@@ -487,12 +1101,22 @@ struct QueryInput {
     table: Option<String>,
     offset: Option<usize>,
     sql: Option<String>,
+    /// omit null fields from result objects
+    #[serde(default)]
+    compact: bool,
+    /// run on these shard indices only instead of all
+    shards: Option<Vec<usize>>,
 }
 
 #[derive(Deserialize)]
 struct SqlInput {
     sql: String,
     limit: Option<usize>,
+    offset: Option<usize>,
+    #[serde(default)]
+    compact: bool,
+    /// SELECT only: run on these shard indices instead of all
+    shards: Option<Vec<usize>>,
 }
 
 #[derive(Deserialize)]
@@ -503,6 +1127,15 @@ struct SchemaInput {
     fields: HashMap<String, SchemaField>,
 }
 
+#[derive(Deserialize)]
+struct JsonSchemaInput {
+    family: String, // "doc" or "row"
+    namespace: Option<String>,
+    name: String,
+    /// a standard JSON Schema for the collection's objects
+    schema: serde_json::Value,
+}
+
 #[derive(Deserialize)]
 struct UserCreateInput {
     user: String,
@@ -512,9 +1145,10 @@ struct UserCreateInput {
 
 #[derive(Deserialize)]
 struct VectorInput {
-    id: Uuid,
+    /// generated when omitted (single puts only) and returned in the response
+    id: Option<Uuid>,
     vector: Vec<f32>,
-    meta: Option<HashMap<String, String>>,
+    meta: Option<VectorMeta>,
     namespace: Option<String>,
 }
 
@@ -525,7 +1159,7 @@ struct VectorBulk {
 
 #[derive(Deserialize)]
 struct VectorMetaInput {
-    meta: HashMap<String, String>,
+    meta: VectorMeta,
 }
 
 #[derive(Deserialize)]
@@ -536,6 +1170,7 @@ struct VectorMetaDeleteInput {
 #[derive(Deserialize)]
 struct RowInput {
     id: Option<Uuid>,
+    key: Option<String>,
     data: serde_json::Value,
     namespace: Option<String>,
     table: Option<String>,
@@ -544,12 +1179,38 @@ struct RowInput {
 #[derive(Deserialize)]
 struct VectorSearchInput {
     query: Vec<f32>,
-    k: Option<usize>,
-    metric: Option<String>,
-    filter_ids: Option<Vec<Uuid>>,
+    #[serde(flatten)]
+    opts: VectorSearchOpts,
+}
+
+#[derive(Deserialize)]
+struct VectorSearchBatchInput {
+    queries: Vec<Vec<f32>>,
+    #[serde(flatten)]
+    opts: VectorSearchOpts,
+}
+
+#[derive(Deserialize)]
+struct VectorSearchOpts {
+    k: Option<usize>,
+    metric: Option<String>,
+    /// also score each hit under these metrics (`l2`, `cosine`, `dot`)
+    metrics: Option<Vec<String>>,
+    filter_ids: Option<Vec<Uuid>>,
+    /// only ids in `from..=to`, compared byte by byte
+    id_range: Option<pieskieo_core::VectorIdRange>,
     ef_search: Option<usize>,
-    filter_meta: Option<HashMap<String, String>>,
+    filter_meta: Option<HashMap<String, serde_json::Value>>,
     namespace: Option<String>,
+    #[serde(default)]
+    include_vectors: bool,
+    #[serde(default)]
+    include_meta: bool,
+    /// rescore hits exactly and map every score into [0, 1]
+    #[serde(default)]
+    normalized: bool,
+    /// `X-Pieskieo-Next` token of the previous page
+    after: Option<String>,
 }
 
 #[derive(Deserialize)]
@@ -569,11 +1230,63 @@ struct ReshardRequest {
     shards: usize,
 }
 
+#[derive(Deserialize)]
+struct ReshardQuery {
+    /// report where records would land instead of resharding
+    #[serde(default)]
+    dry_run: bool,
+}
+
+/// Outcome of a reshard dry run. Counts cover docs, rows and vectors.
+#[derive(Serialize)]
+struct ReshardPlan {
+    shards: usize,
+    records: usize,
+    /// records that would change shard
+    moved: usize,
+    before_counts: BTreeMap<usize, usize>,
+    after_counts: BTreeMap<usize, usize>,
+}
+
 #[derive(Serialize)]
 struct ReshardStatus {
     status: Option<ReshardReport>,
     paused: bool,
 }
+#[derive(Deserialize)]
+struct NamespaceQuery {
+    namespace: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct DropNamespaceQuery {
+    force: Option<bool>,
+}
+
+#[derive(Deserialize)]
+struct RangeQuery {
+    field: String,
+    after_value: Option<f64>,
+    /// id of the last doc on the previous page; breaks ties on `after_value`
+    after_id: Option<Uuid>,
+    limit: Option<usize>,
+    namespace: Option<String>,
+    collection: Option<String>,
+}
+
+#[derive(Serialize)]
+struct RangeCursor {
+    after_value: f64,
+    after_id: Uuid,
+}
+
+#[derive(Serialize)]
+struct RangePage {
+    items: Vec<(Uuid, serde_json::Value)>,
+    /// pass back as `after_value`/`after_id` for the next page; absent on the last
+    next: Option<RangeCursor>,
+}
+
 #[derive(Deserialize)]
 struct WalQuery {
     since: Option<u64>,
@@ -609,28 +1322,95 @@ struct ApiResponse<T> {
     data: T,
 }
 
+impl<T> ApiResponse<T> {
+    /// Adds the milliseconds since `started`, if timing was asked for.
+    fn timed(self, started: Option<Instant>) -> TimedResponse<T> {
+        TimedResponse {
+            ok: self.ok,
+            data: self.data,
+            took_ms: started.map(|s| s.elapsed().as_secs_f64() * 1000.0),
+        }
+    }
+}
+
+/// `ApiResponse` plus the server-side handler time under `?timing=true`.
+#[derive(Serialize)]
+struct TimedResponse<T> {
+    ok: bool,
+    data: T,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    took_ms: Option<f64>,
+}
+
+#[derive(Deserialize)]
+struct TimingQuery {
+    #[serde(default)]
+    timing: bool,
+}
+
+#[derive(Deserialize)]
+struct DocQueryFlags {
+    #[serde(default)]
+    timing: bool,
+    #[serde(default)]
+    debug: bool,
+}
+
+/// `/v1/doc/query` response; `debug` is set for filter queries under `?debug=true`.
+#[derive(Serialize)]
+struct DocQueryResponse {
+    #[serde(flatten)]
+    inner: TimedResponse<Vec<(Uuid, serde_json::Value)>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    debug: Option<QueryStats>,
+}
+
+/// `ApiResponse<Uuid>` plus whether the put inserted a new id.
+#[derive(Serialize)]
+struct PutResponse {
+    ok: bool,
+    data: Uuid,
+    created: bool,
+    /// natural key the id was derived from, echoed as sent
+    #[serde(skip_serializing_if = "Option::is_none")]
+    key: Option<String>,
+}
+
 #[derive(Deserialize)]
 struct NsParams {
     namespace: Option<String>,
     collection: Option<String>,
     table: Option<String>,
+    /// on a miss, look on every shard (overrides `PIESKIEO_SCATTER_GET`)
+    scatter: Option<bool>,
 }
 
 #[derive(Serialize)]
 struct VectorOutput {
     id: Uuid,
     vector: Vec<f32>,
-    meta: Option<HashMap<String, String>>,
+    meta: Option<VectorMeta>,
 }
 
 struct DbPool {
-    shards: Vec<Arc<PieskieoDb>>,
+    /// `None` for a shard that failed to open under `PIESKIEO_SKIP_FAILED_SHARDS`
+    shards: Vec<Option<Arc<PieskieoDb>>>,
+    /// why each unavailable shard failed to open
+    down: BTreeMap<usize, String>,
     template: PieskieoVectorParams,
 }
 
 impl DbPool {
-    fn new(base_dir: &str, params: PieskieoVectorParams, shards: usize) -> anyhow::Result<Self> {
+    /// With `skip_failed`, a shard that fails to open is logged and left out
+    /// instead of failing the whole pool, as long as one shard opens.
+    fn new(
+        base_dir: &str,
+        params: PieskieoVectorParams,
+        shards: usize,
+        skip_failed: bool,
+    ) -> anyhow::Result<Self> {
         let mut v = Vec::with_capacity(shards.max(1));
+        let mut down = BTreeMap::new();
         for i in 0..shards.max(1) {
             let mut p = params.clone();
             p.shard_id = i;
@@ -640,32 +1420,80 @@ impl DbPool {
             } else {
                 base_dir.to_string()
             };
-            std::fs::create_dir_all(&dir)?;
-            v.push(Arc::new(PieskieoDb::open_with_params(&dir, p)?));
+            let opened = std::fs::create_dir_all(&dir)
+                .map_err(anyhow::Error::from)
+                .and_then(|_| PieskieoDb::open_with_params(&dir, p).map_err(anyhow::Error::from));
+            match opened {
+                Ok(db) => v.push(Some(Arc::new(db))),
+                Err(e) if skip_failed => {
+                    tracing::error!(
+                        shard = i,
+                        dir = %dir,
+                        error = %e,
+                        "shard failed to open; serving without it"
+                    );
+                    down.insert(i, format!("{e:#}"));
+                    v.push(None);
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        if down.len() == v.len() {
+            anyhow::bail!("no shard could be opened");
         }
         Ok(Self {
             shards: v,
+            down,
             template: params,
         })
     }
 
-    fn shard_for(&self, id: &Uuid) -> Arc<PieskieoDb> {
-        if self.shards.len() == 1 {
-            return self.shards[0].clone();
-        }
-        let mut arr = [0u8; 8];
-        arr.copy_from_slice(&id.as_bytes()[..8]);
-        let idx = (u64::from_le_bytes(arr) as usize) % self.shards.len();
-        self.shards[idx].clone()
+    fn shard_for(&self, id: &Uuid) -> Result<Arc<PieskieoDb>, ApiError> {
+        self.shard(pieskieo_core::shard_index(id, self.shards.len()))
+    }
+
+    /// Shard `i`, or 503 while it is down.
+    fn shard(&self, i: usize) -> Result<Arc<PieskieoDb>, ApiError> {
+        self.shards[i]
+            .clone()
+            .ok_or_else(|| ApiError::Unavailable(format!("shard {i} is unavailable")))
     }
 
+    /// Every shard that is up; fan-out reads skip the ones that are down.
     fn each(&self) -> impl Iterator<Item = Arc<PieskieoDb>> + '_ {
-        self.shards.iter().cloned()
+        self.shards.iter().flatten().cloned()
+    }
+
+    /// `(index, shard)` for every shard that is up.
+    fn live(&self) -> impl Iterator<Item = (usize, &Arc<PieskieoDb>)> + '_ {
+        self.shards
+            .iter()
+            .enumerate()
+            .filter_map(|(i, shard)| Some((i, shard.as_ref()?)))
+    }
+
+    /// The shards a read runs on: every shard, or just the listed indices
+    /// (each once, in index order).
+    fn pick(&self, only: Option<&[usize]>) -> Result<Vec<Arc<PieskieoDb>>, ApiError> {
+        let Some(only) = only else {
+            return Ok(self.each().collect());
+        };
+        if only.is_empty() {
+            return Err(ApiError::BadRequest("shards must not be empty".into()));
+        }
+        if let Some(bad) = only.iter().find(|i| **i >= self.shards.len()) {
+            return Err(ApiError::BadRequest(format!(
+                "shard {bad} out of range: {} shards",
+                self.shards.len()
+            )));
+        }
+        let picked: BTreeSet<usize> = only.iter().copied().collect();
+        picked.into_iter().map(|i| self.shard(i)).collect()
     }
 
     fn counts(&self) -> HashMap<usize, usize> {
         let mut out = HashMap::new();
-        for shard in &self.shards {
+        for shard in self.each() {
             let m = shard.metrics();
             out.insert(shard.shard_id(), m.docs + m.rows);
         }
@@ -687,8 +1515,16 @@ impl DbPool {
             link_top_k: 0,
             shard_id: 0,
             shard_total: self.shards.len(),
+            docs_written: 0,
+            rows_written: 0,
+            vectors_written: 0,
+            edges_written: 0,
+            compactions: 0,
+            wal_records_compacted: 0,
+            last_compaction_ms: 0,
+            last_compaction_us: 0,
         };
-        for shard in &self.shards {
+        for shard in self.each() {
             let m = shard.metrics();
             agg.docs += m.docs;
             agg.rows += m.rows;
@@ -700,13 +1536,43 @@ impl DbPool {
             agg.link_top_k = m.link_top_k;
             agg.wal_bytes += m.wal_bytes;
             agg.snapshot_mtime = agg.snapshot_mtime.or(m.snapshot_mtime);
+            agg.docs_written += m.docs_written;
+            agg.rows_written += m.rows_written;
+            agg.vectors_written += m.vectors_written;
+            agg.edges_written += m.edges_written;
+            agg.compactions += m.compactions;
+            agg.wal_records_compacted += m.wal_records_compacted;
+            if m.last_compaction_ms > agg.last_compaction_ms {
+                agg.last_compaction_ms = m.last_compaction_ms;
+                agg.last_compaction_us = m.last_compaction_us;
+            }
         }
         agg
     }
 
+    /// Per-namespace vector counts and memory summed over shards; rebuild
+    /// time is the slowest shard's.
+    fn vector_ns_stats(&self) -> Vec<pieskieo_core::engine::VectorNsStats> {
+        let mut merged: BTreeMap<String, pieskieo_core::engine::VectorNsStats> = BTreeMap::new();
+        for shard in self.each() {
+            for s in shard.vector_ns_stats() {
+                let e = merged.entry(s.namespace.clone()).or_default();
+                e.namespace = s.namespace;
+                e.vectors += s.vectors;
+                e.tombstones += s.tombstones;
+                // shards rebuild in parallel, so the slowest one is the wait
+                e.last_rebuild_us = e.last_rebuild_us.max(s.last_rebuild_us);
+                e.memory_bytes += s.memory_bytes;
+                e.owned_bytes += s.owned_bytes;
+                e.leaked_bytes += s.leaked_bytes;
+            }
+        }
+        merged.into_values().collect()
+    }
+
     fn wal_all(&self) -> Vec<pieskieo_core::wal::RecordKind> {
         let mut out = Vec::new();
-        for shard in &self.shards {
+        for shard in self.each() {
             if let Ok(mut r) = shard.wal_dump() {
                 out.append(&mut r);
             }
@@ -719,27 +1585,170 @@ impl DbPool {
     }
 }
 
+/// Create the first admin in `<data_dir>/auth_users.json` (default: `PIESKIEO_DATA`
+/// or the platform data dir). Refuses to touch an existing user file unless `force`,
+/// in which case `user` is replaced and other users are kept. Returns the file path.
+pub fn bootstrap_admin(
+    data_dir: Option<&str>,
+    user: &str,
+    pass: &str,
+    force: bool,
+) -> anyhow::Result<PathBuf> {
+    let user = user.trim();
+    if user.is_empty() {
+        anyhow::bail!("user must not be empty");
+    }
+    PasswordPolicy::from_env()
+        .validate(pass)
+        .map_err(|msg| anyhow::anyhow!(msg))?;
+    let data_dir = data_dir
+        .map(str::to_string)
+        .or_else(|| std::env::var("PIESKIEO_DATA").ok())
+        .unwrap_or_else(default_data_dir);
+    let path = PathBuf::from(data_dir).join("auth_users.json");
+    let mut users: Vec<UserDisk> = match std::fs::read_to_string(&path) {
+        Ok(txt) if !txt.trim().is_empty() => match serde_json::from_str(&txt) {
+            Ok(users) => users,
+            Err(_) if force => Vec::new(),
+            Err(e) => anyhow::bail!("{} is not valid JSON: {e}", path.display()),
+        },
+        _ => Vec::new(),
+    };
+    if !users.is_empty() && !force {
+        anyhow::bail!(
+            "{} already has {} user(s); pass --force to replace {user}",
+            path.display(),
+            users.len()
+        );
+    }
+    users.retain(|u| u.user != user);
+    users.push(UserDisk {
+        user: user.to_string(),
+        pass: AuthConfig::hash_password(pass),
+        role: "admin".into(),
+    });
+    ensure_parent(&path)?;
+    let tmp = path.with_extension("json.tmp");
+    std::fs::write(&tmp, serde_json::to_string_pretty(&users)?)?;
+    std::fs::rename(&tmp, &path)?;
+    Ok(path)
+}
+
 pub async fn serve() -> anyhow::Result<()> {
     let data_dir = std::env::var("PIESKIEO_DATA").unwrap_or_else(|_| default_data_dir());
     init_logging(&data_dir);
 
+    // Exact vector scoring runs on rayon from inside tokio's blocking pool;
+    // cap it so both pools together don't oversubscribe the cores.
+    if let Some(n) = std::env::var("PIESKIEO_RAYON_THREADS")
+        .ok()
+        .and_then(|s| s.parse::<usize>().ok())
+        .filter(|n| *n > 0)
+    {
+        if let Err(e) = rayon::ThreadPoolBuilder::new()
+            .num_threads(n)
+            .build_global()
+        {
+            tracing::warn!(error = %e, "rayon pool already initialised");
+        }
+    }
+
     let auth = Arc::new(RwLock::new(AuthConfig::from_env(&data_dir)));
     let params = vector_params_from_env();
     let shards = params.shard_total.max(1);
-    let pool = Arc::new(RwLock::new(DbPool::new(&data_dir, params, shards)?));
+    let skip_failed = std::env::var("PIESKIEO_SKIP_FAILED_SHARDS")
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .unwrap_or(false);
+    let pool = Arc::new(RwLock::new(DbPool::new(
+        &data_dir,
+        params,
+        shards,
+        skip_failed,
+    )?));
     let limiter = Arc::new(RateLimiter::from_env());
+    let concurrency = Arc::new(ConcurrencyLimiter::from_env());
     let audit = Arc::new(AuditLog::new(
         PathBuf::from(&data_dir).join("logs").join("audit.log"),
     ));
 
+    let check_secs = std::env::var("PIESKIEO_TOMBSTONE_CHECK_SECS")
+        .ok()
+        .and_then(|s| s.parse::<u64>().ok())
+        .unwrap_or(300)
+        .max(1);
+
+    // off unless one of the two is set: vacuum rebuilds HNSW and blocks that
+    // shard's index while it runs
+    let vacuum_secs = std::env::var("PIESKIEO_VACUUM_INTERVAL_SECS")
+        .ok()
+        .and_then(|s| s.parse::<u64>().ok());
+    let vacuum_ratio = std::env::var("PIESKIEO_AUTO_VACUUM_RATIO")
+        .ok()
+        .and_then(|s| s.parse::<f64>().ok());
+    let compaction_task = (vacuum_secs.is_some() || vacuum_ratio.is_some()).then(|| {
+        let secs = vacuum_secs.unwrap_or(check_secs).max(1);
+        Arc::new(TaskHeartbeat::new(Duration::from_secs(secs)))
+    });
+    // a typo here would silently re-map every key, so refuse to start instead
+    let key_space = match std::env::var("PIESKIEO_KEY_NAMESPACE") {
+        Ok(s) => Uuid::parse_str(s.trim())
+            .map_err(|e| anyhow::anyhow!("PIESKIEO_KEY_NAMESPACE is not a UUID: {e}"))?,
+        Err(_) => Uuid::new_v5(&Uuid::NAMESPACE_URL, b"urn:pieskieo:key"),
+    };
+
     let state = AppState {
         pool,
         auth,
         limiter,
+        concurrency,
+        search_limiter: Arc::new(SearchLimiter::from_env()),
         audit,
         data_dir,
         pause_writes: Arc::new(AtomicBool::new(false)),
         reshard_status: Arc::new(RwLock::new(None)),
+        wal_flush: Arc::new(WalFlushStats::default()),
+        metric_prefix: std::env::var("PIESKIEO_METRIC_PREFIX")
+            .ok()
+            .filter(|s| !s.is_empty())
+            .unwrap_or_else(|| "pieskieo".to_string())
+            .into(),
+        key_space,
+        scatter_get: std::env::var("PIESKIEO_SCATTER_GET")
+            .map(|s| matches!(s.to_ascii_lowercase().as_str(), "1" | "true" | "on"))
+            .unwrap_or(false),
+        bulk_delete_max: std::env::var("PIESKIEO_BULK_DELETE_MAX")
+            .ok()
+            .and_then(|s| s.parse::<usize>().ok())
+            .unwrap_or(10_000)
+            .max(1),
+        prefilter_max: std::env::var("PIESKIEO_VECTOR_PREFILTER_MAX")
+            .ok()
+            .and_then(|s| s.parse().ok())
+            .unwrap_or(1024),
+        default_k: std::env::var("PIESKIEO_VECTOR_DEFAULT_K")
+            .ok()
+            .and_then(|s| s.parse().ok())
+            .unwrap_or(10),
+        max_k: std::env::var("PIESKIEO_VECTOR_MAX_K")
+            .ok()
+            .and_then(|s| s.parse::<usize>().ok())
+            .unwrap_or(1000)
+            .max(1),
+        batch_queries_max: std::env::var("PIESKIEO_VECTOR_BATCH_MAX")
+            .ok()
+            .and_then(|s| s.parse::<usize>().ok())
+            .unwrap_or(256)
+            .max(1),
+        shard_addrs: std::env::var("PIESKIEO_SHARD_ADDRS")
+            .map(|s| {
+                s.split(',')
+                    .map(|a| a.trim().trim_end_matches('/').to_string())
+                    .collect()
+            })
+            .unwrap_or_default(),
+        started: Instant::now(),
+        compaction_task,
     };
 
     // background WAL flusher (group commit) for better latency.
@@ -749,29 +1758,75 @@ pub async fn serve() -> anyhow::Result<()> {
         .unwrap_or(50);
     {
         let pool = state.pool.clone();
+        let stats = state.wal_flush.clone();
         tokio::spawn(async move {
             let mut interval = tokio::time::interval(std::time::Duration::from_millis(flush_ms));
+            // a slow fsync must not queue up a burst of back-to-back flushes
+            interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
             loop {
                 interval.tick().await;
-                let guard = pool.read().await;
-                for shard in guard.each() {
+                // only shards with unflushed bytes; release the pool lock before fsync
+                let dirty: Vec<Arc<PieskieoDb>> = pool
+                    .read()
+                    .await
+                    .each()
+                    .filter(|shard| shard.wal_pending_bytes() > 0)
+                    .collect();
+                if dirty.is_empty() {
+                    continue;
+                }
+                let start = Instant::now();
+                for shard in dirty {
                     if let Err(e) = shard.flush_wal() {
                         tracing::warn!("wal flush failed: {e}");
                     }
                 }
+                let us = start.elapsed().as_micros() as u64;
+                stats.last_us.store(us, Ordering::Relaxed);
+                stats.max_us.fetch_max(us, Ordering::Relaxed);
+                stats.runs.fetch_add(1, Ordering::Relaxed);
             }
         });
     }
 
+    {
+        let auth = state.auth.clone();
+        let prune_secs = std::env::var("PIESKIEO_AUTH_PRUNE_SECS")
+            .ok()
+            .and_then(|s| s.parse::<u64>().ok())
+            .unwrap_or(60)
+            .max(1);
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(std::time::Duration::from_secs(prune_secs));
+            loop {
+                interval.tick().await;
+                let auth = auth.read().await;
+                let removed = auth.prune_attempts();
+                if removed > 0 {
+                    tracing::debug!(removed, "pruned stale auth attempt records");
+                }
+                // a no-op unless a failure, login or the prune changed something
+                auth.save_attempts();
+            }
+        });
+    }
+
+    // serializes snapshot, vacuum and rebuild ticks so they never write the
+    // same snapshot files at once
+    let maintenance = Arc::new(tokio::sync::Mutex::new(()));
+
     if let Ok(secs) = std::env::var("PIESKIEO_SNAPSHOT_INTERVAL_SECS") {
         if let Ok(secs) = secs.parse::<u64>() {
             let pool = state.pool.clone();
+            let maintenance = maintenance.clone();
             tokio::spawn(async move {
                 let mut interval = tokio::time::interval(std::time::Duration::from_secs(secs));
                 loop {
                     interval.tick().await;
+                    let _running = maintenance.lock().await;
                     let guard = pool.read().await;
-                    for shard in guard.each() {
+                    // quiet shards keep their last snapshot
+                    for shard in guard.each().filter(|s| s.changed_since_snapshot()) {
                         if let Err(e) = shard.save_vector_snapshot() {
                             tracing::warn!("snapshot save failed: {e}");
                         }
@@ -781,19 +1836,217 @@ pub async fn serve() -> anyhow::Result<()> {
         }
     }
 
+    // one snapshot once a shard has gone quiet, so idle instances are on disk
+    // without periodic snapshots waking them up
+    if let Some(idle_secs) = std::env::var("PIESKIEO_IDLE_SNAPSHOT_SECS")
+        .ok()
+        .and_then(|s| s.parse::<u64>().ok())
+        .filter(|s| *s > 0)
+    {
+        let pool = state.pool.clone();
+        let maintenance = maintenance.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(std::time::Duration::from_secs(idle_secs));
+            loop {
+                interval.tick().await;
+                let now = unix_ms(std::time::SystemTime::now());
+                // same order as the other maintenance tasks: maintenance, then pool
+                let _running = maintenance.lock().await;
+                let guard = pool.read().await;
+                for shard in guard.each() {
+                    let idle = shard
+                        .last_write_ms()
+                        .is_some_and(|w| now.saturating_sub(w) >= idle_secs * 1000);
+                    if !idle || !shard.changed_since_snapshot() {
+                        continue;
+                    }
+                    match shard.save_vector_snapshot() {
+                        Ok(()) => tracing::info!(shard = shard.shard_id(), "idle; snapshot saved"),
+                        Err(e) => tracing::warn!("snapshot save failed: {e}"),
+                    }
+                }
+            }
+        });
+    }
+
+    if let Some(threshold) = std::env::var("PIESKIEO_SNAPSHOT_WAL_BYTES")
+        .ok()
+        .and_then(|s| s.parse::<u64>().ok())
+        .filter(|b| *b > 0)
+    {
+        let pool = state.pool.clone();
+        let maintenance = maintenance.clone();
+        let check_secs = std::env::var("PIESKIEO_SNAPSHOT_WAL_CHECK_SECS")
+            .ok()
+            .and_then(|s| s.parse::<u64>().ok())
+            .unwrap_or(10)
+            .max(1);
+        tokio::spawn(async move {
+            // WAL size after each shard's last checkpoint; live records stay in
+            // the compacted log, so growth since then is what counts
+            let mut snapped_at: HashMap<usize, u64> = HashMap::new();
+            let mut interval = tokio::time::interval(std::time::Duration::from_secs(check_secs));
+            loop {
+                interval.tick().await;
+                let _running = maintenance.lock().await;
+                let guard = pool.read().await;
+                for shard in guard.each() {
+                    let Ok(bytes) = shard.wal_current_offset() else {
+                        continue;
+                    };
+                    let base = snapped_at.entry(shard.shard_id()).or_insert(0);
+                    if bytes < *base {
+                        // compacted by a vacuum or replaced by a reshard
+                        *base = bytes;
+                    }
+                    if bytes - *base < threshold {
+                        continue;
+                    }
+                    let id = shard.shard_id();
+                    let task_shard = shard.clone();
+                    let res = tokio::task::spawn_blocking(move || task_shard.checkpoint()).await;
+                    match res {
+                        Ok(Ok(())) => {
+                            let after = shard.wal_current_offset().unwrap_or(0);
+                            tracing::info!(
+                                shard = id,
+                                wal_bytes = bytes,
+                                compacted_bytes = after,
+                                "WAL grew past PIESKIEO_SNAPSHOT_WAL_BYTES; snapshot saved and WAL compacted"
+                            );
+                            *base = after;
+                        }
+                        Ok(Err(e)) => tracing::warn!("checkpoint failed: {e}"),
+                        Err(e) => tracing::warn!("checkpoint panicked: {e}"),
+                    }
+                }
+            }
+        });
+    }
+
+    {
+        let pool = state.pool.clone();
+        let warn_ratio = std::env::var("PIESKIEO_TOMBSTONE_WARN_RATIO")
+            .ok()
+            .and_then(|s| s.parse::<f64>().ok())
+            .unwrap_or(0.2);
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(std::time::Duration::from_secs(check_secs));
+            loop {
+                interval.tick().await;
+                let guard = pool.read().await;
+                for ns in guard.vector_ns_stats() {
+                    let ratio = ns.tombstone_ratio();
+                    if ratio >= warn_ratio {
+                        tracing::warn!(
+                            namespace = %ns.namespace,
+                            tombstones = ns.tombstones,
+                            ratio,
+                            "vector tombstones are high; vacuum this namespace"
+                        );
+                    }
+                }
+            }
+        });
+    }
+
+    if let Some(heartbeat) = state.compaction_task.clone() {
+        let pool = state.pool.clone();
+        let pause_writes = state.pause_writes.clone();
+        let maintenance = maintenance.clone();
+        let min_ratio = vacuum_ratio.unwrap_or(0.1);
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(heartbeat.interval);
+            loop {
+                interval.tick().await;
+                if pause_writes.load(Ordering::SeqCst) {
+                    heartbeat.beat();
+                    continue;
+                }
+                let _running = maintenance.lock().await;
+                let guard = pool.read().await;
+                for shard in guard.each() {
+                    for ns in shard.vector_ns_stats() {
+                        if ns.tombstones == 0 || ns.tombstone_ratio() < min_ratio {
+                            continue;
+                        }
+                        // the last clean namespace compacts the whole WAL,
+                        // which reads and rewrites it; keep that off the executor
+                        let task_shard = shard.clone();
+                        let task_ns = ns.namespace.clone();
+                        let res = tokio::task::spawn_blocking(move || {
+                            task_shard.vacuum_ns(Some(&task_ns))
+                        })
+                        .await;
+                        match res {
+                            Ok(Ok(())) => tracing::info!(
+                                namespace = %ns.namespace,
+                                shard = shard.shard_id(),
+                                tombstones = ns.tombstones,
+                                "auto-vacuumed vector namespace"
+                            ),
+                            Ok(Err(e)) => tracing::warn!("auto-vacuum failed: {e}"),
+                            Err(e) => tracing::warn!("auto-vacuum panicked: {e}"),
+                        }
+                    }
+                }
+                heartbeat.beat();
+            }
+        });
+    }
+
     if let Ok(secs) = std::env::var("PIESKIEO_REBUILD_INTERVAL_SECS") {
         if let Ok(secs) = secs.parse::<u64>() {
             let pool = state.pool.clone();
+            // each rebuild holds a blocking thread and a core; the cap keeps
+            // cores free for searches
+            let permits = Arc::new(tokio::sync::Semaphore::new(
+                std::env::var("PIESKIEO_REBUILD_CONCURRENCY")
+                    .ok()
+                    .and_then(|s| s.parse::<usize>().ok())
+                    .unwrap_or(1)
+                    .max(1),
+            ));
             tokio::spawn(async move {
                 let mut interval = tokio::time::interval(std::time::Duration::from_secs(secs));
                 loop {
                     interval.tick().await;
+                    let _running = maintenance.lock().await;
                     let guard = pool.read().await;
-                    for shard in guard.each() {
-                        if let Err(e) = shard.rebuild_vectors() {
-                            tracing::warn!("vector rebuild failed: {e}");
+                    let jobs = guard.each().flat_map(|shard| {
+                        shard
+                            .dirty_vector_namespaces()
+                            .into_iter()
+                            .map(move |ns| (shard.clone(), ns))
+                    });
+                    let runs = jobs.map(|(shard, ns)| {
+                        let permits = permits.clone();
+                        async move {
+                            let Ok(_permit) = permits.acquire_owned().await else {
+                                return;
+                            };
+                            let id = shard.shard_id();
+                            let task_ns = ns.clone();
+                            let res = tokio::task::spawn_blocking(move || {
+                                shard.rebuild_vectors_ns(Some(&task_ns))
+                            })
+                            .await;
+                            match res {
+                                Ok(Ok(())) => tracing::info!(
+                                    shard = id,
+                                    namespace = %ns,
+                                    "rebuilt changed vector namespace"
+                                ),
+                                Ok(Err(e)) => {
+                                    tracing::warn!(namespace = %ns, "vector rebuild failed: {e}")
+                                }
+                                Err(e) => {
+                                    tracing::warn!(namespace = %ns, "vector rebuild panicked: {e}")
+                                }
+                            }
                         }
-                    }
+                    });
+                    join_all(runs).await;
                 }
             });
         }
@@ -801,44 +2054,86 @@ pub async fn serve() -> anyhow::Result<()> {
 
     let app = Router::new()
         .route("/healthz", get(health))
+        .route("/readyz", get(ready))
+        .route("/v1/openapi.json", get(openapi))
         .route("/v1/doc", post(put_doc))
         .route("/v1/doc/:id", get(get_doc))
         .route("/v1/doc/:id", delete(delete_doc))
+        .route("/v1/doc/delete", post(delete_docs))
+        .route("/v1/doc/:id", head(head_doc))
+        .route("/v1/doc/:id", patch(patch_doc))
+        .route("/v1/doc/key/:key", get(get_doc_by_key))
         .route("/v1/doc/query", post(query_docs))
+        .route("/v1/doc/count", post(count_docs))
+        .route("/v1/doc/range", get(range_docs))
         .route("/v1/row", post(put_row))
         .route("/v1/row/:id", get(get_row))
         .route("/v1/row/:id", delete(delete_row))
+        .route("/v1/row/delete", post(delete_rows))
+        .route("/v1/row/:id", head(head_row))
+        .route("/v1/row/key/:key", get(get_row_by_key))
         .route("/v1/row/query", post(query_rows))
+        .route("/v1/row/count", post(count_rows))
         .route("/v1/vector", post(put_vector))
         .route("/v1/vector/:id/meta", post(update_vector_meta))
         .route("/v1/vector/config", post(update_vector_config))
         .route("/v1/vector/:id/meta/delete", post(delete_vector_meta_keys))
         .route("/v1/vector/:id", get(get_vector))
+        .route("/v1/vector/:id", head(head_vector))
+        .route("/v1/vector/mget", post(mget_vectors))
         .route("/v1/vector/vacuum", post(vacuum_vectors))
+        .route("/v1/vector/stats", get(vector_stats))
         .route("/v1/shard/which/:id", get(which_shard))
+        .route("/v1/shard/map", get(shard_map))
         .route("/v1/vector/search", post(search_vector))
+        .route("/v1/vector/search/batch", post(search_vector_batch))
         .route("/v1/vector/rebuild", post(rebuild_vectors))
         .route("/v1/vector/snapshot/save", post(save_snapshot))
+        .route("/v1/vector/export", get(export_vectors))
+        .route("/v1/vector/import", post(import_vectors))
         .route("/v1/vector/bulk", post(put_vector_bulk))
         .route("/v1/vector/:id", delete(delete_vector))
+        .route("/v1/vector/delete", post(delete_vectors))
         .route("/v1/schema", post(set_schema))
+        .route("/v1/schema/jsonschema", post(set_json_schema))
         .route("/v1/sql", post(query_sql))
+        .route("/v1/sql/validate", post(validate_sql))
         .route("/v1/replica/wal", get(replica_wal))
+        .route("/v1/replica/head", get(replica_head))
         .route("/v1/replica/stream", get(replica_stream))
         .route("/v1/replica/apply", post(replica_apply))
         .route("/v1/replica/ws", get(replica_ws))
         .route("/metrics", get(metrics))
         .route("/v1/admin/reshard", post(reshard))
         .route("/v1/admin/reshard/status", get(reshard_status))
+        .route("/v1/admin/namespace/:ns", delete(drop_namespace))
+        .route("/v1/admin/flush", post(flush_wal_all))
+        .route("/v1/batch", post(apply_batch))
         .route("/v1/graph/edge", post(add_edge))
+        .route("/v1/graph/components", get(graph_components))
+        .route("/v1/graph/export", get(export_graph))
+        .route("/v1/graph/import", post(import_graph))
         .route("/v1/graph/:id", get(list_neighbors))
+        .route("/v1/graph/:id", delete(delete_node))
         .route("/v1/graph/:id/bfs", get(list_bfs))
         .route("/v1/graph/:id/dfs", get(list_dfs))
         .route("/v1/auth/users", get(list_users))
         .route("/v1/auth/users", post(create_user))
         .layer(middleware::from_fn_with_state(
-            state.audit.clone(),
-            audit_middleware,
+            state.pool.clone(),
+            durable_middleware,
+        ))
+        .layer(middleware::from_fn_with_state(
+            std::env::var("PIESKIEO_REQUEST_TIMEOUT_SECS")
+                .ok()
+                .and_then(|s| s.parse::<u64>().ok())
+                .map(Duration::from_secs)
+                .unwrap_or_default(),
+            timeout_middleware,
+        ))
+        .layer(middleware::from_fn_with_state(
+            state.concurrency.clone(),
+            concurrency_middleware,
         ))
         .layer(middleware::from_fn_with_state(
             state.limiter.clone(),
@@ -848,6 +2143,12 @@ pub async fn serve() -> anyhow::Result<()> {
             state.auth.clone(),
             auth_middleware,
         ))
+        // outermost so rejected auth and rate-limited requests are audited too
+        .layer(middleware::from_fn_with_state(
+            state.audit.clone(),
+            audit_middleware,
+        ))
+        .layer(middleware::from_fn(client_ip_middleware))
         .layer(DefaultBodyLimit::max(
             std::env::var("PIESKIEO_BODY_LIMIT_MB")
                 .ok()
@@ -857,6 +2158,10 @@ pub async fn serve() -> anyhow::Result<()> {
                 * 1024,
         ))
         .with_state(state);
+    let app = match compression_layer() {
+        Some(layer) => app.layer(layer),
+        None => app,
+    };
 
     let addr: SocketAddr = std::env::var("PIESKIEO_LISTEN")
         .unwrap_or_else(|_| "0.0.0.0:8000".into())
@@ -957,6 +2262,10 @@ fn vector_params_from_env() -> PieskieoVectorParams {
         .ok()
         .and_then(|s| s.parse::<usize>().ok())
         .unwrap_or(100_000);
+    let initial_capacity = std::env::var("PIESKIEO_VECTOR_INITIAL_CAPACITY")
+        .ok()
+        .and_then(|s| s.parse::<usize>().ok())
+        .unwrap_or(pieskieo_core::vector::DEFAULT_INITIAL_CAPACITY);
     let link_top_k = std::env::var("PIESKIEO_LINK_K")
         .ok()
         .and_then(|s| s.parse::<usize>().ok())
@@ -969,6 +2278,22 @@ fn vector_params_from_env() -> PieskieoVectorParams {
         .ok()
         .and_then(|s| s.parse::<usize>().ok())
         .unwrap_or(0);
+    let link_weight = std::env::var("PIESKIEO_LINK_WEIGHT")
+        .ok()
+        .and_then(|s| pieskieo_core::LinkWeight::parse(&s))
+        .unwrap_or_default();
+    let wal_fail_fast = std::env::var("PIESKIEO_WAL_FAIL_FAST")
+        .map(|s| !matches!(s.to_ascii_lowercase().as_str(), "0" | "false" | "off"))
+        .unwrap_or(true);
+    // PIESKIEO_EXACT_BELOW is accepted as a shorter alias
+    let exact_below = std::env::var("PIESKIEO_VECTOR_EXACT_BELOW")
+        .or_else(|_| std::env::var("PIESKIEO_EXACT_BELOW"))
+        .ok()
+        .and_then(|s| s.parse::<usize>().ok())
+        .unwrap_or(0);
+    let sql_prefix_family = std::env::var("PIESKIEO_SQL_PREFIX_FAMILY")
+        .map(|s| !matches!(s.to_ascii_lowercase().as_str(), "0" | "false" | "off"))
+        .unwrap_or(true);
 
     PieskieoVectorParams {
         metric,
@@ -978,7 +2303,42 @@ fn vector_params_from_env() -> PieskieoVectorParams {
         link_top_k,
         shard_id,
         shard_total,
+        link_weight,
+        wal_fail_fast,
+        exact_below,
+        sql_prefix_family,
+        initial_capacity,
+    }
+}
+
+/// gzip/br for clients that send `Accept-Encoding`; off by default since tiny
+/// JSON replies cost more CPU to compress than they save on the wire.
+fn compression_layer() -> Option<CompressionLayer<impl Predicate>> {
+    let enabled = std::env::var("PIESKIEO_COMPRESSION")
+        .map(|s| matches!(s.to_ascii_lowercase().as_str(), "1" | "true" | "on"))
+        .unwrap_or(false);
+    if !enabled {
+        return None;
     }
+    let min_bytes = std::env::var("PIESKIEO_COMPRESSION_MIN_BYTES")
+        .ok()
+        .and_then(|s| s.parse::<u16>().ok())
+        .unwrap_or(1024);
+    // streamed bodies (NDJSON export) have no length and are always compressed;
+    // 101 upgrades and error replies are left alone
+    let predicate = DefaultPredicate::new()
+        .and(SizeAbove::new(min_bytes))
+        .and(success_only);
+    Some(CompressionLayer::new().compress_when(predicate))
+}
+
+fn success_only(
+    status: axum::http::StatusCode,
+    _: axum::http::Version,
+    _: &axum::http::HeaderMap,
+    _: &axum::http::Extensions,
+) -> bool {
+    status.is_success()
 }
 
 fn default_data_dir() -> String {
@@ -1054,7 +2414,7 @@ async fn health(State(state): State<AppState>) -> Result<Json<HealthStatus>, Api
     Ok(Json(HealthStatus {
         status: "healthy".to_string(),
         version: env!("CARGO_PKG_VERSION").to_string(),
-        uptime_seconds: 0, // TODO: track startup time
+        uptime_seconds: state.started.elapsed().as_secs(),
         total_docs: m.docs,
         total_rows: m.rows,
         total_vectors: m.vectors,
@@ -1063,27 +2423,115 @@ async fn health(State(state): State<AppState>) -> Result<Json<HealthStatus>, Api
     }))
 }
 
+/// 503 while any shard's WAL is failing to flush, so load balancers stop routing writes here.
+async fn ready(
+    State(state): State<AppState>,
+) -> Result<impl axum::response::IntoResponse, ApiError> {
+    let pool = state.pool.read().await;
+    let unhealthy: Vec<usize> = pool
+        .each()
+        .filter(|shard| !shard.wal_healthy())
+        .map(|shard| shard.shard_id())
+        .collect();
+    let status = if unhealthy.is_empty() {
+        axum::http::StatusCode::OK
+    } else {
+        axum::http::StatusCode::SERVICE_UNAVAILABLE
+    };
+    Ok((
+        status,
+        Json(serde_json::json!({
+            "ready": unhealthy.is_empty(),
+            "wal_unhealthy_shards": unhealthy,
+            // the node still serves the other shards, so these don't fail readiness
+            "down_shards": pool.down,
+            // null when auto-vacuum is off; a stale task is reported, not failed
+            "compaction_task": state.compaction_task.as_ref().map(|t| serde_json::json!({
+                "last_beat_ms": t.last_ms.load(Ordering::Relaxed),
+                "stale": t.stale(),
+            })),
+        })),
+    ))
+}
+
+// Hand-maintained; keep in sync with the router when endpoints change.
+const OPENAPI_DOC: &str = include_str!("openapi.json");
+
+async fn openapi() -> Result<Json<serde_json::Value>, ApiError> {
+    let mut doc: serde_json::Value =
+        serde_json::from_str(OPENAPI_DOC).map_err(|e| ApiError::Internal(e.into()))?;
+    doc["info"]["version"] = serde_json::Value::String(env!("CARGO_PKG_VERSION").to_string());
+    Ok(Json(doc))
+}
+
 async fn put_doc(
     State(state): State<AppState>,
     Json(input): Json<DocInput>,
-) -> Result<Json<ApiResponse<Uuid>>, ApiError> {
+) -> Result<Json<PutResponse>, ApiError> {
     if state.pause_writes.load(std::sync::atomic::Ordering::SeqCst) {
         return Err(ApiError::Conflict("resharding in progress".into()));
     }
-    let id = input.id.unwrap_or_else(Uuid::new_v4);
-    state
+    let id = entity_id(
+        &state,
+        input.id,
+        input.key.as_deref(),
+        input.namespace.as_deref(),
+        input.collection.as_deref(),
+    )?;
+    let data = keyed_data(input.data, input.key.as_deref())?;
+    let created = state
         .pool
         .read()
         .await
-        .shard_for(&id)
+        .shard_for(&id)?
         .put_doc_ns(
             input.namespace.as_deref(),
             input.collection.as_deref(),
             id,
-            input.data,
+            data,
         )
         .map_err(ApiError::from)?;
-    Ok(Json(ApiResponse { ok: true, data: id }))
+    Ok(Json(PutResponse {
+        ok: true,
+        data: id,
+        created,
+        key: input.key,
+    }))
+}
+
+/// Explicit id, else a UUIDv5 of the natural key, else a fresh v4.
+fn entity_id(
+    state: &AppState,
+    id: Option<Uuid>,
+    key: Option<&str>,
+    ns: Option<&str>,
+    collection: Option<&str>,
+) -> Result<Uuid, ApiError> {
+    match (id, key) {
+        (Some(_), Some(_)) => Err(ApiError::BadRequest(
+            "pass either id or key, not both".into(),
+        )),
+        (Some(id), None) => Ok(id),
+        (None, Some(key)) => Ok(pieskieo_core::key_id(&state.key_space, ns, collection, key)),
+        (None, None) => Ok(Uuid::new_v4()),
+    }
+}
+
+/// Payload field that keeps the natural key a record was written under, so
+/// reads hand it back as sent rather than only its UUIDv5.
+const KEY_FIELD: &str = "_key";
+
+fn keyed_data(
+    mut data: serde_json::Value,
+    key: Option<&str>,
+) -> Result<serde_json::Value, ApiError> {
+    if let Some(key) = key {
+        let obj = data
+            .as_object_mut()
+            .ok_or_else(|| ApiError::BadRequest("key requires an object body".into()))?;
+        obj.insert(KEY_FIELD.into(), serde_json::Value::String(key.to_string()));
+    }
+    Ok(data)
 }
 
 async fn get_doc(
@@ -1091,31 +2539,130 @@ async fn get_doc(
     Path(id): Path<Uuid>,
     Query(ns): Query<NsParams>,
 ) -> Result<Json<ApiResponse<serde_json::Value>>, ApiError> {
-    let doc = state
-        .pool
-        .read()
-        .await
-        .shard_for(&id)
-        .get_doc_ns(ns.namespace.as_deref(), ns.collection.as_deref(), &id)
-        .ok_or(ApiError::NotFound)?;
+    let routed = state.pool.read().await.shard_for(&id)?.get_doc_ns(
+        ns.namespace.as_deref(),
+        ns.collection.as_deref(),
+        &id,
+    );
+    let doc = match routed {
+        Some(doc) => doc,
+        None if ns.scatter.unwrap_or(state.scatter_get) => {
+            let NsParams {
+                namespace,
+                collection,
+                ..
+            } = ns;
+            scatter_get(&state, id, "doc", move |shard| {
+                shard.find_doc_ns(namespace.as_deref(), collection.as_deref(), &id)
+            })
+            .await?
+            .ok_or(ApiError::NotFound)?
+        }
+        None => return Err(ApiError::NotFound),
+    };
     Ok(Json(ApiResponse {
         ok: true,
         data: doc,
     }))
 }
 
-async fn delete_doc(
+/// `get_doc` addressed by natural key, deriving the id as `POST` does.
+async fn get_doc_by_key(
     State(state): State<AppState>,
-    Path(id): Path<Uuid>,
+    Path(key): Path<String>,
     Query(ns): Query<NsParams>,
-) -> Result<Json<ApiResponse<&'static str>>, ApiError> {
-    state
+) -> Result<Json<ApiResponse<serde_json::Value>>, ApiError> {
+    let id = pieskieo_core::key_id(
+        &state.key_space,
+        ns.namespace.as_deref(),
+        ns.collection.as_deref(),
+        &key,
+    );
+    get_doc(State(state), Path(id), Query(ns)).await
+}
+
+/// `GET` without the body: 200 if the doc exists, else 404.
+async fn head_doc(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+    Query(ns): Query<NsParams>,
+) -> Result<axum::http::StatusCode, ApiError> {
+    let routed = state.pool.read().await.shard_for(&id)?.has_doc_ns(
+        ns.namespace.as_deref(),
+        ns.collection.as_deref(),
+        &id,
+    );
+    if routed {
+        return Ok(axum::http::StatusCode::OK);
+    }
+    if ns.scatter.unwrap_or(state.scatter_get) {
+        let NsParams {
+            namespace,
+            collection,
+            ..
+        } = ns;
+        let found = scatter_get(&state, id, "doc", move |shard| {
+            shard.find_doc_ns(namespace.as_deref(), collection.as_deref(), &id)
+        })
+        .await?;
+        if found.is_some() {
+            return Ok(axum::http::StatusCode::OK);
+        }
+    }
+    Err(ApiError::NotFound)
+}
+
+#[derive(Deserialize)]
+struct PatchDocQuery {
+    namespace: Option<String>,
+    collection: Option<String>,
+    /// `deep` (default, JSON merge patch) or `shallow` (top-level fields only)
+    merge: Option<String>,
+}
+
+async fn patch_doc(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+    Query(q): Query<PatchDocQuery>,
+    Json(changes): Json<serde_json::Value>,
+) -> Result<Json<ApiResponse<serde_json::Value>>, ApiError> {
+    if state.pause_writes.load(std::sync::atomic::Ordering::SeqCst) {
+        return Err(ApiError::Conflict("resharding in progress".into()));
+    }
+    let deep = match q.merge.as_deref() {
+        None | Some("deep") => true,
+        Some("shallow") => false,
+        Some(_) => return Err(ApiError::BadRequest("merge must be deep or shallow".into())),
+    };
+    let doc = state
         .pool
         .read()
         .await
-        .shard_for(&id)
+        .shard_for(&id)?
+        .patch_doc_ns(
+            q.namespace.as_deref(),
+            q.collection.as_deref(),
+            id,
+            changes,
+            deep,
+        )
+        .map_err(ApiError::from)?;
+    Ok(Json(ApiResponse {
+        ok: true,
+        data: doc,
+    }))
+}
+
+async fn delete_doc(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+    Query(ns): Query<NsParams>,
+) -> Result<Json<ApiResponse<&'static str>>, ApiError> {
+    let pool = state.pool.read().await;
+    pool.shard_for(&id)?
         .delete_doc_ns(ns.namespace.as_deref(), ns.collection.as_deref(), &id)
         .map_err(ApiError::from)?;
+    remove_node_everywhere(&pool, id)?;
     Ok(Json(ApiResponse {
         ok: true,
         data: "deleted",
@@ -1125,66 +2672,411 @@ async fn delete_doc(
 async fn put_row(
     State(state): State<AppState>,
     Json(input): Json<RowInput>,
-) -> Result<Json<ApiResponse<Uuid>>, ApiError> {
+) -> Result<Json<PutResponse>, ApiError> {
     if state.pause_writes.load(std::sync::atomic::Ordering::SeqCst) {
         return Err(ApiError::Conflict("resharding in progress".into()));
     }
-    let id = input.id.unwrap_or_else(Uuid::new_v4);
-    state
+    let id = entity_id(
+        &state,
+        input.id,
+        input.key.as_deref(),
+        input.namespace.as_deref(),
+        input.table.as_deref(),
+    )?;
+    let data = keyed_data(input.data, input.key.as_deref())?;
+    let created = state
         .pool
         .read()
         .await
-        .shard_for(&id)
+        .shard_for(&id)?
         .put_row_ns(
             input.namespace.as_deref(),
             input.table.as_deref(),
             id,
-            &input.data,
+            &data,
         )
         .map_err(ApiError::from)?;
-    Ok(Json(ApiResponse { ok: true, data: id }))
+    Ok(Json(PutResponse {
+        ok: true,
+        data: id,
+        created,
+        key: input.key,
+    }))
 }
 
 async fn query_docs(
     State(state): State<AppState>,
+    Query(t): Query<DocQueryFlags>,
     Json(input): Json<QueryInput>,
-) -> Result<Json<ApiResponse<Vec<(Uuid, serde_json::Value)>>>, ApiError> {
-    let mut hits = Vec::new();
-    if let Some(sql) = input.sql {
-        let guard = state.pool.read().await;
-        for shard in guard.each() {
-            match shard.query_sql(&sql)? {
-                SqlResult::Select(mut rows) => {
-                    hits.append(&mut rows);
-                }
-                _ => return Err(ApiError::BadRequest("SQL must be SELECT".into())),
-            }
-        }
-        if let Some(limit) = input.limit {
-            hits.truncate(limit);
-        }
+) -> Result<Json<DocQueryResponse>, ApiError> {
+    let started = Instant::now();
+    let compact = input.compact;
+    let shards = state.pool.read().await.pick(input.shards.as_deref())?;
+    let mut debug = None;
+    let mut hits: Vec<(Uuid, serde_json::Value)> = if let Some(sql) = input.sql {
+        fan_out_select(shards, sql, input.limit).await?
     } else {
-        let guard = state.pool.read().await;
-        for shard in guard.each() {
-            hits.extend(shard.query_docs_ns(
+        let limit = input.limit.unwrap_or(100);
+        let offset = input.offset.unwrap_or(0);
+        let budget = ShardBudget::new(limit.saturating_add(offset), offset == 0);
+        let per_shard = fan_out(shards, move |shard| {
+            let Some(want) = budget.claim() else {
+                return Ok((Vec::new(), None));
+            };
+            let (rows, stats) = shard.query_docs_ns_with_stats(
                 input.namespace.as_deref(),
                 input.collection.as_deref(),
                 &input.filter,
-                input.limit.unwrap_or(100),
-                input.offset.unwrap_or(0),
-            ));
+                want,
+                0,
+            );
+            budget.record(rows.len());
+            Ok((rows, Some(stats)))
+        })
+        .await?;
+        let (per_shard, stats): (Vec<_>, Vec<_>) = per_shard.into_iter().unzip();
+        if t.debug {
+            // shards the budget skipped examined nothing; the index counts as
+            // used only if every shard that ran took the index path
+            let ran: Vec<QueryStats> = stats.into_iter().flatten().collect();
+            debug = Some(QueryStats {
+                used_index: !ran.is_empty() && ran.iter().all(|s| s.used_index),
+                scanned: ran.iter().map(|s| s.scanned).sum(),
+                matched: ran.iter().map(|s| s.matched).sum(),
+            });
         }
+        per_shard
+            .into_iter()
+            .flatten()
+            .skip(offset)
+            .take(limit)
+            .collect()
+    };
+    if compact {
+        compact_hits(&mut hits);
     }
-    Ok(Json(ApiResponse {
+    let resp = ApiResponse {
         ok: true,
         data: hits,
+    };
+    Ok(Json(DocQueryResponse {
+        inner: resp.timed(t.timing.then_some(started)),
+        debug,
+    }))
+}
+
+#[derive(Deserialize)]
+struct CountInput {
+    #[serde(default)]
+    filter: HashMap<String, serde_json::Value>,
+    namespace: Option<String>,
+    collection: Option<String>,
+    table: Option<String>,
+    /// count on these shard indices only instead of all
+    shards: Option<Vec<usize>>,
+}
+
+#[derive(Serialize)]
+struct CountOutput {
+    count: usize,
+}
+
+/// Matches `/v1/doc/query` filters, summed over shards without returning docs.
+async fn count_docs(
+    State(state): State<AppState>,
+    Json(input): Json<CountInput>,
+) -> Result<Json<ApiResponse<CountOutput>>, ApiError> {
+    let shards = state.pool.read().await.pick(input.shards.as_deref())?;
+    let per_shard = fan_out(shards, move |shard| {
+        Ok(shard.count_docs_ns(
+            input.namespace.as_deref(),
+            input.collection.as_deref(),
+            &input.filter,
+        ))
+    })
+    .await?;
+    Ok(Json(ApiResponse {
+        ok: true,
+        data: CountOutput {
+            count: per_shard.into_iter().sum(),
+        },
+    }))
+}
+
+async fn range_docs(
+    State(state): State<AppState>,
+    Query(q): Query<RangeQuery>,
+) -> Result<Json<ApiResponse<RangePage>>, ApiError> {
+    let limit = q.limit.unwrap_or(100).clamp(1, 10_000);
+    let after = match (q.after_value, q.after_id) {
+        (Some(v), id) => Some((v, id)),
+        (None, None) => None,
+        (None, Some(_)) => {
+            return Err(ApiError::BadRequest("after_id requires after_value".into()))
+        }
+    };
+    let field = q.field.clone();
+    let shards: Vec<Arc<PieskieoDb>> = state.pool.read().await.each().collect();
+    // every shard returns its own first `limit`; the merged head is the global page
+    let per_shard = fan_out(shards, move |shard| {
+        Ok(shard.query_range(
+            q.namespace.as_deref(),
+            q.collection.as_deref(),
+            &q.field,
+            after,
+            limit,
+        ))
+    })
+    .await?;
+    let mut keyed: Vec<(f64, Uuid, serde_json::Value)> = per_shard
+        .into_iter()
+        .flatten()
+        .filter_map(|(id, doc)| Some((doc.get(&field)?.as_f64()?, id, doc)))
+        .collect();
+    keyed.sort_by(|a, b| a.0.total_cmp(&b.0).then(a.1.cmp(&b.1)));
+    keyed.truncate(limit);
+    let next = match keyed.last() {
+        Some((v, id, _)) if keyed.len() == limit => Some(RangeCursor {
+            after_value: *v,
+            after_id: *id,
+        }),
+        _ => None,
+    };
+    Ok(Json(ApiResponse {
+        ok: true,
+        data: RangePage {
+            items: keyed.into_iter().map(|(_, id, doc)| (id, doc)).collect(),
+            next,
+        },
+    }))
+}
+
+fn compact_hits(hits: &mut [(Uuid, serde_json::Value)]) {
+    for (_, v) in hits.iter_mut() {
+        pieskieo_core::strip_nulls(v);
+    }
+}
+
+/// Run `f` against every shard on the blocking pool; results come back in shard order.
+async fn fan_out<T, F>(shards: Vec<Arc<PieskieoDb>>, f: F) -> Result<Vec<T>, ApiError>
+where
+    T: Send + 'static,
+    F: Fn(Arc<PieskieoDb>) -> Result<T, ApiError> + Send + Sync + 'static,
+{
+    let f = Arc::new(f);
+    let handles = shards
+        .into_iter()
+        .map(|shard| {
+            let f = f.clone();
+            tokio::task::spawn_blocking(move || f(shard))
+        })
+        .collect::<Vec<_>>();
+    let mut out = Vec::with_capacity(handles.len());
+    for res in join_all(handles).await {
+        out.push(res.map_err(|e| ApiError::Internal(anyhow::anyhow!(e)))??);
+    }
+    Ok(out)
+}
+
+/// Fallback for a point read the routed shard missed: ask every shard at
+/// once. A hit means the record lives on a shard that does not own its id
+/// (e.g. after an interrupted reshard), so it is logged for repair.
+async fn scatter_get<F>(
+    state: &AppState,
+    id: Uuid,
+    kind: &'static str,
+    find: F,
+) -> Result<Option<serde_json::Value>, ApiError>
+where
+    F: Fn(&PieskieoDb) -> Option<serde_json::Value> + Send + Sync + 'static,
+{
+    let shards: Vec<Arc<PieskieoDb>> = state.pool.read().await.each().collect();
+    let total = shards.len();
+    let hits = fan_out(shards, move |shard| {
+        Ok(find(&shard).map(|v| (shard.shard_id(), v)))
+    })
+    .await?;
+    let Some((shard, value)) = hits.into_iter().flatten().next() else {
+        return Ok(None);
+    };
+    tracing::warn!(
+        %id,
+        kind,
+        shard,
+        owner = pieskieo_core::shard_index(&id, total),
+        "{kind} found on non-owning shard {shard}; reshard to repair routing"
+    );
+    Ok(Some(value))
+}
+
+/// Row budget shared by concurrent shard tasks. Order-less queries may be
+/// satisfied by any rows, so once enough are gathered the remaining shards
+/// are skipped; otherwise every shard is asked for the full amount so the
+/// merged result stays deterministic.
+struct ShardBudget {
+    want: usize,
+    gathered: AtomicUsize,
+    shared: bool,
+}
+
+impl ShardBudget {
+    fn new(want: usize, shared: bool) -> Self {
+        Self {
+            want,
+            gathered: AtomicUsize::new(0),
+            shared,
+        }
+    }
+
+    /// How many rows this shard should produce, or None to skip it.
+    fn claim(&self) -> Option<usize> {
+        if !self.shared {
+            return Some(self.want);
+        }
+        let have = self.gathered.load(Ordering::Relaxed);
+        (have < self.want).then(|| self.want - have)
+    }
+
+    fn record(&self, n: usize) {
+        self.gathered.fetch_add(n, Ordering::Relaxed);
+    }
+}
+
+async fn fan_out_select(
+    shards: Vec<Arc<PieskieoDb>>,
+    sql: String,
+    limit: Option<usize>,
+) -> Result<Vec<(Uuid, serde_json::Value)>, ApiError> {
+    let unordered = sql_is_unordered(&sql);
+    let budget = ShardBudget::new(limit.unwrap_or(usize::MAX), limit.is_some() && unordered);
+    // each shard sorts only its own rows, so ordered results are merged by the same keys
+    let merge = (shards.len() > 1 && !unordered).then(|| (shards[0].clone(), sql.clone()));
+    let per_shard = fan_out(shards, move |shard| {
+        if budget.claim().is_none() {
+            return Ok(Vec::new());
+        }
+        match shard.query_sql(&sql)? {
+            SqlResult::Select(rows) => {
+                budget.record(rows.len());
+                Ok(rows)
+            }
+            _ => Err(ApiError::BadRequest("SQL must be SELECT".into())),
+        }
+    })
+    .await?;
+    if let Some((shard, sql)) = merge {
+        let mut rows: Vec<_> = per_shard.into_iter().flatten().collect();
+        shard.sort_merged_rows(&sql, &mut rows)?;
+        rows.truncate(limit.unwrap_or(usize::MAX));
+        return Ok(rows);
+    }
+    // move rows straight into the capped result instead of merging then truncating
+    Ok(per_shard
+        .into_iter()
+        .flatten()
+        .take(limit.unwrap_or(usize::MAX))
+        .collect())
+}
+
+/// The engine's LIMIT when a SELECT has none.
+const SQL_DEFAULT_LIMIT: usize = 100;
+
+/// Put body `limit`/`offset` in place of the statement's own LIMIT/OFFSET.
+/// Shards get `LIMIT offset + limit` without OFFSET and the caller cuts the
+/// page from the merged rows, so a fixed statement pages across shards.
+fn page_select(
+    sql: &str,
+    limit: Option<usize>,
+    offset: Option<usize>,
+) -> Result<(String, usize, usize), ApiError> {
+    use sqlparser::ast::{Expr, Statement, Value};
+    let mut ast = Parser::parse_sql(&GenericDialect {}, sql)
+        .map_err(|e: sqlparser::parser::ParserError| ApiError::BadRequest(e.to_string()))?;
+    let Some(Statement::Query(q)) = ast.first_mut() else {
+        return Err(ApiError::BadRequest("SQL must be SELECT".into()));
+    };
+    let literal = |e: Option<&Expr>| match e {
+        Some(Expr::Value(Value::Number(n, _))) => n.parse().ok(),
+        _ => None,
+    };
+    let limit = limit
+        .or_else(|| literal(q.limit.as_ref()))
+        .unwrap_or(SQL_DEFAULT_LIMIT);
+    let offset = offset
+        .or_else(|| literal(q.offset.as_ref().map(|o| &o.value)))
+        .unwrap_or(0);
+    q.limit = Some(Expr::Value(Value::Number(
+        offset.saturating_add(limit).to_string(),
+        false,
+    )));
+    q.offset = None;
+    Ok((q.to_string(), limit, offset))
+}
+
+/// True for a plain SELECT without ORDER BY or aggregates, where any rows satisfy LIMIT.
+fn sql_is_unordered(sql: &str) -> bool {
+    let Ok(ast) = Parser::parse_sql(&GenericDialect {}, sql) else {
+        return false;
+    };
+    match ast.first() {
+        Some(sqlparser::ast::Statement::Query(q)) => {
+            q.order_by.is_empty()
+                && match q.body.as_ref() {
+                    sqlparser::ast::SetExpr::Select(sel) => !sel.projection.iter().any(|item| {
+                        matches!(
+                            item,
+                            sqlparser::ast::SelectItem::UnnamedExpr(
+                                sqlparser::ast::Expr::Function(_)
+                            ) | sqlparser::ast::SelectItem::ExprWithAlias {
+                                expr: sqlparser::ast::Expr::Function(_),
+                                ..
+                            }
+                        )
+                    }),
+                    _ => false,
+                }
+        }
+        _ => false,
+    }
+}
+
+/// Parse and plan without executing; any rejection comes back as 400 with
+/// the reason `/v1/sql` would have failed with.
+async fn validate_sql(
+    State(state): State<AppState>,
+    Json(input): Json<SqlInput>,
+) -> Result<Json<ApiResponse<SqlAnalysis>>, ApiError> {
+    let shard = state
+        .pool
+        .read()
+        .await
+        .each()
+        .next()
+        .ok_or_else(|| ApiError::Unavailable("no shard is available".into()))?;
+    let analysis = shard
+        .analyze_sql(&input.sql)
+        .map_err(|e| ApiError::BadRequest(e.to_string()))?;
+    Ok(Json(ApiResponse {
+        ok: true,
+        data: analysis,
     }))
 }
 
 async fn query_sql(
     State(state): State<AppState>,
     Extension(role): Extension<Role>,
+    Query(t): Query<TimingQuery>,
     Json(input): Json<SqlInput>,
+) -> Result<Json<TimedResponse<serde_json::Value>>, ApiError> {
+    let started = Instant::now();
+    let Json(resp) = exec_sql(state, role, input).await?;
+    Ok(Json(resp.timed(t.timing.then_some(started))))
+}
+
+async fn exec_sql(
+    state: AppState,
+    role: Role,
+    input: SqlInput,
 ) -> Result<Json<ApiResponse<serde_json::Value>>, ApiError> {
     if !authorize(role, "/v1/sql", "POST") {
         return Err(ApiError::Forbidden);
@@ -1198,15 +3090,20 @@ async fn query_sql(
     let first = &ast[0];
     let is_select = matches!(first, sqlparser::ast::Statement::Query(_));
     if is_select {
-        let mut rows = Vec::new();
-        for shard in state.pool.read().await.each() {
-            match shard.query_sql(&input.sql)? {
-                SqlResult::Select(mut r) => rows.append(&mut r),
-                _ => {}
-            }
-        }
-        if let Some(limit) = input.limit {
-            rows.truncate(limit);
+        let shards = state.pool.read().await.pick(input.shards.as_deref())?;
+        let mut rows = if input.limit.is_some() || input.offset.is_some() {
+            let (sql, limit, offset) = page_select(&input.sql, input.limit, input.offset)?;
+            fan_out_select(shards, sql, Some(offset.saturating_add(limit)))
+                .await?
+                .into_iter()
+                .skip(offset)
+                .take(limit)
+                .collect()
+        } else {
+            fan_out_select(shards, input.sql, None).await?
+        };
+        if input.compact {
+            compact_hits(&mut rows);
         }
         return Ok(Json(ApiResponse {
             ok: true,
@@ -1214,27 +3111,39 @@ async fn query_sql(
         }));
     }
 
+    if input.shards.is_some() {
+        return Err(ApiError::BadRequest("shards only applies to SELECT".into()));
+    }
     // non-select: route to first shard (or broadcast for update/delete)
     match first {
         sqlparser::ast::Statement::Update { .. } | sqlparser::ast::Statement::Delete { .. } => {
             let mut affected = 0usize;
+            let mut ids = Vec::new();
             let guard = state.pool.read().await;
             for shard in guard.each() {
                 match shard.query_sql(&input.sql)? {
-                    SqlResult::Update { affected: a } | SqlResult::Delete { affected: a } => {
+                    SqlResult::Update {
+                        affected: a,
+                        ids: mut i,
+                    }
+                    | SqlResult::Delete {
+                        affected: a,
+                        ids: mut i,
+                    } => {
                         affected += a;
+                        ids.append(&mut i);
                     }
                     _ => {}
                 }
             }
             Ok(Json(ApiResponse {
                 ok: true,
-                data: serde_json::json!({ "kind": "write", "affected": affected }),
+                data: serde_json::json!({ "kind": "write", "affected": affected, "ids": ids }),
             }))
         }
         sqlparser::ast::Statement::Insert { .. } => {
             // choose shard 0 for now
-            let shard = state.pool.read().await.shards[0].clone();
+            let shard = state.pool.read().await.shard(0)?;
             match shard.query_sql(&input.sql)? {
                 SqlResult::Insert { ids } => Ok(Json(ApiResponse {
                     ok: true,
@@ -1249,72 +3158,176 @@ async fn query_sql(
     }
 }
 
+#[derive(Deserialize)]
+struct SchemaQuery {
+    /// report existing records the schema would reject instead of applying it
+    #[serde(default)]
+    dry_run: bool,
+}
+
+/// Most violations listed by a schema dry run; `violation_count` is exact.
+const SCHEMA_DRY_RUN_MAX: usize = 1000;
+
 async fn set_schema(
     State(state): State<AppState>,
     Extension(role): Extension<Role>,
+    Query(q): Query<SchemaQuery>,
     Json(input): Json<SchemaInput>,
-) -> Result<Json<ApiResponse<&'static str>>, ApiError> {
+) -> Result<Json<ApiResponse<serde_json::Value>>, ApiError> {
     if !authorize(role, "/v1/schema", "POST") {
         return Err(ApiError::Forbidden);
     }
     let def = SchemaDef {
         fields: input.fields,
     };
-    match input.family.as_str() {
+    apply_schema(
+        &state,
+        q.dry_run,
+        &input.family,
+        input.namespace.as_deref(),
+        &input.name,
+        def,
+    )
+    .await
+}
+
+/// `POST /v1/schema` for a standard JSON Schema instead of `fields`; see
+/// `SchemaDef::from_json_schema` for what translates.
+async fn set_json_schema(
+    State(state): State<AppState>,
+    Extension(role): Extension<Role>,
+    Query(q): Query<SchemaQuery>,
+    Json(input): Json<JsonSchemaInput>,
+) -> Result<Json<ApiResponse<serde_json::Value>>, ApiError> {
+    if !authorize(role, "/v1/schema/jsonschema", "POST") {
+        return Err(ApiError::Forbidden);
+    }
+    let def = SchemaDef::from_json_schema(&input.schema)?;
+    apply_schema(
+        &state,
+        q.dry_run,
+        &input.family,
+        input.namespace.as_deref(),
+        &input.name,
+        def,
+    )
+    .await
+}
+
+async fn apply_schema(
+    state: &AppState,
+    dry_run: bool,
+    family: &str,
+    namespace: Option<&str>,
+    name: &str,
+    def: SchemaDef,
+) -> Result<Json<ApiResponse<serde_json::Value>>, ApiError> {
+    if dry_run {
+        let rows = match family {
+            "doc" | "docs" | "collection" | "collections" => false,
+            "row" | "rows" | "table" | "tables" => true,
+            _ => return Err(ApiError::BadRequest("family must be doc or row".into())),
+        };
+        let mut violations = Vec::new();
+        for shard in state.pool.read().await.each() {
+            violations.extend(shard.validate_against(rows, namespace, Some(name), &def));
+        }
+        let violation_count = violations.len();
+        violations.truncate(SCHEMA_DRY_RUN_MAX);
+        return Ok(Json(ApiResponse {
+            ok: true,
+            data: serde_json::json!({
+                "violation_count": violation_count,
+                "violations": violations,
+            }),
+        }));
+    }
+    match family {
         "doc" | "docs" | "collection" | "collections" => {
             let guard = state.pool.read().await;
             for shard in guard.each() {
-                shard.set_doc_schema(input.namespace.as_deref(), Some(&input.name), def.clone())?;
+                shard.set_doc_schema(namespace, Some(name), def.clone())?;
             }
         }
         "row" | "rows" | "table" | "tables" => {
             let guard = state.pool.read().await;
             for shard in guard.each() {
-                shard.set_row_schema(input.namespace.as_deref(), Some(&input.name), def.clone())?;
+                shard.set_row_schema(namespace, Some(name), def.clone())?;
             }
         }
         _ => return Err(ApiError::BadRequest("family must be doc or row".into())),
     }
     Ok(Json(ApiResponse {
         ok: true,
-        data: "schema set",
+        data: serde_json::Value::from("schema set"),
+    }))
+}
+
+async fn count_rows(
+    State(state): State<AppState>,
+    Json(input): Json<CountInput>,
+) -> Result<Json<ApiResponse<CountOutput>>, ApiError> {
+    let shards = state.pool.read().await.pick(input.shards.as_deref())?;
+    let per_shard = fan_out(shards, move |shard| {
+        Ok(shard.count_rows_ns(
+            input.namespace.as_deref(),
+            input.table.as_deref(),
+            &input.filter,
+        ))
+    })
+    .await?;
+    Ok(Json(ApiResponse {
+        ok: true,
+        data: CountOutput {
+            count: per_shard.into_iter().sum(),
+        },
     }))
 }
 
 async fn query_rows(
     State(state): State<AppState>,
+    Query(t): Query<TimingQuery>,
     Json(input): Json<QueryInput>,
-) -> Result<Json<ApiResponse<Vec<(Uuid, serde_json::Value)>>>, ApiError> {
-    let mut hits = Vec::new();
-    if let Some(sql) = input.sql {
-        let guard = state.pool.read().await;
-        for shard in guard.each() {
-            match shard.query_sql(&sql)? {
-                SqlResult::Select(mut rows) => {
-                    hits.append(&mut rows);
-                }
-                _ => return Err(ApiError::BadRequest("SQL must be SELECT".into())),
-            }
-        }
-        if let Some(limit) = input.limit {
-            hits.truncate(limit);
-        }
+) -> Result<Json<TimedResponse<Vec<(Uuid, serde_json::Value)>>>, ApiError> {
+    let started = Instant::now();
+    let compact = input.compact;
+    let shards = state.pool.read().await.pick(input.shards.as_deref())?;
+    let mut hits: Vec<(Uuid, serde_json::Value)> = if let Some(sql) = input.sql {
+        fan_out_select(shards, sql, input.limit).await?
     } else {
-        let guard = state.pool.read().await;
-        for shard in guard.each() {
-            hits.extend(shard.query_rows_ns(
+        let limit = input.limit.unwrap_or(100);
+        let offset = input.offset.unwrap_or(0);
+        let budget = ShardBudget::new(limit.saturating_add(offset), offset == 0);
+        let per_shard = fan_out(shards, move |shard| {
+            let Some(want) = budget.claim() else {
+                return Ok(Vec::new());
+            };
+            let rows = shard.query_rows_ns(
                 input.namespace.as_deref(),
                 input.table.as_deref(),
                 &input.filter,
-                input.limit.unwrap_or(100),
-                input.offset.unwrap_or(0),
-            ));
-        }
+                want,
+                0,
+            );
+            budget.record(rows.len());
+            Ok(rows)
+        })
+        .await?;
+        per_shard
+            .into_iter()
+            .flatten()
+            .skip(offset)
+            .take(limit)
+            .collect()
+    };
+    if compact {
+        compact_hits(&mut hits);
     }
-    Ok(Json(ApiResponse {
+    let resp = ApiResponse {
         ok: true,
         data: hits,
-    }))
+    };
+    Ok(Json(resp.timed(t.timing.then_some(started))))
 }
 
 async fn get_row(
@@ -1322,19 +3335,75 @@ async fn get_row(
     Path(id): Path<Uuid>,
     Query(ns): Query<NsParams>,
 ) -> Result<Json<ApiResponse<serde_json::Value>>, ApiError> {
-    let row = state
-        .pool
-        .read()
-        .await
-        .shard_for(&id)
-        .get_row_ns(ns.namespace.as_deref(), ns.table.as_deref(), &id)
-        .ok_or(ApiError::NotFound)?;
+    let routed = state.pool.read().await.shard_for(&id)?.get_row_ns(
+        ns.namespace.as_deref(),
+        ns.table.as_deref(),
+        &id,
+    );
+    let row = match routed {
+        Some(row) => row,
+        None if ns.scatter.unwrap_or(state.scatter_get) => {
+            let NsParams {
+                namespace, table, ..
+            } = ns;
+            scatter_get(&state, id, "row", move |shard| {
+                shard.find_row_ns(namespace.as_deref(), table.as_deref(), &id)
+            })
+            .await?
+            .ok_or(ApiError::NotFound)?
+        }
+        None => return Err(ApiError::NotFound),
+    };
     Ok(Json(ApiResponse {
         ok: true,
         data: row,
     }))
 }
 
+/// Row counterpart of `get_doc_by_key`.
+async fn get_row_by_key(
+    State(state): State<AppState>,
+    Path(key): Path<String>,
+    Query(ns): Query<NsParams>,
+) -> Result<Json<ApiResponse<serde_json::Value>>, ApiError> {
+    let id = pieskieo_core::key_id(
+        &state.key_space,
+        ns.namespace.as_deref(),
+        ns.table.as_deref(),
+        &key,
+    );
+    get_row(State(state), Path(id), Query(ns)).await
+}
+
+/// Row counterpart of `head_doc`.
+async fn head_row(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+    Query(ns): Query<NsParams>,
+) -> Result<axum::http::StatusCode, ApiError> {
+    let routed = state.pool.read().await.shard_for(&id)?.has_row_ns(
+        ns.namespace.as_deref(),
+        ns.table.as_deref(),
+        &id,
+    );
+    if routed {
+        return Ok(axum::http::StatusCode::OK);
+    }
+    if ns.scatter.unwrap_or(state.scatter_get) {
+        let NsParams {
+            namespace, table, ..
+        } = ns;
+        let found = scatter_get(&state, id, "row", move |shard| {
+            shard.find_row_ns(namespace.as_deref(), table.as_deref(), &id)
+        })
+        .await?;
+        if found.is_some() {
+            return Ok(axum::http::StatusCode::OK);
+        }
+    }
+    Err(ApiError::NotFound)
+}
+
 async fn delete_row(
     State(state): State<AppState>,
     Path(id): Path<Uuid>,
@@ -1344,7 +3413,7 @@ async fn delete_row(
         .pool
         .read()
         .await
-        .shard_for(&id)
+        .shard_for(&id)?
         .delete_row_ns(ns.namespace.as_deref(), ns.table.as_deref(), &id)
         .map_err(ApiError::from)?;
     Ok(Json(ApiResponse {
@@ -1356,26 +3425,19 @@ async fn delete_row(
 async fn put_vector(
     State(state): State<AppState>,
     Json(input): Json<VectorInput>,
-) -> Result<Json<ApiResponse<&'static str>>, ApiError> {
+) -> Result<Json<ApiResponse<Uuid>>, ApiError> {
     if state.pause_writes.load(std::sync::atomic::Ordering::SeqCst) {
         return Err(ApiError::Conflict("resharding in progress".into()));
     }
+    let id = input.id.unwrap_or_else(Uuid::new_v4);
     state
         .pool
         .read()
         .await
-        .shard_for(&input.id)
-        .put_vector_with_meta_ns(
-            input.namespace.as_deref(),
-            input.id,
-            input.vector,
-            input.meta,
-        )
+        .shard_for(&id)?
+        .put_vector_with_meta_ns(input.namespace.as_deref(), id, input.vector, input.meta)
         .map_err(ApiError::from)?;
-    Ok(Json(ApiResponse {
-        ok: true,
-        data: "stored",
-    }))
+    Ok(Json(ApiResponse { ok: true, data: id }))
 }
 
 async fn put_vector_bulk(
@@ -1385,13 +3447,24 @@ async fn put_vector_bulk(
     if state.pause_writes.load(std::sync::atomic::Ordering::SeqCst) {
         return Err(ApiError::Conflict("resharding in progress".into()));
     }
+    // the response is only a count, so generated ids could never be read back
+    let ids = input
+        .items
+        .iter()
+        .enumerate()
+        .map(|(n, item)| {
+            item.id.ok_or_else(|| {
+                ApiError::BadRequest(format!("items[{n}]: id is required in bulk puts"))
+            })
+        })
+        .collect::<Result<Vec<Uuid>, ApiError>>()?;
     let mut stored = 0usize;
     let pool = state.pool.read().await;
-    for item in input.items {
-        pool.shard_for(&item.id)
+    for (id, item) in ids.into_iter().zip(input.items) {
+        pool.shard_for(&id)?
             .put_vector_with_meta_ns(
                 item.namespace.as_deref(),
-                item.id,
+                id,
                 item.vector.clone(),
                 item.meta.clone(),
             )
@@ -1408,26 +3481,131 @@ async fn delete_vector(
     State(state): State<AppState>,
     Path(id): Path<Uuid>,
 ) -> Result<Json<ApiResponse<&'static str>>, ApiError> {
-    state
-        .pool
-        .read()
-        .await
-        .shard_for(&id)
+    let pool = state.pool.read().await;
+    pool.shard_for(&id)?
         .delete_vector(&id)
         .map_err(ApiError::from)?;
+    remove_node_everywhere(&pool, id)?;
     Ok(Json(ApiResponse {
         ok: true,
         data: "deleted",
     }))
 }
 
+#[derive(Deserialize)]
+struct BulkDeleteInput {
+    ids: Vec<Uuid>,
+    namespace: Option<String>,
+    collection: Option<String>,
+    table: Option<String>,
+}
+
+#[derive(Serialize)]
+struct BulkDeleteOutput {
+    deleted: usize,
+    /// requested ids that did not exist, in request order
+    missing: Vec<Uuid>,
+}
+
+async fn delete_docs(
+    State(state): State<AppState>,
+    Json(input): Json<BulkDeleteInput>,
+) -> Result<Json<ApiResponse<BulkDeleteOutput>>, ApiError> {
+    let collection = input.collection.clone();
+    bulk_delete(&state, BatchFamily::Doc, input, collection).await
+}
+
+async fn delete_rows(
+    State(state): State<AppState>,
+    Json(input): Json<BulkDeleteInput>,
+) -> Result<Json<ApiResponse<BulkDeleteOutput>>, ApiError> {
+    let table = input.table.clone();
+    bulk_delete(&state, BatchFamily::Row, input, table).await
+}
+
+/// Vectors are found in whichever namespace holds them, so `namespace` is ignored.
+async fn delete_vectors(
+    State(state): State<AppState>,
+    Json(input): Json<BulkDeleteInput>,
+) -> Result<Json<ApiResponse<BulkDeleteOutput>>, ApiError> {
+    bulk_delete(&state, BatchFamily::Vector, input, None).await
+}
+
+/// Groups `ids` by shard and deletes each group with one WAL write; a shard
+/// that fails leaves the groups already deleted on other shards in place.
+/// The deletes and edge cleanup run on a blocking thread.
+async fn bulk_delete(
+    state: &AppState,
+    family: BatchFamily,
+    input: BulkDeleteInput,
+    collection: Option<String>,
+) -> Result<Json<ApiResponse<BulkDeleteOutput>>, ApiError> {
+    if state.pause_writes.load(std::sync::atomic::Ordering::SeqCst) {
+        return Err(ApiError::Conflict("resharding in progress".into()));
+    }
+    if input.ids.len() > state.bulk_delete_max {
+        return Err(ApiError::BadRequest(format!(
+            "at most {} ids per bulk delete",
+            state.bulk_delete_max
+        )));
+    }
+    let pool = state.pool.read().await;
+    let mut by_shard: BTreeMap<usize, Vec<Uuid>> = BTreeMap::new();
+    for id in &input.ids {
+        by_shard
+            .entry(pieskieo_core::shard_index(id, pool.shards.len()))
+            .or_default()
+            .push(*id);
+    }
+    let groups = by_shard
+        .into_iter()
+        .map(|(shard, ids)| Ok((pool.shard(shard)?, ids)))
+        .collect::<Result<Vec<_>, ApiError>>()?;
+    let shards: Vec<Arc<PieskieoDb>> = pool.each().collect();
+    let namespace = input.namespace.clone();
+    let deleted = tokio::task::spawn_blocking(move || {
+        let mut deleted = std::collections::HashSet::new();
+        for (shard, ids) in groups {
+            deleted.extend(shard.delete_many(
+                family,
+                namespace.as_deref(),
+                collection.as_deref(),
+                &ids,
+            )?);
+        }
+        if family != BatchFamily::Row {
+            // incoming edges live on the shards owning their sources
+            let ids: Vec<Uuid> = deleted.iter().copied().collect();
+            for shard in &shards {
+                shard.remove_nodes(&ids)?;
+            }
+        }
+        Ok::<_, ApiError>(deleted)
+    })
+    .await
+    .map_err(|e| ApiError::Internal(anyhow::anyhow!(e)))??;
+    let mut reported = std::collections::HashSet::new();
+    let missing = input
+        .ids
+        .into_iter()
+        .filter(|id| !deleted.contains(id) && reported.insert(*id))
+        .collect();
+    Ok(Json(ApiResponse {
+        ok: true,
+        data: BulkDeleteOutput {
+            deleted: deleted.len(),
+            missing,
+        },
+    }))
+}
+
 async fn get_vector(
     State(state): State<AppState>,
     Path(id): Path<Uuid>,
 ) -> Result<Json<ApiResponse<VectorOutput>>, ApiError> {
     let pool = state.pool.read().await;
     let (vector, meta) = pool
-        .shard_for(&id)
+        .shard_for(&id)?
         .get_vector(&id)
         .ok_or(ApiError::NotFound)?;
     Ok(Json(ApiResponse {
@@ -1436,6 +3614,39 @@ async fn get_vector(
     }))
 }
 
+/// Existence check that skips copying the embedding.
+async fn head_vector(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+) -> Result<axum::http::StatusCode, ApiError> {
+    if state.pool.read().await.shard_for(&id)?.has_vector(&id) {
+        Ok(axum::http::StatusCode::OK)
+    } else {
+        Err(ApiError::NotFound)
+    }
+}
+
+#[derive(Deserialize)]
+struct VectorMgetInput {
+    ids: Vec<Uuid>,
+}
+
+/// Batch form of `get_vector`: hits come back in request order and missing
+/// ids are left out.
+async fn mget_vectors(
+    State(state): State<AppState>,
+    Json(input): Json<VectorMgetInput>,
+) -> Result<Json<ApiResponse<Vec<VectorOutput>>>, ApiError> {
+    let pool = state.pool.read().await;
+    let mut data = Vec::with_capacity(input.ids.len());
+    for id in input.ids {
+        if let Some((vector, meta)) = pool.shard_for(&id)?.get_vector(&id) {
+            data.push(VectorOutput { id, vector, meta });
+        }
+    }
+    Ok(Json(ApiResponse { ok: true, data }))
+}
+
 async fn update_vector_meta(
     State(state): State<AppState>,
     Path(id): Path<Uuid>,
@@ -1445,7 +3656,7 @@ async fn update_vector_meta(
         .pool
         .read()
         .await
-        .shard_for(&id)
+        .shard_for(&id)?
         .update_vector_meta(id, input.meta)
         .map_err(ApiError::from)?;
     Ok(Json(ApiResponse {
@@ -1463,7 +3674,7 @@ async fn delete_vector_meta_keys(
         .pool
         .read()
         .await
-        .shard_for(&id)
+        .shard_for(&id)?
         .remove_vector_meta_keys(id, &input.keys)
         .map_err(ApiError::from)?;
     Ok(Json(ApiResponse {
@@ -1475,62 +3686,366 @@ async fn delete_vector_meta_keys(
 async fn search_vector(
     State(state): State<AppState>,
     Json(input): Json<VectorSearchInput>,
-) -> Result<Json<ApiResponse<Vec<pieskieo_core::VectorSearchResult>>>, ApiError> {
-    let k = input.k.unwrap_or(10);
-    let metric = match input.metric.as_deref() {
-        Some("cosine") => pieskieo_core::vector::VectorMetric::Cosine,
-        Some("dot") => pieskieo_core::vector::VectorMetric::Dot,
-        Some("l2") => pieskieo_core::vector::VectorMetric::L2,
-        _ => pieskieo_core::vector::VectorMetric::L2,
-    };
-
-    if let Some(ef) = input.ef_search {
-        let guard = state.pool.read().await;
-        for shard in guard.each() {
-            shard.set_ef_search(ef);
+) -> Result<
+    (
+        axum::http::HeaderMap,
+        Json<ApiResponse<Vec<pieskieo_core::VectorSearchResult>>>,
+    ),
+    ApiError,
+> {
+    let pool = state.pool.read().await;
+    let plan = VectorSearchPlan::new(input.opts, &pool, &state)?;
+    let (hits, next) = plan.run(&pool, &state.search_limiter, input.query).await?;
+    let mut headers = plan.headers();
+    if let Some(next) = next {
+        if let Ok(token) = search_after_token(&next).parse() {
+            headers.insert("x-pieskieo-next", token);
         }
     }
+    Ok((
+        headers,
+        Json(ApiResponse {
+            ok: true,
+            data: hits,
+        }),
+    ))
+}
 
-    // For now metric selection is per-query; in future persist per-index config.
+async fn search_vector_batch(
+    State(state): State<AppState>,
+    Json(input): Json<VectorSearchBatchInput>,
+) -> Result<
+    (
+        axum::http::HeaderMap,
+        Json<ApiResponse<Vec<Vec<pieskieo_core::VectorSearchResult>>>>,
+    ),
+    ApiError,
+> {
+    if input.opts.after.is_some() {
+        return Err(ApiError::BadRequest(
+            "after pages a single search, not a batch".into(),
+        ));
+    }
+    if input.queries.len() > state.batch_queries_max {
+        return Err(ApiError::BadRequest(format!(
+            "at most {} queries per batch",
+            state.batch_queries_max
+        )));
+    }
     let pool = state.pool.read().await;
-    let futures = pool
-        .each()
-        .map(|shard| {
-            let q = input.query.clone();
-            let filter = input.filter_meta.clone();
-            let ns = input.namespace.clone();
-            tokio::task::spawn_blocking(move || match ns {
-                Some(ref ns) => {
-                    shard.search_vector_metric_ns(Some(ns.as_str()), &q, k, metric, filter)
-                }
-                None => shard.search_vector_metric(&q, k, metric, filter),
-            })
+    let plan = VectorSearchPlan::new(input.opts, &pool, &state)?;
+    // one slot per shard covers the whole batch, so a large batch cannot
+    // fill a shard's queue by itself
+    let slots = VectorSearchPlan::acquire(&pool, &state.search_limiter).await?;
+    // every query fans out to every shard at once; the blocking pool spreads them over cores
+    let runs = input
+        .queries
+        .into_iter()
+        .map(|q| plan.run_under(&pool, &slots, q));
+    let results = join_all(runs)
+        .await
+        .into_iter()
+        .map(|res| res.map(|(hits, _)| hits))
+        .collect::<Result<Vec<_>, ApiError>>()?;
+    Ok((
+        plan.headers(),
+        Json(ApiResponse {
+            ok: true,
+            data: results,
+        }),
+    ))
+}
+
+/// Opaque continuation token: base64 of the cursor's JSON.
+fn search_after_token(after: &pieskieo_core::SearchAfter) -> String {
+    B64.encode(serde_json::to_vec(after).unwrap_or_default())
+}
+
+fn parse_search_after(token: &str) -> Result<pieskieo_core::SearchAfter, ApiError> {
+    B64.decode(token)
+        .ok()
+        .and_then(|bytes| serde_json::from_slice(&bytes).ok())
+        .ok_or_else(|| ApiError::BadRequest("invalid after token".into()))
+}
+
+/// Search settings resolved once per request and shared by each query vector.
+struct VectorSearchPlan {
+    k: usize,
+    // the requested k exceeded PIESKIEO_VECTOR_MAX_K and was cut to it
+    k_clamped: bool,
+    metric: pieskieo_core::vector::VectorMetric,
+    // rescored over the final hits only, so ranking still follows `metric`
+    extra_metrics: Vec<pieskieo_core::vector::VectorMetric>,
+    filter_meta: Option<HashMap<String, serde_json::Value>>,
+    namespace: Option<String>,
+    allow: Option<Arc<std::collections::HashSet<Uuid>>>,
+    prefilter: Option<Arc<std::collections::HashSet<Uuid>>>,
+    id_range: Option<pieskieo_core::VectorIdRange>,
+    prefilter_max: usize,
+    include_vectors: bool,
+    include_meta: bool,
+    normalized: bool,
+    after: Option<pieskieo_core::SearchAfter>,
+}
+
+impl VectorSearchPlan {
+    fn new(opts: VectorSearchOpts, pool: &DbPool, state: &AppState) -> Result<Self, ApiError> {
+        let metric = opts
+            .metric
+            .as_deref()
+            .and_then(pieskieo_core::vector::VectorMetric::parse)
+            .unwrap_or(pieskieo_core::vector::VectorMetric::L2);
+        let mut extra_metrics = Vec::new();
+        for name in opts.metrics.unwrap_or_default() {
+            let m = pieskieo_core::vector::VectorMetric::parse(&name).ok_or_else(|| {
+                ApiError::BadRequest(format!("unknown metric {name}: use l2, cosine or dot"))
+            })?;
+            if !extra_metrics.contains(&m) {
+                extra_metrics.push(m);
+            }
+        }
+
+        if let Some(ef) = opts.ef_search {
+            for shard in pool.each() {
+                shard.set_ef_search(ef);
+            }
+        }
+
+        // Small candidate sets are scored exactly so ids outside the global ANN top-k still match;
+        // large ones fall back to ANN with a post-filter.
+        let prefilter_max = state.prefilter_max;
+        if let Some(range) = &opts.id_range {
+            if range.from > range.to {
+                return Err(ApiError::BadRequest(
+                    "id_range.from is after id_range.to".into(),
+                ));
+            }
+        }
+        let allow: Option<Arc<std::collections::HashSet<Uuid>>> = opts.filter_ids.map(|ids| {
+            Arc::new(
+                ids.into_iter()
+                    .filter(|id| opts.id_range.is_none_or(|r| r.contains(id)))
+                    .collect(),
+            )
+        });
+        let prefilter = allow
+            .as_ref()
+            .filter(|ids| ids.len() <= prefilter_max)
+            .cloned();
+
+        let max_k = state.max_k;
+        let k = opts.k.unwrap_or(state.default_k);
+        let after = opts.after.as_deref().map(parse_search_after).transpose()?;
+
+        Ok(Self {
+            k: k.min(max_k),
+            k_clamped: k > max_k,
+            metric,
+            extra_metrics,
+            filter_meta: opts.filter_meta,
+            namespace: opts.namespace,
+            allow,
+            prefilter,
+            id_range: opts.id_range,
+            prefilter_max,
+            include_vectors: opts.include_vectors,
+            include_meta: opts.include_meta,
+            normalized: opts.normalized,
+            after,
         })
-        .collect::<Vec<_>>();
-    let mut all_hits = Vec::new();
-    for res in join_all(futures).await {
-        if let Ok(Ok(mut h)) = res {
-            all_hits.append(&mut h);
+    }
+
+    /// `X-Pieskieo-K-Clamped: <k used>` tells clients their k was cut down.
+    fn headers(&self) -> axum::http::HeaderMap {
+        let mut headers = axum::http::HeaderMap::new();
+        if self.k_clamped {
+            headers.insert("x-pieskieo-k-clamped", self.k.into());
         }
+        headers
     }
-    all_hits.sort_by(|a, b| b.score.partial_cmp(&a.score).unwrap());
-    all_hits.truncate(k);
-    let mut hits = all_hits;
-    if let Some(filter_ids) = input.filter_ids {
-        let allow: std::collections::HashSet<Uuid> = filter_ids.into_iter().collect();
-        hits.retain(|h| allow.contains(&h.id));
+
+    /// A search slot on every live shard. Slots are taken in shard order, so
+    /// searches waiting on each other across shards can't deadlock.
+    async fn acquire(pool: &DbPool, limiter: &SearchLimiter) -> Result<ShardSlots, ApiError> {
+        let mut slots = Vec::new();
+        for (idx, shard) in pool.live() {
+            slots.push((shard.clone(), Arc::new(limiter.acquire(idx).await?)));
+        }
+        Ok(slots)
+    }
+
+    /// Fan one query out to every shard and merge the per-shard top-k, plus
+    /// the cursor for the next page when this one is full.
+    async fn run(
+        &self,
+        pool: &DbPool,
+        limiter: &SearchLimiter,
+        query: Vec<f32>,
+    ) -> Result<
+        (
+            Vec<pieskieo_core::VectorSearchResult>,
+            Option<pieskieo_core::SearchAfter>,
+        ),
+        ApiError,
+    > {
+        let slots = Self::acquire(pool, limiter).await?;
+        self.run_under(pool, &slots, query).await
+    }
+
+    /// `run` with slots already held, so a batch takes one slot per shard for
+    /// all of its queries.
+    async fn run_under(
+        &self,
+        pool: &DbPool,
+        slots: &ShardSlots,
+        query: Vec<f32>,
+    ) -> Result<
+        (
+            Vec<pieskieo_core::VectorSearchResult>,
+            Option<pieskieo_core::SearchAfter>,
+        ),
+        ApiError,
+    > {
+        let (k, metric, after) = (self.k, self.metric, self.after);
+        let query = Arc::new(query);
+        // For now metric selection is per-query; in future persist per-index config.
+        let futures = slots
+            .iter()
+            .map(|(shard, slot)| {
+                let (shard, slot) = (shard.clone(), slot.clone());
+                let q = query.clone();
+                let filter = self.filter_meta.clone();
+                let ns = self.namespace.clone();
+                let prefilter = self.prefilter.clone();
+                let (range, prefilter_max) = (self.id_range, self.prefilter_max);
+                tokio::task::spawn_blocking(move || {
+                    let _slot = slot;
+                    // a narrow id range is scored exactly, like a short filter_ids
+                    let prefilter = prefilter.or_else(|| {
+                        range.and_then(|r| {
+                            shard
+                                .vector_ids_in_range(ns.as_deref(), &r, prefilter_max)
+                                .map(Arc::new)
+                        })
+                    });
+                    match (prefilter, ns) {
+                        (Some(ids), ns) => {
+                            // every candidate is scored anyway; return them all
+                            // so the cursor can be applied after the merge
+                            let want = if after.is_some() { k.max(ids.len()) } else { k };
+                            shard.search_vector_ids_ns(
+                                ns.as_deref(),
+                                &q,
+                                want,
+                                metric,
+                                &ids,
+                                filter,
+                            )
+                        }
+                        (None, Some(ref ns)) => shard.search_vector_metric_ns(
+                            Some(ns.as_str()),
+                            &q,
+                            k,
+                            metric,
+                            filter,
+                            after.as_ref(),
+                        ),
+                        (None, None) => {
+                            shard.search_vector_metric(&q, k, metric, filter, after.as_ref())
+                        }
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        let mut all_hits = Vec::new();
+        for res in join_all(futures).await {
+            if let Ok(Ok(mut h)) = res {
+                all_hits.append(&mut h);
+            }
+        }
+        if let Some(allow) = &self.allow {
+            all_hits.retain(|h| allow.contains(&h.id));
+        }
+        if let Some(range) = &self.id_range {
+            all_hits.retain(|h| range.contains(&h.id));
+        }
+        if let Some(after) = &after {
+            all_hits.retain(|h| after.admits(h));
+        }
+        all_hits.sort_by(pieskieo_core::vector::rank);
+        all_hits.truncate(k);
+        // taken before any rescoring, so it stays in the ranking's score units
+        let next = all_hits
+            .last()
+            .filter(|_| all_hits.len() == k)
+            .map(pieskieo_core::SearchAfter::of);
+        if self.include_vectors
+            || self.include_meta
+            || self.normalized
+            || !self.extra_metrics.is_empty()
+        {
+            // ANN scores are distances on the graph, so normalizing starts
+            // from the metric's exact score
+            let score = |m: pieskieo_core::vector::VectorMetric, v: &[f32]| {
+                let s = m.score(&query, v);
+                if self.normalized {
+                    m.normalize(s)
+                } else {
+                    s
+                }
+            };
+            for hit in all_hits.iter_mut() {
+                let found = pool
+                    .shard_for(&hit.id)
+                    .ok()
+                    .and_then(|shard| shard.get_vector(&hit.id));
+                if let Some((vector, meta)) = found {
+                    if self.normalized {
+                        hit.score = score(metric, &vector);
+                    }
+                    if !self.extra_metrics.is_empty() {
+                        hit.scores = Some(
+                            self.extra_metrics
+                                .iter()
+                                .map(|m| (m.name().to_string(), score(*m, &vector)))
+                                .collect(),
+                        );
+                    }
+                    if self.include_vectors {
+                        hit.vector = Some(vector);
+                    }
+                    if self.include_meta {
+                        hit.meta = Some(meta.unwrap_or_default());
+                    }
+                }
+            }
+        }
+        Ok((all_hits, next))
     }
-    Ok(Json(ApiResponse {
-        ok: true,
-        data: hits,
-    }))
 }
 
 async fn update_vector_config(
     State(state): State<AppState>,
+    Query(q): Query<NamespaceQuery>,
     Json(input): Json<VectorConfigInput>,
 ) -> Result<Json<ApiResponse<&'static str>>, ApiError> {
     let pool = state.pool.read().await;
+    if let Some(ns) = q.namespace.as_deref() {
+        if input.ef_search.is_some() || input.ef_construction.is_some() {
+            return Err(ApiError::BadRequest(
+                "namespace applies to link_top_k only".into(),
+            ));
+        }
+        if let Some(k) = input.link_top_k {
+            for shard in pool.each() {
+                shard.set_link_top_k_ns(Some(ns), k);
+            }
+        }
+        return Ok(Json(ApiResponse {
+            ok: true,
+            data: "updated",
+        }));
+    }
     if let Some(ef) = input.ef_search {
         for shard in pool.each() {
             shard.set_ef_search(ef);
@@ -1543,11 +4058,7 @@ async fn update_vector_config(
     }
     if let Some(k) = input.link_top_k {
         for shard in pool.each() {
-            if let Some(db_mut) = Arc::get_mut(&mut shard.clone()) {
-                db_mut.set_link_top_k(k);
-            } else {
-                tracing::warn!("link_top_k update skipped (db state is shared)");
-            }
+            shard.set_link_top_k(k);
         }
     }
     Ok(Json(ApiResponse {
@@ -1558,9 +4069,26 @@ async fn update_vector_config(
 
 async fn rebuild_vectors(
     State(state): State<AppState>,
+    Query(q): Query<NamespaceQuery>,
 ) -> Result<Json<ApiResponse<&'static str>>, ApiError> {
+    let mut rebuilt = false;
     for shard in state.pool.read().await.each() {
-        shard.rebuild_vectors().map_err(ApiError::from)?;
+        match q.namespace.as_deref() {
+            Some(ns) => match shard.rebuild_vectors_ns(Some(ns)) {
+                Err(PieskieoError::NotFound) => {}
+                res => {
+                    res.map_err(ApiError::from)?;
+                    rebuilt = true;
+                }
+            },
+            None => {
+                shard.rebuild_vectors().map_err(ApiError::from)?;
+                rebuilt = true;
+            }
+        }
+    }
+    if !rebuilt {
+        return Err(ApiError::NotFound);
     }
     Ok(Json(ApiResponse {
         ok: true,
@@ -1570,9 +4098,27 @@ async fn rebuild_vectors(
 
 async fn vacuum_vectors(
     State(state): State<AppState>,
+    Query(q): Query<NamespaceQuery>,
 ) -> Result<Json<ApiResponse<&'static str>>, ApiError> {
+    let mut vacuumed = false;
     for shard in state.pool.read().await.each() {
-        shard.vacuum().map_err(ApiError::from)?;
+        match q.namespace.as_deref() {
+            Some(ns) => match shard.vacuum_ns(Some(ns)) {
+                // a namespace may only have vectors on some shards
+                Err(PieskieoError::NotFound) => {}
+                res => {
+                    res.map_err(ApiError::from)?;
+                    vacuumed = true;
+                }
+            },
+            None => {
+                shard.vacuum().map_err(ApiError::from)?;
+                vacuumed = true;
+            }
+        }
+    }
+    if !vacuumed {
+        return Err(ApiError::NotFound);
     }
     Ok(Json(ApiResponse {
         ok: true,
@@ -1580,6 +4126,47 @@ async fn vacuum_vectors(
     }))
 }
 
+#[derive(Serialize)]
+struct VectorNsStatsOut {
+    namespace: String,
+    vectors: usize,
+    tombstones: usize,
+    tombstone_ratio: f64,
+    last_rebuild_us: u64,
+    memory_bytes: u64,
+    owned_bytes: u64,
+    leaked_bytes: u64,
+}
+
+impl From<pieskieo_core::engine::VectorNsStats> for VectorNsStatsOut {
+    fn from(s: pieskieo_core::engine::VectorNsStats) -> Self {
+        Self {
+            tombstone_ratio: s.tombstone_ratio(),
+            namespace: s.namespace,
+            vectors: s.vectors,
+            tombstones: s.tombstones,
+            last_rebuild_us: s.last_rebuild_us,
+            memory_bytes: s.memory_bytes,
+            owned_bytes: s.owned_bytes,
+            leaked_bytes: s.leaked_bytes,
+        }
+    }
+}
+
+async fn vector_stats(
+    State(state): State<AppState>,
+) -> Result<Json<ApiResponse<Vec<VectorNsStatsOut>>>, ApiError> {
+    let data = state
+        .pool
+        .read()
+        .await
+        .vector_ns_stats()
+        .into_iter()
+        .map(VectorNsStatsOut::from)
+        .collect();
+    Ok(Json(ApiResponse { ok: true, data }))
+}
+
 async fn save_snapshot(
     State(state): State<AppState>,
 ) -> Result<Json<ApiResponse<&'static str>>, ApiError> {
@@ -1592,6 +4179,79 @@ async fn save_snapshot(
     }))
 }
 
+/// The namespace named by `?namespace=`, required by export and import so a
+/// forgotten parameter can't replace the default namespace.
+fn required_namespace(q: NamespaceQuery) -> Result<String, ApiError> {
+    q.namespace
+        .filter(|ns| !ns.is_empty())
+        .ok_or_else(|| ApiError::BadRequest("namespace is required".into()))
+}
+
+/// One snapshot of `namespace` merged from every shard, in the format of
+/// the per-namespace files under `<data>/vectors`.
+async fn export_vectors(
+    State(state): State<AppState>,
+    Query(q): Query<NamespaceQuery>,
+) -> Result<axum::response::Response, ApiError> {
+    let ns = required_namespace(q)?;
+    let pool = state.pool.read().await;
+    let mut entries = Vec::new();
+    // every shard must answer, or the snapshot would silently miss vectors
+    for i in 0..pool.shards.len() {
+        let blob = pool
+            .shard(i)?
+            .export_vectors_ns(&ns)
+            .map_err(ApiError::from)?;
+        entries.extend(pieskieo_core::decode_snapshot(&blob).map_err(ApiError::from)?);
+    }
+    let blob = pieskieo_core::encode_snapshot(entries).map_err(ApiError::from)?;
+    let disposition = format!("attachment; filename=\"{ns}.snapshot\"");
+    Ok((
+        [
+            (
+                axum::http::header::CONTENT_TYPE,
+                "application/octet-stream".to_string(),
+            ),
+            (axum::http::header::CONTENT_DISPOSITION, disposition),
+        ],
+        blob,
+    )
+        .into_response())
+}
+
+/// Replaces `namespace` on every shard with the vectors of an exported
+/// snapshot, each routed to its owning shard. Shards are loaded one after
+/// another, so a failure leaves the shards before it already replaced.
+async fn import_vectors(
+    State(state): State<AppState>,
+    Query(q): Query<NamespaceQuery>,
+    body: axum::body::Bytes,
+) -> Result<Json<ApiResponse<usize>>, ApiError> {
+    if state.pause_writes.load(std::sync::atomic::Ordering::SeqCst) {
+        return Err(ApiError::Conflict("resharding in progress".into()));
+    }
+    let ns = required_namespace(q)?;
+    let entries = pieskieo_core::decode_snapshot(&body)
+        .map_err(|_| ApiError::BadRequest("body is not a vector snapshot".into()))?;
+    let pool = state.pool.read().await;
+    let mut by_shard: Vec<Vec<pieskieo_core::SnapshotEntry>> = vec![Vec::new(); pool.shards.len()];
+    for entry in entries {
+        by_shard[pieskieo_core::shard_index(&entry.0, pool.shards.len())].push(entry);
+    }
+    let mut loaded = 0;
+    for (i, entries) in by_shard.into_iter().enumerate() {
+        let blob = pieskieo_core::encode_snapshot(entries).map_err(ApiError::from)?;
+        loaded += pool
+            .shard(i)?
+            .import_vectors_ns(&ns, &blob)
+            .map_err(ApiError::from)?;
+    }
+    Ok(Json(ApiResponse {
+        ok: true,
+        data: loaded,
+    }))
+}
+
 async fn add_edge(
     State(state): State<AppState>,
     Json(input): Json<EdgeInput>,
@@ -1601,7 +4261,7 @@ async fn add_edge(
         .pool
         .read()
         .await
-        .shard_for(&input.src)
+        .shard_for(&input.src)?
         .add_edge(input.src, input.dst, weight)
         .map_err(ApiError::from)?;
     Ok(Json(ApiResponse {
@@ -1614,60 +4274,516 @@ async fn list_neighbors(
     State(state): State<AppState>,
     Path(id): Path<Uuid>,
 ) -> Result<Json<ApiResponse<Vec<pieskieo_core::Edge>>>, ApiError> {
-    let edges = state.pool.read().await.shard_for(&id).neighbors(id, 100);
+    let edges = state.pool.read().await.shard_for(&id)?.neighbors(id, 100);
     Ok(Json(ApiResponse {
         ok: true,
         data: edges,
     }))
 }
 
+async fn delete_node(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+) -> Result<Json<ApiResponse<usize>>, ApiError> {
+    if state.pause_writes.load(std::sync::atomic::Ordering::SeqCst) {
+        return Err(ApiError::Conflict("resharding in progress".into()));
+    }
+    let removed = remove_node_everywhere(&*state.pool.read().await, id)?;
+    Ok(Json(ApiResponse {
+        ok: true,
+        data: removed,
+    }))
+}
+
+/// Edges pointing at `id` are stored with their source's shard, so sweep them all.
+fn remove_node_everywhere(pool: &DbPool, id: Uuid) -> Result<usize, ApiError> {
+    let mut removed = 0;
+    for shard in pool.each() {
+        removed += shard.remove_node(id).map_err(ApiError::from)?;
+    }
+    Ok(removed)
+}
+
+/// `remove_node_everywhere` for many ids, one WAL write per shard.
+fn remove_nodes_everywhere(pool: &DbPool, ids: &[Uuid]) -> Result<usize, ApiError> {
+    let mut removed = 0;
+    for shard in pool.each() {
+        removed += shard.remove_nodes(ids).map_err(ApiError::from)?;
+    }
+    Ok(removed)
+}
+
 async fn list_bfs(
     State(state): State<AppState>,
     Path(id): Path<Uuid>,
 ) -> Result<Json<ApiResponse<Vec<pieskieo_core::Edge>>>, ApiError> {
-    let edges = state.pool.read().await.shard_for(&id).bfs(id, 100);
+    let edges = state.pool.read().await.shard_for(&id)?.bfs(id, 100);
+    Ok(Json(ApiResponse {
+        ok: true,
+        data: edges,
+    }))
+}
+
+async fn list_dfs(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+) -> Result<Json<ApiResponse<Vec<pieskieo_core::Edge>>>, ApiError> {
+    let edges = state.pool.read().await.shard_for(&id)?.dfs(id, 100);
     Ok(Json(ApiResponse {
         ok: true,
         data: edges,
     }))
 }
 
-async fn list_dfs(
-    State(state): State<AppState>,
-    Path(id): Path<Uuid>,
-) -> Result<Json<ApiResponse<Vec<pieskieo_core::Edge>>>, ApiError> {
-    let edges = state.pool.read().await.shard_for(&id).dfs(id, 100);
-    Ok(Json(ApiResponse {
-        ok: true,
-        data: edges,
-    }))
+#[derive(Deserialize)]
+struct ComponentsQuery {
+    /// most component sizes listed (default 100)
+    limit: Option<usize>,
+}
+
+#[derive(Serialize)]
+struct GraphComponents {
+    components: usize,
+    nodes: usize,
+    /// component sizes, largest first, capped at `limit`
+    sizes: Vec<usize>,
+}
+
+/// Components span shards whenever an edge's target lives elsewhere, so the
+/// per-shard components are merged on shared nodes.
+async fn graph_components(
+    State(state): State<AppState>,
+    Query(q): Query<ComponentsQuery>,
+) -> Result<Json<ApiResponse<GraphComponents>>, ApiError> {
+    let per_shard: Vec<Vec<Uuid>> = state
+        .pool
+        .read()
+        .await
+        .each()
+        .flat_map(|shard| shard.graph_components())
+        .collect();
+    let merged = pieskieo_core::graph::connected(
+        per_shard
+            .iter()
+            .flat_map(|c| c.iter().map(move |id| (c[0], *id))),
+    );
+    let mut sizes: Vec<usize> = merged.iter().map(Vec::len).collect();
+    let nodes = sizes.iter().sum();
+    let components = sizes.len();
+    sizes.truncate(q.limit.unwrap_or(100));
+    Ok(Json(ApiResponse {
+        ok: true,
+        data: GraphComponents {
+            components,
+            nodes,
+            sizes,
+        },
+    }))
+}
+
+/// Edges this many at a time per NDJSON chunk of the graph export.
+const GRAPH_EXPORT_CHUNK: usize = 1000;
+
+/// NDJSON, one `{src, dst, weight, auto}` per line, shard by shard; the edges
+/// are copied up front so the stream holds no locks.
+async fn export_graph(State(state): State<AppState>) -> axum::response::Response {
+    let edges: Vec<pieskieo_core::Edge> = state
+        .pool
+        .read()
+        .await
+        .each()
+        .flat_map(|shard| shard.export_edges())
+        .collect();
+    let chunks: Vec<Result<String, std::io::Error>> = edges
+        .chunks(GRAPH_EXPORT_CHUNK)
+        .map(|chunk| {
+            let mut out = String::new();
+            for e in chunk {
+                out.push_str(&serde_json::to_string(e)?);
+                out.push('\n');
+            }
+            Ok(out)
+        })
+        .collect();
+    (
+        [(axum::http::header::CONTENT_TYPE, "application/x-ndjson")],
+        Body::from_stream(futures::stream::iter(chunks)),
+    )
+        .into_response()
+}
+
+/// Edges per WAL write while streaming a graph import.
+const GRAPH_IMPORT_BATCH: usize = 1000;
+
+/// Longest NDJSON line a graph import buffers; an edge is well under 200 bytes.
+const GRAPH_IMPORT_MAX_LINE: usize = 64 * 1024;
+
+/// Unflushed WAL bytes a shard may hold during an import before the import
+/// fsyncs it and reads on, so a huge body can't outrun the disk.
+const GRAPH_IMPORT_FLUSH_BYTES: u64 = 8 * 1024 * 1024;
+
+/// `PIESKIEO_IMPORT_LIMIT_MB` caps one streamed import (unset or 0: no cap);
+/// imports are not bound by `PIESKIEO_BODY_LIMIT_MB`.
+fn import_limit_bytes() -> Option<u64> {
+    static LIMIT: std::sync::OnceLock<Option<u64>> = std::sync::OnceLock::new();
+    *LIMIT.get_or_init(|| {
+        std::env::var("PIESKIEO_IMPORT_LIMIT_MB")
+            .ok()
+            .and_then(|s| s.parse::<u64>().ok())
+            .filter(|mb| *mb > 0)
+            .map(|mb| mb * 1024 * 1024)
+    })
+}
+
+/// Accepts the export's NDJSON (blank lines skipped; `auto` defaults to false)
+/// as a stream, loading every `GRAPH_IMPORT_BATCH` edges as they arrive, so
+/// memory stays bounded by the batch rather than the body. A bad line stops
+/// the import; batches before it stay loaded.
+async fn import_graph(
+    State(state): State<AppState>,
+    body: Body,
+) -> Result<Json<ApiResponse<usize>>, ApiError> {
+    use futures::StreamExt;
+
+    let limit = import_limit_bytes();
+    let mut stream = body.into_data_stream();
+    let mut buf: Vec<u8> = Vec::new();
+    let mut batch = Vec::with_capacity(GRAPH_IMPORT_BATCH);
+    let mut read = 0u64;
+    let mut line_no = 0usize;
+    let mut loaded = 0usize;
+    loop {
+        let chunk = stream
+            .next()
+            .await
+            .transpose()
+            .map_err(|e| ApiError::BadRequest(format!("reading body: {e}")))?;
+        let done = chunk.is_none();
+        match chunk {
+            Some(bytes) => {
+                read += bytes.len() as u64;
+                if limit.is_some_and(|max| read > max) {
+                    return Err(ApiError::BadRequest(format!(
+                        "import exceeds PIESKIEO_IMPORT_LIMIT_MB ({loaded} edges loaded)"
+                    )));
+                }
+                buf.extend_from_slice(&bytes);
+            }
+            // the last line may lack its newline
+            None if !buf.is_empty() => buf.push(b'\n'),
+            None => {}
+        }
+        let mut consumed = 0;
+        while let Some(pos) = buf[consumed..].iter().position(|b| *b == b'\n') {
+            let line = &buf[consumed..consumed + pos];
+            consumed += pos + 1;
+            line_no += 1;
+            if line.len() > GRAPH_IMPORT_MAX_LINE {
+                return Err(line_too_long(line_no, loaded));
+            }
+            if line.iter().all(u8::is_ascii_whitespace) {
+                continue;
+            }
+            let edge: pieskieo_core::Edge = serde_json::from_slice(line).map_err(|e| {
+                ApiError::BadRequest(format!(
+                    "line {line_no}: {e} ({loaded} edges loaded before it)"
+                ))
+            })?;
+            batch.push(edge);
+            if batch.len() >= GRAPH_IMPORT_BATCH {
+                loaded += import_edge_batch(&state, &mut batch).await?;
+            }
+        }
+        buf.drain(..consumed);
+        // what is left is an unfinished line
+        if buf.len() > GRAPH_IMPORT_MAX_LINE {
+            return Err(line_too_long(line_no + 1, loaded));
+        }
+        if done {
+            break;
+        }
+    }
+    loaded += import_edge_batch(&state, &mut batch).await?;
+    Ok(Json(ApiResponse {
+        ok: true,
+        data: loaded,
+    }))
+}
+
+fn line_too_long(line_no: usize, loaded: usize) -> ApiError {
+    ApiError::BadRequest(format!(
+        "line {line_no} is longer than {GRAPH_IMPORT_MAX_LINE} bytes ({loaded} edges loaded before it)"
+    ))
+}
+
+/// Drains `batch` with one WAL write per shard. The pool lock is taken per
+/// batch so a long import doesn't hold off a reshard.
+async fn import_edge_batch(
+    state: &AppState,
+    batch: &mut Vec<pieskieo_core::Edge>,
+) -> Result<usize, ApiError> {
+    if batch.is_empty() {
+        return Ok(0);
+    }
+    if state.pause_writes.load(std::sync::atomic::Ordering::SeqCst) {
+        return Err(ApiError::Conflict("resharding in progress".into()));
+    }
+    let pool = state.pool.read().await;
+    let mut by_shard: BTreeMap<usize, Vec<pieskieo_core::Edge>> = BTreeMap::new();
+    for e in batch.drain(..) {
+        by_shard
+            .entry(pieskieo_core::shard_index(&e.src, pool.shards.len()))
+            .or_default()
+            .push(e);
+    }
+    let mut loaded = 0;
+    for (shard, edges) in by_shard {
+        let db = pool.shard(shard)?;
+        db.import_edges(&edges)?;
+        if db.wal_pending_bytes() >= GRAPH_IMPORT_FLUSH_BYTES {
+            db.flush_wal()?;
+        }
+        loaded += edges.len();
+    }
+    Ok(loaded)
+}
+
+#[derive(Deserialize)]
+struct BatchInput {
+    ops: Vec<BatchOp>,
+}
+
+/// Atomic only within one shard, so every op must route to the same shard.
+async fn apply_batch(
+    State(state): State<AppState>,
+    Json(input): Json<BatchInput>,
+) -> Result<Json<ApiResponse<Vec<bool>>>, ApiError> {
+    if state.pause_writes.load(std::sync::atomic::Ordering::SeqCst) {
+        return Err(ApiError::Conflict("resharding in progress".into()));
+    }
+    let pool = state.pool.read().await;
+    let shard_of = |op: &BatchOp| pieskieo_core::shard_index(&op.key(), pool.shards.len());
+    let Some(shard) = input.ops.first().map(shard_of) else {
+        return Ok(Json(ApiResponse {
+            ok: true,
+            data: Vec::new(),
+        }));
+    };
+    if let Some(op) = input.ops.iter().find(|op| shard_of(*op) != shard) {
+        return Err(ApiError::BadRequest(format!(
+            "batch spans shards: {} belongs to shard {}, the batch to shard {shard}",
+            op.key(),
+            shard_of(op)
+        )));
+    }
+    // incoming edges of deleted docs/vectors live on other shards
+    let unlinked: Vec<Uuid> = input
+        .ops
+        .iter()
+        .filter_map(|op| match op {
+            BatchOp::Delete { family, id, .. } if *family != BatchFamily::Row => Some(*id),
+            _ => None,
+        })
+        .collect();
+    let created = pool
+        .shard(shard)?
+        .apply_batch(input.ops)
+        .map_err(ApiError::from)?;
+    for id in unlinked {
+        remove_node_everywhere(&pool, id)?;
+    }
+    Ok(Json(ApiResponse {
+        ok: true,
+        data: created,
+    }))
+}
+
+async fn which_shard(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+) -> Result<Json<ApiResponse<HashMap<&'static str, usize>>>, ApiError> {
+    let pool = state.pool.read().await;
+    let shard_total = pool.shards.len();
+    let shard_id = pieskieo_core::shard_index(&id, shard_total);
+    let mut map = HashMap::new();
+    map.insert("shard_id", shard_id);
+    map.insert("shard_total", shard_total);
+    Ok(Json(ApiResponse {
+        ok: true,
+        data: map,
+    }))
+}
+
+#[derive(Serialize)]
+struct ShardNode {
+    shard: usize,
+    url: Option<String>,
+}
+
+#[derive(Serialize)]
+struct ShardMap {
+    shards: usize,
+    nodes: Vec<ShardNode>,
+}
+
+async fn shard_map(State(state): State<AppState>) -> Json<ApiResponse<ShardMap>> {
+    let shards = state.pool.read().await.shards.len();
+    let nodes = (0..shards)
+        .map(|shard| ShardNode {
+            shard,
+            url: state
+                .shard_addrs
+                .get(shard)
+                .filter(|a| !a.is_empty())
+                .cloned(),
+        })
+        .collect();
+    Json(ApiResponse {
+        ok: true,
+        data: ShardMap { shards, nodes },
+    })
+}
+
+#[derive(Serialize)]
+struct ShardMetricsOut {
+    shard: usize,
+    docs: usize,
+    rows: usize,
+    vectors: usize,
+}
+
+#[derive(Serialize)]
+struct MetricsOut {
+    docs: usize,
+    rows: usize,
+    vectors: usize,
+    vector_tombstones: usize,
+    hnsw_ready: bool,
+    ef_search: usize,
+    ef_construction: usize,
+    link_top_k: usize,
+    shard_total: usize,
+    wal_bytes: u64,
+    docs_written_total: u64,
+    rows_written_total: u64,
+    vectors_written_total: u64,
+    edges_written_total: u64,
+    rate_rejects: u64,
+    concurrency_rejects: u64,
+    wal_flush_last_us: u64,
+    wal_flush_max_us: u64,
+    wal_flush_runs: u64,
+    shards: Vec<ShardMetricsOut>,
+    /// shards that failed to open, with the error
+    down_shards: BTreeMap<usize, String>,
+    vector_namespaces: Vec<VectorNsStatsOut>,
+    uptime_seconds: u64,
+    compactions_total: u64,
+    wal_records_compacted_total: u64,
+    /// unix ms of the newest compaction on any shard, and its duration; 0 if none
+    last_compaction_ms: u64,
+    last_compaction_us: u64,
+    /// whether the auto-vacuum task is still ticking; null when it is disabled
+    compaction_task_up: Option<bool>,
+    /// Linux only; null elsewhere or when /proc is unreadable
+    process_resident_memory_bytes: Option<u64>,
+    process_open_fds: Option<u64>,
+}
+
+/// Resident set size and open file descriptors of this process, from /proc.
+#[cfg(target_os = "linux")]
+fn process_stats() -> (Option<u64>, Option<u64>) {
+    let rss = std::fs::read_to_string("/proc/self/status")
+        .ok()
+        .and_then(|status| {
+            let line = status.lines().find(|l| l.starts_with("VmRSS:"))?;
+            let kb: u64 = line.split_whitespace().nth(1)?.parse().ok()?;
+            Some(kb * 1024)
+        });
+    let fds = std::fs::read_dir("/proc/self/fd")
+        .ok()
+        .map(|dir| dir.count() as u64);
+    (rss, fds)
 }
 
-async fn which_shard(
-    State(state): State<AppState>,
-    Path(id): Path<Uuid>,
-) -> Result<Json<ApiResponse<HashMap<&'static str, usize>>>, ApiError> {
-    let pool = state.pool.read().await;
-    let shard_total = pool.shards.len();
-    let mut arr = [0u8; 8];
-    arr.copy_from_slice(&id.as_bytes()[..8]);
-    let shard_id = (u64::from_le_bytes(arr) as usize) % shard_total;
-    let mut map = HashMap::new();
-    map.insert("shard_id", shard_id);
-    map.insert("shard_total", shard_total);
-    Ok(Json(ApiResponse {
-        ok: true,
-        data: map,
-    }))
+#[cfg(not(target_os = "linux"))]
+fn process_stats() -> (Option<u64>, Option<u64>) {
+    (None, None)
+}
+
+fn wants_json(headers: &axum::http::HeaderMap) -> bool {
+    headers
+        .get(axum::http::header::ACCEPT)
+        .and_then(|v| v.to_str().ok())
+        .map(|v| {
+            v.split(',')
+                .any(|t| t.split(';').next().unwrap_or("").trim() == "application/json")
+        })
+        .unwrap_or(false)
 }
 
 async fn metrics(
     State(state): State<AppState>,
-) -> Result<impl axum::response::IntoResponse, ApiError> {
+    headers: axum::http::HeaderMap,
+) -> Result<axum::response::Response, ApiError> {
     let guard = state.pool.read().await;
     let m = guard.aggregate_metrics();
+    let uptime = state.started.elapsed().as_secs();
+    let (rss, fds) = process_stats();
+    let task_up = state.compaction_task.as_ref().map(|t| !t.stale());
+    if wants_json(&headers) {
+        let data = MetricsOut {
+            docs: m.docs,
+            rows: m.rows,
+            vectors: m.vectors,
+            vector_tombstones: m.vector_tombstones,
+            hnsw_ready: m.hnsw_ready,
+            ef_search: m.ef_search,
+            ef_construction: m.ef_construction,
+            link_top_k: m.link_top_k,
+            shard_total: m.shard_total,
+            wal_bytes: m.wal_bytes,
+            docs_written_total: m.docs_written,
+            rows_written_total: m.rows_written,
+            vectors_written_total: m.vectors_written,
+            edges_written_total: m.edges_written,
+            rate_rejects: state.limiter.rejected.load(Ordering::Relaxed),
+            concurrency_rejects: state.concurrency.rejected.load(Ordering::Relaxed),
+            wal_flush_last_us: state.wal_flush.last_us.load(Ordering::Relaxed),
+            wal_flush_max_us: state.wal_flush.max_us.load(Ordering::Relaxed),
+            wal_flush_runs: state.wal_flush.runs.load(Ordering::Relaxed),
+            shards: guard
+                .live()
+                .map(|(shard, db)| {
+                    let s = db.metrics();
+                    ShardMetricsOut {
+                        shard,
+                        docs: s.docs,
+                        rows: s.rows,
+                        vectors: s.vectors,
+                    }
+                })
+                .collect(),
+            down_shards: guard.down.clone(),
+            vector_namespaces: guard
+                .vector_ns_stats()
+                .into_iter()
+                .map(VectorNsStatsOut::from)
+                .collect(),
+            uptime_seconds: uptime,
+            compactions_total: m.compactions,
+            wal_records_compacted_total: m.wal_records_compacted,
+            last_compaction_ms: m.last_compaction_ms,
+            last_compaction_us: m.last_compaction_us,
+            compaction_task_up: task_up,
+            process_resident_memory_bytes: rss,
+            process_open_fds: fds,
+        };
+        return Ok(Json(ApiResponse { ok: true, data }).into_response());
+    }
+    let p = &state.metric_prefix;
     let mut body = format!(
-        "pieskieo_docs {}\npieskieo_rows {}\npieskieo_vectors {}\npieskieo_vector_tombstones {}\npieskieo_hnsw_ready {}\npieskieo_ef_search {}\npieskieo_ef_construction {}\npieskieo_link_top_k {}\npieskieo_shard_total {}\n",
+        "{p}_docs {}\n{p}_rows {}\n{p}_vectors {}\n{p}_vector_tombstones {}\n{p}_hnsw_ready {}\n{p}_ef_search {}\n{p}_ef_construction {}\n{p}_link_top_k {}\n{p}_shard_total {}\n",
         m.docs,
         m.rows,
         m.vectors,
@@ -1678,15 +4794,77 @@ async fn metrics(
         m.link_top_k,
         m.shard_total,
     );
+    body.push_str(&format!(
+        "{p}_docs_written_total {}\n{p}_rows_written_total {}\n{p}_vectors_written_total {}\n{p}_edges_written_total {}\n",
+        m.docs_written, m.rows_written, m.vectors_written, m.edges_written,
+    ));
     let rejects = state.limiter.rejected.load(Ordering::Relaxed);
-    body.push_str(&format!("pieskieo_rate_rejects {}\n", rejects));
-    for (idx, shard) in guard.shards.iter().enumerate() {
+    body.push_str(&format!("{p}_rate_rejects {}\n", rejects));
+    body.push_str(&format!(
+        "{p}_concurrency_rejects {}\n",
+        state.concurrency.rejected.load(Ordering::Relaxed)
+    ));
+    body.push_str(&format!(
+        "{p}_vector_search_rejects {}\n",
+        state.search_limiter.rejected.load(Ordering::Relaxed)
+    ));
+    for (idx, n) in state.search_limiter.in_flight().into_iter().enumerate() {
+        body.push_str(&format!(
+            "{p}_vector_searches_in_flight{{shard=\"{idx}\"}} {n}\n"
+        ));
+    }
+    body.push_str(&format!(
+        "{p}_wal_flush_last_us {}\n{p}_wal_flush_max_us {}\n{p}_wal_flush_runs {}\n",
+        state.wal_flush.last_us.load(Ordering::Relaxed),
+        state.wal_flush.max_us.load(Ordering::Relaxed),
+        state.wal_flush.runs.load(Ordering::Relaxed),
+    ));
+    body.push_str(&format!("{p}_uptime_seconds {uptime}\n"));
+    body.push_str(&format!(
+        "{p}_compactions_total {}\n{p}_wal_records_compacted_total {}\n{p}_last_compaction_timestamp {}\n{p}_last_compaction_duration_us {}\n",
+        m.compactions,
+        m.wal_records_compacted,
+        m.last_compaction_ms as f64 / 1000.0,
+        m.last_compaction_us,
+    ));
+    if let Some(up) = task_up {
+        body.push_str(&format!("{p}_compaction_task_up {}\n", u8::from(up)));
+    }
+    if let Some(rss) = rss {
+        body.push_str(&format!("{p}_process_resident_memory_bytes {rss}\n"));
+    }
+    if let Some(fds) = fds {
+        body.push_str(&format!("{p}_process_open_fds {fds}\n"));
+    }
+    for idx in 0..guard.shards.len() {
+        body.push_str(&format!(
+            "{p}_shard_up{{shard=\"{idx}\"}} {}\n",
+            u8::from(!guard.down.contains_key(&idx))
+        ));
+    }
+    for (idx, shard) in guard.live() {
         let s = shard.metrics();
         body.push_str(&format!(
-            "pieskieo_shard_vectors{{shard=\"{}\"}} {}\npieskieo_shard_docs{{shard=\"{}\"}} {}\npieskieo_shard_rows{{shard=\"{}\"}} {}\n",
+            "{p}_shard_vectors{{shard=\"{}\"}} {}\n{p}_shard_docs{{shard=\"{}\"}} {}\n{p}_shard_rows{{shard=\"{}\"}} {}\n",
             idx, s.vectors, idx, s.docs, idx, s.rows
         ));
     }
+    for ns in guard.vector_ns_stats() {
+        body.push_str(&format!(
+            "{p}_vector_tombstone_ratio{{namespace=\"{}\"}} {}\n{p}_vector_rebuild_last_us{{namespace=\"{}\"}} {}\n",
+            ns.namespace,
+            ns.tombstone_ratio(),
+            ns.namespace,
+            ns.last_rebuild_us
+        ));
+        body.push_str(&format!(
+            "{p}_vector_bytes{{namespace=\"{ns}\"}} {}\n{p}_vector_owned_bytes{{namespace=\"{ns}\"}} {}\n{p}_vector_leaked_bytes{{namespace=\"{ns}\"}} {}\n",
+            ns.memory_bytes,
+            ns.owned_bytes,
+            ns.leaked_bytes,
+            ns = ns.namespace,
+        ));
+    }
     let resp = (
         [(
             axum::http::header::CONTENT_TYPE,
@@ -1694,7 +4872,7 @@ async fn metrics(
         )],
         body,
     );
-    Ok(resp)
+    Ok(resp.into_response())
 }
 
 async fn replica_wal(
@@ -1708,7 +4886,7 @@ async fn replica_wal(
     let since = q.since.unwrap_or(0);
     let mut slices = Vec::new();
     let guard = state.pool.read().await;
-    for (idx, shard) in guard.shards.iter().enumerate() {
+    for (idx, shard) in guard.live() {
         let (records, end) = shard.wal_replay_since(since).map_err(ApiError::from)?;
         let mut encoded: Vec<String> = Vec::with_capacity(records.len());
         for rec in records {
@@ -1728,6 +4906,33 @@ async fn replica_wal(
     }))
 }
 
+#[derive(Serialize)]
+struct WalHead {
+    shard: usize,
+    end_offset: u64,
+}
+
+async fn replica_head(
+    State(state): State<AppState>,
+    Extension(role): Extension<Role>,
+) -> Result<Json<ApiResponse<Vec<WalHead>>>, ApiError> {
+    if !matches!(role, Role::Admin) {
+        return Err(ApiError::Forbidden);
+    }
+    let guard = state.pool.read().await;
+    let mut heads = Vec::with_capacity(guard.shards.len());
+    for (idx, shard) in guard.live() {
+        heads.push(WalHead {
+            shard: idx,
+            end_offset: shard.wal_current_offset().map_err(ApiError::from)?,
+        });
+    }
+    Ok(Json(ApiResponse {
+        ok: true,
+        data: heads,
+    }))
+}
+
 async fn replica_apply(
     State(state): State<AppState>,
     Extension(role): Extension<Role>,
@@ -1771,7 +4976,7 @@ async fn replica_stream(
         let guard = state.pool.read().await;
         let mut slices = Vec::new();
         let mut max_end = since;
-        for (idx, shard) in guard.shards.iter().enumerate() {
+        for (idx, shard) in guard.live() {
             let (records, end) = shard
                 .wal_replay_since(last_offset)
                 .map_err(ApiError::from)?;
@@ -1825,7 +5030,7 @@ async fn replica_ws_loop(mut socket: WebSocket, state: AppState, since: u64) {
         let guard = state.pool.read().await;
         let mut slices = Vec::new();
         let mut max_end = offset;
-        for (idx, shard) in guard.shards.iter().enumerate() {
+        for (idx, shard) in guard.live() {
             match shard.wal_replay_since(offset) {
                 Ok((records, end)) => {
                     if !records.is_empty() {
@@ -1879,14 +5084,105 @@ async fn replica_ws_loop(mut socket: WebSocket, state: AppState, since: u64) {
     }
 }
 
+#[derive(Serialize)]
+struct ShardFlush {
+    shard: usize,
+    /// WAL bytes that were buffered when the flush started
+    bytes: u64,
+}
+
+/// Durability barrier: fsyncs every shard's WAL, so everything acknowledged
+/// before the call is on disk when it returns (e.g. ahead of a filesystem
+/// snapshot).
+async fn flush_wal_all(
+    State(state): State<AppState>,
+    Extension(role): Extension<Role>,
+) -> Result<Json<ApiResponse<Vec<ShardFlush>>>, ApiError> {
+    if !matches!(role, Role::Admin) {
+        return Err(ApiError::Forbidden);
+    }
+    let mut out = Vec::new();
+    for shard in state.pool.read().await.each() {
+        let bytes = shard.wal_pending_bytes();
+        shard.flush_wal().map_err(ApiError::from)?;
+        out.push(ShardFlush {
+            shard: shard.shard_id(),
+            bytes,
+        });
+    }
+    Ok(Json(ApiResponse {
+        ok: true,
+        data: out,
+    }))
+}
+
+/// Tenant offboarding: remove a namespace's docs, rows, vectors and their
+/// edges from every shard. The default namespace needs `?force=true`.
+async fn drop_namespace(
+    State(state): State<AppState>,
+    Extension(role): Extension<Role>,
+    Path(ns): Path<String>,
+    Query(q): Query<DropNamespaceQuery>,
+) -> Result<Json<ApiResponse<NamespaceDrop>>, ApiError> {
+    if !matches!(role, Role::Admin) {
+        return Err(ApiError::Forbidden);
+    }
+    if state.pause_writes.load(std::sync::atomic::Ordering::SeqCst) {
+        return Err(ApiError::Conflict("resharding in progress".into()));
+    }
+    if ns == "default" && !q.force.unwrap_or(false) {
+        return Err(ApiError::BadRequest(
+            "refusing to drop the default namespace without force=true".into(),
+        ));
+    }
+    let pool = state.pool.read().await;
+    let mut total = NamespaceDrop::default();
+    for shard in pool.each() {
+        let dropped = shard.drop_namespace(&ns).map_err(ApiError::from)?;
+        total.docs += dropped.docs;
+        total.rows += dropped.rows;
+        total.vectors += dropped.vectors;
+        total.edges += dropped.edges;
+        total.ids.extend(dropped.ids);
+    }
+    // edges into the namespace may be held by shards owning their sources
+    total.edges += remove_nodes_everywhere(&pool, &std::mem::take(&mut total.ids))?;
+    tracing::info!(
+        namespace = %ns,
+        docs = total.docs,
+        rows = total.rows,
+        vectors = total.vectors,
+        edges = total.edges,
+        "namespace dropped"
+    );
+    Ok(Json(ApiResponse {
+        ok: true,
+        data: total,
+    }))
+}
+
 async fn reshard(
     State(state): State<AppState>,
     Extension(role): Extension<Role>,
+    Query(q): Query<ReshardQuery>,
     Json(input): Json<ReshardRequest>,
-) -> Result<Json<ApiResponse<&'static str>>, ApiError> {
+) -> Result<Json<ApiResponse<serde_json::Value>>, ApiError> {
     if !matches!(role, Role::Admin) {
         return Err(ApiError::Forbidden);
     }
+    // a down shard's records are only in its WAL, which a reshard can't read
+    if let Some(shard) = state.pool.read().await.down.keys().next() {
+        return Err(ApiError::Conflict(format!(
+            "shard {shard} is unavailable; resharding needs every shard"
+        )));
+    }
+    if q.dry_run {
+        let plan = reshard_plan(&*state.pool.read().await, input.shards.max(1));
+        return Ok(Json(ApiResponse {
+            ok: true,
+            data: serde_json::to_value(plan).map_err(|e| ApiError::Internal(e.into()))?,
+        }));
+    }
     state
         .pause_writes
         .store(true, std::sync::atomic::Ordering::SeqCst);
@@ -1907,7 +5203,8 @@ async fn reshard(
     };
     let mut params = template.clone();
     params.shard_total = new_shards;
-    let new_pool = DbPool::new(&state.data_dir, params, new_shards).map_err(ApiError::Internal)?;
+    let new_pool =
+        DbPool::new(&state.data_dir, params, new_shards, false).map_err(ApiError::Internal)?;
     for rec in &wal {
         for shard in new_pool.each() {
             shard
@@ -1936,10 +5233,35 @@ async fn reshard(
     }
     Ok(Json(ApiResponse {
         ok: true,
-        data: "resharded",
+        data: serde_json::json!("resharded"),
     }))
 }
 
+/// Route every live record through `new_shards` without touching the pool.
+fn reshard_plan(pool: &DbPool, new_shards: usize) -> ReshardPlan {
+    let mut plan = ReshardPlan {
+        shards: new_shards,
+        records: 0,
+        moved: 0,
+        before_counts: BTreeMap::new(),
+        after_counts: (0..new_shards).map(|i| (i, 0)).collect(),
+    };
+    for shard in pool.each() {
+        let from = shard.shard_id();
+        let ids = shard.record_ids();
+        plan.records += ids.len();
+        *plan.before_counts.entry(from).or_default() += ids.len();
+        for id in ids {
+            let to = pieskieo_core::shard_index(&id, new_shards);
+            *plan.after_counts.entry(to).or_default() += 1;
+            if to != from {
+                plan.moved += 1;
+            }
+        }
+    }
+    plan
+}
+
 async fn reshard_status(
     State(state): State<AppState>,
 ) -> Result<Json<ApiResponse<ReshardStatus>>, ApiError> {
@@ -1951,8 +5273,135 @@ async fn reshard_status(
     }))
 }
 
+/// Address of the client behind the request, as resolved by `client_ip_middleware`.
+#[derive(Clone, Copy)]
+struct ClientIp(IpAddr);
+
+/// An address or CIDR block listed in `PIESKIEO_TRUST_PROXY`.
+struct TrustedNet {
+    addr: IpAddr,
+    prefix: u32,
+}
+
+impl TrustedNet {
+    fn parse(s: &str) -> Option<Self> {
+        let (addr, prefix) = match s.split_once('/') {
+            Some((a, p)) => (a.parse::<IpAddr>().ok()?, Some(p.parse::<u32>().ok()?)),
+            None => (s.parse::<IpAddr>().ok()?, None),
+        };
+        let max = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix = prefix.unwrap_or(max);
+        if prefix > max {
+            return None;
+        }
+        // `contains` canonicalizes peers, so `::ffff:a.b.c.d` is kept as IPv4
+        match addr.to_canonical() {
+            IpAddr::V4(v4) if addr.is_ipv6() && prefix >= 96 => Some(Self {
+                addr: IpAddr::V4(v4),
+                prefix: prefix - 96,
+            }),
+            _ => Some(Self { addr, prefix }),
+        }
+    }
+
+    fn contains(&self, ip: IpAddr) -> bool {
+        let bits = |ip: IpAddr| match ip {
+            IpAddr::V4(v4) => (u32::from(v4) as u128) << 96,
+            IpAddr::V6(v6) => u128::from(v6),
+        };
+        let ip = ip.to_canonical();
+        if ip.is_ipv4() != self.addr.is_ipv4() {
+            return false;
+        }
+        let mask = u128::MAX.checked_shl(128 - self.prefix).unwrap_or(0);
+        bits(ip) & mask == bits(self.addr) & mask
+    }
+}
+
+/// `PIESKIEO_TRUST_PROXY`: comma-separated proxy addresses or CIDR blocks
+/// whose `X-Forwarded-For`/`X-Real-IP` headers are believed. Unset, the
+/// headers are ignored and the socket peer is the client.
+fn trusted_proxies() -> &'static [TrustedNet] {
+    static NETS: std::sync::OnceLock<Vec<TrustedNet>> = std::sync::OnceLock::new();
+    NETS.get_or_init(|| {
+        std::env::var("PIESKIEO_TRUST_PROXY")
+            .unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+            .filter(|s| !s.is_empty())
+            .filter_map(|s| {
+                let net = TrustedNet::parse(s);
+                if net.is_none() {
+                    tracing::warn!(entry = %s, "ignoring invalid PIESKIEO_TRUST_PROXY entry");
+                }
+                net
+            })
+            .collect()
+    })
+}
+
+/// The client address for a request from `peer`. Forwarding headers count
+/// only when `peer` is a trusted proxy; `X-Forwarded-For` is then walked from
+/// the right, since each proxy appends the address it saw, and the first hop
+/// that is not itself a trusted proxy is the client. Anything left of it can
+/// be forged by the client and is never used.
+fn resolve_client_ip(
+    peer: IpAddr,
+    headers: &axum::http::HeaderMap,
+    trusted: &[TrustedNet],
+) -> IpAddr {
+    let is_trusted = |ip: IpAddr| trusted.iter().any(|n| n.contains(ip));
+    if !is_trusted(peer) {
+        return peer;
+    }
+    let parse = |s: &str| {
+        let s = s.trim();
+        s.parse::<IpAddr>()
+            .ok()
+            .or_else(|| s.parse::<SocketAddr>().ok().map(|a| a.ip()))
+    };
+    let forwarded: Vec<&str> = headers
+        .get_all("x-forwarded-for")
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .collect();
+    if forwarded.is_empty() {
+        return headers
+            .get("x-real-ip")
+            .and_then(|v| v.to_str().ok())
+            .and_then(parse)
+            .unwrap_or(peer);
+    }
+    let mut client = peer;
+    for hop in forwarded.iter().rev() {
+        // a malformed hop ends the chain at the last proxy we trust
+        let Some(ip) = parse(hop) else {
+            break;
+        };
+        client = ip;
+        if !is_trusted(ip) {
+            break;
+        }
+    }
+    client
+}
+
+/// Outermost layer: resolves the client address once for the rate limiter,
+/// concurrency limiter, auth lockouts and audit log.
+async fn client_ip_middleware(
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    mut req: Request<Body>,
+    next: Next,
+) -> axum::response::Response {
+    let ip = resolve_client_ip(addr.ip(), req.headers(), trusted_proxies());
+    req.extensions_mut().insert(ClientIp(ip));
+    next.run(req).await
+}
+
 async fn auth_middleware(
     State(auth): State<Arc<RwLock<AuthConfig>>>,
+    Extension(ClientIp(ip)): Extension<ClientIp>,
     mut req: Request<Body>,
     next: Next,
 ) -> Result<axum::response::Response, ApiError> {
@@ -1963,32 +5412,35 @@ async fn auth_middleware(
     if let Some(header) = req.headers().get(axum::http::header::AUTHORIZATION) {
         if let Ok(val) = header.to_str() {
             if let Some(tok) = val.strip_prefix("Bearer ") {
-                if let Some(expected) = &auth_guard.bearer {
-                    if tok == expected {
-                        req.extensions_mut().insert(Role::Admin);
-                        return Ok(next.run(req).await);
+                if let Some(rec) = auth_guard.tokens.iter().find(|t| t.token == tok) {
+                    if authorize(rec.role, req.uri().path(), req.method().as_str()) {
+                        return Ok(run_as(rec.role, req, next).await);
                     }
+                    let mut resp = ApiError::Forbidden.into_response();
+                    resp.extensions_mut().insert(rec.role);
+                    return Ok(resp);
                 }
             }
             if let Some(basic) = val.strip_prefix("Basic ") {
                 if let Ok(decoded) = B64.decode(basic) {
                     if let Ok(s) = String::from_utf8(decoded) {
                         if let Some((u, p)) = s.split_once(':') {
-                            if auth_guard.check_lockout(u) {
+                            if auth_guard.check_lockout(u, ip) {
                                 return Err(ApiError::Unauthorized);
                             }
                             if let Some(user) = auth_guard.users.iter().find(|usr| {
                                 usr.user == u && AuthConfig::verify_password(&usr.password_hash, p)
                             }) {
                                 auth_guard.record_success(u);
-                                req.extensions_mut().insert(user.role);
                                 if authorize(user.role, req.uri().path(), req.method().as_str()) {
-                                    return Ok(next.run(req).await);
+                                    return Ok(run_as(user.role, req, next).await);
                                 } else {
-                                    return Err(ApiError::Forbidden);
+                                    let mut resp = ApiError::Forbidden.into_response();
+                                    resp.extensions_mut().insert(user.role);
+                                    return Ok(resp);
                                 }
                             } else {
-                                auth_guard.record_failure(u);
+                                auth_guard.record_failure(u, ip);
                                 tracing::warn!(user = %u, "auth failure");
                             }
                         }
@@ -2000,47 +5452,137 @@ async fn auth_middleware(
     Err(ApiError::Unauthorized)
 }
 
+/// Run the request as `role`, exposing the role to handlers via the request
+/// and to the outer audit layer via the response.
+async fn run_as(role: Role, mut req: Request<Body>, next: Next) -> axum::response::Response {
+    req.extensions_mut().insert(role);
+    let mut resp = next.run(req).await;
+    resp.extensions_mut().insert(role);
+    resp
+}
+
 async fn rate_limit_middleware(
     State(limiter): State<Arc<RateLimiter>>,
-    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    Extension(ClientIp(ip)): Extension<ClientIp>,
+    req: Request<Body>,
+    next: Next,
+) -> Result<axum::response::Response, ApiError> {
+    let decision = limiter.allow(ip);
+    let mut resp = if decision.allowed {
+        next.run(req).await
+    } else {
+        let mut resp = axum::response::Response::new(axum::body::Body::empty());
+        *resp.status_mut() = axum::http::StatusCode::TOO_MANY_REQUESTS;
+        resp
+    };
+    decision.apply_headers(resp.headers_mut());
+    Ok(resp)
+}
+
+async fn concurrency_middleware(
+    State(limiter): State<Arc<ConcurrencyLimiter>>,
+    Extension(ClientIp(ip)): Extension<ClientIp>,
+    req: Request<Body>,
+    next: Next,
+) -> Result<axum::response::Response, ApiError> {
+    let Some(_slot) = limiter.acquire(ip) else {
+        let mut resp = axum::response::Response::new(axum::body::Body::empty());
+        *resp.status_mut() = axum::http::StatusCode::TOO_MANY_REQUESTS;
+        return Ok(resp);
+    };
+    Ok(next.run(req).await)
+}
+
+/// Exports and streams that read everything and must outlive the request timeout.
+const TIMEOUT_EXEMPT: &[&str] = &[
+    "/v1/vector/export",
+    "/v1/graph/export",
+    "/v1/replica/stream",
+    "/v1/replica/ws",
+];
+
+/// Only reads are cut off: dropping a write handler part way through would
+/// keep the writes it had already made, so writes always run to completion.
+fn timeout_applies(path: &str, method: &str) -> bool {
+    if TIMEOUT_EXEMPT.contains(&path) {
+        return false;
+    }
+    is_read_path(path, method)
+        || (method.eq_ignore_ascii_case("POST")
+            && (path == "/v1/doc/query" || path == "/v1/row/query"))
+}
+
+async fn timeout_middleware(
+    State(limit): State<Duration>,
     req: Request<Body>,
     next: Next,
 ) -> Result<axum::response::Response, ApiError> {
-    match limiter.allow(addr.ip()) {
-        Ok(()) => Ok(next.run(req).await),
-        Err(retry_after) => {
+    if limit.is_zero() || !timeout_applies(req.uri().path(), req.method().as_str()) {
+        return Ok(next.run(req).await);
+    }
+    let path = req.uri().path().to_string();
+    match tokio::time::timeout(limit, next.run(req)).await {
+        Ok(resp) => Ok(resp),
+        Err(_) => {
+            tracing::warn!(%path, secs = limit.as_secs(), "request timed out");
             let mut resp = axum::response::Response::new(axum::body::Body::empty());
-            *resp.status_mut() = axum::http::StatusCode::TOO_MANY_REQUESTS;
-            resp.headers_mut().insert(
-                axum::http::header::RETRY_AFTER,
-                axum::http::HeaderValue::from_str(&retry_after.as_secs().max(1).to_string())
-                    .unwrap_or(axum::http::HeaderValue::from_static("60")),
-            );
+            *resp.status_mut() = axum::http::StatusCode::REQUEST_TIMEOUT;
             Ok(resp)
         }
     }
 }
 
+/// A successful write sent with `?durable=true` or `X-Durable: true` is
+/// acknowledged only after every shard with unflushed WAL bytes has been
+/// fsynced, instead of waiting for the background flusher.
+async fn durable_middleware(
+    State(pool): State<Arc<RwLock<DbPool>>>,
+    req: Request<Body>,
+    next: Next,
+) -> Result<axum::response::Response, ApiError> {
+    let truthy = |v: &str| matches!(v.to_ascii_lowercase().as_str(), "1" | "true" | "on");
+    let asked = req.uri().query().is_some_and(|q| {
+        q.split('&')
+            .any(|kv| kv.strip_prefix("durable=").is_some_and(truthy))
+    }) || req
+        .headers()
+        .get("x-durable")
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(truthy);
+    let durable = asked && is_write_path(req.uri().path(), req.method().as_str());
+    let res = next.run(req).await;
+    if !durable || !res.status().is_success() {
+        return Ok(res);
+    }
+    let dirty: Vec<Arc<PieskieoDb>> = pool
+        .read()
+        .await
+        .each()
+        .filter(|shard| shard.wal_pending_bytes() > 0)
+        .collect();
+    for shard in dirty {
+        shard.flush_wal().map_err(ApiError::from)?;
+    }
+    Ok(res)
+}
+
 async fn audit_middleware(
     State(audit): State<Arc<AuditLog>>,
-    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    Extension(ClientIp(ip)): Extension<ClientIp>,
     req: Request<Body>,
     next: Next,
 ) -> Result<axum::response::Response, ApiError> {
     let method = req.method().clone();
     let path = req.uri().path().to_string();
     let start = Instant::now();
-    let role = req.extensions().get::<Role>().copied();
     let res = next.run(req).await;
     let status = res.status().as_u16();
-    audit.write(
-        addr.ip(),
-        method.as_str(),
-        &path,
-        status,
-        role,
-        start.elapsed(),
-    );
+    if !audit.should_log(method.as_str(), &path, status) {
+        return Ok(res);
+    }
+    // set by auth_middleware, which runs inside this layer
+    let role = res.extensions().get::<Role>().copied();
+    audit.write(ip, method.as_str(), &path, status, role, start.elapsed());
     Ok(res)
 }
 
@@ -2058,15 +5600,21 @@ fn authorize(role: Role, path: &str, method: &str) -> bool {
 }
 
 fn is_read_path(path: &str, method: &str) -> bool {
-    if path == "/healthz" || path == "/metrics" {
+    if path == "/healthz" || path == "/readyz" || path == "/metrics" {
         return true;
     }
     let m = method.to_uppercase();
-    if m == "GET" {
+    if m == "GET" || m == "HEAD" {
         return true;
     }
-    // vector search is POST but read
-    if path.contains("/vector/search") && m == "POST" {
+    // vector search, mget, counts and SQL validation are POST but read
+    if (path.contains("/vector/search")
+        || path == "/v1/vector/mget"
+        || path == "/v1/sql/validate"
+        || path == "/v1/doc/count"
+        || path == "/v1/row/count")
+        && m == "POST"
+    {
         return true;
     }
     if path.contains("/graph") && m == "GET" {
@@ -2118,6 +5666,8 @@ enum ApiError {
     Conflict(String),
     Unauthorized,
     Forbidden,
+    /// the request routes to a shard that is down
+    Unavailable(String),
     Internal(anyhow::Error),
 }
 
@@ -2145,13 +5695,37 @@ impl axum::response::IntoResponse for ApiError {
             ApiError::Conflict(msg) => (StatusCode::CONFLICT, msg).into_response(),
             ApiError::Unauthorized => StatusCode::UNAUTHORIZED.into_response(),
             ApiError::Forbidden => StatusCode::FORBIDDEN.into_response(),
+            ApiError::Unavailable(msg) => (StatusCode::SERVICE_UNAVAILABLE, msg).into_response(),
             ApiError::Internal(err) => {
                 tracing::error!("api_error" = %err);
-                StatusCode::INTERNAL_SERVER_ERROR.into_response()
+                internal_error_response(&err, debug_errors())
             }
         }
     }
 }
+
+/// A 500, with the error chain as its body only when `debug` is set.
+fn internal_error_response(err: &anyhow::Error, debug: bool) -> axum::response::Response {
+    use axum::http::StatusCode;
+    use axum::response::IntoResponse;
+    if debug {
+        (StatusCode::INTERNAL_SERVER_ERROR, format!("{err:#}")).into_response()
+    } else {
+        StatusCode::INTERNAL_SERVER_ERROR.into_response()
+    }
+}
+
+/// `PIESKIEO_DEBUG_ERRORS=true` puts the internal error chain in 500 bodies.
+/// Development only: messages can expose paths and internals.
+fn debug_errors() -> bool {
+    static ON: std::sync::OnceLock<bool> = std::sync::OnceLock::new();
+    *ON.get_or_init(|| {
+        std::env::var("PIESKIEO_DEBUG_ERRORS")
+            .map(|s| matches!(s.to_ascii_lowercase().as_str(), "1" | "true" | "on"))
+            .unwrap_or(false)
+    })
+}
+
 async fn list_users(
     State(state): State<AppState>,
     Extension(role): Extension<Role>,
@@ -2179,10 +5753,10 @@ async fn create_user(
     if !matches!(role, Role::Admin) {
         return Err(ApiError::Forbidden);
     }
-    if let Err(msg) = AuthConfig::validate_password(&input.pass) {
+    let mut auth = state.auth.write().await;
+    if let Err(msg) = auth.policy.validate(&input.pass) {
         return Err(ApiError::BadRequest(msg));
     }
-    let mut auth = state.auth.write().await;
     let role = input
         .role
         .as_deref()
@@ -2199,3 +5773,209 @@ async fn create_user(
         data: "created",
     }))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn internal_error_body_only_in_debug_mode() -> anyhow::Result<()> {
+        let err = anyhow::anyhow!("disk full").context("writing /data/wal.log");
+
+        let resp = internal_error_response(&err, false);
+        assert_eq!(resp.status(), axum::http::StatusCode::INTERNAL_SERVER_ERROR);
+        let body = axum::body::to_bytes(resp.into_body(), usize::MAX).await?;
+        assert!(body.is_empty());
+
+        let resp = internal_error_response(&err, true);
+        assert_eq!(resp.status(), axum::http::StatusCode::INTERNAL_SERVER_ERROR);
+        let body = axum::body::to_bytes(resp.into_body(), usize::MAX).await?;
+        assert_eq!(body, "writing /data/wal.log: disk full");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn ordered_paging_across_shards() -> anyhow::Result<()> {
+        let (dir_a, dir_b) = (tempfile::tempdir()?, tempfile::tempdir()?);
+        let shards = vec![
+            Arc::new(PieskieoDb::open(dir_a.path())?),
+            Arc::new(PieskieoDb::open(dir_b.path())?),
+        ];
+        // interleaved so shard order and sort order disagree
+        for (shard, ages) in shards.iter().zip([[10, 40, 50], [20, 30, 60]]) {
+            for age in ages {
+                shard.put_doc_ns(
+                    Some("default"),
+                    Some("c"),
+                    Uuid::new_v4(),
+                    serde_json::json!({ "age": age }),
+                )?;
+            }
+        }
+        let page = |offset: usize| {
+            let shards = shards.clone();
+            async move {
+                let sql = "SELECT * FROM docs.default.c ORDER BY age";
+                let (sql, limit, offset) = page_select(sql, Some(2), Some(offset))?;
+                let rows = fan_out_select(shards, sql, Some(offset + limit)).await?;
+                Ok::<_, ApiError>(
+                    rows.into_iter()
+                        .skip(offset)
+                        .take(limit)
+                        .map(|(_, v)| v["age"].as_i64().unwrap())
+                        .collect::<Vec<_>>(),
+                )
+            }
+        };
+        assert_eq!(page(0).await.unwrap(), [10, 20]);
+        assert_eq!(page(2).await.unwrap(), [30, 40]);
+        assert_eq!(page(4).await.unwrap(), [50, 60]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn lockout_survives_save_and_reload() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let data_dir = dir.path().to_str().unwrap();
+        let auth = AuthConfig::from_env(data_dir);
+        let (ip, other_ip): (IpAddr, IpAddr) = ("10.0.0.1".parse()?, "10.0.0.2".parse()?);
+        for _ in 0..auth.max_failures {
+            auth.record_failure("mallory", ip);
+        }
+        assert!(auth.check_lockout("mallory", other_ip));
+        let until = auth.attempts.lock().unwrap()["mallory"]
+            .locked_until
+            .unwrap();
+        auth.write_attempts();
+
+        let reloaded = AuthConfig::from_env(data_dir);
+        assert!(reloaded.check_lockout("mallory", other_ip));
+        assert!(!reloaded.check_lockout("alice", other_ip));
+        // stored as unix milliseconds and re-anchored, so only rounding differs
+        let restored = reloaded.attempts.lock().unwrap()["mallory"]
+            .locked_until
+            .unwrap();
+        let drift = if restored > until {
+            restored - until
+        } else {
+            until - restored
+        };
+        assert!(drift < Duration::from_secs(1));
+        Ok(())
+    }
+
+    #[test]
+    fn trusted_net_matches_v4_and_v6_cidrs() {
+        let ip = |s: &str| s.parse::<IpAddr>().unwrap();
+        let net = TrustedNet::parse("10.0.0.0/8").unwrap();
+        assert!(net.contains(ip("10.1.2.3")));
+        assert!(net.contains(ip("::ffff:10.1.2.3")));
+        assert!(!net.contains(ip("11.0.0.1")));
+        assert!(!net.contains(ip("::a01:203")));
+
+        let net = TrustedNet::parse("2001:db8::/32").unwrap();
+        assert!(net.contains(ip("2001:db8:ffff::1")));
+        assert!(!net.contains(ip("2001:db9::1")));
+        assert!(!net.contains(ip("10.0.0.1")));
+
+        // a bare address is a single host
+        let net = TrustedNet::parse("10.0.0.1").unwrap();
+        assert!(net.contains(ip("10.0.0.1")));
+        assert!(!net.contains(ip("10.0.0.2")));
+
+        // mapped entries match the canonical IPv4 peer
+        let net = TrustedNet::parse("::ffff:10.0.0.1").unwrap();
+        assert!(net.contains(ip("10.0.0.1")));
+        let net = TrustedNet::parse("::ffff:10.0.0.0/104").unwrap();
+        assert!(net.contains(ip("10.9.9.9")));
+        assert!(!net.contains(ip("11.0.0.1")));
+
+        assert!(TrustedNet::parse("10.0.0.0/33").is_none());
+        assert!(TrustedNet::parse("2001:db8::/129").is_none());
+        assert!(TrustedNet::parse("proxy.internal").is_none());
+    }
+
+    #[test]
+    fn client_ip_walks_only_trusted_hops() {
+        let ip = |s: &str| s.parse::<IpAddr>().unwrap();
+        let trusted = [
+            TrustedNet::parse("10.0.0.0/8").unwrap(),
+            TrustedNet::parse("fd00::/8").unwrap(),
+        ];
+        let headers = |pairs: &[(&'static str, &str)]| {
+            let mut map = axum::http::HeaderMap::new();
+            for (name, value) in pairs {
+                map.append(*name, value.parse().unwrap());
+            }
+            map
+        };
+        let resolve = |peer: &str, pairs: &[(&'static str, &str)]| {
+            resolve_client_ip(ip(peer), &headers(pairs), &trusted)
+        };
+
+        // an untrusted peer cannot pick its own address
+        let spoof = [
+            ("x-forwarded-for", "198.51.100.7"),
+            ("x-real-ip", "198.51.100.8"),
+        ];
+        assert_eq!(resolve("203.0.113.5", &spoof), ip("203.0.113.5"));
+
+        // several trusted hops are skipped, and anything left of the client is ignored
+        let chain = [(
+            "x-forwarded-for",
+            "6.6.6.6, 198.51.100.7, 10.0.0.9, fd00::3",
+        )];
+        assert_eq!(resolve("10.0.0.2", &chain), ip("198.51.100.7"));
+        let split = [
+            ("x-forwarded-for", "198.51.100.7:4711"),
+            ("x-forwarded-for", "10.0.0.9"),
+        ];
+        assert_eq!(resolve("fd00::1", &split), ip("198.51.100.7"));
+        let all_trusted = [("x-forwarded-for", "10.0.0.8, 10.0.0.9")];
+        assert_eq!(resolve("10.0.0.2", &all_trusted), ip("10.0.0.8"));
+
+        // a malformed hop stops at the last trusted proxy
+        let malformed = [("x-forwarded-for", "198.51.100.7, not-an-ip, 10.0.0.9")];
+        assert_eq!(resolve("10.0.0.2", &malformed), ip("10.0.0.9"));
+        let only_malformed = [("x-forwarded-for", "not-an-ip")];
+        assert_eq!(resolve("10.0.0.2", &only_malformed), ip("10.0.0.2"));
+
+        // X-Real-IP is the fallback when there is no X-Forwarded-For
+        let real = [("x-real-ip", "198.51.100.8")];
+        assert_eq!(resolve("10.0.0.2", &real), ip("198.51.100.8"));
+        assert_eq!(resolve("::ffff:10.0.0.2", &real), ip("198.51.100.8"));
+        let bad_real = [("x-real-ip", "garbage")];
+        assert_eq!(resolve("10.0.0.2", &bad_real), ip("10.0.0.2"));
+        assert_eq!(resolve("10.0.0.2", &[]), ip("10.0.0.2"));
+    }
+
+    #[test]
+    fn password_policy_modes() {
+        let strict = PasswordPolicy {
+            min_len: 8,
+            require_classes: true,
+        };
+        assert!(strict.validate("Passw0rd!").is_ok());
+        assert!(strict.validate("Pa0!").is_err());
+        assert!(strict.validate("password1!").is_err());
+        assert!(strict.validate("Password!!").is_err());
+
+        let length_only = PasswordPolicy {
+            min_len: 8,
+            require_classes: false,
+        };
+        assert!(length_only.validate("correct horse").is_ok());
+        assert!(length_only.validate("short").is_err());
+        assert!(length_only.validate("aaaabbbbcccc").is_err());
+        assert!(length_only.validate("aaaabbbbccccde").is_ok());
+
+        // the distinct-character bar never exceeds the length
+        let short = PasswordPolicy {
+            min_len: 3,
+            require_classes: false,
+        };
+        assert!(short.validate("abc").is_ok());
+        assert!(short.validate("abab").is_err());
+        assert!(short.validate("ab").is_err());
+    }
+}
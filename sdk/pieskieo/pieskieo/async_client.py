@@ -25,15 +25,17 @@ class AsyncPieskieoClient:
         meta: Optional[Dict[str, str]] = None,
         namespace: Optional[str] = None,
     ) -> uuid.UUID:
-        vec_id = id or uuid.uuid4()
-        payload = {"id": str(vec_id), "vector": vector}
+        payload = {"vector": vector}
+        if id is not None:
+            payload["id"] = str(id)
         if namespace:
             payload["namespace"] = namespace
         if meta is not None:
             payload["meta"] = meta
         r = await self.client.post(f"{self.base}/v1/vector", json=payload)
         r.raise_for_status()
-        return vec_id
+        # the server assigns an id when none is sent
+        return uuid.UUID(r.json()["data"])
 
     async def put_vectors_bulk(self, items: List[Dict]) -> int:
         normalized = []